@@ -0,0 +1,401 @@
+use crate::SharedError;
+use clap::Args as ClapArgs;
+use colored::Colorize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{timeout, MissedTickBehavior};
+
+// a mail taking longer than this is counted as an error
+const MAIL_TIMEOUT: Duration = Duration::from_secs(30);
+
+const WORDS: &[&str] = &[
+    "invoice", "account", "order", "password", "reset", "welcome", "shipping", "update", "your",
+    "the", "we", "have", "received", "please", "confirm", "details", "below", "thanks", "team",
+    "today", "payment", "new", "security", "notice", "link", "click", "expires", "in", "hours",
+];
+
+#[derive(ClapArgs, Debug)]
+pub struct BenchArgs {
+    // the top level --help is handled by hand, which clap carries over to subcommands
+    #[arg(long, short, action = clap::ArgAction::Help, help = "Print help")]
+    help: Option<bool>,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1:2525",
+        value_name = "HOST:PORT",
+        help = "The SMTP server to send to"
+    )]
+    pub target: String,
+
+    #[arg(long, default_value = "100", help = "Mails sent per second")]
+    pub rate: u32,
+
+    #[arg(
+        long,
+        default_value = "10k",
+        value_parser = parse_size,
+        help = "Size of each mail, `k` and `m` suffixes are accepted"
+    )]
+    pub size: usize,
+
+    #[arg(
+        long,
+        default_value = "10s",
+        value_parser = parse_duration,
+        help = "How long to send for, `s`, `m` and `h` suffixes are accepted"
+    )]
+    pub duration: Duration,
+
+    #[arg(
+        long,
+        default_value = "512",
+        help = "Maximum number of SMTP connections open at once"
+    )]
+    pub concurrency: usize,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Clients listing the mails over HTTP meanwhile, each sending its next request once answered"
+    )]
+    pub readers: usize,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1:8080",
+        value_name = "HOST:PORT",
+        help = "The HTTP API the readers list the mails from"
+    )]
+    pub http: String,
+
+    #[arg(long, value_name = "KEY", help = "The API key of the readers")]
+    pub key: Option<String>,
+}
+
+/// Parses `512`, `10k` or `2m` (powers of 1024) into bytes.
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim().to_lowercase();
+    let (number, multiplier) = match value.strip_suffix(['k', 'm']) {
+        Some(number) if value.ends_with('k') => (number, 1024),
+        Some(number) => (number, 1024 * 1024),
+        None => (value.as_str(), 1),
+    };
+    number
+        .parse::<usize>()
+        .map(|number| number * multiplier)
+        .map_err(|_| format!("Invalid size `{}`, expected e.g. 512, 10k or 2m", value))
+}
+
+/// Parses `60`, `60s`, `5m` or `1h` into a duration.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim().to_lowercase();
+    let (number, multiplier) = match value.strip_suffix(['s', 'm', 'h']) {
+        Some(number) if value.ends_with('h') => (number, 3600),
+        Some(number) if value.ends_with('m') => (number, 60),
+        Some(number) => (number, 1),
+        None => (value.as_str(), 1),
+    };
+    number
+        .parse::<u64>()
+        .map(|number| Duration::from_secs(number * multiplier))
+        .map_err(|_| format!("Invalid duration `{}`, expected e.g. 60s, 5m or 1h", value))
+}
+
+/// The value under which `percent`% of the sorted samples fall (nearest rank).
+pub fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((percent / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// a cheap xorshift, the traffic only has to look varied
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[(self.next() % items.len() as u64) as usize]
+    }
+}
+
+/// Builds a mail of roughly `size` bytes, with a few senders and recipients to spread it across.
+pub fn generate_mail(sequence: u64, size: usize) -> (String, String, String) {
+    let mut rng = Rng(sequence.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1);
+    let from = format!("{}@bench.test", rng.pick(&["noreply", "billing", "alerts", "news"]));
+    let to = format!("user{}@example.com", rng.next() % 100);
+    let subject = (0..5).map(|_| rng.pick(WORDS)).collect::<Vec<_>>().join(" ");
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let mut data = format!(
+        "From: <{}>\r\nTo: <{}>\r\nSubject: {}\r\nMessage-ID: <{}.{}@bench.test>\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from, to, subject, timestamp, sequence
+    );
+
+    // wrapped like mail clients do, so lines stay under the SMTP limit
+    let mut line = String::new();
+    while data.len() + line.len() < size {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(rng.pick(WORDS));
+        if line.len() >= 72 {
+            data.push_str(&line);
+            data.push_str("\r\n");
+            line.clear();
+        }
+    }
+    data.push_str(&line);
+    data.push_str("\r\n");
+
+    (from, to, data)
+}
+
+async fn read_reply(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Result<(), String> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.map_err(|e| e.kind().to_string())? == 0 {
+            return Err("connection closed".to_string());
+        }
+        // multiline replies continue with `250-`, the last line is `250 `
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        return match line.chars().next() {
+            Some('2') | Some('3') => Ok(()),
+            _ => Err(format!("rejected ({})", line.get(..3).unwrap_or(line.trim_end()))),
+        };
+    }
+}
+
+/// Lists the 50 newest mails with `GET /mails` over a new HTTP connection, and reads the whole
+/// response.
+pub async fn list_mails(target: &str, key: Option<&str>) -> Result<(), String> {
+    let mut stream = TcpStream::connect(target)
+        .await
+        .map_err(|e| format!("connect: {}", e.kind()))?;
+    let mut request = format!("GET /mails?limit=50 HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", target);
+    if let Some(key) = key {
+        request.push_str(&format!("X-Api-Key: {}\r\n", key));
+    }
+    request.push_str("\r\n");
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| e.kind().to_string())?;
+
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .await
+        .map_err(|e| e.kind().to_string())?;
+    // an unauthenticated request is closed without a response
+    match response.get(9..12) {
+        Some(b"200") => Ok(()),
+        Some(status) => Err(format!("status {}", String::from_utf8_lossy(status))),
+        None => Err("connection closed".to_string()),
+    }
+}
+
+/// Delivers a mail over a new SMTP connection, the error says at which step it failed.
+pub async fn send_mail(target: &str, from: &str, to: &[&str], data: &[u8]) -> Result<(), String> {
+    let stream = TcpStream::connect(target)
+        .await
+        .map_err(|e| format!("connect: {}", e.kind()))?;
+    // commands are small writes waiting on a reply, don't let Nagle's algorithm hold them back
+    stream.set_nodelay(true).map_err(|e| e.kind().to_string())?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    read_reply(&mut reader).await?;
+    let mut commands = vec![
+        "EHLO bench.test\r\n".to_string(),
+        format!("MAIL FROM:<{}>\r\n", from),
+    ];
+    commands.extend(to.iter().map(|to| format!("RCPT TO:<{}>\r\n", to)));
+    commands.push("DATA\r\n".to_string());
+    for command in commands {
+        writer
+            .write_all(command.as_bytes())
+            .await
+            .map_err(|e| e.kind().to_string())?;
+        read_reply(&mut reader).await?;
+    }
+
+    // dot-stuffing, so that a line starting with `.` doesn't end the data early
+    let mut payload = Vec::with_capacity(data.len() + 7);
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        if line.starts_with(b".") {
+            payload.push(b'.');
+        }
+        payload.extend_from_slice(line);
+    }
+    if !payload.ends_with(b"\n") {
+        payload.extend_from_slice(b"\r\n");
+    }
+    payload.extend_from_slice(b".\r\n");
+    writer
+        .write_all(&payload)
+        .await
+        .map_err(|e| e.kind().to_string())?;
+    read_reply(&mut reader).await?;
+
+    // the mail is accepted at this point, a failing QUIT doesn't matter
+    let _ = writer.write_all(b"QUIT\r\n").await;
+    Ok(())
+}
+
+pub async fn run(args: BenchArgs) -> Result<(), SharedError> {
+    if args.rate == 0 {
+        return Err("--rate must be at least 1".into());
+    }
+
+    println!(
+        "Sending {} mails/s of {} bytes to {} for {}s...",
+        args.rate,
+        args.size,
+        args.target,
+        args.duration.as_secs()
+    );
+    if args.readers > 0 {
+        println!("Listing the mails from {} with {} readers meanwhile...", args.http, args.readers);
+    }
+
+    // started along with the mails, so that the reads compete with the ingestion
+    let (reads, mut read) = mpsc::unbounded_channel();
+    for _ in 0..args.readers {
+        let reads = reads.clone();
+        let http = args.http.clone();
+        let key = args.key.clone();
+        let duration = args.duration;
+        tokio::spawn(async move {
+            let started = Instant::now();
+            while started.elapsed() < duration {
+                let start = Instant::now();
+                let result = match timeout(MAIL_TIMEOUT, list_mails(&http, key.as_deref())).await {
+                    Ok(result) => result.map(|_| start.elapsed()),
+                    Err(_) => Err("timeout".to_string()),
+                };
+                if reads.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(reads);
+
+    let target = Arc::new(args.target);
+    let limit = Arc::new(Semaphore::new(args.concurrency.max(1)));
+    let (results, mut received) = mpsc::unbounded_channel();
+
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / args.rate as f64));
+    // when falling behind, keep the pace instead of bursting to catch up
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let started = Instant::now();
+    let mut sequence = 0;
+    while started.elapsed() < args.duration {
+        ticker.tick().await;
+        let permit = limit.clone().acquire_owned().await?;
+        let target = target.clone();
+        let results = results.clone();
+        let (from, to, data) = generate_mail(sequence, args.size);
+        sequence += 1;
+
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let recipients = [to.as_str()];
+            let sent = send_mail(&target, &from, &recipients, data.as_bytes());
+            let result = match timeout(MAIL_TIMEOUT, sent).await {
+                Ok(result) => result.map(|_| start.elapsed()),
+                Err(_) => Err("timeout".to_string()),
+            };
+            let _ = results.send(result);
+            drop(permit);
+        });
+    }
+    drop(results);
+
+    let (latencies, errors) = collect(&mut received).await;
+    let (read_latencies, read_errors) = collect(&mut read).await;
+    let elapsed = started.elapsed();
+
+    let failed: u64 = errors.values().sum();
+    println!();
+    println!("{}", "Results:".bold());
+    println!("  sent       {}", sequence);
+    println!("  delivered  {}", latencies.len());
+    println!("  failed     {}", failed);
+    println!(
+        "  throughput {:.1} mails/s",
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    print_latencies("Latency:", &latencies);
+    print_errors("Errors:", errors);
+
+    if args.readers > 0 {
+        let failed: u64 = read_errors.values().sum();
+        println!();
+        println!("{}", "Reads:".bold());
+        println!("  listed     {}", read_latencies.len());
+        println!("  failed     {}", failed);
+        println!(
+            "  throughput {:.1} requests/s",
+            read_latencies.len() as f64 / elapsed.as_secs_f64()
+        );
+        print_latencies("Read latency:", &read_latencies);
+        print_errors("Read errors:", read_errors);
+    }
+
+    Ok(())
+}
+
+// the sorted latencies of what succeeded, and the count of each error
+async fn collect(
+    results: &mut mpsc::UnboundedReceiver<Result<Duration, String>>,
+) -> (Vec<Duration>, BTreeMap<String, u64>) {
+    let mut latencies = Vec::new();
+    let mut errors: BTreeMap<String, u64> = BTreeMap::new();
+    while let Some(result) = results.recv().await {
+        match result {
+            Ok(latency) => latencies.push(latency),
+            Err(error) => *errors.entry(error).or_default() += 1,
+        }
+    }
+    latencies.sort();
+    (latencies, errors)
+}
+
+fn print_latencies(title: &str, latencies: &[Duration]) {
+    println!();
+    println!("{}", title.bold());
+    for (label, percent) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("p99.9", 99.9), ("max", 100.0)] {
+        println!("  {:<10} {:.2} ms", label, percentile(latencies, percent).as_secs_f64() * 1000.0);
+    }
+}
+
+fn print_errors(title: &str, errors: BTreeMap<String, u64>) {
+    if errors.is_empty() {
+        return;
+    }
+    println!();
+    println!("{}", title.bold());
+    for (error, count) in errors {
+        println!("  {:<10} {}", count, error.red());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::cli::Args;
+use crate::duplicates::Policy;
+use crate::rules::Action;
+use crate::store::Storage;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+}
+
+/// The effective configuration, as shown to admins. Secrets are never part of it, only whether
+/// they are set.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Config {
+    // the TOML file the options were read from, if any
+    pub config_file: Option<PathBuf>,
+    // what the SMTP and HTTP ports listen on
+    pub bind: Option<IpAddr>,
+    pub smtp_ports: Vec<u16>,
+    // implicit TLS
+    pub smtps_port: Option<u16>,
+    // whether STARTTLS and SMTPS use a generated certificate rather than `--tls-cert`
+    pub tls_self_signed: bool,
+    // accepted by SMTP AUTH, empty when any credentials are
+    pub smtp_users: Vec<String>,
+    // serving the mails over POP3
+    pub pop3_port: Option<u16>,
+    pub http_port: u16,
+    // HTTPS, with `--http-tls-cert`
+    pub http_tls: bool,
+    // how many API keys there are of each role
+    pub admin_keys: usize,
+    pub read_keys: usize,
+    // mail retention in minutes, `None` keeps mails forever
+    pub lifetime: Option<u16>,
+    // evicting the oldest mails past them, `None` for no limit
+    pub max_mails: Option<usize>,
+    // bytes of raw mail data
+    pub max_db_size: Option<usize>,
+    pub session_ttl: u32,
+    pub panel_user: Option<String>,
+    pub sentry: bool,
+    pub error_webhook: bool,
+    // mails waiting to be stored before SMTP answers 452
+    pub queue_capacity: usize,
+    // bytes, past which SMTP answers 452 and HTTP 503
+    pub memory_budget: Option<usize>,
+    // bytes, past which SMTP answers 552, `None` for no limit
+    pub max_message_size: Option<usize>,
+    // open SMTP, POP3 and HTTP connections, overall and from a single IP, `None` for no limit
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    // per client IP and per second, past which SMTP answers 421 and HTTP 429, `None` for no limit
+    pub smtp_rate: Option<u32>,
+    pub http_rate: Option<u32>,
+    // seconds given to the open connections on SIGINT or SIGTERM
+    pub shutdown_timeout: u64,
+    // seed of the virtual clock mail ids and timestamps come from, `None` uses the wall clock
+    pub deterministic: Option<u64>,
+    pub duplicates: Policy,
+    // scheduled maintenance, as given to `--job`
+    pub jobs: Vec<String>,
+    // recipient routing, as given to `--rule`
+    pub rules: Vec<String>,
+    // what RCPT TO accepts, as given, empty when any recipient is
+    pub accept_domains: Vec<String>,
+    pub accept_recipients: Vec<String>,
+    pub reject_recipients: Vec<String>,
+    // DKIM and SPF checked on receipt, through `dns_server` if set
+    pub verify_auth: bool,
+    pub dns_server: Option<SocketAddr>,
+    // where the `relay` rules deliver, without the password
+    pub relay: Option<String>,
+    // the database directory
+    pub db_path: PathBuf,
+    pub storage: Storage,
+}
+
+impl Config {
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let smtp_ports = args
+            .smtp_port
+            .split(',')
+            .map(|port| port.trim().parse::<u16>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Wrong SMTP ports: {}", args.smtp_port))?;
+        if args.relay.is_none() && args.rule.iter().any(|rule| rule.action == Action::Relay) {
+            return Err("The `relay` rules need --relay".to_string());
+        }
+        // a stored database would get the ids of the seed again, and its mails overwritten
+        if args.deterministic.is_some() && args.storage != Storage::Memory {
+            return Err("--deterministic needs --storage memory".to_string());
+        }
+
+        Ok(Config {
+            config_file: args.config.clone(),
+            bind: Some(args.bind),
+            smtp_ports,
+            smtps_port: args.smtps_port,
+            // known once the certificate is loaded
+            tls_self_signed: false,
+            smtp_users: args.smtp_user.iter().map(|credential| credential.username.clone()).collect(),
+            pop3_port: args.pop3_port,
+            http_port: args.http_ports,
+            http_tls: args.http_tls_cert.is_some(),
+            admin_keys: args.key.len(),
+            read_keys: args.read_key.len(),
+            lifetime: args.lifetime,
+            max_mails: args.max_mails,
+            max_db_size: args.max_db_size,
+            session_ttl: args.session_ttl,
+            panel_user: args.panel_user.clone(),
+            sentry: args.sentry_dsn.is_some() || std::env::var("SENTRY_DSN").is_ok(),
+            error_webhook: args.error_webhook.is_some(),
+            queue_capacity: args.queue_capacity,
+            memory_budget: args.memory_budget,
+            max_message_size: Some(args.max_message_size).filter(|size| *size > 0),
+            max_connections: Some(args.max_connections).filter(|max| *max > 0),
+            max_connections_per_ip: Some(args.max_connections_per_ip).filter(|max| *max > 0),
+            smtp_rate: Some(args.smtp_rate).filter(|rate| *rate > 0),
+            http_rate: Some(args.http_rate).filter(|rate| *rate > 0),
+            shutdown_timeout: args.shutdown_timeout,
+            deterministic: args.deterministic,
+            duplicates: args.duplicates,
+            jobs: args.job.iter().map(|job| job.spec.clone()).collect(),
+            rules: args.rule.iter().map(|rule| rule.spec.clone()).collect(),
+            accept_domains: args.accept_domain.clone(),
+            accept_recipients: args.accept_recipient.iter().map(|pattern| pattern.spec.clone()).collect(),
+            reject_recipients: args.reject_recipient.iter().map(|pattern| pattern.spec.clone()).collect(),
+            verify_auth: args.verify_auth,
+            dns_server: args.dns_server,
+            relay: args.relay.as_ref().map(|upstream| upstream.spec.clone()),
+            db_path: args.db_path.clone(),
+            storage: args.storage,
+        })
+    }
+}
+
+pub fn init(config: Config) {
+    *CONFIG.write().unwrap() = config;
+}
+
+pub fn get() -> Config {
+    CONFIG.read().unwrap().clone()
+}
+
+pub fn set_tls_self_signed(self_signed: bool) {
+    CONFIG.write().unwrap().tls_self_signed = self_signed;
+}
+
+/// The ports actually listened on, once bound: `0` is any free one.
+pub fn set_ports(smtp_ports: Vec<u16>, smtps_port: Option<u16>, pop3_port: Option<u16>, http_port: u16) {
+    let mut config = CONFIG.write().unwrap();
+    config.smtp_ports = smtp_ports;
+    config.smtps_port = smtps_port;
+    config.pop3_port = pop3_port;
+    config.http_port = http_port;
+}
+
+pub fn lifetime() -> Option<u16> {
+    CONFIG.read().unwrap().lifetime
+}
+
+pub fn max_message_size() -> Option<usize> {
+    CONFIG.read().unwrap().max_message_size
+}
+
+pub fn duplicates() -> Policy {
+    CONFIG.read().unwrap().duplicates
+}
+
+pub fn verify_auth() -> bool {
+    CONFIG.read().unwrap().verify_auth
+}
+
+/// Takes the settings of `new` that apply without a restart. The listeners, TLS, API keys and
+/// storage keep the ones they started with.
+pub fn reload(new: Config) {
+    let mut config = CONFIG.write().unwrap();
+    config.smtp_users = new.smtp_users;
+    config.lifetime = new.lifetime;
+    config.max_mails = new.max_mails;
+    config.max_db_size = new.max_db_size;
+    config.memory_budget = new.memory_budget;
+    config.max_message_size = new.max_message_size;
+    config.max_connections = new.max_connections;
+    config.max_connections_per_ip = new.max_connections_per_ip;
+    config.smtp_rate = new.smtp_rate;
+    config.http_rate = new.http_rate;
+    config.shutdown_timeout = new.shutdown_timeout;
+    config.duplicates = new.duplicates;
+    config.jobs = new.jobs;
+    config.rules = new.rules;
+    config.accept_domains = new.accept_domains;
+    config.accept_recipients = new.accept_recipients;
+    config.reject_recipients = new.reject_recipients;
+    config.verify_auth = new.verify_auth;
+    config.dns_server = new.dns_server;
+    config.relay = new.relay;
+}
+
+/// The number of API keys once some were created or revoked through `/admin/keys`.
+pub fn set_keys(admin_keys: usize, read_keys: usize) {
+    let mut config = CONFIG.write().unwrap();
+    config.admin_keys = admin_keys;
+    config.read_keys = read_keys;
+}
+
+/// Changes the retention at runtime, the cleaner picks it up on its next run.
+pub fn set_lifetime(lifetime: Option<u16>) {
+    CONFIG.write().unwrap().lifetime = lifetime;
+}
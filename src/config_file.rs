@@ -0,0 +1,160 @@
+use crate::cli::Args;
+use crate::{config, dns, jobs, memory, relay, rules, smtp, webhooks, SharedError};
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, Parser};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+// the variables are named after the options, `--max-db-size` is `$MAILSINK_MAX_DB_SIZE`
+const ENV_PREFIX: &str = "MAILSINK_";
+
+/// Parses the command line, completed by the `MAILSINK_*` variables then by the `--config` file.
+/// Both take the options by their long name and go through the same checks as the command line.
+pub fn load(argv: Vec<OsString>) -> Result<Args, SharedError> {
+    let command = Args::command();
+    // only to know what the command line sets, it's checked for real below
+    let given = command.clone().ignore_errors(true).try_get_matches_from(&argv)?;
+
+    let path = given
+        .get_one::<PathBuf>("config")
+        .cloned()
+        .or_else(|| std::env::var_os(format!("{}CONFIG", ENV_PREFIX)).map(PathBuf::from));
+    let mut file = match &path {
+        Some(path) => read(path)?,
+        None => BTreeMap::new(),
+    };
+
+    let mut options = Vec::new();
+    if let (Some(path), None) = (&path, given.get_one::<PathBuf>("config")) {
+        options.push(OsString::from(format!("--config={}", path.display())));
+    }
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        if matches!(id, "help" | "version" | "config") {
+            continue;
+        }
+
+        // `max_mail_age` as well as `lifetime`
+        let names = std::iter::once(long)
+            .chain(arg.get_all_aliases().into_iter().flatten())
+            .map(|name| name.replace('-', "_"))
+            .collect::<Vec<_>>();
+        let from_file = names.iter().find_map(|name| file.remove_entry(name));
+        if given.value_source(id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let multiple = matches!(arg.get_action(), ArgAction::Append);
+        let from_env = names
+            .iter()
+            .find_map(|name| std::env::var(format!("{}{}", ENV_PREFIX, name.to_uppercase())).ok());
+        let values = match (from_env, from_file) {
+            // e.g. $MAILSINK_WEBHOOK=https://a.test,https://b.test
+            (Some(value), _) if multiple => value
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .collect(),
+            (Some(value), _) => vec![value],
+            (None, Some((name, value))) => values(&name, value, multiple, path.as_deref().unwrap())?,
+            (None, None) => continue,
+        };
+        options.extend(values.into_iter().map(|value| OsString::from(format!("--{}={}", long, value))));
+    }
+
+    if let (Some(name), Some(path)) = (file.keys().next(), &path) {
+        return Err(format!("Unknown option `{}` in {}", name, path.display()).into());
+    }
+
+    // before the command line's, which may end with a subcommand
+    let mut argv = argv.into_iter();
+    let argv = argv.next().into_iter().chain(options).chain(argv);
+    Ok(Args::try_parse_from(argv)?)
+}
+
+// the settings of a TOML file, with `max-db-size` taken as `max_db_size`
+fn read(path: &Path) -> Result<BTreeMap<String, toml::Value>, SharedError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let table = content
+        .parse::<toml::Table>()
+        .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+    Ok(table
+        .into_iter()
+        .map(|(name, value)| (name.replace('-', "_"), value))
+        .collect())
+}
+
+// the command line values of a setting of the file
+fn values(name: &str, value: toml::Value, multiple: bool, path: &Path) -> Result<Vec<String>, SharedError> {
+    let values = match value {
+        toml::Value::Array(values) => values,
+        value => vec![value],
+    };
+    let values = values
+        .into_iter()
+        .map(|value| match value {
+            toml::Value::String(value) => Ok(value),
+            toml::Value::Integer(value) => Ok(value.to_string()),
+            toml::Value::Float(value) => Ok(value.to_string()),
+            toml::Value::Boolean(value) => Ok(value.to_string()),
+            _ => Err(format!(
+                "`{}` in {} should be a string, a number or a list of them",
+                name,
+                path.display()
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(match multiple {
+        true => values,
+        // e.g. `smtp_port = [25, 587]` for `--smtp-port 25,587`
+        false => vec![values.join(",")],
+    })
+}
+
+/// Loads the configuration again on SIGHUP and applies what doesn't need a restart: the
+/// retention, rules, relay upstream, jobs, webhooks, chaos rules, recipient policy, SMTP AUTH users,
+/// duplicates policy, memory budget, maximum message size and shutdown timeout.
+pub async fn reload_on_hangup(argv: Vec<OsString>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!(error = %e, "Failed to listen for SIGHUP, the configuration can't be reloaded");
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        match load(argv.clone()).and_then(|args| apply(&args)) {
+            Ok(()) => info!("Reloaded the configuration"),
+            Err(e) => error!(error = %e, "Failed to reload the configuration, keeping the current one"),
+        }
+    }
+}
+
+fn apply(args: &Args) -> Result<(), SharedError> {
+    let webhooks = webhooks::from_args(&args.webhook)?;
+    config::reload(config::Config::from_args(args)?);
+    jobs::init(args.job.clone());
+    rules::init(args.rule.clone());
+    relay::init(args.relay.clone());
+    smtp::auth::init(args.smtp_user.clone());
+    smtp::chaos::init(args.chaos.clone());
+    smtp::recipients::init(smtp::recipients::Policy {
+        domains: args.accept_domain.clone(),
+        accept: args.accept_recipient.clone(),
+        reject: args.reject_recipient.clone(),
+    });
+    webhooks::set(webhooks);
+    dns::init(args.dns_server);
+    memory::set_budget(config::get().memory_budget);
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::smtp::mail::{Header, Mail};
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::time::Duration;
+
+// past this, the diff is approximated instead of blocking the request
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Equal,
+    Changed,
+    // only in the second mail
+    Added,
+    // only in the first mail
+    Removed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HeaderDiff {
+    pub name: String,
+    pub a: Option<String>,
+    pub b: Option<String>,
+    pub change: Change,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LineDiff {
+    pub change: Change,
+    pub value: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MailDiff {
+    pub a: u128,
+    pub b: u128,
+    pub headers: Vec<HeaderDiff>,
+    pub body: Vec<LineDiff>,
+}
+
+pub fn diff_mails(a: &Mail, b: &Mail) -> MailDiff {
+    MailDiff {
+        a: a.id,
+        b: b.id,
+        headers: diff_headers(&a.headers(), &b.headers()),
+        body: diff_lines(&a.parse_body(), &b.parse_body()),
+    }
+}
+
+/// Compares headers by name (case insensitive), in the order of the first mail then the names
+/// only found in the second one. Repeated headers are compared as a whole, one value per line.
+pub fn diff_headers(a: &[Header], b: &[Header]) -> Vec<HeaderDiff> {
+    let a = group_headers(a);
+    let b = group_headers(b);
+
+    let mut diffs = Vec::new();
+    for (key, name, a_value) in &a {
+        let b_value = b
+            .iter()
+            .find(|(other, _, _)| other == key)
+            .map(|(_, _, value)| value.clone());
+        let change = match &b_value {
+            Some(b_value) if b_value == a_value => Change::Equal,
+            Some(_) => Change::Changed,
+            None => Change::Removed,
+        };
+        diffs.push(HeaderDiff {
+            name: name.clone(),
+            a: Some(a_value.clone()),
+            b: b_value,
+            change,
+        });
+    }
+
+    for (key, name, b_value) in b {
+        if !a.iter().any(|(other, _, _)| *other == key) {
+            diffs.push(HeaderDiff {
+                name,
+                a: None,
+                b: Some(b_value),
+                change: Change::Added,
+            });
+        }
+    }
+
+    diffs
+}
+
+// (lowercased name, name as first seen, values joined by new lines)
+fn group_headers(headers: &[Header]) -> Vec<(String, String, String)> {
+    let mut groups: Vec<(String, String, String)> = Vec::new();
+    for header in headers {
+        let key = header.name.to_lowercase();
+        match groups.iter_mut().find(|(other, _, _)| *other == key) {
+            Some((_, _, value)) => {
+                value.push('\n');
+                value.push_str(&header.value);
+            }
+            None => groups.push((key, header.name.clone(), header.value.clone())),
+        }
+    }
+    groups
+}
+
+pub fn diff_lines(a: &str, b: &str) -> Vec<LineDiff> {
+    let diff = TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(a, b);
+
+    diff.iter_all_changes()
+        .map(|change| LineDiff {
+            change: match change.tag() {
+                ChangeTag::Equal => Change::Equal,
+                ChangeTag::Delete => Change::Removed,
+                ChangeTag::Insert => Change::Added,
+            },
+            value: change
+                .value()
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,312 @@
+use lazy_static::lazy_static;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const TIMEOUT: Duration = Duration::from_secs(2);
+// a lost UDP datagram is sent again once
+const ATTEMPTS: usize = 2;
+// what a resolver answers over UDP without EDNS
+const MAX_UDP_SIZE: usize = 512;
+
+const TYPE_A: u16 = 1;
+const TYPE_MX: u16 = 15;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const NXDOMAIN: u8 = 3;
+
+lazy_static! {
+    static ref SERVER: RwLock<Option<SocketAddr>> = RwLock::new(None);
+}
+
+/// A lookup, Ok and empty when the name or the record doesn't exist, Err when the answer
+/// couldn't be had.
+pub type Lookup<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+/// The records the DKIM and SPF checks look up.
+pub trait Resolver: Send + Sync {
+    fn txt<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<String>>;
+
+    /// The A and AAAA records.
+    fn ips<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<IpAddr>>;
+
+    /// The exchanges of the MX records, most preferred first.
+    fn mx<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<String>>;
+}
+
+/// `1.1.1.1`, or with a port `127.0.0.1:5353`.
+pub fn parse_server(value: &str) -> Result<SocketAddr, String> {
+    value
+        .parse::<SocketAddr>()
+        .or_else(|_| value.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| format!("Invalid DNS server `{}`, expected an IP address and an optional port", value))
+}
+
+/// Sets the server queried, `--dns-server`, instead of the first one of `/etc/resolv.conf`.
+pub fn init(server: Option<SocketAddr>) {
+    *SERVER.write().unwrap() = server;
+}
+
+/// Asks the configured server, which is expected to resolve recursively.
+pub fn resolver() -> Dns {
+    let server = SERVER.read().unwrap().unwrap_or_else(|| {
+        std::fs::read_to_string(RESOLV_CONF)
+            .ok()
+            .and_then(|conf| first_nameserver(&conf))
+            .unwrap_or(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53))
+    });
+    Dns { server }
+}
+
+/// The first `nameserver` line of a `resolv.conf`.
+pub fn first_nameserver(conf: &str) -> Option<SocketAddr> {
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|address| address.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+}
+
+/// A stub resolver over UDP, and TCP for the answers that don't fit.
+pub struct Dns {
+    server: SocketAddr,
+}
+
+impl Resolver for Dns {
+    fn txt<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<String>> {
+        Box::pin(async move {
+            Ok(self
+                .query(name, TYPE_TXT)
+                .await?
+                .into_iter()
+                .filter_map(|record| match record {
+                    Record::Txt(text) => Some(text),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+
+    fn ips<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<IpAddr>> {
+        Box::pin(async move {
+            let mut ips = Vec::new();
+            for kind in [TYPE_A, TYPE_AAAA] {
+                ips.extend(self.query(name, kind).await?.into_iter().filter_map(|record| match record {
+                    Record::Ip(ip) => Some(ip),
+                    _ => None,
+                }));
+            }
+            Ok(ips)
+        })
+    }
+
+    fn mx<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut exchanges: Vec<(u16, String)> = self
+                .query(name, TYPE_MX)
+                .await?
+                .into_iter()
+                .filter_map(|record| match record {
+                    Record::Mx(preference, exchange) => Some((preference, exchange)),
+                    _ => None,
+                })
+                .collect();
+            exchanges.sort();
+            Ok(exchanges.into_iter().map(|(_, exchange)| exchange).collect())
+        })
+    }
+}
+
+impl Dns {
+    async fn query(&self, name: &str, kind: u16) -> Result<Vec<Record>, String> {
+        let mut id = [0; 2];
+        getrandom::fill(&mut id).expect("no system randomness available");
+        let id = u16::from_be_bytes(id);
+        let request = encode_query(id, name, kind)?;
+        let failed = |e: std::io::Error| format!("DNS server {} failed: {}", self.server, e);
+
+        let socket = UdpSocket::bind(match self.server {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        })
+        .await
+        .map_err(failed)?;
+        socket.connect(self.server).await.map_err(failed)?;
+
+        let mut buffer = vec![0; MAX_UDP_SIZE];
+        for _ in 0..ATTEMPTS {
+            socket.send(&request).await.map_err(failed)?;
+            // an answer to another query (or a spoofed one) is ignored, the right one may follow
+            let answer = tokio::time::timeout(TIMEOUT, async {
+                loop {
+                    let size = socket.recv(&mut buffer).await?;
+                    if buffer[..size].starts_with(&id.to_be_bytes()) {
+                        return Ok::<_, std::io::Error>(size);
+                    }
+                }
+            })
+            .await;
+            match answer {
+                Ok(Ok(size)) => match decode_answer(&buffer[..size], kind)? {
+                    Answer::Records(records) => return Ok(records),
+                    Answer::Truncated => return self.query_tcp(&request, kind).await,
+                },
+                Ok(Err(e)) => return Err(failed(e)),
+                Err(_) => continue,
+            }
+        }
+        Err(format!("DNS server {} timed out", self.server))
+    }
+
+    async fn query_tcp(&self, request: &[u8], kind: u16) -> Result<Vec<Record>, String> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(self.server).await?;
+            stream.write_all(&(request.len() as u16).to_be_bytes()).await?;
+            stream.write_all(request).await?;
+            let size = stream.read_u16().await?;
+            let mut answer = vec![0; size as usize];
+            stream.read_exact(&mut answer).await?;
+            Ok::<_, std::io::Error>(answer)
+        };
+        let answer = tokio::time::timeout(TIMEOUT, exchange)
+            .await
+            .map_err(|_| format!("DNS server {} timed out", self.server))?
+            .map_err(|e| format!("DNS server {} failed: {}", self.server, e))?;
+        match decode_answer(&answer, kind)? {
+            Answer::Records(records) => Ok(records),
+            Answer::Truncated => Err("Truncated DNS answer over TCP".to_string()),
+        }
+    }
+}
+
+/// A record of the answer section, of the type asked for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Record {
+    Ip(IpAddr),
+    // the strings of the record, concatenated
+    Txt(String),
+    Mx(u16, String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Answer {
+    Records(Vec<Record>),
+    // to be asked again over TCP
+    Truncated,
+}
+
+/// A query for the records of type `kind` of `name`, recursion desired.
+pub fn encode_query(id: u16, name: &str, kind: u16) -> Result<Vec<u8>, String> {
+    let mut message = Vec::with_capacity(18 + name.len());
+    message.extend_from_slice(&id.to_be_bytes());
+    // RD, one question
+    message.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid domain name `{}`", name));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&kind.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// The records of type `kind` in an answer, none if the name doesn't exist.
+pub fn decode_answer(message: &[u8], kind: u16) -> Result<Answer, String> {
+    let malformed = || "Malformed DNS answer".to_string();
+    let header = message.get(..12).ok_or_else(malformed)?;
+    if header[2] & 0x02 != 0 {
+        return Ok(Answer::Truncated);
+    }
+    match header[3] & 0x0f {
+        0 => {}
+        NXDOMAIN => return Ok(Answer::Records(Vec::new())),
+        code => return Err(format!("DNS server failure (rcode {})", code)),
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+
+    let mut position = 12;
+    for _ in 0..questions {
+        position = read_name(message, position)?.1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        position = read_name(message, position)?.1;
+        let fixed = message.get(position..position + 10).ok_or_else(malformed)?;
+        let record_kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let length = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let start = position + 10;
+        let data = message.get(start..start + length).ok_or_else(malformed)?;
+        position = start + length;
+        // the CNAMEs followed by the server come along, only their targets' records matter
+        if record_kind != kind {
+            continue;
+        }
+        records.push(match kind {
+            TYPE_A => Record::Ip(IpAddr::V4(Ipv4Addr::from(
+                <[u8; 4]>::try_from(data).map_err(|_| malformed())?,
+            ))),
+            TYPE_AAAA => Record::Ip(IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(data).map_err(|_| malformed())?,
+            ))),
+            TYPE_MX => {
+                let preference = data.get(..2).ok_or_else(malformed)?;
+                Record::Mx(
+                    u16::from_be_bytes([preference[0], preference[1]]),
+                    read_name(message, start + 2)?.0,
+                )
+            }
+            TYPE_TXT => {
+                let mut text = Vec::new();
+                let mut strings = data;
+                while let Some((&length, rest)) = strings.split_first() {
+                    text.extend_from_slice(rest.get(..length as usize).ok_or_else(malformed)?);
+                    strings = &rest[length as usize..];
+                }
+                Record::Txt(String::from_utf8_lossy(&text).into_owned())
+            }
+            _ => continue,
+        });
+    }
+    Ok(Answer::Records(records))
+}
+
+// the name at `position`, following the compression pointers, and where the record goes on
+fn read_name(message: &[u8], mut position: usize) -> Result<(String, usize), String> {
+    let malformed = || "Malformed DNS name".to_string();
+    let mut labels = Vec::new();
+    let mut end = None;
+    // each pointer has to go back, so that a loop can't go on forever
+    let mut limit = position;
+    loop {
+        let length = *message.get(position).ok_or_else(malformed)? as usize;
+        match length {
+            0 => break,
+            _ if length & 0xc0 == 0xc0 => {
+                let low = *message.get(position + 1).ok_or_else(malformed)? as usize;
+                let target = ((length & 0x3f) << 8) | low;
+                if target >= limit {
+                    return Err(malformed());
+                }
+                end.get_or_insert(position + 2);
+                limit = target;
+                position = target;
+            }
+            _ => {
+                let label = message.get(position + 1..position + 1 + length).ok_or_else(malformed)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                position += 1 + length;
+            }
+        }
+    }
+    Ok((labels.join("."), end.unwrap_or(position + 1)))
+}
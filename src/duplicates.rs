@@ -0,0 +1,52 @@
+use crate::store::{self, Store, Tree};
+use clap::ValueEnum;
+use mailparse::MailHeaderMap;
+use serde::Serialize;
+
+// duplicate key -> id of the first mail received with it
+const TREE: &str = "duplicates";
+
+/// What happens to a mail that was already received.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// Store it, marked as a duplicate of the first one
+    #[default]
+    Flag,
+    /// Acknowledge it without storing it
+    Drop,
+    /// Refuse it with 550
+    Reject,
+}
+
+/// Identifies a message by its Message-ID, or by a hash of its content when it has none.
+pub fn key(data: &[u8]) -> Vec<u8> {
+    let message_id = mailparse::parse_headers(data)
+        .ok()
+        .and_then(|(headers, _)| headers.get_first_value("Message-ID"))
+        .map(|message_id| message_id.trim().to_string())
+        .filter(|message_id| !message_id.is_empty());
+
+    match message_id {
+        Some(message_id) => format!("id:{}", message_id).into_bytes(),
+        None => format!("hash:{:016x}", fnv1a(data)).into_bytes(),
+    }
+}
+
+// unlike the std hasher, guaranteed to give the same hash across builds
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub fn tree(db: &Store) -> store::Result<Tree> {
+    db.open_tree(TREE)
+}
+
+pub fn clear(db: &Store) -> store::Result<usize> {
+    let tree = tree(db)?;
+    let count = tree.len();
+    tree.clear()?;
+    Ok(count)
+}
@@ -0,0 +1,64 @@
+use crate::filter::MailFilter;
+use crate::smtp::mail::Mail;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+// slow subscribers skip events instead of holding back the SMTP side
+const CHANNEL_CAPACITY: usize = 256;
+
+lazy_static! {
+    static ref EVENTS: broadcast::Sender<Event> = broadcast::channel(CHANNEL_CAPACITY).0;
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Mail {
+        id: u128,
+        from: Vec<String>,
+        to: Vec<String>,
+        subject: Option<String>,
+        timestamp: u128,
+    },
+    // writes to the database fail, SMTP answers 452 until it recovers
+    StorageFailing {
+        error: String,
+    },
+    StorageRecovered,
+}
+
+impl Event {
+    pub fn mail_received(mail: &Mail) -> Self {
+        Event::Mail {
+            id: mail.id,
+            from: mail.from.iter().cloned().collect(),
+            to: mail.to.iter().cloned().collect(),
+            subject: mail.subject.clone(),
+            timestamp: mail.timestamp(),
+        }
+    }
+
+    /// Whether a subscriber filtering on the `to` and `from` of `filter` wants the event. Only
+    /// mails are filtered, the storage going down concerns everyone.
+    pub fn matches(&self, filter: &MailFilter) -> bool {
+        let Event::Mail { from, to, .. } = self else {
+            return true;
+        };
+        let contains = |addresses: &[String], part: &Option<String>| {
+            part.as_ref().is_none_or(|part| {
+                addresses.iter().any(|address| address.to_lowercase().contains(part))
+            })
+        };
+        contains(to, &filter.to) && contains(from, &filter.from)
+    }
+}
+
+pub fn publish(event: Event) {
+    // an error only means nobody is listening
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    EVENTS.subscribe()
+}
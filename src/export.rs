@@ -0,0 +1,133 @@
+use crate::jobs::{civil_date, MONTHS, WEEKDAYS};
+use crate::smtp::mail::Mail;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::ZipWriter;
+
+/// What `GET /export` downloads, from its `?format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Mbox,
+    EmlZip,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "mbox" => Ok(Format::Mbox),
+            "eml-zip" => Ok(Format::EmlZip),
+            _ => Err(format!("Invalid format `{}`, expected mbox or eml-zip", format)),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Mbox => "application/mbox",
+            Format::EmlZip => "application/zip",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Mbox => "mbox",
+            Format::EmlZip => "zip",
+        }
+    }
+}
+
+/// A mail as an entry of an mbox file, in the mboxrd flavour: a `From ` line with its sender and
+/// reception date, its lines starting with `>*From ` quoted with one more `>`, LF line endings
+/// and a blank line after it.
+pub fn mbox_entry(mail: &Mail) -> Vec<u8> {
+    // the smallest one, so that the same mail always gives the same file
+    let sender = mail
+        .from
+        .iter()
+        .filter(|address| !address.is_empty())
+        .min()
+        .map(|address| address.replace(char::is_whitespace, ""))
+        .unwrap_or_else(|| "MAILER-DAEMON".to_string());
+
+    let mut entry = Vec::with_capacity(mail.data.len() + 128);
+    entry.extend_from_slice(format!("From {} {}\n", sender, asctime(mail.timestamp())).as_bytes());
+    for line in mail.data.split_inclusive(|byte| *byte == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let quotes = line.iter().take_while(|byte| **byte == b'>').count();
+        if line[quotes..].starts_with(b"From ") {
+            entry.push(b'>');
+        }
+        entry.extend_from_slice(line);
+        entry.push(b'\n');
+    }
+    entry.push(b'\n');
+    entry
+}
+
+// e.g. `Mon Jan  1 00:00:00 2024`, in UTC, as the `From ` line wants it
+fn asctime(millis: u128) -> String {
+    let seconds = (millis / 1000) as u64;
+    let days = seconds / 86400;
+    let (year, month, day) = civil_date(days);
+    format!(
+        "{} {} {:>2} {:02}:{:02}:{:02} {}",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        year
+    )
+}
+
+/// A zip of `<mail_id>.eml` files, written without seeking so that it can be sent as it's built.
+pub struct EmlZip {
+    zip: ZipWriter<StreamWriter<Buffer>>,
+    buffer: Buffer,
+}
+
+// what the zip wrote since it was last drained
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl EmlZip {
+    pub fn new() -> Self {
+        let buffer = Buffer::default();
+        EmlZip {
+            zip: ZipWriter::new_stream(buffer.clone()),
+            buffer,
+        }
+    }
+
+    /// Adds the mail and returns the bytes of the archive written since the last call.
+    pub fn add(&mut self, mail: &Mail) -> zip::result::ZipResult<Vec<u8>> {
+        self.zip.start_file(format!("{}.eml", mail.id), SimpleFileOptions::default())?;
+        self.zip.write_all(&mail.data)?;
+        Ok(self.buffer.take())
+    }
+
+    /// The end of the archive: the last entry and the central directory.
+    pub fn finish(self) -> zip::result::ZipResult<Vec<u8>> {
+        self.zip.finish()?;
+        Ok(self.buffer.take())
+    }
+}
@@ -0,0 +1,230 @@
+use crate::smtp::mail::Mail;
+use crate::smtp::verification::Outcome;
+use crate::summary::MailSummary;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// Criteria shared by the listing (and bulk) endpoints, built from the query string.
+/// Every criterion is optional and they all have to match.
+#[derive(Default, Debug, Clone)]
+pub struct MailFilter {
+    /// free text, looked up in the addresses, the subject and the raw data
+    pub search: Option<String>,
+    /// part of a recipient address
+    pub to: Option<String>,
+    /// part of a sender address
+    pub from: Option<String>,
+    /// part of the subject
+    pub subject: Option<String>,
+    /// received at or after, in milliseconds since the unix epoch
+    pub since: Option<u128>,
+    /// received before, in milliseconds since the unix epoch
+    pub until: Option<u128>,
+    pub has_attachment: Option<bool>,
+    /// given by the rules or the API, only known from the summary, see [`MailFilter::matches_labels`]
+    pub tag: Option<String>,
+    pub namespace: Option<String>,
+    /// the SMTP AUTH username
+    pub user: Option<String>,
+    /// fetched already, see `GET /mails/<mail_id>`
+    pub read: Option<bool>,
+    /// the result of one of the DKIM signatures, `none` for an unsigned mail, see `--verify-auth`
+    pub dkim: Option<Outcome>,
+    pub spf: Option<Outcome>,
+}
+
+impl MailFilter {
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(MailFilter {
+            search: text_param(query, "search").or_else(|| text_param(query, "q")),
+            to: text_param(query, "to"),
+            from: text_param(query, "from"),
+            subject: text_param(query, "subject_contains"),
+            since: timestamp_param(query, "since")?,
+            // `before` reads better when purging
+            until: timestamp_param(query, "until")?.or(timestamp_param(query, "before")?),
+            has_attachment: bool_param(query, "has_attachment")?,
+            tag: text_param(query, "tag"),
+            namespace: text_param(query, "namespace"),
+            user: text_param(query, "user"),
+            read: bool_param(query, "unread")?.map(|unread| !unread).or(bool_param(query, "read")?),
+            dkim: outcome_param(query, "dkim")?,
+            spf: outcome_param(query, "spf")?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.search.is_none()
+            && self.to.is_none()
+            && self.from.is_none()
+            && self.subject.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.has_attachment.is_none()
+            && self.tag.is_none()
+            && self.namespace.is_none()
+            && self.user.is_none()
+            && self.read.is_none()
+            && self.dkim.is_none()
+            && self.spf.is_none()
+    }
+
+    pub fn matches(&self, mail: &Mail) -> bool {
+        let timestamp = mail.timestamp();
+        if self.since.is_some_and(|since| timestamp < since)
+            || self.until.is_some_and(|until| timestamp >= until)
+        {
+            return false;
+        }
+
+        if let Some(to) = &self.to {
+            if !mail.to.iter().any(|address| address.to_lowercase().contains(to)) {
+                return false;
+            }
+        }
+
+        if let Some(from) = &self.from {
+            if !mail.from.iter().any(|address| address.to_lowercase().contains(from)) {
+                return false;
+            }
+        }
+
+        if let Some(subject) = &self.subject {
+            if !mail.subject.as_deref().unwrap_or("").to_lowercase().contains(subject) {
+                return false;
+            }
+        }
+
+        if let Some(search) = &self.search {
+            let found = mail.to.iter().any(|to| to.to_lowercase().contains(search))
+                || mail.from.iter().any(|from| from.to_lowercase().contains(search))
+                || mail.subject.as_deref().unwrap_or("").to_lowercase().contains(search)
+                || mail.data_str().to_lowercase().contains(search);
+            if !found {
+                return false;
+            }
+        }
+
+        // parsing the MIME tree is the most expensive check, keep it last
+        if let Some(has_attachment) = self.has_attachment {
+            if mail.attachments().is_empty() == has_attachment {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Like [`MailFilter::matches`] but from the summary, except for `search` which may need the
+    /// raw data, see [`MailFilter::matches_search`].
+    pub fn matches_summary(&self, summary: &MailSummary) -> bool {
+        if self.since.is_some_and(|since| summary.timestamp < since)
+            || self.until.is_some_and(|until| summary.timestamp >= until)
+        {
+            return false;
+        }
+
+        if let Some(to) = &self.to {
+            if !summary.to.iter().any(|address| address.to_lowercase().contains(to)) {
+                return false;
+            }
+        }
+
+        if let Some(from) = &self.from {
+            if !summary.from.iter().any(|address| address.to_lowercase().contains(from)) {
+                return false;
+            }
+        }
+
+        if let Some(subject) = &self.subject {
+            if !summary.subject.as_deref().unwrap_or("").to_lowercase().contains(subject) {
+                return false;
+            }
+        }
+
+        self.has_attachment
+            .is_none_or(|has_attachment| summary.has_attachment == has_attachment)
+            && self.matches_labels(summary)
+    }
+
+    /// The part of the criteria that [`MailFilter::matches`] can't check from the mail alone.
+    pub fn matches_labels(&self, summary: &MailSummary) -> bool {
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| summary.tags.iter().any(|candidate| candidate.to_lowercase() == *tag))
+            && self.namespace.as_ref().is_none_or(|namespace| {
+                summary
+                    .namespace
+                    .as_deref()
+                    .is_some_and(|candidate| candidate.to_lowercase() == *namespace)
+            })
+            && self.user.as_ref().is_none_or(|user| {
+                summary.user.as_deref().is_some_and(|candidate| candidate.to_lowercase() == *user)
+            })
+            && self.read.is_none_or(|read| summary.read == read)
+            // mails that weren't verified match neither
+            && self.dkim.is_none_or(|dkim| {
+                summary
+                    .authentication
+                    .as_ref()
+                    .is_some_and(|authentication| authentication.dkim_results().contains(&dkim))
+            })
+            && self.spf.is_none_or(|spf| {
+                summary
+                    .authentication
+                    .as_ref()
+                    .is_some_and(|authentication| authentication.spf.result == spf)
+            })
+    }
+
+    /// `data` is only called when the addresses and the subject don't match already.
+    pub fn matches_search(&self, summary: &MailSummary, data: impl FnOnce() -> Option<Bytes>) -> bool {
+        let Some(search) = &self.search else {
+            return true;
+        };
+
+        summary.to.iter().any(|to| to.to_lowercase().contains(search))
+            || summary.from.iter().any(|from| from.to_lowercase().contains(search))
+            || summary.subject.as_deref().unwrap_or("").to_lowercase().contains(search)
+            || data().is_some_and(|data| String::from_utf8_lossy(&data).to_lowercase().contains(search))
+    }
+}
+
+// lowercased, and empty values are ignored so that `?to=` means "no filter"
+fn text_param(query: &HashMap<String, String>, name: &str) -> Option<String> {
+    query
+        .get(name)
+        .map(|value| value.trim().to_lowercase())
+        .filter(|value| !value.is_empty())
+}
+
+fn timestamp_param(query: &HashMap<String, String>, name: &str) -> Result<Option<u128>, String> {
+    match query.get(name).map(|value| value.trim()) {
+        None | Some("") => Ok(None),
+        Some(value) => value
+            .parse::<u128>()
+            .map(Some)
+            .map_err(|_| format!("Invalid {}: expected a timestamp in milliseconds", name)),
+    }
+}
+
+fn bool_param(query: &HashMap<String, String>, name: &str) -> Result<Option<bool>, String> {
+    match query.get(name).map(|value| value.trim()) {
+        None | Some("") => Ok(None),
+        Some("1") | Some("true") => Ok(Some(true)),
+        Some("0") | Some("false") => Ok(Some(false)),
+        Some(_) => Err(format!("Invalid {}: expected true or false", name)),
+    }
+}
+
+fn outcome_param(query: &HashMap<String, String>, name: &str) -> Result<Option<Outcome>, String> {
+    match query.get(name).map(|value| value.trim()) {
+        None | Some("") => Ok(None),
+        Some(value) => Outcome::parse(value).map(Some).ok_or_else(|| {
+            format!(
+                "Invalid {}: expected pass, fail, softfail, neutral, none, temperror or permerror",
+                name
+            )
+        }),
+    }
+}
@@ -1,27 +1,54 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytes::Bytes;
 use psutil::process::Process;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use sled::Db;
 use std::collections::HashMap;
 use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
 use std::future::Future;
+use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use sysinfo::{Disks, System};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
-use tokio::net::TcpStream;
-
-use tokio::sync::{Mutex as AsyncMutex, Mutex};
-
-use crate::smtp::mail::Mail;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    BufWriter, ReadHalf, WriteHalf,
+};
+use tokio::time::timeout;
+
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::filter::MailFilter;
+use crate::limits::{self, Rate};
+use crate::ingest::Queue;
+use crate::summary::{MailSummary, Order};
+use crate::memory::Reservation;
+use crate::session::{RevokeError, Role};
+use crate::{
+    config, diff, duplicates, events, export, jobs, memory, metrics, relay, retention, session, shutdown,
+    smtp, snapshot, snowflake, stats, summary, upload, webhooks,
+};
+use crate::smtp::mail::{Attachment, Header, Mail};
+use crate::smtp::verification::Authentication;
+use crate::smtp::Submitted;
+use crate::store::{self, Store};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::HashSet;
+use std::io::Write;
+use tokio::sync::broadcast::error::RecvError;
 use url::form_urlencoded;
 use url::Url;
+use tracing::{debug, warn};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Hash)]
 enum Method {
     GET,
     POST,
     PUT,
+    PATCH,
     DELETE,
 }
 
@@ -31,18 +58,48 @@ impl Method {
             "GET" => Some(Method::GET),
             "POST" => Some(Method::POST),
             "PUT" => Some(Method::PUT),
+            "PATCH" => Some(Method::PATCH),
             "DELETE" => Some(Method::DELETE),
             _ => None,
         }
     }
 }
 
+/// A connection to the HTTP port, plain or over TLS.
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> Stream for S {}
+
+type Reader = BufReader<ReadHalf<Box<dyn Stream>>>;
+
+// the response side of a connection, written to like the `BufWriter` it wraps
+struct Writer {
+    inner: BufWriter<WriteHalf<Box<dyn Stream>>>,
+    // whether the connection is reused for another request once the response is sent, told to
+    // the client in the `Connection` header
+    keep_alive: bool,
+}
+
+impl Deref for Writer {
+    type Target = BufWriter<WriteHalf<Box<dyn Stream>>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for Writer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
 // Define a type alias for the handler function
 type Handler = Box<
     dyn Fn(
             Request,
-            Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-            Arc<Mutex<Db>>,
+            Arc<AsyncMutex<Writer>>,
+            Store,
         )
             -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send>>
         + Send
@@ -56,37 +113,167 @@ struct Request {
     path: String,
     query: HashMap<String, String>,
     params: HashMap<String, String>,
+    // header names are lowercased
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+    // accounts for the body, until the request is handled or the mail it carries stored
+    reservation: Reservation,
+}
+
+// sent before closing a connection over the limits, unless it's HTTPS
+pub(crate) const TOO_MANY_CONNECTIONS: &[u8] =
+    b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+// forms and API payloads, mails posted to `/mails` go up to `--max-message-size` instead
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+// mails posted to `/mails` with `--max-message-size 0`, a body is held in memory whole
+const MAX_MAIL_BODY_SIZE: usize = 256 * 1024 * 1024;
+// request line and headers
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+// clients sending too slowly (or not at all) would otherwise hold their task forever
+pub(crate) const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+// comments on the event stream keep proxies from closing it and tell us when the client is gone
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+enum Head {
+    Closed,
+    TooLarge,
+    // the request line, and the headers with lowercased names
+    Complete(String, HashMap<String, String>),
 }
 
+async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Head> {
+    let mut reader = reader.take(MAX_HEAD_SIZE as u64);
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).await? == 0 {
+        return Ok(Head::Closed);
+    }
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    // reads stop at the limit, whatever was left is cut off
+    if reader.limit() == 0 {
+        return Ok(Head::TooLarge);
+    }
+    Ok(Head::Complete(request_line, headers))
+}
+
+/// Serves the requests of a connection one after the other, for as long as the client keeps it
+/// alive (the default with HTTP/1.1).
 pub(crate) async fn handle_client(
-    stream: TcpStream,
-    db: Arc<Mutex<Db>>,
-    key: &str,
+    stream: impl Stream + 'static,
+    peer: IpAddr,
+    db: Store,
+    router: &Router,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let (reader, writer) = stream.into_split();
+    let (reader, writer) = tokio::io::split(Box::new(stream) as Box<dyn Stream>);
     let mut reader = BufReader::new(reader);
-    let writer = Arc::new(AsyncMutex::new(BufWriter::new(writer)));
+    let writer = Arc::new(AsyncMutex::new(Writer {
+        inner: BufWriter::new(writer),
+        keep_alive: false,
+    }));
+
+    let mut first = true;
+    loop {
+        // Read the request line and the headers
+        let head = tokio::select! {
+            biased;
+            head = timeout(HEAD_TIMEOUT, read_head(&mut reader)) => head,
+            // waiting for another request, nothing is lost by closing
+            _ = shutdown::stopped(), if !first => return Ok(()),
+        };
+        let (request_line, headers) = match head {
+            Ok(Ok(Head::Complete(request_line, headers))) => (request_line, headers),
+            Ok(Ok(Head::Closed)) => return Ok(()),
+            Ok(Ok(Head::TooLarge)) => {
+                metrics::HTTP_HEAD_TOO_LARGE.inc();
+                let mut writer = writer.lock().await;
+                writer.keep_alive = false;
+                write_status(&mut writer, "431 Request Header Fields Too Large").await?;
+                return Ok(());
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            // an idle connection kept alive, not a slow client
+            Err(_) if !first => return Ok(()),
+            Err(_) => {
+                metrics::HTTP_TIMEOUTS.inc();
+                let mut writer = writer.lock().await;
+                writer.keep_alive = false;
+                write_status(&mut writer, "408 Request Timeout").await?;
+                return Ok(());
+            }
+        };
+        first = false;
 
-    // Read the request line
-    let mut request_line = String::new();
-    let bytes_read = reader.read_line(&mut request_line).await?;
-    if bytes_read == 0 {
-        return Ok(());
+        if !limits::allow(Rate::HttpRequests, peer) {
+            metrics::HTTP_THROTTLED.inc();
+            let mut writer = writer.lock().await;
+            // the body is left unread, the connection can't be reused
+            writer.keep_alive = false;
+            let headers = [("Retry-After", "1".to_string())];
+            let message = b"Too many requests, slow down";
+            write_response(&mut writer, "429 Too Many Requests", "text/plain", &headers, message).await?;
+            return Ok(());
+        }
+        handle_request(&request_line, headers, &mut reader, writer.clone(), db.clone(), router).await?;
+        if !writer.lock().await.keep_alive || shutdown::stopping() {
+            return Ok(());
+        }
+    }
+}
+
+// HTTP/1.1 connections stay open unless the client asks otherwise, HTTP/1.0 ones only if it asks
+fn keep_alive(version: &str, headers: &HashMap<String, String>) -> bool {
+    let connection = headers
+        .get("connection")
+        .map(|connection| connection.to_lowercase())
+        .unwrap_or_default();
+    let asks = |option: &str| connection.split(',').any(|token| token.trim() == option);
+    match version {
+        "HTTP/1.1" => !asks("close"),
+        _ => asks("keep-alive"),
     }
+}
 
+async fn handle_request(
+    request_line: &str,
+    headers: HashMap<String, String>,
+    reader: &mut Reader,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+    router: &Router,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse the request line
     let request_line = request_line.trim_end();
     let mut parts = request_line.split_whitespace();
     let method_str = parts.next();
     let path_and_query = parts.next();
-    let _version = parts.next();
+    let version = parts.next().unwrap_or_default();
+    writer.lock().await.keep_alive = keep_alive(version, &headers);
 
     if let (Some(method_str), Some(path_and_query)) = (method_str, path_and_query) {
         // parse the method
         let method = Method::from_str(method_str);
         if method.is_none() {
             // Method isn't Allowed
-            writer.lock().await.get_mut().shutdown().await?;
+            let mut writer = writer.lock().await;
+            writer.keep_alive = false;
+            writer.get_mut().shutdown().await?;
             return Ok(());
         }
         let method = method.unwrap();
@@ -99,53 +286,178 @@ pub(crate) async fn handle_client(
             .decode_utf8()
             .unwrap()
             .to_string();
+        // never the query, which carries the key
+        debug!(method = method_str, path = %path, "Request");
 
-        let query_pairs = form_urlencoded::parse(url.query().unwrap_or("").as_bytes())
+        let mut query_pairs = form_urlencoded::parse(url.query().unwrap_or("").as_bytes())
             .into_owned()
             .collect::<HashMap<String, String>>();
 
-        // check if the key is provided and valid before proceeding
-        if let Some(k) = query_pairs.get("k") {
-            if k != key {
-                // 403 Forbidden, just close the connection without any response to avoid leaking information
-                writer.lock().await.get_mut().shutdown().await?;
+        // the end of a chunked body can't be found without decoding it, so the next request neither
+        if headers.contains_key("transfer-encoding") {
+            let mut writer = writer.lock().await;
+            writer.keep_alive = false;
+            write_status(&mut writer, "411 Length Required").await?;
+            return Ok(());
+        }
+        let content_length = headers
+            .get("content-length")
+            .and_then(|length| length.parse::<usize>().ok())
+            .unwrap_or(0);
+        let max_body_size = match (&method, path.trim_end_matches('/')) {
+            (Method::POST | Method::PUT, "/mails") => config::max_message_size().unwrap_or(MAX_MAIL_BODY_SIZE),
+            _ => MAX_BODY_SIZE,
+        };
+        if content_length > max_body_size {
+            // the body is left unread, the connection can't be reused
+            let mut writer = writer.lock().await;
+            writer.keep_alive = false;
+            write_status(&mut writer, "413 Payload Too Large").await?;
+            return Ok(());
+        }
+
+        let session_token = headers
+            .get("cookie")
+            .and_then(|cookies| session::token_from_cookies(cookies))
+            .map(str::to_string);
+
+        let mut request = Request {
+            method,
+            path,
+            query: HashMap::new(),
+            params: HashMap::new(),
+            headers,
+            // read once the request is known to be allowed, see `read_body`
+            body: Vec::new(),
+            reservation: Reservation::new(),
+        };
+
+        // the login flow is the only thing reachable without being authenticated
+        if let Some(handler) = login_route(&request) {
+            if !read_body(&mut request, content_length, reader, &writer).await? {
                 return Ok(());
             }
-        } else {
-            // 401 Unauthorized, just close the connection without any response to avoid leaking information
-            writer.lock().await.get_mut().shutdown().await?;
+            request.query = query_pairs;
+            return handler(request, writer, session_token).await;
+        }
+
+        let key = request_key(&request.headers, &query_pairs);
+        let key_role = key.as_deref().map(session::key_role);
+        let session_role = session_token.as_deref().and_then(session::role);
+
+        if let (Some(key), Some(Some(role)), true) = (&key, key_role, query_pairs.contains_key("k")) {
+            if request.method == Method::GET && is_page(&request.path) {
+                // trade the key for a session, so that it doesn't stay in the address bar nor the history
+                query_pairs.remove("k");
+                let mut location = request.path.clone();
+                if !query_pairs.is_empty() {
+                    location.push('?');
+                    location.push_str(
+                        &form_urlencoded::Serializer::new(String::new())
+                            .extend_pairs(&query_pairs)
+                            .finish(),
+                    );
+                }
+                let cookie = session::set_cookie(&session::create_with_key(key, role));
+                let mut writer = writer.lock().await;
+                writer.keep_alive &= content_length == 0;
+                redirect(&mut writer, &location, Some(cookie)).await?;
+                return Ok(());
+            }
+        }
+
+        // a wrong key is refused even with a session
+        let Some(role) = key_role.unwrap_or(session_role) else {
+            if request.method == Method::GET && is_page(&request.path) {
+                let mut writer = writer.lock().await;
+                writer.keep_alive &= content_length == 0;
+                redirect(&mut writer, "/login", None).await?;
+            } else if key_role.is_none() && session_token.is_some() {
+                // an expired panel session, let the panel send the user back to the login page
+                let mut writer = writer.lock().await;
+                writer.keep_alive &= content_length == 0;
+                write_status(&mut writer, "401 Unauthorized").await?;
+            } else {
+                // just close the connection without any response to avoid leaking information
+                let mut writer = writer.lock().await;
+                writer.keep_alive = false;
+                writer.get_mut().shutdown().await?;
+            }
+            return Ok(());
+        };
+
+        if role < required_role(&request) {
+            let mut writer = writer.lock().await;
+            writer.keep_alive &= content_length == 0;
+            let message = b"This key is read-only";
+            write_response(&mut writer, "403 Forbidden", "text/plain", &[], message).await?;
             return Ok(());
         }
 
-        let routes = build_routes();
-        if let Some((handler, params)) = find_handler(&routes, &method, &path) {
-            let request = Request {
-                method,
-                path,
-                query: query_pairs,
-                params,
-            };
+        if !read_body(&mut request, content_length, reader, &writer).await? {
+            return Ok(());
+        }
+        if let Some((handler, params)) = router.find(&request.method, &request.path) {
+            request.query = query_pairs;
+            request.params = params;
             handler(request, writer.clone(), db.clone()).await?;
         } else {
             let mut writer = writer.lock().await;
-            writer.write_all(b"HTTP/1.1 404 Not Found\r\n\r\n").await?;
-            writer.flush().await?;
+            write_status(&mut writer, "404 Not Found").await?;
         }
     } else {
         // bad request (most likely a skill issue)
         let mut writer = writer.lock().await;
-        writer
-            .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
-            .await?;
-        writer.flush().await?;
+        writer.keep_alive = false;
+        write_status(&mut writer, "400 Bad Request").await?;
     }
 
     Ok(())
 }
 
-// function to build the routing table
-fn build_routes() -> Vec<(Method, String, Handler)> {
+// Reads a body of `content_length` bytes into the request, false if it was answered instead. The
+// request is authenticated by then, so that the memory is only committed to known clients.
+async fn read_body(
+    request: &mut Request,
+    content_length: usize,
+    reader: &mut Reader,
+    writer: &Arc<AsyncMutex<Writer>>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // shed load rather than getting killed for using too much memory
+    if memory::exceeded() || !request.reservation.grow(content_length) {
+        metrics::MEMORY_REJECTED.inc();
+        let mut writer = writer.lock().await;
+        writer.keep_alive = false;
+        let headers = [("Retry-After", "5".to_string())];
+        let message = b"Memory budget exceeded, try again later";
+        write_response(&mut writer, "503 Service Unavailable", "text/plain", &headers, message).await?;
+        return Ok(false);
+    }
+    let mut body = vec![0; content_length];
+    match timeout(BODY_TIMEOUT, reader.read_exact(&mut body)).await {
+        Ok(read) => read?,
+        Err(_) => {
+            metrics::HTTP_TIMEOUTS.inc();
+            let mut writer = writer.lock().await;
+            writer.keep_alive = false;
+            write_status(&mut writer, "408 Request Timeout").await?;
+            return Ok(false);
+        }
+    };
+    request.body = body;
+    Ok(true)
+}
+
+// the routing table, see Router
+fn build_routes(queue: Queue) -> Vec<(Method, String, Handler)> {
+    let put_queue = queue.clone();
     vec![
+        // before /mails/:mail_id, which would match it as well
+        (
+            Method::GET,
+            "/mails/diff".to_string(),
+            Box::new(|request, writer, db| Box::pin(diff_mails_handler(request, writer, db))),
+        ),
         (
             Method::GET,
             "/mails/:mail_id".to_string(),
@@ -161,6 +473,36 @@ fn build_routes() -> Vec<(Method, String, Handler)> {
             "/mails/from/:email".to_string(),
             Box::new(|request, writer, db| Box::pin(get_mails_from_to_handler(request, writer, db, false))),
         ),
+        (
+            Method::GET,
+            "/mails/:mail_id/attachments.zip".to_string(),
+            Box::new(|request, writer, db| Box::pin(get_attachments_zip_handler(request, writer, db))),
+        ),
+        (
+            Method::GET,
+            "/mails/:mail_id/attachments".to_string(),
+            Box::new(|request, writer, db| Box::pin(get_attachments_handler(request, writer, db))),
+        ),
+        (
+            Method::GET,
+            "/mails/:mail_id/attachments/:index".to_string(),
+            Box::new(|request, writer, db| Box::pin(get_attachment_handler(request, writer, db))),
+        ),
+        (
+            Method::GET,
+            "/mails/:mail_id/html".to_string(),
+            Box::new(|request, writer, _| Box::pin(moved_mail_html_handler(request, writer))),
+        ),
+        (
+            Method::GET,
+            "/mails/:mail_id/raw".to_string(),
+            Box::new(|request, writer, db| Box::pin(get_mail_raw_handler(request, writer, db))),
+        ),
+        (
+            Method::PATCH,
+            "/mails/:mail_id".to_string(),
+            Box::new(|request, writer, db| Box::pin(patch_mail_handler(request, writer, db))),
+        ),
         (
             Method::DELETE,
             "/mails/:mail_id".to_string(),
@@ -174,7 +516,17 @@ fn build_routes() -> Vec<(Method, String, Handler)> {
         (
             Method::DELETE,
             "/mails".to_string(),
-            Box::new(|_, writer, db| Box::pin(delete_all_mails_handler(writer, db))),
+            Box::new(|request, writer, db| Box::pin(delete_mails_handler(request, writer, db))),
+        ),
+        (
+            Method::POST,
+            "/mails".to_string(),
+            Box::new(move |request, writer, _| Box::pin(create_mail_handler(request, writer, queue.clone()))),
+        ),
+        (
+            Method::PUT,
+            "/mails".to_string(),
+            Box::new(move |request, writer, _| Box::pin(create_mail_handler(request, writer, put_queue.clone()))),
         ),
         (
             Method::DELETE,
@@ -186,157 +538,708 @@ fn build_routes() -> Vec<(Method, String, Handler)> {
             "/mails/from/:email".to_string(),
             Box::new(|request, writer, db| Box::pin(delete_mails_from_to_handler(request, writer, db, false))),
         ),
+        (
+            Method::GET,
+            "/mailboxes".to_string(),
+            Box::new(|_, writer, db| Box::pin(mailboxes_handler(writer, db))),
+        ),
+        (
+            Method::GET,
+            "/mailboxes/:email/mails".to_string(),
+            Box::new(|request, writer, db| Box::pin(get_mails_from_to_handler(request, writer, db, true))),
+        ),
+        (
+            Method::DELETE,
+            "/mailboxes/:email".to_string(),
+            Box::new(|request, writer, db| Box::pin(delete_mails_from_to_handler(request, writer, db, true))),
+        ),
+        (
+            Method::GET,
+            "/stats".to_string(),
+            Box::new(|request, writer, db| Box::pin(stats_handler(request, writer, db))),
+        ),
         (
             Method::GET,
             "/info".to_string(),
             Box::new(|_, writer, db| Box::pin(info_handler(writer, db))),
         ),
+        (
+            Method::GET,
+            "/state".to_string(),
+            Box::new(|request, writer, db| Box::pin(state_handler(request, writer, db))),
+        ),
+        (
+            Method::GET,
+            "/export".to_string(),
+            Box::new(|request, writer, db| Box::pin(export_handler(request, writer, db))),
+        ),
+        (
+            Method::GET,
+            "/metrics".to_string(),
+            Box::new(|_, writer, _| Box::pin(metrics_handler(writer))),
+        ),
         (
             Method::GET,
             "/preview/:mail_id".to_string(),
-            Box::new(|request, writer, db| Box::pin(preview_mail_handler(request, writer, db))),
+            Box::new(|request, writer, db| Box::pin(get_mail_html_handler(request, writer, db))),
+        ),
+        (
+            Method::GET,
+            "/open/:mail_id".to_string(),
+            Box::new(|request, writer, db| Box::pin(open_mail_handler(request, writer, db))),
+        ),
+        (
+            Method::GET,
+            "/events".to_string(),
+            Box::new(|request, writer, _| Box::pin(events_handler(request, writer))),
+        ),
+        (
+            Method::GET,
+            "/admin/config".to_string(),
+            Box::new(|_, writer, _| Box::pin(admin_config_handler(writer))),
+        ),
+        (
+            Method::PUT,
+            "/admin/retention".to_string(),
+            Box::new(|request, writer, _| Box::pin(admin_retention_handler(request, writer))),
+        ),
+        (
+            Method::GET,
+            "/admin/webhooks".to_string(),
+            Box::new(|_, writer, _| Box::pin(admin_webhooks_handler(writer))),
+        ),
+        (
+            Method::PUT,
+            "/admin/webhooks".to_string(),
+            Box::new(|request, writer, _| Box::pin(admin_set_webhooks_handler(request, writer))),
+        ),
+        (
+            Method::GET,
+            "/admin/keys".to_string(),
+            Box::new(|_, writer, _| Box::pin(admin_keys_handler(writer))),
+        ),
+        (
+            Method::POST,
+            "/admin/keys".to_string(),
+            Box::new(|request, writer, _| Box::pin(admin_create_key_handler(request, writer))),
+        ),
+        (
+            Method::DELETE,
+            "/admin/keys/:key_id".to_string(),
+            Box::new(|request, writer, _| Box::pin(admin_revoke_key_handler(request, writer))),
+        ),
+        (
+            Method::GET,
+            "/admin/chaos".to_string(),
+            Box::new(|_, writer, _| Box::pin(admin_chaos_handler(writer))),
+        ),
+        (
+            Method::PUT,
+            "/admin/chaos".to_string(),
+            Box::new(|request, writer, _| Box::pin(admin_set_chaos_handler(request, writer))),
+        ),
+        (
+            Method::POST,
+            "/admin/purge".to_string(),
+            Box::new(|request, writer, db| Box::pin(admin_purge_handler(request, writer, db))),
+        ),
+        (
+            Method::POST,
+            "/admin/flush".to_string(),
+            Box::new(|_, writer, db| Box::pin(admin_flush_handler(writer, db))),
+        ),
+        (
+            Method::POST,
+            "/reset".to_string(),
+            Box::new(|_, writer, db| Box::pin(reset_handler(writer, db))),
+        ),
+        (
+            Method::GET,
+            "/admin/sessions".to_string(),
+            Box::new(|_, writer, _| Box::pin(admin_sessions_handler(writer))),
+        ),
+        (
+            Method::DELETE,
+            "/admin/sessions/:session_id".to_string(),
+            Box::new(|request, writer, _| Box::pin(admin_kill_session_handler(request, writer))),
+        ),
+        (
+            Method::GET,
+            "/compare".to_string(),
+            Box::new(|_, writer, _| Box::pin(compare_handler(writer))),
+        ),
+        (
+            Method::GET,
+            "/settings".to_string(),
+            Box::new(|_, writer, _| Box::pin(settings_handler(writer))),
         ),
         (
             Method::GET,
             "/panel".to_string(),
             Box::new(|_, writer, _| Box::pin(panel_handler(writer))),
         ),
+        (
+            Method::GET,
+            "/".to_string(),
+            Box::new(|_, writer, _| Box::pin(panel_handler(writer))),
+        ),
     ]
 }
 
-// function to find the appropriate handler
-fn find_handler<'a>(
-    routes: &'a [(Method, String, Handler)],
-    method: &Method,
-    request_path: &str,
-) -> Option<(&'a Handler, HashMap<String, String>)> {
-    for (route_method, route_path, handler) in routes {
-        if method == route_method {
-            if let Some(params) = match_path(route_path, request_path) {
-                return Some((handler, params));
-            }
-        }
+type LoginHandler = fn(
+    Request,
+    Arc<AsyncMutex<Writer>>,
+    Option<String>,
+) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send>>;
+
+// routes reachable without a key nor a session, they also get the session cookie if any
+fn login_route(request: &Request) -> Option<LoginHandler> {
+    match (&request.method, request.path.trim_end_matches('/')) {
+        (Method::GET, "/login") => Some(|_, writer, _| Box::pin(login_page_handler(writer))),
+        (Method::POST, "/login") => Some(|request, writer, _| Box::pin(login_handler(request, writer))),
+        (Method::POST, "/logout") => Some(|_, writer, token| Box::pin(logout_handler(writer, token))),
+        _ => None,
     }
-    None
 }
 
-// function to match paths with parameters
-fn match_path(route_path: &str, request_path: &str) -> Option<HashMap<String, String>> {
-    let route_parts: Vec<&str> = route_path.trim_end_matches('/').split('/').collect();
-    let request_parts: Vec<&str> = request_path.trim_end_matches('/').split('/').collect();
+// the key of `X-Api-Key` or `Authorization: Bearer`, else of `?k`
+fn request_key(headers: &HashMap<String, String>, query: &HashMap<String, String>) -> Option<String> {
+    headers
+        .get("x-api-key")
+        .map(String::as_str)
+        .or_else(|| headers.get("authorization")?.strip_prefix("Bearer "))
+        .or(query.get("k").map(String::as_str))
+        .map(str::to_string)
+}
 
-    if route_parts.len() != request_parts.len() {
-        return None;
+// read-only keys can list and download the mails, but neither change anything nor see the
+// configuration
+fn required_role(request: &Request) -> Role {
+    match request.method {
+        Method::GET if !request.path.starts_with("/admin") => Role::Read,
+        _ => Role::Admin,
     }
+}
+
+// pages opened by a browser, sent to the login page instead of having the connection dropped
+fn is_page(path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    path.is_empty()
+        || path == "/panel"
+        || path == "/settings"
+        || path == "/compare"
+        || path.starts_with("/open/")
+        || path.starts_with("/preview/")
+}
+
+enum Segment {
+    Literal(String),
+    // `:name`
+    Param(String),
+}
 
-    let mut params = HashMap::new();
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
 
-    for (route_part, request_part) in route_parts.iter().zip(request_parts.iter()) {
-        if route_part.starts_with(':') {
-            let name = route_part.trim_start_matches(':');
-            params.insert(name.to_string(), request_part.to_string());
-        } else if route_part != request_part {
+impl Route {
+    fn matches(&self, request_parts: &[&str]) -> Option<HashMap<String, String>> {
+        if self.segments.len() != request_parts.len() {
             return None;
         }
-    }
 
-    Some(params)
-}
+        let mut params = HashMap::new();
+        for (segment, request_part) in self.segments.iter().zip(request_parts) {
+            match segment {
+                Segment::Param(name) => {
+                    params.insert(name.clone(), request_part.to_string());
+                }
+                Segment::Literal(literal) if literal != request_part => return None,
+                Segment::Literal(_) => {}
+            }
+        }
 
-//     HANDLERS     //
+        Some(params)
+    }
+}
 
-async fn get_mail_handler(
-    request: Request,
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mail_id = request.params.get("mail_id").unwrap();
+/// The routing table, built once at startup and shared by every connection.
+pub(crate) struct Router {
+    routes: Vec<Route>,
+}
 
-    let mail_id = u128::from_str_radix(mail_id, 10).map_err(|_| {
-        Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Invalid mail_id",
-        )) as Box<dyn Error + Send + Sync>
-    })?;
+impl Router {
+    /// The routes of the API and the panel, `POST /mails` handing its mails over to `queue` like
+    /// SMTP does.
+    pub(crate) fn new(queue: Queue) -> Self {
+        let routes = build_routes(queue)
+            .into_iter()
+            .map(|(method, path, handler)| Route {
+                method,
+                segments: split_path(&path)
+                    .into_iter()
+                    .map(|part| match part.strip_prefix(':') {
+                        Some(name) => Segment::Param(name.to_string()),
+                        None => Segment::Literal(part.to_string()),
+                    })
+                    .collect(),
+                handler,
+            })
+            .collect();
+
+        Router { routes }
+    }
 
-    let db = db.lock().await;
-    let result = db.get(mail_id.to_le_bytes());
+    // the first matching route wins, so more specific ones are declared first
+    fn find(&self, method: &Method, request_path: &str) -> Option<(&Handler, HashMap<String, String>)> {
+        let request_parts = split_path(request_path);
+        self.routes
+            .iter()
+            .filter(|route| route.method == *method)
+            .find_map(|route| route.matches(&request_parts).map(|params| (&route.handler, params)))
+    }
+}
 
-    let mut writer = writer.lock().await;
+fn split_path(path: &str) -> Vec<&str> {
+    path.trim_end_matches('/').split('/').collect()
+}
 
-    if let Ok(Some(data)) = result {
-        let mail: Mail = bincode::deserialize(&data)?;
-        let mut json = serde_json::to_value(&mail)?;
-        json["body"] = Value::String(mail.parse_body());
-        Value::Number(serde_json::Number::from_str(&mail.timestamp().to_string()).unwrap());
-        let json = serde_json::to_string(&json)?;
+// how the client finds the end of the body
+enum Body {
+    Length(usize),
+    Chunked,
+    // for streams like the events, the connection can't be reused after them
+    UntilClose,
+}
 
-        writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-        writer
-            .write_all(b"Content-Type: application/json\r\n")
-            .await?;
-        writer
-            .write_all(format!("Content-Length: {}\r\n", json.len()).as_bytes())
-            .await?;
-        writer.write_all(b"\r\n").await?;
-        writer.write_all(json.as_bytes()).await?;
-    } else {
-        writer.write_all(b"HTTP/1.1 404 Not Found\r\n\r\n").await?;
+// the status line and the headers, `Date` and `Connection` included
+async fn write_head(
+    writer: &mut Writer,
+    status: &str,
+    content_type: Option<&str>,
+    headers: &[(&str, String)],
+    body: Body,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    debug!(status, "Response");
+    if matches!(body, Body::UntilClose) {
+        writer.keep_alive = false;
+    }
+    let connection = if writer.keep_alive { "keep-alive" } else { "close" };
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nDate: {}\r\nConnection: {}\r\n",
+        status,
+        http_date(SystemTime::now()),
+        connection
+    );
+    if let Some(content_type) = content_type {
+        head.push_str(&format!("Content-Type: {}\r\n", content_type));
     }
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    match body {
+        Body::Length(length) => head.push_str(&format!("Content-Length: {}\r\n", length)),
+        Body::Chunked => head.push_str("Transfer-Encoding: chunked\r\n"),
+        Body::UntilClose => {}
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes()).await?;
+    Ok(())
+}
 
+// e.g. `Mon, 01 Jan 2024 00:00:00 GMT`
+fn http_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = seconds / 86400;
+    let (year, month, day) = jobs::civil_date(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        jobs::WEEKDAYS[(days % 7) as usize],
+        day,
+        jobs::MONTHS[month as usize - 1],
+        year,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// a response without a body, e.g. `404 Not Found`
+async fn write_status(writer: &mut Writer, status: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    write_head(writer, status, None, &[], Body::Length(0)).await?;
     writer.flush().await?;
     Ok(())
 }
 
-async fn delete_mail_handler(
-    request: Request,
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
+// for lists, so that they're sent as they're read instead of being built in memory first
+async fn write_chunked_head(
+    writer: &mut Writer,
+    status: &str,
+    content_type: &str,
+    headers: &[(&str, String)],
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mail_id = request.params.get("mail_id").unwrap();
-    let mail_id = u128::from_str_radix(mail_id, 10).map_err(|_| {
-        Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Invalid mail_id",
-        )) as Box<dyn Error + Send + Sync>
-    })?;
+    write_head(writer, status, Some(content_type), headers, Body::Chunked).await
+}
 
-    let db = db.lock().await;
-    let result = db.get(mail_id.to_le_bytes());
+async fn write_chunk(
+    writer: &mut Writer,
+    data: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // an empty chunk would end the body
+    if data.is_empty() {
+        return Ok(());
+    }
+    writer
+        .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+        .await?;
+    writer.write_all(data).await?;
+    writer.write_all(b"\r\n").await?;
+    Ok(())
+}
 
-    let mut writer = writer.lock().await;
+async fn finish_chunked(
+    writer: &mut Writer,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    writer.write_all(b"0\r\n\r\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
 
-    if let Ok(Some(data)) = result {
-        db.remove(mail_id.to_le_bytes()).unwrap();
-        let mail: Mail = bincode::deserialize(&data)?;
-        let mut json = serde_json::to_value(&mail)?;
-        json["body"] = Value::String(mail.parse_body());
-        Value::Number(serde_json::Number::from_str(&mail.timestamp().to_string()).unwrap());
-        let json = serde_json::to_string(&json)?;
-
-        writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-        writer
-            .write_all(b"Content-Type: application/json\r\n")
-            .await?;
-        writer
-            .write_all(format!("Content-Length: {}\r\n", json.len()).as_bytes())
-            .await?;
-        writer.write_all(b"\r\n").await?;
-        writer.write_all(json.as_bytes()).await?;
-    } else {
-        writer.write_all(b"HTTP/1.1 404 Not Found\r\n\r\n").await?;
-    }
+// one element of a streamed JSON array
+async fn write_json_chunk<T: Serialize>(
+    writer: &mut Writer,
+    value: &T,
+    first: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut chunk = if first { Vec::new() } else { vec![b','] };
+    serde_json::to_writer(&mut chunk, value)?;
+    write_chunk(writer, &chunk).await
+}
+
+// writes a whole response at once, for handlers that already have their body in memory
+async fn write_response(
+    writer: &mut Writer,
+    status: &str,
+    content_type: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    write_head(writer, status, Some(content_type), headers, Body::Length(body.len())).await?;
+    writer.write_all(body).await?;
     writer.flush().await?;
     Ok(())
 }
 
-async fn get_mails_handler(
-    request: Request,
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
+async fn redirect(
+    writer: &mut Writer,
+    location: &str,
+    cookie: Option<String>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let limit = request
-        .query
+    let mut headers = vec![("Location", location.to_string())];
+    if let Some(cookie) = cookie {
+        headers.push(("Set-Cookie", cookie));
+    }
+    write_response(writer, "303 See Other", "text/plain", &headers, b"").await
+}
+
+// every page shares the theme variables and the theme switching script
+fn page(html: &str) -> String {
+    html.replace("{{theme}}", include_str!("pages/theme.html"))
+}
+
+fn mail_id_param(request: &Request) -> Result<u128, Box<dyn Error + Send + Sync>> {
+    request
+        .params
+        .get("mail_id")
+        .and_then(|mail_id| mail_id.parse::<u128>().ok())
+        .ok_or_else(|| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid mail_id",
+            )) as Box<dyn Error + Send + Sync>
+        })
+}
+
+// ascii fallback for old clients plus the RFC 5987 form for the real (utf-8) name
+fn content_disposition(disposition: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        fallback,
+        utf8_percent_encode(filename, NON_ALPHANUMERIC)
+    )
+}
+
+// content types coming from a mail are attacker controlled, only keep plain mime types
+fn safe_content_type(content_type: &str) -> &str {
+    let valid = !content_type.is_empty()
+        && content_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/+-.!#$&^_".contains(c));
+    if valid {
+        content_type
+    } else {
+        "application/octet-stream"
+    }
+}
+
+// types the browser can display without running anything (svg and html can carry scripts)
+fn is_inline_safe(content_type: &str) -> bool {
+    (content_type.starts_with("image/") && content_type != "image/svg+xml")
+        || content_type == "application/pdf"
+        || content_type == "text/plain"
+}
+
+fn zip_attachments(attachments: Vec<(Attachment, Vec<u8>)>) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut names = HashSet::new();
+
+    for (index, (attachment, content)) in attachments.into_iter().enumerate() {
+        // no directories, and several attachments may share the same name
+        let mut name = attachment.filename.replace(['/', '\\'], "_");
+        if !names.insert(name.clone()) {
+            name = format!("{}-{}", index, name);
+            names.insert(name.clone());
+        }
+
+        zip.start_file(name, zip::write::SimpleFileOptions::default())?;
+        zip.write_all(&content)?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+// a mail as returned by the API, serialized straight from it rather than through a `Value`
+#[derive(Serialize)]
+pub(crate) struct MailJson<'a> {
+    #[serde(flatten)]
+    mail: &'a Mail,
+    body: String,
+    timestamp: u128,
+    #[serde(flatten)]
+    details: Option<MailDetails>,
+}
+
+#[derive(Serialize)]
+struct MailDetails {
+    html: Option<String>,
+    text: Option<String>,
+    headers: Vec<Header>,
+    attachments: Vec<Attachment>,
+    ingest_latency_us: Option<u64>,
+    user: Option<String>,
+    tags: Vec<String>,
+    read: bool,
+    // how its delivery through `--relay` goes, for the mails matching a `relay` rule
+    relay: Option<relay::Status>,
+    // the DKIM and SPF results, see `--verify-auth`
+    authentication: Option<Authentication>,
+}
+
+impl<'a> MailJson<'a> {
+    pub(crate) fn new(mail: &'a Mail, with_details: bool) -> Self {
+        MailJson {
+            mail,
+            body: mail.parse_body(),
+            timestamp: mail.timestamp(),
+            details: with_details.then(|| MailDetails {
+                html: mail.html_body(),
+                text: mail.text_body(),
+                headers: mail.headers(),
+                attachments: mail.attachments(),
+                ingest_latency_us: None,
+                user: None,
+                tags: Vec::new(),
+                read: false,
+                relay: None,
+                authentication: None,
+            }),
+        }
+    }
+
+    /// Adds what only the summary knows to the details.
+    pub(crate) fn with_summary(mut self, summary: Option<MailSummary>) -> Self {
+        if let (Some(details), Some(summary)) = (self.details.as_mut(), summary) {
+            details.ingest_latency_us = summary.ingest_latency_us;
+            details.user = summary.user;
+            details.tags = summary.tags;
+            details.read = summary.read;
+            details.authentication = summary.authentication;
+        }
+        self
+    }
+
+    pub(crate) fn with_relay(mut self, status: Option<relay::Status>) -> Self {
+        if let Some(details) = self.details.as_mut() {
+            details.relay = status;
+        }
+        self
+    }
+}
+
+//     HANDLERS     //
+
+async fn get_mail_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = request.params.get("mail_id").unwrap();
+
+    let mail_id = mail_id.parse::<u128>().map_err(|_| {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Invalid mail_id",
+        )) as Box<dyn Error + Send + Sync>
+    })?;
+
+    let result = db.get_mail(mail_id);
+
+    let mut writer = writer.lock().await;
+
+    if let Ok(Some(mail)) = result {
+        // so that test stages sharing the sink can tell the mails they haven't consumed yet
+        let summary = summary::update(&db, mail_id, |summary| summary.read = true)?;
+        let mut mail_json = MailJson::new(&mail, true)
+            .with_summary(summary)
+            .with_relay(relay::status(&db, mail_id)?);
+        if let Some(details) = mail_json.details.as_mut() {
+            // so that tests can assert on the attachments without downloading them one by one
+            if request.query.get("content").is_some_and(|content| content == "1") {
+                details.attachments = mail
+                    .attachments_with_content()
+                    .into_iter()
+                    .map(|(attachment, content)| Attachment {
+                        content: Some(BASE64_STANDARD.encode(content)),
+                        ..attachment
+                    })
+                    .collect();
+            }
+        }
+        let json = serde_json::to_vec(&mail_json)?;
+
+        write_response(&mut writer, "200 OK", "application/json", &[], &json).await?;
+    } else {
+        write_status(&mut writer, "404 Not Found").await?;
+    }
+
+    writer.flush().await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct MailPatch {
+    read: Option<bool>,
+    // replaces the tags, `add_tags` and `remove_tags` change them instead
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    add_tags: Vec<String>,
+    #[serde(default)]
+    remove_tags: Vec<String>,
+}
+
+impl MailPatch {
+    fn apply(&self, summary: &mut MailSummary) {
+        if let Some(read) = self.read {
+            summary.read = read;
+        }
+        if let Some(tags) = &self.tags {
+            summary.tags.clear();
+            add_tags(&mut summary.tags, tags);
+        }
+        add_tags(&mut summary.tags, &self.add_tags);
+        summary.tags.retain(|tag| {
+            !self.remove_tags.iter().any(|removed| removed.trim().eq_ignore_ascii_case(tag))
+        });
+    }
+}
+
+// trimmed, and only once whatever the case, as `?tag` compares them
+fn add_tags(tags: &mut Vec<String>, added: &[String]) {
+    for tag in added.iter().map(|tag| tag.trim()).filter(|tag| !tag.is_empty()) {
+        if !tags.iter().any(|existing| existing.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+}
+
+async fn patch_mail_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = mail_id_param(&request)?;
+    let patch: MailPatch = match serde_json::from_slice(&request.body) {
+        Ok(patch) => patch,
+        Err(e) => {
+            let mut writer = writer.lock().await;
+            let message = format!("Invalid patch: {}", e);
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], message.as_bytes()).await?;
+            return Ok(());
+        }
+    };
+
+    let updated = summary::update(&db, mail_id, |summary| patch.apply(summary))?;
+    let mut writer = writer.lock().await;
+    match updated {
+        Some(summary) => {
+            let json = serde_json::to_vec(&summary)?;
+            write_response(&mut writer, "200 OK", "application/json", &[], &json).await?;
+        }
+        None => write_status(&mut writer, "404 Not Found").await?,
+    }
+    Ok(())
+}
+
+async fn delete_mail_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = request.params.get("mail_id").unwrap();
+    let mail_id = mail_id.parse::<u128>().map_err(|_| {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Invalid mail_id",
+        )) as Box<dyn Error + Send + Sync>
+    })?;
+
+    let result = db.get_mail(mail_id);
+
+    let mut writer = writer.lock().await;
+
+    if let Ok(Some(mail)) = result {
+        db.delete(mail_id)?;
+        let json = serde_json::to_vec(&MailJson::new(&mail, false))?;
+
+        write_response(&mut writer, "200 OK", "application/json", &[], &json).await?;
+    } else {
+        write_status(&mut writer, "404 Not Found").await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn get_mails_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let limit = request
+        .query
         .get("limit")
         .unwrap_or(&String::from("10"))
         .parse::<usize>()
@@ -354,39 +1257,51 @@ async fn get_mails_handler(
         .parse::<usize>()
         .unwrap();
 
-    let search = request.query.get("search").unwrap_or(&String::new()).to_lowercase();
-
+    let page = MailFilter::from_query(&request.query).and_then(|filter| {
+        let order = match request.query.get("order") {
+            Some(order) => Order::parse(order)?,
+            None => Order::default(),
+        };
+        let after = match request.query.get("after") {
+            Some(after) => Some(after.parse::<u128>().map_err(|_| format!("Invalid mail id: {}", after))?),
+            None => None,
+        };
+        Ok((filter, order, after))
+    });
+    let (filter, order, after) = match page {
+        Ok(page) => page,
+        Err(e) => {
+            let mut writer = writer.lock().await;
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await?;
+            return Ok(());
+        }
+    };
 
-    let db = db.lock().await;
-    let mut iter = db.iter().rev();
-    let mut mails = Vec::new();
+    // read without any lock, SMTP keeps storing mails while the response is sent
+    let iter = db.iter_page(order, after, offset)?;
     let mut count = 0;
 
-    for _ in 0..offset {
-        if iter.next().is_none() {
-            break;
-        }
-    }
-    
     let mut search_skipped = 0;
 
-    while let Some(result) = iter.next() {
-        let (_, data) = result?;
-        let mail: Mail = bincode::deserialize(&data)?;
-
-        if search.is_empty()
-            || mail.to.iter().any(|to| to.to_lowercase().contains(&search))
-            || mail.from.iter().any(|from| from.to_lowercase().contains(&search))
-            || mail.subject.clone().expect("subject").to_lowercase().contains(&search)
-            || mail.data.to_lowercase().contains(&search)
-        {
+    let mut writer = writer.lock().await;
+    write_chunked_head(&mut writer, "200 OK", "application/json", &[]).await?;
+    write_chunk(&mut writer, b"[").await?;
+
+    for summary in iter {
+        let summary = summary?;
+
+        // the full mail is only needed to search its content
+        let matches = filter.matches_summary(&summary)
+            && filter.matches_search(&summary, || mail_data(&db, summary.id));
+
+        if matches {
             // Si search_offset est activé, on saute les résultats avant le search_offset
             if search_skipped < search_offset {
                 search_skipped += 1;
                 continue;
             }
 
-            mails.push(mail);
+            write_json_chunk(&mut writer, &summary, count == 0).await?;
             count += 1;
         }
 
@@ -395,65 +1310,492 @@ async fn get_mails_handler(
         }
     }
 
-    let mut mails_json = Vec::new();
-    for mail in &mails {
-        let mut json: Value = serde_json::to_value(mail)?;
-        let parsed_body = mail.parse_body();
-        json["body"] = Value::String(parsed_body);
-        json["timestamp"] =
-            Value::Number(serde_json::Number::from_str(&mail.timestamp().to_string()).unwrap());
-        mails_json.push(json);
-    }
-    let json = serde_json::to_string(&mails_json)?;
+    write_chunk(&mut writer, b"]").await?;
+    finish_chunked(&mut writer).await
+}
+
+// the raw data of a mail, for the filters that can't be answered from its summary
+fn mail_data(db: &Store, id: u128) -> Option<Bytes> {
+    db.get_mail(id).ok().flatten().map(|mail| mail.data)
+}
+
+// the stored mails matching the filters of `GET /mails`, oldest first, as one mbox file or a zip of
+// `.eml` files, streamed as they're read
+async fn export_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let format = export::Format::parse(request.query.get("format").map(String::as_str).unwrap_or_default());
+    let (format, filter) = match (format, MailFilter::from_query(&request.query)) {
+        (Ok(format), Ok(filter)) => (format, filter),
+        (Err(e), _) | (_, Err(e)) => {
+            let mut writer = writer.lock().await;
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await?;
+            return Ok(());
+        }
+    };
 
     let mut writer = writer.lock().await;
-    writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-    writer
-        .write_all(b"Content-Type: application/json\r\n")
-        .await?;
-    writer
-        .write_all(format!("Content-Length: {}\r\n", json.len()).as_bytes())
-        .await?;
-    writer.write_all(b"\r\n").await?;
-    writer.write_all(json.as_bytes()).await?;
+    let filename = format!("mail-sink-export.{}", format.extension());
+    write_chunked_head(
+        &mut writer,
+        "200 OK",
+        format.content_type(),
+        &[("Content-Disposition", content_disposition("attachment", &filename))],
+    )
+    .await?;
+
+    let mut zip = export::EmlZip::new();
+    let mut count = 0;
+    for result in summary::tree(&db)?.iter() {
+        let (_, data) = result?;
+        let summary: MailSummary = bincode::deserialize(&data)?;
+        if !filter.matches_summary(&summary) || !filter.matches_search(&summary, || mail_data(&db, summary.id)) {
+            continue;
+        }
+        // removed since the summary was read
+        let Some(mail) = db.get_mail(summary.id)? else {
+            continue;
+        };
+        let chunk = match format {
+            export::Format::Mbox => export::mbox_entry(&mail),
+            export::Format::EmlZip => zip.add(&mail)?,
+        };
+        write_chunk(&mut writer, &chunk).await?;
+        count += 1;
+    }
+    if format == export::Format::EmlZip {
+        write_chunk(&mut writer, &zip.finish()?).await?;
+    }
+    debug!(count, "Exported mails");
+    finish_chunked(&mut writer).await
+}
 
-    writer.flush().await?;
+// a mail stored as if it had come through SMTP, rules, duplicates, events and webhooks included
+async fn create_mail_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    queue: Queue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let content_type = request.headers.get("content-type").map(String::as_str).unwrap_or_default();
+    let upload = match content_type.split(';').next().unwrap_or_default().trim() {
+        "message/rfc822" => upload::from_raw(
+            &request.body,
+            request.query.get("from").map(String::as_str),
+            request.query.get("to").map(String::as_str),
+        ),
+        "" | "application/json" => upload::from_json(&request.body),
+        _ => {
+            let mut writer = writer.lock().await;
+            let message = b"Expected message/rfc822 or application/json";
+            write_response(&mut writer, "415 Unsupported Media Type", "text/plain", &[], message).await?;
+            return Ok(());
+        }
+    };
+    let upload = match upload {
+        Ok(upload) => upload,
+        Err(e) => {
+            let mut writer = writer.lock().await;
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    // a message made of JSON fields can end up larger than the body
+    if config::max_message_size().is_some_and(|max| upload.data.len() > max) {
+        metrics::MESSAGES_TOO_LARGE.inc();
+        let mut writer = writer.lock().await;
+        write_status(&mut writer, "413 Payload Too Large").await?;
+        return Ok(());
+    }
+
+    let envelope = (upload.from, upload.to);
+    match smtp::submit(&queue, envelope, upload.data, request.reservation, None, None) {
+        Submitted::Queued(id, stored) => {
+            // answered once stored, so that the mail can be fetched right after
+            let _ = stored.await;
+            let mut writer = writer.lock().await;
+            let headers = [("Location", format!("/mails/{}", id))];
+            let json = serde_json::to_vec(&json!({ "id": id }))?;
+            write_response(&mut writer, "201 Created", "application/json", &headers, &json).await?;
+        }
+        Submitted::Dropped(id) => {
+            let mut writer = writer.lock().await;
+            let json = serde_json::to_vec(&json!({ "id": id }))?;
+            write_response(&mut writer, "202 Accepted", "application/json", &[], &json).await?;
+        }
+        Submitted::Duplicate(original) => {
+            let mut writer = writer.lock().await;
+            let headers = [("Location", format!("/mails/{}", original))];
+            let message = b"Duplicate message, already received";
+            write_response(&mut writer, "409 Conflict", "text/plain", &headers, message).await?;
+        }
+        Submitted::Refused => {
+            let mut writer = writer.lock().await;
+            let headers = [("Retry-After", "5".to_string())];
+            let message = b"Insufficient system storage, try again later";
+            write_response(&mut writer, "503 Service Unavailable", "text/plain", &headers, message).await?;
+        }
+    }
     Ok(())
 }
 
-async fn delete_all_mails_handler(
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
+async fn delete_mails_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let db = db.lock().await;
-    let count = db.len();
-    db.clear().unwrap();
+    let filter = MailFilter::from_query(&request.query);
+    let ids = request.query.get("ids").map(|ids| {
+        ids.split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse::<u128>())
+            .collect::<Result<Vec<_>, _>>()
+    });
+
+    let (filter, ids) = match (filter, ids) {
+        (Ok(filter), None) => (filter, None),
+        (Ok(filter), Some(Ok(ids))) => (filter, Some(ids)),
+        (Err(e), _) => {
+            let mut writer = writer.lock().await;
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await?;
+            return Ok(());
+        }
+        (_, Some(Err(_))) => {
+            let mut writer = writer.lock().await;
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], b"Invalid ids").await?;
+            return Ok(());
+        }
+    };
+
+    let count = match ids {
+        // explicit selection, still narrowed down by the filters if any
+        Some(ids) => {
+            let mut count = 0;
+            for id in ids {
+                if let Some(mail) = db.get_mail(id)? {
+                    let labeled = summary::get(&db, id)?
+                        .is_some_and(|summary| filter.matches_labels(&summary));
+                    if filter.matches(&mail) && labeled {
+                        db.delete(id)?;
+                        count += 1;
+                    }
+                }
+            }
+            count
+        }
+        None if filter.is_empty() => {
+            // a mail stored in between would be left without its summary
+            let _exclusive = db.exclusive().await;
+            let count = db.len();
+            db.clear()?;
+            summary::clear(&db)?;
+            duplicates::clear(&db)?;
+            relay::clear(&db)?;
+            count
+        }
+        None => {
+            let mut ids = Vec::new();
+            for result in summary::tree(&db)?.iter() {
+                let (_, data) = result?;
+                let summary: MailSummary = bincode::deserialize(&data)?;
+                if filter.matches_summary(&summary)
+                    && filter.matches_search(&summary, || mail_data(&db, summary.id))
+                {
+                    ids.push(summary.id);
+                }
+            }
+            for id in &ids {
+                db.delete(*id)?;
+            }
+            ids.len()
+        }
+    };
 
     let json = format!(r#"{{"deleted":{}}}"#, count);
 
     let mut writer = writer.lock().await;
-    writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-    writer
-        .write_all(b"Content-Type: application/json\r\n")
-        .await?;
-    writer
-        .write_all(format!("Content-Length: {}\r\n", json.len()).as_bytes())
-        .await?;
-    writer.write_all(b"\r\n").await?;
-    writer.write_all(json.as_bytes()).await?;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await?;
+    Ok(())
+}
+
+async fn diff_mails_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let ids = ["a", "b"].map(|name| {
+        request
+            .query
+            .get(name)
+            .and_then(|id| id.parse::<u128>().ok())
+    });
+    let [Some(a), Some(b)] = ids else {
+        let mut writer = writer.lock().await;
+        let message = b"Expected ?a=<mail_id>&b=<mail_id>";
+        return write_response(&mut writer, "400 Bad Request", "text/plain", &[], message).await;
+    };
+
+    let a = db.get_mail(a)?;
+    let b = db.get_mail(b)?;
+
+    let mut writer = writer.lock().await;
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let json = serde_json::to_string(&diff::diff_mails(&a, &b))?;
+            write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+        }
+        _ => {
+            write_status(&mut writer, "404 Not Found").await?;
+            Ok(())
+        }
+    }
+}
+
+async fn get_attachment_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = mail_id_param(&request)?;
+    let index = request
+        .params
+        .get("index")
+        .and_then(|index| index.parse::<usize>().ok())
+        .ok_or_else(|| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid attachment index",
+            )) as Box<dyn Error + Send + Sync>
+        })?;
+    let inline = request
+        .query
+        .get("inline")
+        .is_some_and(|inline| inline == "1" || inline == "true");
+
+    let attachment = match db.get_mail(mail_id) {
+        Ok(Some(mail)) => mail.attachments_with_content().into_iter().nth(index),
+        _ => None,
+    };
+
+    let mut writer = writer.lock().await;
+    match attachment {
+        Some((attachment, content)) => {
+            let content_type = safe_content_type(&attachment.content_type);
+            let disposition = if inline && is_inline_safe(content_type) {
+                "inline"
+            } else {
+                "attachment"
+            };
+            let headers = [
+                (
+                    "Content-Disposition",
+                    content_disposition(disposition, &attachment.filename),
+                ),
+                ("X-Content-Type-Options", "nosniff".to_string()),
+            ];
+            write_response(&mut writer, "200 OK", content_type, &headers, &content).await?;
+        }
+        None => {
+            write_status(&mut writer, "404 Not Found").await?;
+        }
+    }
 
-    writer.flush().await?;
     Ok(())
 }
 
+// mail HTML is attacker controlled: a sandboxed document gets its own origin, so it can't reach
+// the API with the panel's cookie, and it runs no script nor fetches anything remote
+const MAIL_HTML_CSP: &str =
+    "sandbox allow-popups allow-popups-to-escape-sandbox; default-src 'none'; img-src data: cid:; style-src 'unsafe-inline'";
+
+async fn get_mail_html_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = mail_id_param(&request)?;
+
+    let mail = db.get_mail(mail_id)?;
+
+    let mut writer = writer.lock().await;
+    match mail {
+        Some(mail) => {
+            let headers = [
+                ("Content-Security-Policy", MAIL_HTML_CSP.to_string()),
+                ("X-Content-Type-Options", "nosniff".to_string()),
+                ("Referrer-Policy", "no-referrer".to_string()),
+            ];
+            let html = mail.html_preview();
+            write_response(&mut writer, "200 OK", "text/html; charset=utf-8", &headers, html.as_bytes()).await
+        }
+        None => {
+            write_status(&mut writer, "404 Not Found").await?;
+            Ok(())
+        }
+    }
+}
+
+// where `/preview/:mail_id` was served until it took the place of the panel's page
+async fn moved_mail_html_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = mail_id_param(&request)?;
+    let mut writer = writer.lock().await;
+    redirect(&mut writer, &format!("/preview/{}", mail_id), None).await
+}
+
+async fn get_mail_raw_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = mail_id_param(&request)?;
+
+    let mail = db.get_mail(mail_id)?;
+
+    let mut writer = writer.lock().await;
+    match mail {
+        // as received, for mail clients to open
+        Some(mail) => {
+            let headers = [(
+                "Content-Disposition",
+                content_disposition("attachment", &format!("{}.eml", mail_id)),
+            )];
+            write_response(&mut writer, "200 OK", "message/rfc822", &headers, &mail.data).await
+        }
+        None => {
+            write_status(&mut writer, "404 Not Found").await?;
+            Ok(())
+        }
+    }
+}
+
+// the attachments alone, without the rest of the mail
+async fn get_attachments_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = mail_id_param(&request)?;
+    let mail = db.get_mail(mail_id)?;
+
+    let mut writer = writer.lock().await;
+    match mail {
+        Some(mail) => {
+            let json = serde_json::to_vec(&mail.attachments())?;
+            write_response(&mut writer, "200 OK", "application/json", &[], &json).await?;
+        }
+        None => write_status(&mut writer, "404 Not Found").await?,
+    }
+    Ok(())
+}
+
+async fn get_attachments_zip_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mail_id = mail_id_param(&request)?;
+
+    let attachments = match db.get_mail(mail_id) {
+        Ok(Some(mail)) => mail.attachments_with_content(),
+        _ => Vec::new(),
+    };
+
+    let mut writer = writer.lock().await;
+    if attachments.is_empty() {
+        write_status(&mut writer, "404 Not Found").await?;
+        return Ok(());
+    }
+
+    let zip = zip_attachments(attachments)?;
+    let headers = [(
+        "Content-Disposition",
+        content_disposition("attachment", &format!("mail-{}-attachments.zip", mail_id)),
+    )];
+    write_response(&mut writer, "200 OK", "application/zip", &headers, &zip).await?;
+
+    Ok(())
+}
+
+async fn state_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let since = match request.query.get("since").map(|value| value.trim()) {
+        None | Some("") => Ok(0),
+        Some(value) => value.parse::<u128>(),
+    };
+    let Ok(since) = since else {
+        let mut writer = writer.lock().await;
+        let message = b"Invalid since: expected a timestamp in milliseconds";
+        return write_response(&mut writer, "400 Bad Request", "text/plain", &[], message).await;
+    };
+
+    let json = serde_json::to_vec(&snapshot::query(&db, since)?)?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], &json).await
+}
+
+async fn stats_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let parse = |name: &str| -> Result<Option<u128>, String> {
+        match request.query.get(name).map(|value| value.trim()) {
+            None | Some("") => Ok(None),
+            Some(value) => value
+                .parse::<u128>()
+                .map(Some)
+                .map_err(|_| format!("Invalid {}: expected a timestamp in milliseconds", name)),
+        }
+    };
+    let window = parse("since").and_then(|since| Ok((since, parse("until")?)));
+
+    let (since, until) = match window {
+        Ok(window) => window,
+        Err(e) => {
+            let mut writer = writer.lock().await;
+            return write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await;
+        }
+    };
+
+    let stats = stats::query(&db, since, until)?;
+    let (mails, bytes) = summary::usage(&db)?;
+    let disk_bytes = db.size_on_disk()?;
+
+    let config = config::get();
+    let mut json = serde_json::to_value(&stats)?;
+    json["since"] = serde_json::to_value(since)?;
+    json["until"] = serde_json::to_value(until)?;
+    // what the retention limits are checked against, whatever the window
+    json["usage"] = json!({
+        "mails": mails,
+        "bytes": bytes,
+        "disk_bytes": disk_bytes,
+        "lifetime": config.lifetime,
+        "max_mails": config.max_mails,
+        "max_db_size": config.max_db_size,
+    });
+    let json = serde_json::to_string(&json)?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
 async fn info_handler(
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let db = db.lock().await;
     let count = db.len();
 
-    let database_disk_usage = db.size_on_disk().unwrap();
+    let database_disk_usage = db.size_on_disk()?;
     let pid = std::process::id();
     let mut process = Process::new(pid).unwrap();
 
@@ -483,8 +1825,31 @@ async fn info_handler(
         .sum();
     let free_space: u64 = disks.iter().map(|disk| disk.available_space()).sum();
 
+    let now = SystemTime::now();
+    let since = |ago: Duration| {
+        (now - ago).duration_since(UNIX_EPOCH).map(|since| since.as_millis()).unwrap_or(0)
+    };
+    let received_last_hour = summary::received_since(&db, since(Duration::from_secs(60 * 60)))?;
+    let received_last_day = summary::received_since(&db, since(Duration::from_secs(24 * 60 * 60)))?;
+    let started = metrics::started();
+
+    let config = config::get();
+    let bind = config.bind.unwrap_or(IpAddr::from([0, 0, 0, 0]));
+    let address = |port: u16| SocketAddr::new(bind, port).to_string();
+    let listeners = json!({
+        "smtp": config.smtp_ports.iter().map(|port| address(*port)).collect::<Vec<_>>(),
+        "smtps": config.smtps_port.map(address),
+        "pop3": config.pop3_port.map(address),
+        (if config.http_tls { "https" } else { "http" }): address(config.http_port),
+    });
+
     let json = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "started_at": started.duration_since(UNIX_EPOCH).map(|started| started.as_millis()).unwrap_or(0),
+        "uptime_seconds": now.duration_since(started).unwrap_or_default().as_secs(),
         "mail_count": count,
+        "received_last_hour": received_last_hour,
+        "received_last_day": received_last_day,
         "database_disk_usage": database_disk_usage,
         "memory_usage": mem_usage,
         "machine_memory_usage": machine_memory_usage,
@@ -494,92 +1859,485 @@ async fn info_handler(
         "max_cpu_usage": max_cpu_usage,
         "disk_usage": disk_usage,
         "free_space": free_space,
+        "jobs": jobs::statuses(),
+        "listeners": listeners,
+        // the same as `GET /admin/config`, which never holds secrets
+        "config": config,
     });
 
     let json = serde_json::to_string(&json)?;
 
     let mut writer = writer.lock().await;
-    writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-    writer
-        .write_all(b"Content-Type: application/json\r\n")
-        .await?;
-    writer
-        .write_all(format!("Content-Length: {}\r\n", json.len()).as_bytes())
-        .await?;
-    writer.write_all(b"\r\n").await?;
-
-    writer.write_all(json.as_bytes()).await?;
-
-    writer.flush().await?;
-    Ok(())
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
 }
 
-async fn preview_mail_handler(
+async fn open_mail_handler(
     request: Request,
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let mail_id = request.params.get("mail_id").unwrap();
-    let mail_id = u128::from_str_radix(mail_id, 10).map_err(|_| {
+    let mail_id = mail_id.parse::<u128>().map_err(|_| {
         Box::new(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
             "Invalid mail_id",
         )) as Box<dyn Error + Send + Sync>
     })?;
 
-    let db = db.lock().await;
     let result = db.get(mail_id.to_le_bytes());
 
     let mut writer = writer.lock().await;
 
-    if result.is_err() {
-        writer
-            .write_all(b"HTTP/1.1 500 Internal Server Error\r\n\r\n")
-            .await?;
-        writer.flush().await?;
-        return Ok(());
-    } else if result.unwrap().is_none() {
-        writer.write_all(b"HTTP/1.1 404 Not Found\r\n\r\n").await?;
-        writer.flush().await?;
-        return Ok(());
+    match result {
+        Err(_) => {
+            write_status(&mut writer, "500 Internal Server Error").await?;
+            return Ok(());
+        }
+        Ok(None) => {
+            write_status(&mut writer, "404 Not Found").await?;
+            return Ok(());
+        }
+        Ok(Some(_)) => {}
     }
 
     // return preview.html
-    let body = include_bytes!("pages/preview.html");
-    writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-    writer.write_all(b"Content-Type: text/html\r\n").await?;
-    writer
-        .write_all(format!("Content-Length: {}\r\n", body.len()).as_bytes())
-        .await?;
+    let body = page(include_str!("pages/preview.html"));
+    write_response(&mut writer, "200 OK", "text/html", &[], body.as_bytes()).await
+}
 
-    writer.write_all(b"\r\n").await?;
-    writer.write_all(body).await?;
+async fn metrics_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = metrics::render();
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "text/plain; version=0.0.4", &[], body.as_bytes()).await
+}
+
+async fn events_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // only ?to and ?from are looked at
+    let filter = match MailFilter::from_query(&request.query) {
+        Ok(filter) => filter,
+        Err(e) => {
+            let mut writer = writer.lock().await;
+            return write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await;
+        }
+    };
+    let mut events = events::subscribe();
+
+    let mut writer = writer.lock().await;
+    let headers = [("Cache-Control", "no-cache".to_string())];
+    write_head(&mut writer, "200 OK", Some("text/event-stream"), &headers, Body::UntilClose).await?;
+    writer.write_all(b": connected\n\n").await?;
     writer.flush().await?;
+
+    // not reset by the events filtered out, which the client doesn't see
+    let mut keep_alive = tokio::time::interval_at(
+        tokio::time::Instant::now() + KEEP_ALIVE_INTERVAL,
+        KEEP_ALIVE_INTERVAL,
+    );
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if event.matches(&filter) => {
+                    format!("data: {}\n\n", serde_json::to_string(&event)?)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+            // the client reconnects to the next instance by itself
+            _ = shutdown::stopped() => break,
+        };
+
+        if writer.write_all(message.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+            // client went away
+            break;
+        }
+    }
+
     Ok(())
 }
 
+async fn login_page_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let password_login = if session::password_login_enabled() {
+        ""
+    } else {
+        "hidden"
+    };
+    let body = page(include_str!("pages/login.html")).replace("{{password_login}}", password_login);
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "text/html", &[], body.as_bytes()).await
+}
+
+async fn login_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let form = form_urlencoded::parse(&request.body)
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+
+    let token = match (form.get("key"), form.get("username"), form.get("password")) {
+        (Some(key), _, _) => session::key_role(key).map(|role| session::create_with_key(key, role)),
+        (None, Some(username), Some(password)) => {
+            session::check_credentials(username, password).then(|| session::create(Role::Admin))
+        }
+        _ => None,
+    };
+
+    let mut writer = writer.lock().await;
+    if let Some(token) = token {
+        let cookie = session::set_cookie(&token);
+        redirect(&mut writer, "/panel", Some(cookie)).await
+    } else {
+        redirect(&mut writer, "/login?error=1", None).await
+    }
+}
+
+async fn logout_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+    session_token: Option<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some(token) = session_token {
+        session::remove(&token);
+    }
+
+    let mut writer = writer.lock().await;
+    redirect(&mut writer, "/login", Some(session::clear_cookie())).await
+}
+
 async fn panel_handler(
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
+    writer: Arc<AsyncMutex<Writer>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let body = include_bytes!("pages/panel.html");
+    let body = page(include_str!("pages/panel.html"));
 
     let mut writer = writer.lock().await;
-    writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-    writer.write_all(b"Content-Type: text/html\r\n").await?;
-    writer
-        .write_all(format!("Content-Length: {}\r\n", body.len()).as_bytes())
-        .await?;
+    write_response(&mut writer, "200 OK", "text/html", &[], body.as_bytes()).await
+}
 
-    writer.write_all(b"\r\n").await?;
-    writer.write_all(body).await?;
-    writer.flush().await?;
-    Ok(())
+async fn compare_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = page(include_str!("pages/compare.html"));
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "text/html", &[], body.as_bytes()).await
+}
+
+async fn settings_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = page(include_str!("pages/settings.html"));
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "text/html", &[], body.as_bytes()).await
+}
+
+async fn admin_config_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let json = serde_json::to_string(&config::get())?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn admin_retention_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // {"lifetime": 60} keeps mails for an hour, {"lifetime": null} forever
+    let lifetime = serde_json::from_slice::<Value>(&request.body)
+        .ok()
+        .and_then(|json| match json.get("lifetime") {
+            Some(Value::Null) => Some(None),
+            Some(lifetime) => lifetime
+                .as_u64()
+                .filter(|lifetime| *lifetime > 0)
+                .and_then(|lifetime| u16::try_from(lifetime).ok())
+                .map(Some),
+            None => None,
+        });
+
+    let mut writer = writer.lock().await;
+    match lifetime {
+        // like --lifetime, the mails would be expired as soon as they arrive
+        Some(Some(_)) if config::get().deterministic.is_some() => {
+            let message = b"Mail timestamps don't follow the clock in deterministic mode, there's no retention";
+            write_response(&mut writer, "409 Conflict", "text/plain", &[], message).await
+        }
+        Some(lifetime) => {
+            config::set_lifetime(lifetime);
+            let json = serde_json::to_string(&config::get())?;
+            write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+        }
+        None => {
+            let message = b"Expected {\"lifetime\": <minutes between 1 and 65535> or null}";
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], message).await
+        }
+    }
+}
+
+async fn admin_webhooks_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let json = serde_json::to_string(&json!({ "urls": webhooks::get() }))?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn admin_set_webhooks_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // {"urls": [...]} replaces the webhooks, {"urls": []} removes them
+    let urls = serde_json::from_slice::<Value>(&request.body)
+        .ok()
+        .and_then(|json| serde_json::from_value::<Vec<String>>(json.get("urls")?.clone()).ok());
+
+    let mut writer = writer.lock().await;
+    match urls.map(|urls| webhooks::validate(&urls).map(|_| urls)) {
+        Some(Ok(urls)) => {
+            webhooks::set(urls);
+            let json = serde_json::to_string(&json!({ "urls": webhooks::get() }))?;
+            write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+        }
+        Some(Err(e)) => write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await,
+        None => {
+            let message = b"Expected {\"urls\": [<http(s) URL>, ...]}";
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], message).await
+        }
+    }
+}
+
+async fn admin_chaos_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let json = serde_json::to_string(&json!({ "rules": smtp::chaos::get() }))?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn admin_set_chaos_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // {"rules": [...]} replaces the chaos rules, {"rules": []} behaves again
+    let specs = serde_json::from_slice::<Value>(&request.body)
+        .ok()
+        .and_then(|json| serde_json::from_value::<Vec<String>>(json.get("rules")?.clone()).ok());
+
+    let mut writer = writer.lock().await;
+    let rules = specs.map(|specs| {
+        specs
+            .iter()
+            .map(|spec| smtp::chaos::parse(spec))
+            .collect::<Result<Vec<_>, _>>()
+    });
+    match rules {
+        Some(Ok(rules)) => {
+            smtp::chaos::init(rules);
+            let json = serde_json::to_string(&json!({ "rules": smtp::chaos::get() }))?;
+            write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+        }
+        Some(Err(e)) => write_response(&mut writer, "400 Bad Request", "text/plain", &[], e.as_bytes()).await,
+        None => {
+            let message = b"Expected {\"rules\": [\"<stage>[:<pattern>] <fault> [<probability>%]\", ...]}";
+            write_response(&mut writer, "400 Bad Request", "text/plain", &[], message).await
+        }
+    }
+}
+
+async fn admin_purge_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // defaults to the configured retention, ?older_than=<minutes> overrides it
+    let lifetime = match request.query.get("older_than") {
+        Some(older_than) => older_than.parse::<u16>().ok(),
+        None => config::lifetime(),
+    };
+
+    let Some(lifetime) = lifetime else {
+        let mut writer = writer.lock().await;
+        let message = b"No retention configured, pass ?older_than=<minutes>";
+        return write_response(&mut writer, "400 Bad Request", "text/plain", &[], message).await;
+    };
+
+    let count = retention::purge_expired(&db, lifetime)?;
+
+    let json = format!(r#"{{"deleted":{}}}"#, count);
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn admin_flush_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let size_before = db.size_on_disk()?;
+    // writes out what sled still buffers; the file does not shrink, the space of removed entries is only reused
+    db.flush_async().await?;
+    let size_after = db.size_on_disk()?;
+
+    let json = json!({
+        "size_before": size_before,
+        "size_after": size_after,
+    });
+    let json = serde_json::to_string(&json)?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn reset_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // the mails being stored are waited for and the next ones held back, so it all goes at once
+    let exclusive = db.exclusive().await;
+    // the previous run's mails would otherwise still reach the webhooks and the upstream
+    let webhooks = webhooks::cancel();
+    let relays = relay::cancel();
+    let mails = db.len();
+    db.clear()?;
+    summary::clear(&db)?;
+    let duplicates = duplicates::clear(&db)?;
+    let relay_statuses = relay::clear(&db)?;
+    let stats = stats::clear(&db)?;
+    let rejections = snapshot::clear();
+    let rate_limits = limits::clear();
+    // the next mail gets the first id again, as after a restart
+    if let Some(seed) = config::get().deterministic {
+        snowflake::set_deterministic(seed);
+    }
+    drop(exclusive);
+
+    let json = json!({
+        "mails": mails,
+        "stats": stats,
+        "rejections": rejections,
+        "duplicates": duplicates,
+        "relay_statuses": relay_statuses,
+        "rate_limits": rate_limits,
+        "webhooks": webhooks,
+        "relays": relays,
+    });
+    let json = serde_json::to_string(&json)?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn admin_keys_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let json = serde_json::to_string(&json!({ "keys": session::keys() }))?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn admin_create_key_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // {"role": "admin"} or {"role": "read"}
+    let role = serde_json::from_slice::<Value>(&request.body)
+        .ok()
+        .and_then(|json| serde_json::from_value::<Role>(json.get("role")?.clone()).ok());
+
+    let mut writer = writer.lock().await;
+    let Some(role) = role else {
+        let message = b"Expected {\"role\": \"admin\"|\"read\"}";
+        return write_response(&mut writer, "400 Bad Request", "text/plain", &[], message).await;
+    };
+    match session::create_key(role) {
+        Some((key, info)) => {
+            let (admin_keys, read_keys) = session::key_counts();
+            config::set_keys(admin_keys, read_keys);
+            // the only time the key itself is shown
+            let mut json = serde_json::to_value(&info)?;
+            json["key"] = Value::String(key);
+            let json = serde_json::to_vec(&json)?;
+            write_response(&mut writer, "201 Created", "application/json", &[], &json).await
+        }
+        None => {
+            let message = b"No API keys are set up";
+            write_response(&mut writer, "503 Service Unavailable", "text/plain", &[], message).await
+        }
+    }
+}
+
+async fn admin_revoke_key_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let revoked = request
+        .params
+        .get("key_id")
+        .and_then(|id| id.parse::<u64>().ok())
+        .map(session::revoke_key);
+
+    let mut writer = writer.lock().await;
+    match revoked {
+        Some(Ok(())) => {
+            let (admin_keys, read_keys) = session::key_counts();
+            config::set_keys(admin_keys, read_keys);
+            write_response(&mut writer, "204 No Content", "text/plain", &[], b"").await
+        }
+        Some(Err(RevokeError::LastAdmin)) => {
+            let message = b"The last admin key can't be revoked";
+            write_response(&mut writer, "409 Conflict", "text/plain", &[], message).await
+        }
+        Some(Err(RevokeError::NotFound)) | None => {
+            write_response(&mut writer, "404 Not Found", "text/plain", &[], b"No such key").await
+        }
+    }
+}
+
+async fn admin_sessions_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let json = serde_json::to_string(&smtp::sessions::list())?;
+
+    let mut writer = writer.lock().await;
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
+}
+
+async fn admin_kill_session_handler(
+    request: Request,
+    writer: Arc<AsyncMutex<Writer>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let killed = request
+        .params
+        .get("session_id")
+        .and_then(|id| id.parse::<u64>().ok())
+        .is_some_and(smtp::sessions::kill);
+
+    let mut writer = writer.lock().await;
+    if killed {
+        write_response(&mut writer, "204 No Content", "text/plain", &[], b"").await
+    } else {
+        write_response(&mut writer, "404 Not Found", "text/plain", &[], b"No such session").await
+    }
 }
 
 async fn get_mails_from_to_handler(
     request: Request,
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
     to: bool,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let email_filter = request.params.get("email").unwrap().to_lowercase();
@@ -596,100 +2354,62 @@ async fn get_mails_from_to_handler(
         .parse::<usize>()
         .unwrap();
 
-    let db = db.lock().await;
-    let mut iter = db.iter().rev();
-    let mut mails = Vec::new();
+    // the index only holds the mails of the address, no need to look at the others
+    let ids = summary::ids_by_address(&db, to, &email_filter)?.skip(offset);
     let mut count = 0;
 
-    for _ in 0..offset {
-        if iter.next().is_none() {
+    let mut writer = writer.lock().await;
+    write_chunked_head(&mut writer, "200 OK", "application/json", &[]).await?;
+    write_chunk(&mut writer, b"[").await?;
+
+    for id in ids {
+        if count >= limit {
             break;
         }
+        // removed since it was indexed
+        let Some(summary) = summary::get(&db, id?)? else {
+            continue;
+        };
+        write_json_chunk(&mut writer, &summary, count == 0).await?;
+        count += 1;
     }
 
-    while let Some(result) = iter.next() {
-        let (_, data) = result?;
-        let mail: Mail = bincode::deserialize(&data)?;
-
-        if to {
-            if mail.to.iter().any(|to_email| to_email.to_lowercase() == email_filter) {
-                mails.push(mail);
-                count += 1;
-                if count >= limit {
-                    break;
-                }
-            }
-        } else {
-            if mail.from.iter().any(|to_email| to_email.to_lowercase() == email_filter) {
-                mails.push(mail);
-                count += 1;
-                if count >= limit {
-                    break;
-                }
-            }
-        }
-    }
+    write_chunk(&mut writer, b"]").await?;
+    finish_chunked(&mut writer).await
+}
 
-    let mut mails_json = Vec::new();
-    for mail in &mails {
-        let mut json: Value = serde_json::to_value(mail)?;
-        let parsed_body = mail.parse_body();
-        json["body"] = Value::String(parsed_body);
-        json["timestamp"] =
-            Value::Number(serde_json::Number::from_str(&mail.timestamp().to_string()).unwrap());
-        mails_json.push(json);
-    }
-    let json = serde_json::to_string(&mails_json)?;
+async fn mailboxes_handler(
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mailboxes = summary::mailboxes(&db)?;
 
     let mut writer = writer.lock().await;
-    writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-    writer
-        .write_all(b"Content-Type: application/json\r\n")
-        .await?;
-    writer
-        .write_all(format!("Content-Length: {}\r\n", json.len()).as_bytes())
-        .await?;
-    writer.write_all(b"\r\n").await?;
-    writer.write_all(json.as_bytes()).await?;
-
-    writer.flush().await?;
-    Ok(())
+    write_chunked_head(&mut writer, "200 OK", "application/json", &[]).await?;
+    write_chunk(&mut writer, b"[").await?;
+    for (index, mailbox) in mailboxes.iter().enumerate() {
+        write_json_chunk(&mut writer, mailbox, index == 0).await?;
+    }
+    write_chunk(&mut writer, b"]").await?;
+    finish_chunked(&mut writer).await
 }
 
 async fn delete_mails_from_to_handler(
     request: Request,
-    writer: Arc<AsyncMutex<BufWriter<tokio::net::tcp::OwnedWriteHalf>>>,
-    db: Arc<Mutex<Db>>,
+    writer: Arc<AsyncMutex<Writer>>,
+    db: Store,
     to: bool,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let email_filter = request.params.get("email").unwrap().to_lowercase();
 
-    let db = db.lock().await;
-    let mut iter = db.iter().rev();
-    let mut mail_ids = Vec::new();
-
-
-    while let Some(result) = iter.next() {
-        let (_, data) = result?;
-        let mail: Mail = bincode::deserialize(&data)?;
-
-        if to {
-            if mail.to.iter().any(|to_email| to_email.to_lowercase() == email_filter) {
-                mail_ids.push(mail.id);
-            }
-        } else {
-            if mail.from.iter().any(|to_email| to_email.to_lowercase() == email_filter) {
-                mail_ids.push(mail.id);
-            }
-        }
-    }
+    let mail_ids = summary::ids_by_address(&db, to, &email_filter)?.collect::<store::Result<Vec<_>>>()?;
 
     let count = mail_ids.len();
 
     for id in mail_ids {
-        match db.remove(id.to_le_bytes()) {
+        match db.delete(id) {
             Ok(_) => {}
-            Err(e) => eprint!("Failed te detelet mais {}: {}", id, e)
+            Err(e) => warn!(mail_id = id, error = %e, "Failed to delete mail"),
         }
     }
 
@@ -697,16 +2417,5 @@ async fn delete_mails_from_to_handler(
     let json = format!(r#"{{"deleted":{}}}"#, count);
 
     let mut writer = writer.lock().await;
-    writer.write_all(b"HTTP/1.1 200 OK\r\n").await?;
-    writer
-        .write_all(b"Content-Type: application/json\r\n")
-        .await?;
-    writer
-        .write_all(format!("Content-Length: {}\r\n", json.len()).as_bytes())
-        .await?;
-    writer.write_all(b"\r\n").await?;
-    writer.write_all(json.as_bytes()).await?;
-
-    writer.flush().await?;
-    Ok(())
+    write_response(&mut writer, "200 OK", "application/json", &[], json.as_bytes()).await
 }
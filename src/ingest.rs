@@ -0,0 +1,214 @@
+use crate::memory::Reservation;
+use crate::rules::Labels;
+use crate::smtp::mail::Mail;
+use crate::snapshot::Rejection;
+use crate::store::Store;
+use crate::summary::MailSummary;
+use crate::{
+    duplicates, events, metrics, relay, report, snapshot, stats, storage, summary, webhooks, SharedError,
+};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{info, Instrument, Span};
+
+// sled takes the writes concurrently, a second writer only keeps a slow one (e.g. flushing) from
+// holding up the queue
+const WRITERS: usize = 2;
+
+struct Queued {
+    mail: Mail,
+    // accounts for the mail until it's stored
+    reservation: Reservation,
+    received: Instant,
+    duplicate_of: Option<u128>,
+    labels: Labels,
+    // the span of the SMTP session, so that storing the mail is logged along with it
+    span: Span,
+    // told once the mail is stored (or failed to be)
+    stored: oneshot::Sender<()>,
+}
+
+/// Hands the mails received over SMTP to the writer tasks.
+#[derive(Clone)]
+pub struct Queue {
+    sender: mpsc::Sender<Queued>,
+    db: Store,
+    // the ids of the mails waiting for a writer, which duplicates can refer to too
+    pending: Arc<StdMutex<HashSet<u128>>>,
+    // the relay queue of the same sink, for the `relay` rules
+    relays: relay::Sender,
+}
+
+impl Queue {
+    /// Starts the writer tasks, storing up to `capacity` mails waiting for them. The mails to
+    /// relay go to `relays`.
+    pub fn start(db: Store, capacity: usize, relays: relay::Sender) -> Self {
+        let (sender, receiver) = mpsc::channel::<Queued>(capacity.max(1));
+        metrics::INGEST_QUEUE_CAPACITY.set(capacity.max(1) as i64);
+
+        let pending = Arc::new(StdMutex::new(HashSet::new()));
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..WRITERS {
+            let receiver = receiver.clone();
+            let db = db.clone();
+            let pending = pending.clone();
+            tokio::spawn(async move {
+                loop {
+                    // only one writer waits on the channel at a time, the others on this lock
+                    let Some(queued) = receiver.lock().await.recv().await else {
+                        break;
+                    };
+                    metrics::INGEST_QUEUE_DEPTH.dec();
+                    let id = queued.mail.id;
+                    let span = queued.span.clone();
+                    let stored = store(&db, queued).instrument(span).await;
+                    pending.lock().unwrap().remove(&id);
+                    // nobody waiting for it is fine
+                    let _ = stored.send(());
+                }
+            });
+        }
+
+        Queue {
+            sender,
+            db,
+            pending,
+            relays,
+        }
+    }
+
+    /// The id of the first mail received with the same Message-ID (or content), if it's still
+    /// around. Otherwise `mail` becomes the one the next ones are compared to.
+    pub fn find_duplicate(&self, mail: &Mail) -> Result<Option<u128>, SharedError> {
+        let key = duplicates::key(&mail.data);
+        let tree = duplicates::tree(&self.db)?;
+
+        let mut current = tree.get(&key)?;
+        loop {
+            if let Some(original) = &current {
+                let original = u128::from_le_bytes(original.as_ref().try_into()?);
+                let exists = self.pending.lock().unwrap().contains(&original)
+                    || self.db.contains_key(original.to_le_bytes())?;
+                if exists {
+                    metrics::DUPLICATES.inc();
+                    stats::record_duplicate(&self.db, mail)?;
+                    return Ok(Some(original));
+                }
+            }
+
+            // only if no other copy took the place in the meantime, so that two copies received at
+            // once can't both be taken for the first one
+            match tree.compare_and_swap(&key, current.as_ref(), Some(mail.id.to_le_bytes().to_vec()))? {
+                Ok(()) => return Ok(None),
+                Err(swap) => current = swap.current,
+            }
+        }
+    }
+
+    pub fn relays(&self) -> &relay::Sender {
+        &self.relays
+    }
+
+    /// Whether every mail queued so far has been stored (or failed to).
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+
+    /// Queues a mail without waiting, with a receiver told once it's stored, or None when the
+    /// queue is full and the mail should be refused for now.
+    pub fn push(
+        &self,
+        mail: Mail,
+        reservation: Reservation,
+        duplicate_of: Option<u128>,
+        labels: Labels,
+    ) -> Option<oneshot::Receiver<()>> {
+        let id = mail.id;
+        let (stored, receiver) = oneshot::channel();
+        let queued = Queued {
+            mail,
+            reservation,
+            received: Instant::now(),
+            duplicate_of,
+            labels,
+            span: Span::current(),
+            stored,
+        };
+
+        // counted before sending, so that a writer can't take it out first
+        metrics::INGEST_QUEUE_DEPTH.inc();
+        self.pending.lock().unwrap().insert(id);
+        match self.sender.try_send(queued) {
+            Ok(()) => Some(receiver),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                metrics::INGEST_QUEUE_DEPTH.dec();
+                self.pending.lock().unwrap().remove(&id);
+                metrics::INGEST_REJECTED.inc();
+                snapshot::reject(Rejection::QueueFull);
+                None
+            }
+        }
+    }
+}
+
+// hands back the sender, to tell once the mail is no longer pending
+async fn store(db: &Store, queued: Queued) -> oneshot::Sender<()> {
+    let Queued {
+        mail,
+        reservation,
+        received,
+        duplicate_of,
+        labels,
+        stored,
+        ..
+    } = queued;
+    // a reset waits for the mail and its summary, or clears them both
+    let _writing = db.writing().await;
+    match db.put_mail(&mail) {
+        Ok(_) => {
+            let latency = received.elapsed();
+            metrics::MAILS_STORED.inc();
+            metrics::INGEST_LATENCY.observe(latency);
+            let summary = MailSummary {
+                ingest_latency_us: Some(latency.as_micros() as u64),
+                duplicate_of,
+                tags: labels.tags,
+                namespace: labels.namespace,
+                user: labels.user,
+                authentication: labels.authentication,
+                ..MailSummary::from_mail(&mail)
+            };
+            info!(
+                mail_id = mail.id,
+                latency_us = latency.as_micros() as u64,
+                "Mail stored"
+            );
+            if let Err(e) = summary::insert(db, &summary) {
+                report::report(
+                    report::Kind::Storage,
+                    &format!("Failed to store the summary of mail {}: {}", mail.id, e),
+                );
+            }
+            if let Err(e) = stats::record(db, &mail) {
+                report::report(
+                    report::Kind::Storage,
+                    &format!("Failed to count mail {} in the stats: {}", mail.id, e),
+                );
+            }
+            events::publish(events::Event::mail_received(&mail));
+            webhooks::notify(&mail, &summary);
+        }
+        Err(e) => {
+            report::report(
+                report::Kind::Storage,
+                &format!("Failed to store mail {}: {}", mail.id, e),
+            );
+            storage::failed(db, &e);
+        }
+    }
+    drop(reservation);
+    stored
+}
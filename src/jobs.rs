@@ -0,0 +1,414 @@
+use crate::filter::MailFilter;
+use crate::smtp::mail::Mail;
+use crate::store::Store;
+use crate::summary::{self, MailSummary};
+use crate::{report, SharedError};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::form_urlencoded;
+
+// how far ahead the next run is looked for, a schedule like `0 0 30 2 *` never matches
+const LOOKAHEAD_MINUTES: u64 = 366 * 24 * 60;
+
+lazy_static! {
+    static ref JOBS: std::sync::Mutex<Vec<(Job, Status)>> = std::sync::Mutex::new(Vec::new());
+}
+
+/// When a job runs, from a 5 fields cron expression (minute, hour, day of month, month, day of
+/// week), in UTC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // as in cron, a job runs on either day when both are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    /// Accepts `*`, values, ranges (`1-5`), steps (`*/15`, `0-30/10`), lists of them (`1,15`)
+    /// and the `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shorthands.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "Invalid schedule `{}`, expected 5 fields: minute hour day month weekday",
+                expression
+            ));
+        };
+
+        // sunday is both 0 and 7
+        let mut weekdays_set = parse_field(weekdays, 0, 7)?;
+        if weekdays_set & 1 << 7 != 0 {
+            weekdays_set = (weekdays_set | 1) & !(1 << 7);
+        }
+
+        Ok(Schedule {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekdays_set,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+
+    /// Whether the minute starting at `timestamp` (seconds since the unix epoch) is scheduled.
+    pub fn matches(&self, timestamp: u64) -> bool {
+        let minute = timestamp / 60 % 60;
+        let hour = timestamp / 3600 % 24;
+        let days = timestamp / 86400;
+        let (_, month, day) = civil_date(days);
+        // 1970-01-01 was a thursday
+        let weekday = (days + 4) % 7;
+
+        let day_matches = self.days & 1 << day != 0;
+        let weekday_matches = self.weekdays & 1 << weekday != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+
+        self.minutes & 1 << minute != 0
+            && self.hours & 1 << hour != 0
+            && self.months & 1 << month != 0
+            && day_matches
+    }
+
+    /// The first scheduled minute strictly after `timestamp`, in seconds.
+    pub fn next(&self, timestamp: u64) -> Option<u64> {
+        let start = timestamp / 60 + 1;
+        (start..start + LOOKAHEAD_MINUTES)
+            .map(|minute| minute * 60)
+            .find(|&minute| self.matches(minute))
+    }
+}
+
+// a bit set of the allowed values
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let invalid = || {
+        format!("Invalid schedule field `{}`, expected values from {} to {}", field, min, max)
+    };
+    let value = |value: &str| {
+        value
+            .parse::<u64>()
+            .ok()
+            .filter(|value| (min..=max).contains(value))
+            .ok_or_else(invalid)
+    };
+
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse::<u64>().ok().filter(|step| *step > 0);
+                (range, step.ok_or_else(invalid)?)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/10` means from 5 to the end, every 10
+                None if part.contains('/') => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+// English names for dates in mail and HTTP headers, the weekdays starting with 1970-01-01's
+pub(crate) const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+pub(crate) const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// (year, month, day) of a day counted from 1970-01-01, after Howard Hinnant's `civil_from_days`
+pub(crate) fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Which mails a job works on: the filters of `GET /mails`, plus `older_than=<minutes>`.
+#[derive(Clone, Debug)]
+pub struct Selection {
+    filter: MailFilter,
+    older_than: Option<u64>,
+}
+
+impl Selection {
+    fn parse(query: &str) -> Result<Self, String> {
+        let query: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        let older_than = query
+            .get("older_than")
+            .map(|minutes| {
+                minutes
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid older_than `{}`, expected minutes", minutes))
+            })
+            .transpose()?;
+        Ok(Selection {
+            filter: MailFilter::from_query(&query)?,
+            older_than,
+        })
+    }
+
+    fn select(&self, db: &Store) -> Result<Vec<u128>, SharedError> {
+        let max_age = self.older_than.map(|minutes| minutes as u128 * 60 * 1000);
+        let now = now_millis();
+
+        let mut ids = Vec::new();
+        for result in summary::tree(db)?.iter() {
+            let (key, data) = result?;
+            let summary: MailSummary = bincode::deserialize(&data)?;
+            if max_age.is_some_and(|max_age| now.saturating_sub(summary.timestamp) <= max_age) {
+                continue;
+            }
+            if self.filter.matches_summary(&summary)
+                && self.filter.matches_search(&summary, || {
+                    db.get(&key)
+                        .ok()
+                        .flatten()
+                        .and_then(|data| bincode::deserialize::<Mail>(&data).ok())
+                        .map(|mail| mail.data)
+                })
+            {
+                ids.push(summary.id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// Deletes the selected mails
+    Purge(Selection),
+    /// Flushes the database, like `POST /admin/flush`
+    Flush,
+    /// Writes the selected mails to a zip of `.eml` files in the directory, then deletes them
+    Archive(PathBuf, Selection),
+}
+
+/// A scheduled maintenance job, from `--job "<schedule> <action> [arguments]"`.
+#[derive(Clone, Debug)]
+pub struct Job {
+    // as given, to tell the jobs apart in `/info`
+    pub spec: String,
+    pub schedule: Schedule,
+    pub action: Action,
+}
+
+/// Parses e.g. `0 3 * * * purge older_than=1440&to=example.com`,
+/// `@daily archive /var/backups older_than=10080` or `*/30 * * * * flush`.
+pub fn parse(spec: &str) -> Result<Job, String> {
+    let spec = spec.trim();
+    let fields = if spec.starts_with('@') { 1 } else { 5 };
+    let mut words = Vec::new();
+    let mut argument = spec;
+    while words.len() <= fields && !argument.is_empty() {
+        let (word, rest) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+        words.push(word);
+        argument = rest.trim_start();
+    }
+    if words.len() <= fields {
+        return Err(format!("Invalid job `{}`, expected `<schedule> <action> [arguments]`", spec));
+    }
+
+    let schedule = Schedule::parse(&words[..fields].join(" "))?;
+    let action = match words[fields] {
+        "purge" => Action::Purge(Selection::parse(argument)?),
+        "flush" if argument.is_empty() => Action::Flush,
+        "archive" if !argument.is_empty() => {
+            let (dir, query) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+            Action::Archive(PathBuf::from(dir), Selection::parse(query.trim())?)
+        }
+        action => {
+            return Err(format!(
+                "Invalid job action `{}`, expected `purge [filters]`, `flush` or `archive <dir> [filters]`",
+                action
+            ))
+        }
+    };
+
+    Ok(Job {
+        spec: spec.to_string(),
+        schedule,
+        action,
+    })
+}
+
+/// How the last run of a job went, as shown in `/info`.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Status {
+    pub job: String,
+    // milliseconds since the unix epoch
+    pub last_run: Option<u128>,
+    pub last_duration_ms: Option<u128>,
+    pub last_result: Option<String>,
+    pub last_error: Option<String>,
+    pub next_run: Option<u128>,
+}
+
+pub fn init(jobs: Vec<Job>) {
+    *JOBS.lock().unwrap() = jobs
+        .into_iter()
+        .map(|job| {
+            let status = Status {
+                job: job.spec.clone(),
+                ..Status::default()
+            };
+            (job, status)
+        })
+        .collect();
+}
+
+pub fn statuses() -> Vec<Status> {
+    let now = now_millis() as u64 / 1000;
+    JOBS.lock()
+        .unwrap()
+        .iter()
+        .map(|(job, status)| Status {
+            next_run: job.schedule.next(now).map(|next| next as u128 * 1000),
+            ..status.clone()
+        })
+        .collect()
+}
+
+/// Runs a job now, and returns what it did.
+pub async fn run(job: &Job, db: &Store) -> Result<String, SharedError> {
+    match &job.action {
+        Action::Purge(selection) => {
+            let ids = selection.select(db)?;
+            remove(db, &ids)?;
+            Ok(format!("deleted {} mails", ids.len()))
+        }
+        Action::Flush => {
+            let size_before = db.size_on_disk()?;
+            db.flush_async().await?;
+            let size_after = db.size_on_disk()?;
+            Ok(format!("{} bytes on disk, down from {}", size_after, size_before))
+        }
+        Action::Archive(dir, selection) => {
+            let ids = selection.select(db)?;
+            if ids.is_empty() {
+                return Ok("no mail to archive".to_string());
+            }
+            let path = dir.join(format!("mail-sink-{}.zip", now_millis()));
+            archive(db, &ids, &path)?;
+            // only once the archive is safely written
+            remove(db, &ids)?;
+            Ok(format!("archived {} mails to {}", ids.len(), path.display()))
+        }
+    }
+}
+
+fn archive(db: &Store, ids: &[u128], path: &Path) -> Result<(), SharedError> {
+    let mut zip = zip::ZipWriter::new(File::create(path)?);
+    for id in ids {
+        let Some(mail) = db.get_mail(*id)? else {
+            continue;
+        };
+        zip.start_file(format!("{}.eml", id), zip::write::SimpleFileOptions::default())?;
+        zip.write_all(&mail.data)?;
+    }
+    zip.finish()?.sync_all()?;
+    Ok(())
+}
+
+fn remove(db: &Store, ids: &[u128]) -> Result<(), SharedError> {
+    for id in ids {
+        db.delete(*id)?;
+    }
+    Ok(())
+}
+
+/// Runs the jobs due at the start of every minute.
+pub async fn run_scheduler(db: Store) {
+    loop {
+        let now = now_millis();
+        let next_minute = (now / 60_000 + 1) * 60_000;
+        tokio::time::sleep(Duration::from_millis((next_minute - now) as u64)).await;
+
+        let minute = next_minute as u64 / 1000;
+        let due: Vec<(usize, Job)> = JOBS
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, (job, _))| job.schedule.matches(minute))
+            .map(|(index, (job, _))| (index, job.clone()))
+            .collect();
+
+        for (index, job) in due {
+            let started = now_millis();
+            let result = run(&job, &db).await;
+            if let Err(e) = &result {
+                report::report(
+                    report::Kind::Storage,
+                    &format!("Job `{}` failed: {}", job.spec, e),
+                );
+            }
+
+            let mut jobs = JOBS.lock().unwrap();
+            // the jobs may have been reloaded while it ran
+            let Some((_, status)) = jobs.get_mut(index).filter(|(current, _)| current.spec == job.spec)
+            else {
+                continue;
+            };
+            status.last_run = Some(started);
+            status.last_duration_ms = Some(now_millis().saturating_sub(started));
+            match result {
+                Ok(result) => {
+                    status.last_result = Some(result);
+                    status.last_error = None;
+                }
+                Err(e) => {
+                    status.last_result = None;
+                    status.last_error = Some(e.to_string());
+                }
+            }
+        }
+    }
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0)
+}
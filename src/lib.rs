@@ -0,0 +1,118 @@
+//! An SMTP server that keeps every mail it receives, with an HTTP API and a panel to read them,
+//! for testing the mails an application sends.
+//!
+//! Besides the `mail-sink` binary, [`MailSink`] runs one inside another program, e.g. in its
+//! `#[tokio::test]` functions.
+mod bench;
+mod cli;
+mod config;
+mod config_file;
+mod diff;
+mod dns;
+mod duplicates;
+mod events;
+mod export;
+mod filter;
+mod http;
+mod ingest;
+mod jobs;
+mod limits;
+mod logging;
+mod memory;
+mod metrics;
+mod pop3;
+mod relay;
+mod report;
+mod retention;
+mod rules;
+mod session;
+mod shutdown;
+mod sink;
+mod smtp;
+mod snapshot;
+mod snowflake;
+mod stats;
+mod storage;
+mod store;
+mod summary;
+mod tests;
+mod tls;
+mod upload;
+mod webhooks;
+
+pub use crate::sink::{AlreadyRunning, MailSink, Mails, RunningSink};
+pub use crate::smtp::mail::{Attachment, Header, Mail};
+
+use crate::cli::*;
+use crate::store::Storage;
+use clap::CommandFactory;
+use clap_help::Printer;
+use std::error::Error;
+use tracing::{error, info};
+
+pub type SharedError = Box<dyn Error + Send + Sync>;
+
+/// What the `mail-sink` binary does: parses the command line (with `--config` and the
+/// `MAILSINK_*` variables), serves until SIGINT or SIGTERM, then shuts down.
+pub async fn run() -> Result<(), SharedError> {
+    // the uptime counts from here
+    metrics::started();
+    let args = match config_file::load(std::env::args_os().collect()) {
+        Ok(args) => args,
+        Err(e) => match e.downcast::<clap::Error>() {
+            Ok(e) => e.exit(),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(2);
+            }
+        },
+    };
+    if args.help {
+        Printer::new(Args::command())
+            .with("introduction", INTRO)
+            .print_help();
+
+        print_api_usage();
+        return Ok(());
+    }
+
+    if let Some(Command::Bench(bench)) = args.command {
+        return bench::run(bench).await;
+    }
+
+    logging::init(&args.log_level, args.log_format)?;
+    let _report_guard = report::init(args.sentry_dsn.clone(), args.error_webhook.clone())?;
+    // a failing database is reopened by restarting the process
+    if args.storage == Storage::Disk {
+        storage::set_path(args.db_path.clone());
+    }
+    let mut sink = sink::start(&args).await?;
+
+    tokio::task::spawn(config_file::reload_on_hangup(std::env::args_os().collect()));
+    let scheme = if args.http_tls_cert.is_some() { "https" } else { "http" };
+    info!("Panel: {}://localhost:{}/login", scheme, sink.http_addr().port());
+
+    // run until stopped, the services themselves should never complete
+    tokio::select! {
+        result = sink.failed() => {
+            result?;
+            error!("All services have completed unexpectedly ...");
+        }
+        signal = shutdown::signalled() => {
+            info!(signal = signal?, "Shutting down");
+            sink.shutdown().await?;
+            info!("Stopped");
+        }
+        _ = storage::reopen_needed() => {
+            // the open connections finish and the ingest queue empties as on SIGTERM, without the
+            // flush that a failed database never completes
+            sink.drain().await;
+            info!("Restarting");
+            let e = storage::restart();
+            report::report(report::Kind::Storage, &format!("Failed to restart: {}", e));
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
+}
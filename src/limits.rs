@@ -0,0 +1,162 @@
+use crate::config;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+// past this many clients tracked, those that haven't been seen for a second are forgotten
+const BUCKETS_SWEEP_SIZE: usize = 1024;
+// a refused client that doesn't read its reply isn't waited for
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref CONNECTIONS: Mutex<Connections> = Mutex::new(Connections::default());
+    pub(crate) static ref BUCKETS: Mutex<Buckets> = Mutex::new(Buckets::default());
+}
+
+/// The open connections, overall and per client IP.
+#[derive(Default)]
+pub struct Connections {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+impl Connections {
+    /// Counts a connection from `ip`, unless `max` or `max_per_ip` are reached already.
+    pub fn open(&mut self, ip: IpAddr, max: Option<usize>, max_per_ip: Option<usize>) -> Result<(), Refusal> {
+        if max.is_some_and(|max| self.total >= max) {
+            return Err(Refusal::Total);
+        }
+        let from_ip = self.per_ip.get(&ip).copied().unwrap_or(0);
+        if max_per_ip.is_some_and(|max| from_ip >= max) {
+            return Err(Refusal::PerIp);
+        }
+        self.total += 1;
+        *self.per_ip.entry(ip).or_insert(0) += 1;
+        Ok(())
+    }
+
+    pub fn close(&mut self, ip: IpAddr) {
+        if let Some(count) = self.per_ip.get_mut(&ip) {
+            self.total -= 1;
+            *count -= 1;
+            if *count == 0 {
+                self.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Why a connection was refused, see [`connect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// `--max-connections`
+    Total,
+    /// `--max-connections-per-ip`
+    PerIp,
+}
+
+/// An open connection counted against `--max-connections` and `--max-connections-per-ip`, until
+/// dropped.
+pub struct Permit {
+    ip: IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        CONNECTIONS.lock().unwrap().close(self.ip);
+    }
+}
+
+/// Counts a new SMTP, POP3 or HTTP connection from `ip`, unless there are too many already.
+pub fn connect(ip: IpAddr) -> Result<Permit, Refusal> {
+    let config = config::get();
+    CONNECTIONS
+        .lock()
+        .unwrap()
+        .open(ip, config.max_connections, config.max_connections_per_ip)?;
+    Ok(Permit { ip })
+}
+
+/// Tells a refused client why, if it listens, then closes its connection.
+pub async fn refuse<S: AsyncWrite + Unpin>(mut socket: S, reply: &[u8]) {
+    let _ = tokio::time::timeout(REFUSAL_TIMEOUT, async {
+        socket.write_all(reply).await?;
+        socket.shutdown().await
+    })
+    .await;
+}
+
+/// What is limited per client IP and per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rate {
+    /// `--smtp-rate`
+    SmtpCommands,
+    /// `--http-rate`
+    HttpRequests,
+}
+
+impl Rate {
+    fn limit(self) -> Option<u32> {
+        let config = config::get();
+        match self {
+            Rate::SmtpCommands => config.smtp_rate,
+            Rate::HttpRequests => config.http_rate,
+        }
+    }
+}
+
+// a token bucket holding up to a second worth of requests, so that short bursts go through
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The requests (or commands) each client IP can still send, per [`Rate`].
+#[derive(Default)]
+pub struct Buckets(HashMap<(Rate, IpAddr), Bucket>);
+
+impl Buckets {
+    /// Whether `ip` can send one more at `now`, `limit` per second.
+    pub fn take(&mut self, rate: Rate, ip: IpAddr, limit: u32, now: Instant) -> bool {
+        let limit = limit as f64;
+        if self.0.len() >= BUCKETS_SWEEP_SIZE {
+            // full again by now, forgetting them changes nothing
+            self.0.retain(|_, bucket| now.duration_since(bucket.updated) < Duration::from_secs(1));
+        }
+        let bucket = self.0.entry((rate, ip)).or_insert(Bucket {
+            tokens: limit,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit).min(limit);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    pub fn clear(&mut self) -> usize {
+        let count = self.0.len();
+        self.0.clear();
+        count
+    }
+}
+
+/// Forgets what the clients sent so far, so that none is throttled anymore, e.g. on `POST /reset`.
+/// Returns how many clients were tracked.
+pub fn clear() -> usize {
+    BUCKETS.lock().unwrap().clear()
+}
+
+/// Whether `ip` can send one more SMTP command (or HTTP request) right now.
+pub fn allow(rate: Rate, ip: IpAddr) -> bool {
+    match rate.limit() {
+        Some(limit) => BUCKETS.lock().unwrap().take(rate, ip, limit, Instant::now()),
+        None => true,
+    }
+}
@@ -0,0 +1,23 @@
+use crate::SharedError;
+use clap::ValueEnum;
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are written to stdout.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    // one JSON object per line, with the fields of the spans, for Loki and the like
+    Json,
+}
+
+/// Installs the global subscriber. `level` is a level (`info`) or a filter per module
+/// (`info,mail_sink::smtp=debug`).
+pub fn init(level: &str, format: Format) -> Result<(), SharedError> {
+    let filter = EnvFilter::try_new(level).map_err(|e| format!("Invalid log level `{}`: {}", level, e))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        Format::Text => builder.try_init(),
+        Format::Json => builder.json().flatten_event(true).with_current_span(true).try_init(),
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// bytes held by mails being received or waiting to be stored, and by HTTP request bodies
+static USED: AtomicUsize = AtomicUsize::new(0);
+// 0 means no budget
+static BUDGET: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_budget(budget: Option<usize>) {
+    BUDGET.store(budget.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn budget() -> Option<usize> {
+    match BUDGET.load(Ordering::Relaxed) {
+        0 => None,
+        budget => Some(budget),
+    }
+}
+
+pub fn used() -> usize {
+    USED.load(Ordering::Relaxed)
+}
+
+/// Whether new work should be turned away until some memory is released.
+pub fn exceeded() -> bool {
+    budget().is_some_and(|budget| used() >= budget)
+}
+
+/// Memory accounted for on behalf of one message or request, released when dropped.
+#[derive(Debug, Default)]
+pub struct Reservation {
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn new() -> Self {
+        Reservation { bytes: 0 }
+    }
+
+    /// Reserves `bytes` more, or nothing and false when that would go over the budget.
+    pub fn grow(&mut self, bytes: usize) -> bool {
+        let budget = BUDGET.load(Ordering::Relaxed);
+        let reserved = USED.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            let used = used.checked_add(bytes)?;
+            (budget == 0 || used <= budget).then_some(used)
+        });
+        if reserved.is_ok() {
+            self.bytes += bytes;
+        }
+        reserved.is_ok()
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        USED.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
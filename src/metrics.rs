@@ -0,0 +1,303 @@
+use crate::{memory, shutdown};
+use lazy_static::lazy_static;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // set when first read, which main does right away
+    static ref STARTED: SystemTime = SystemTime::now();
+}
+
+/// When the process started, for the uptime.
+pub fn started() -> SystemTime {
+    *STARTED
+}
+
+/// Only goes up, reset when the process restarts.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Goes up and down.
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Gauge(AtomicI64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts observations per bucket, by upper bound in seconds.
+pub struct Histogram {
+    bounds: &'static [f64],
+    // one more than the bounds, for +Inf
+    buckets: [AtomicU64; 16],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new(bounds: &'static [f64]) -> Self {
+        assert!(bounds.len() < 16);
+        Histogram {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; 16],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, text: &mut String, name: &str, help: &str) {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        // buckets are cumulative in the text format
+        let mut count = 0;
+        for (i, bucket) in self.buckets[..=self.bounds.len()].iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let bound = match self.bounds.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(text, "{}_sum {}\n{}_count {}", name, sum, name, count);
+    }
+}
+
+// from a millisecond to 10 seconds
+const LATENCY_BOUNDS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub static INGEST_QUEUE_DEPTH: Gauge = Gauge::new();
+pub static INGEST_QUEUE_CAPACITY: Gauge = Gauge::new();
+pub static INGEST_REJECTED: Counter = Counter::new();
+pub static MAILS_STORED: Counter = Counter::new();
+pub static DUPLICATES: Counter = Counter::new();
+pub static RULE_DROPPED: Counter = Counter::new();
+pub static RULE_FAILURES: Counter = Counter::new();
+pub static WEBHOOKS_DELIVERED: Counter = Counter::new();
+pub static WEBHOOK_FAILURES: Counter = Counter::new();
+pub static RELAY_DELIVERED: Counter = Counter::new();
+pub static RELAY_FAILURES: Counter = Counter::new();
+pub static SMTP_AUTH_FAILURES: Counter = Counter::new();
+pub static POP3_AUTH_FAILURES: Counter = Counter::new();
+pub static CHAOS_FAULTS: Counter = Counter::new();
+pub static RECIPIENTS_REJECTED: Counter = Counter::new();
+pub static MESSAGES_TOO_LARGE: Counter = Counter::new();
+pub static MEMORY_REJECTED: Counter = Counter::new();
+pub static STORAGE_FAILING: Gauge = Gauge::new();
+pub static STORAGE_FAILURES: Counter = Counter::new();
+pub static STORAGE_REJECTED: Counter = Counter::new();
+pub static HTTP_TIMEOUTS: Counter = Counter::new();
+pub static HTTP_HEAD_TOO_LARGE: Counter = Counter::new();
+pub static CONNECTIONS_REJECTED: Counter = Counter::new();
+pub static SMTP_THROTTLED: Counter = Counter::new();
+pub static HTTP_THROTTLED: Counter = Counter::new();
+pub static INGEST_LATENCY: Histogram = Histogram::new(LATENCY_BOUNDS);
+
+/// Every metric, in the Prometheus text format.
+pub fn render() -> String {
+    let gauges: [(&str, &str, i64); 7] = [
+        (
+            "mail_sink_ingest_queue_depth",
+            "Mails accepted over SMTP and waiting to be stored",
+            INGEST_QUEUE_DEPTH.get(),
+        ),
+        (
+            "mail_sink_ingest_queue_capacity",
+            "Mails the ingestion queue holds before SMTP answers 452",
+            INGEST_QUEUE_CAPACITY.get(),
+        ),
+        (
+            "mail_sink_memory_in_flight_bytes",
+            "Memory held by mails being received or stored and by HTTP request bodies",
+            memory::used() as i64,
+        ),
+        (
+            "mail_sink_memory_budget_bytes",
+            "Memory budget past which load is shed, 0 when there is none",
+            memory::budget().unwrap_or(0) as i64,
+        ),
+        (
+            "mail_sink_storage_failing",
+            "1 while writes to the database fail and mails are refused",
+            STORAGE_FAILING.get(),
+        ),
+        (
+            "mail_sink_start_time_seconds",
+            "When the process started, in seconds since the Unix epoch",
+            started().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64,
+        ),
+        (
+            "mail_sink_connections",
+            "Open SMTP, POP3 and HTTP connections",
+            shutdown::connections() as i64,
+        ),
+    ];
+    let counters: [(&str, &str, &Counter); 22] = [
+        (
+            "mail_sink_ingest_rejected_total",
+            "Mails refused with 452 because the ingestion queue was full",
+            &INGEST_REJECTED,
+        ),
+        (
+            "mail_sink_mails_stored_total",
+            "Mails written to the database",
+            &MAILS_STORED,
+        ),
+        (
+            "mail_sink_duplicates_total",
+            "Mails received again, by Message-ID or content, whatever the --duplicates policy",
+            &DUPLICATES,
+        ),
+        (
+            "mail_sink_rule_dropped_total",
+            "Mails acknowledged but not stored because every recipient matched a drop rule",
+            &RULE_DROPPED,
+        ),
+        (
+            "mail_sink_rule_failures_total",
+            "Forwards of the rules that failed",
+            &RULE_FAILURES,
+        ),
+        (
+            "mail_sink_webhooks_delivered_total",
+            "Mails posted to a webhook",
+            &WEBHOOKS_DELIVERED,
+        ),
+        (
+            "mail_sink_webhook_failures_total",
+            "Mails that could not be posted to a webhook, even after retrying",
+            &WEBHOOK_FAILURES,
+        ),
+        (
+            "mail_sink_relay_delivered_total",
+            "Mails delivered through the --relay upstream",
+            &RELAY_DELIVERED,
+        ),
+        (
+            "mail_sink_relay_failures_total",
+            "Mails the --relay upstream refused, or that still failed after retrying",
+            &RELAY_FAILURES,
+        ),
+        (
+            "mail_sink_smtp_auth_failures_total",
+            "SMTP AUTH attempts refused with 535 because the credentials didn't match --smtp-user",
+            &SMTP_AUTH_FAILURES,
+        ),
+        (
+            "mail_sink_pop3_auth_failures_total",
+            "POP3 logins refused because the credentials didn't match --smtp-user",
+            &POP3_AUTH_FAILURES,
+        ),
+        (
+            "mail_sink_chaos_faults_total",
+            "Replies, delays and dropped connections injected into SMTP sessions by --chaos",
+            &CHAOS_FAULTS,
+        ),
+        (
+            "mail_sink_recipients_rejected_total",
+            "RCPT TO refused with 550 by --accept-domain, --accept-recipient or --reject-recipient",
+            &RECIPIENTS_REJECTED,
+        ),
+        (
+            "mail_sink_messages_too_large_total",
+            "Mails refused with 552 because they were over --max-message-size",
+            &MESSAGES_TOO_LARGE,
+        ),
+        (
+            "mail_sink_memory_rejected_total",
+            "SMTP transactions (452) and HTTP requests (503) refused over the memory budget",
+            &MEMORY_REJECTED,
+        ),
+        (
+            "mail_sink_storage_failures_total",
+            "Mails that could not be written to the database",
+            &STORAGE_FAILURES,
+        ),
+        (
+            "mail_sink_storage_rejected_total",
+            "SMTP transactions refused with 452 while the storage was failing",
+            &STORAGE_REJECTED,
+        ),
+        (
+            "mail_sink_http_timeouts_total",
+            "HTTP requests answered 408 because the client sent them too slowly",
+            &HTTP_TIMEOUTS,
+        ),
+        (
+            "mail_sink_http_head_too_large_total",
+            "HTTP requests answered 431 because of a too large request line or headers",
+            &HTTP_HEAD_TOO_LARGE,
+        ),
+        (
+            "mail_sink_connections_rejected_total",
+            "Connections closed right away because of --max-connections or --max-connections-per-ip",
+            &CONNECTIONS_REJECTED,
+        ),
+        (
+            "mail_sink_smtp_throttled_total",
+            "SMTP sessions closed with 421 for sending more commands per second than --smtp-rate",
+            &SMTP_THROTTLED,
+        ),
+        (
+            "mail_sink_http_throttled_total",
+            "HTTP requests answered 429 for coming faster than --http-rate",
+            &HTTP_THROTTLED,
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, help, value) in gauges {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+    }
+    for (name, help, counter) in counters {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, counter.get());
+    }
+    INGEST_LATENCY.render(
+        &mut text,
+        "mail_sink_ingest_latency_seconds",
+        "Time from the end of DATA to the mail being written to the database",
+    );
+    text
+}
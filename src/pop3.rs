@@ -0,0 +1,202 @@
+use crate::smtp::auth;
+use crate::store::Store;
+use crate::{metrics, report, shutdown, summary, SharedError};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::{debug, info};
+
+pub(crate) const TOO_MANY_CONNECTIONS: &[u8] = b"-ERR Too many connections, try again later\r\n";
+const CAPABILITIES: &[u8] = b"+OK Capability list follows\r\nUSER\r\nUIDL\r\n.\r\n";
+
+// a mail of the maildrop, as of the login
+struct Message {
+    id: u128,
+    size: u64,
+    deleted: bool,
+}
+
+/// Serves the stored mails over POP3 (RFC 1939), oldest first. The login takes the
+/// `--smtp-user` credentials, or any without them.
+pub(crate) async fn handle_client(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    db: Store,
+) -> Result<(), SharedError> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    writer.write_all(b"+OK mail-sink POP3 ready\r\n").await?;
+
+    let mut username = None;
+    // None until logged in
+    let mut maildrop: Option<Vec<Message>> = None;
+
+    loop {
+        let mut line = String::new();
+        let bytes_read = tokio::select! {
+            biased;
+            read = reader.read_line(&mut line) => read?,
+            _ = shutdown::stopped() => {
+                reply(&mut writer, "-ERR Server shutting down").await?;
+                return Ok(());
+            }
+        };
+        if bytes_read == 0 {
+            // closed without QUIT, nothing is deleted
+            return Ok(());
+        }
+        let (command, argument) = line.trim_end().split_once(' ').unwrap_or((line.trim_end(), ""));
+        let command = command.to_uppercase();
+        // never the argument, which is the password for PASS
+        debug!(command, "POP3 command");
+
+        let Some(messages) = maildrop.as_mut() else {
+            match command.as_str() {
+                "CAPA" => writer.write_all(CAPABILITIES).await?,
+                "USER" if !argument.is_empty() => {
+                    username = Some(argument.to_string());
+                    reply(&mut writer, "+OK").await?;
+                }
+                "PASS" => match username.take() {
+                    Some(user) if auth::check(&user, argument) => {
+                        let messages = load(&db)?;
+                        info!(user, messages = messages.len(), "POP3 login");
+                        reply(&mut writer, &format!("+OK {} messages", messages.len())).await?;
+                        maildrop = Some(messages);
+                    }
+                    Some(_) => {
+                        metrics::POP3_AUTH_FAILURES.inc();
+                        reply(&mut writer, "-ERR [AUTH] Invalid credentials").await?;
+                    }
+                    None => reply(&mut writer, "-ERR USER first").await?,
+                },
+                "QUIT" => {
+                    reply(&mut writer, "+OK Bye").await?;
+                    return Ok(());
+                }
+                _ => reply(&mut writer, "-ERR Log in first").await?,
+            }
+            continue;
+        };
+
+        match command.as_str() {
+            "STAT" => {
+                let (count, size) = messages
+                    .iter()
+                    .filter(|message| !message.deleted)
+                    .fold((0, 0), |(count, size), message| (count + 1, size + message.size));
+                reply(&mut writer, &format!("+OK {} {}", count, size)).await?;
+            }
+            "LIST" | "UIDL" => {
+                // the size for LIST, the mail id, which never changes, for UIDL
+                let describe = |message: &Message| match command.as_str() {
+                    "LIST" => message.size.to_string(),
+                    _ => message.id.to_string(),
+                };
+                if argument.is_empty() {
+                    let mut listing = String::from("+OK\r\n");
+                    for (index, message) in messages.iter().enumerate() {
+                        if !message.deleted {
+                            listing.push_str(&format!("{} {}\r\n", index + 1, describe(message)));
+                        }
+                    }
+                    listing.push_str(".\r\n");
+                    writer.write_all(listing.as_bytes()).await?;
+                } else {
+                    match find(messages, argument) {
+                        Some(message) => {
+                            reply(&mut writer, &format!("+OK {} {}", argument, describe(message))).await?
+                        }
+                        None => reply(&mut writer, "-ERR No such message").await?,
+                    }
+                }
+            }
+            "RETR" => {
+                let mail = match find(messages, argument) {
+                    Some(message) => db.get_mail(message.id)?,
+                    None => None,
+                };
+                match mail {
+                    Some(mail) => {
+                        let body = multiline(&mail.data);
+                        reply(&mut writer, &format!("+OK {} octets", mail.data.len())).await?;
+                        writer.write_all(&body).await?;
+                    }
+                    // removed since the login, through the API or the retention
+                    None => reply(&mut writer, "-ERR No such message").await?,
+                }
+            }
+            "DELE" => match find(messages, argument) {
+                Some(message) => {
+                    message.deleted = true;
+                    reply(&mut writer, "+OK Deleted").await?;
+                }
+                None => reply(&mut writer, "-ERR No such message").await?,
+            },
+            "RSET" => {
+                messages.iter_mut().for_each(|message| message.deleted = false);
+                reply(&mut writer, &format!("+OK {} messages", messages.len())).await?;
+            }
+            "NOOP" => reply(&mut writer, "+OK").await?,
+            "CAPA" => writer.write_all(CAPABILITIES).await?,
+            "QUIT" => {
+                let deleted = remove(&db, messages);
+                reply(&mut writer, &format!("+OK {} messages deleted", deleted)).await?;
+                return Ok(());
+            }
+            _ => reply(&mut writer, "-ERR Unknown command").await?,
+        }
+    }
+}
+
+async fn reply(writer: &mut (impl AsyncWrite + Unpin), line: &str) -> std::io::Result<()> {
+    writer.write_all(format!("{}\r\n", line).as_bytes()).await
+}
+
+fn load(db: &Store) -> Result<Vec<Message>, SharedError> {
+    let mut messages = Vec::new();
+    for result in summary::oldest(db)? {
+        let (id, size) = result?;
+        messages.push(Message {
+            id,
+            size,
+            deleted: false,
+        });
+    }
+    Ok(messages)
+}
+
+// a message by its number, counted from 1, unless deleted
+fn find<'a>(messages: &'a mut [Message], number: &str) -> Option<&'a mut Message> {
+    let index = number.parse::<usize>().ok()?.checked_sub(1)?;
+    messages.get_mut(index).filter(|message| !message.deleted)
+}
+
+// the mails marked with DELE, removed once the client says QUIT
+fn remove(db: &Store, messages: &[Message]) -> usize {
+    let mut count = 0;
+    for message in messages.iter().filter(|message| message.deleted) {
+        match db.delete(message.id) {
+            Ok(_) => count += 1,
+            Err(e) => report::report(
+                report::Kind::Storage,
+                &format!("Failed to remove mail {} over POP3: {}", message.id, e),
+            ),
+        }
+    }
+    count
+}
+
+/// The raw message as a POP3 multi-line response: CRLF line endings, lines starting with a dot
+/// doubled, and a lone dot at the end.
+pub fn multiline(data: &[u8]) -> Vec<u8> {
+    let mut response = Vec::with_capacity(data.len() + 64);
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.starts_with(b".") {
+            response.push(b'.');
+        }
+        response.extend_from_slice(line);
+        response.extend_from_slice(b"\r\n");
+    }
+    response.extend_from_slice(b".\r\n");
+    response
+}
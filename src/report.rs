@@ -0,0 +1,109 @@
+use crate::SharedError;
+use serde_json::json;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// release tag attached to every report, e.g. `mail-sink@0.1.0`
+pub const RELEASE: &str = concat!(env!("CARGO_PKG_NAME"), "@", env!("CARGO_PKG_VERSION"));
+
+static ERROR_WEBHOOK: OnceLock<String> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    Panic,
+    Storage,
+    // a mail forwarded or posted by the rules
+    Delivery,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Panic => write!(f, "panic"),
+            Kind::Storage => write!(f, "storage"),
+            Kind::Delivery => write!(f, "delivery"),
+        }
+    }
+}
+
+/// Sets up Sentry (from `--sentry-dsn` or the `SENTRY_DSN` env var) and the generic error
+/// webhook. The returned guard must be kept alive for the whole program so that pending
+/// events are flushed on exit.
+pub fn init(
+    sentry_dsn: Option<String>,
+    error_webhook: Option<String>,
+) -> Result<sentry::ClientInitGuard, SharedError> {
+    let dsn = match sentry_dsn {
+        Some(dsn) => Some(dsn.parse().map_err(|e| format!("Invalid Sentry DSN: {}", e))?),
+        None => None,
+    };
+
+    // sentry installs its own panic hook, it only does something if a DSN is configured
+    let mut options = sentry::ClientOptions::default();
+    options.dsn = dsn;
+    options.release = Some(RELEASE.into());
+    let guard = sentry::init(options);
+
+    if let Some(url) = error_webhook {
+        let _ = ERROR_WEBHOOK.set(url);
+
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // we are already unwinding, so post synchronously instead of spawning a thread
+            post_webhook(&webhook_payload(Kind::Panic, &info.to_string()));
+            previous_hook(info);
+        }));
+    }
+
+    Ok(guard)
+}
+
+/// Reports a non-fatal failure to every configured backend. Never blocks the caller.
+pub fn report(kind: Kind, message: &str) {
+    tracing::error!(kind = %kind, "{}", message);
+
+    sentry::with_scope(
+        |scope| scope.set_tag("kind", kind),
+        || sentry::capture_message(message, sentry::Level::Error),
+    );
+
+    if ERROR_WEBHOOK.get().is_some() {
+        let payload = webhook_payload(kind, message);
+        std::thread::spawn(move || post_webhook(&payload));
+    }
+}
+
+fn webhook_payload(kind: Kind, message: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    json!({
+        "kind": kind.to_string(),
+        "message": message,
+        "release": RELEASE,
+        "timestamp": timestamp as u64,
+    })
+    .to_string()
+}
+
+fn post_webhook(payload: &str) {
+    let Some(url) = ERROR_WEBHOOK.get() else {
+        return;
+    };
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(10)))
+        .build()
+        .into();
+
+    if let Err(e) = agent
+        .post(url)
+        .header("Content-Type", "application/json")
+        .send(payload)
+    {
+        tracing::warn!(url, error = %e, "Failed to deliver error report");
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{config, report, snowflake, summary, SharedError};
+use crate::store::Store;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Removes every mail older than `lifetime` minutes and returns how many were removed.
+pub fn purge_expired(db: &Store, lifetime: u16) -> Result<usize, SharedError> {
+    let current_millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let max_age = lifetime as u128 * 60 * 1000;
+
+    // oldest first, so the expired ones are all at the start
+    let mut expired = Vec::new();
+    for result in summary::oldest(db)? {
+        let (id, _) = result?;
+        if current_millis.saturating_sub(snowflake::to_timestamp(id)) <= max_age {
+            break;
+        }
+        expired.push(id);
+    }
+
+    Ok(remove(db, expired, "expired"))
+}
+
+/// Evicts the oldest mails until there are at most `max_mails` of them and their raw data takes
+/// at most `max_bytes`. Returns how many were removed.
+pub fn enforce_limits(
+    db: &Store,
+    max_mails: Option<usize>,
+    max_bytes: Option<u64>,
+) -> Result<usize, SharedError> {
+    if max_mails.is_none() && max_bytes.is_none() {
+        return Ok(0);
+    }
+
+    let (mut count, mut bytes) = summary::usage(db)?;
+    let mut evicted = Vec::new();
+    for result in summary::oldest(db)? {
+        let over = max_mails.is_some_and(|max| count > max) || max_bytes.is_some_and(|max| bytes > max);
+        if !over {
+            break;
+        }
+        let (id, size) = result?;
+        evicted.push(id);
+        count -= 1;
+        bytes -= size;
+    }
+
+    Ok(remove(db, evicted, "evicted"))
+}
+
+fn remove(db: &Store, ids: Vec<u128>, reason: &str) -> usize {
+    let mut count = 0;
+    for id in ids {
+        match db.delete(id) {
+            Ok(_) => count += 1,
+            Err(e) => report::report(
+                report::Kind::Storage,
+                &format!("Failed to remove {} mail {}: {}", reason, id, e),
+            ),
+        }
+    }
+    count
+}
+
+/// Applies the retention every minute. Always running, since the retention can be enabled at
+/// runtime from the admin API.
+pub async fn run_cleaner_service(db: Store) {
+    loop {
+        if let Some(lifetime) = config::lifetime() {
+            match purge_expired(&db, lifetime) {
+                Ok(0) => {}
+                Ok(count) => info!(count, "Cleaned {} emails", count),
+                Err(e) => report::report(
+                    report::Kind::Storage,
+                    &format!("Failed to clean expired mails: {}", e),
+                ),
+            }
+        }
+
+        let config = config::get();
+        match enforce_limits(&db, config.max_mails, config.max_db_size.map(|size| size as u64)) {
+            Ok(0) => {}
+            Ok(count) => info!(count, "Evicted {} emails over the retention limits", count),
+            Err(e) => report::report(
+                report::Kind::Storage,
+                &format!("Failed to evict mails over the retention limits: {}", e),
+            ),
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+    }
+}
@@ -0,0 +1,303 @@
+use crate::smtp::mail::Mail;
+use crate::smtp::verification::Authentication;
+use crate::summary::MailSummary;
+use crate::relay::{self, Relay};
+use crate::{bench, metrics, report, webhooks};
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::RwLock;
+use std::time::Duration;
+
+// a relay taking longer than this counts as failed
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref RULES: RwLock<Vec<Rule>> = RwLock::new(Vec::new());
+}
+
+/// What a rule does to the mails sent to the recipients it matches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Keeps the mail, and stops looking at the next rules
+    Store,
+    /// Doesn't keep the mail, still acknowledging it, and stops looking at the next rules
+    Drop,
+    Tag(String),
+    /// Only the first matching namespace is kept
+    Namespace(String),
+    /// Sends a copy to another SMTP server, at `host:port`
+    Forward(String),
+    /// Delivers the mail for real through the `--relay` upstream, with retries
+    Relay,
+    /// POSTs the summary of the mail, as in the list endpoints, to the URL
+    Webhook(String),
+}
+
+/// A recipient pattern and what to do with the mails sent to it, from
+/// `--rule "<pattern> <action> [argument]"`.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    // as given, for the configuration page
+    pub spec: String,
+    // lowercased, `*` matches any run of characters and `?` a single one
+    pattern: String,
+    pub action: Action,
+}
+
+impl Rule {
+    pub fn matches(&self, address: &str) -> bool {
+        glob(self.pattern.as_bytes(), address.to_lowercase().as_bytes())
+    }
+}
+
+/// Parses e.g. `*@example.com drop`, `alerts+*@* tag alerts`, `*@team.test namespace team`,
+/// `*@partner.test forward relay.partner.test:25`, `qa-lead@example.com relay` or
+/// `* webhook https://hooks.test/mail`.
+pub fn parse(spec: &str) -> Result<Rule, String> {
+    let spec = spec.trim();
+    let mut words = spec.split_whitespace();
+    let (Some(pattern), Some(action)) = (words.next(), words.next()) else {
+        return Err(format!("Invalid rule `{}`, expected `<pattern> <action> [argument]`", spec));
+    };
+    let argument = words.next();
+    if words.next().is_some() {
+        return Err(format!("Invalid rule `{}`, an action takes one argument at most", spec));
+    }
+
+    let action = match (action, argument) {
+        ("store", None) => Action::Store,
+        ("drop", None) => Action::Drop,
+        ("tag", Some(tag)) => Action::Tag(tag.to_string()),
+        ("namespace", Some(namespace)) => Action::Namespace(namespace.to_string()),
+        ("forward", Some(relay)) if relay.contains(':') => Action::Forward(relay.to_string()),
+        ("relay", None) => Action::Relay,
+        ("webhook", Some(url)) if url.starts_with("http://") || url.starts_with("https://") => {
+            Action::Webhook(url.to_string())
+        }
+        _ => {
+            return Err(format!(
+                "Invalid rule action `{}`, expected `store`, `drop`, `tag <tag>`, \
+                 `namespace <name>`, `forward <host:port>`, `relay` or `webhook <url>`",
+                spec.strip_prefix(pattern).unwrap_or(spec).trim()
+            ))
+        }
+    };
+
+    Ok(Rule {
+        spec: spec.to_string(),
+        pattern: pattern.to_lowercase(),
+        action,
+    })
+}
+
+// `*` backtracks to the last star only, which is enough without character classes
+pub(crate) fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p.min(pattern.len())..].iter().all(|c| *c == b'*')
+}
+
+/// How a mail is kept, and who sent it, stored in its summary.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Labels {
+    pub tags: Vec<String>,
+    pub namespace: Option<String>,
+    // the SMTP AUTH username, set by the session rather than the rules
+    pub user: Option<String>,
+    // the DKIM and SPF results, also set by the session, see `--verify-auth`
+    pub authentication: Option<Authentication>,
+}
+
+/// The outcome of the rules for a mail.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Route {
+    pub store: bool,
+    pub labels: Labels,
+    // relay -> the recipients forwarded to it
+    pub forward: BTreeMap<String, Vec<String>>,
+    // the recipients delivered to through the upstream
+    pub relay: Vec<String>,
+    pub webhooks: Vec<String>,
+}
+
+pub fn init(rules: Vec<Rule>) {
+    *RULES.write().unwrap() = rules;
+}
+
+/// Runs every recipient through the rules, in order. A mail is kept unless all of its
+/// recipients end up on `drop`.
+pub fn route(to: &HashSet<String>) -> Route {
+    evaluate(&RULES.read().unwrap(), to)
+}
+
+pub fn evaluate(rules: &[Rule], to: &HashSet<String>) -> Route {
+    let mut route = Route {
+        store: false,
+        labels: Labels::default(),
+        forward: BTreeMap::new(),
+        relay: Vec::new(),
+        webhooks: Vec::new(),
+    };
+    if rules.is_empty() {
+        route.store = true;
+        return route;
+    }
+
+    // sorted, so that the first namespace doesn't depend on the order of a `HashSet`
+    let mut recipients: Vec<&String> = to.iter().collect();
+    recipients.sort();
+    for recipient in recipients {
+        let mut store = true;
+        for rule in rules.iter().filter(|rule| rule.matches(recipient)) {
+            match &rule.action {
+                Action::Store => break,
+                Action::Drop => {
+                    store = false;
+                    break;
+                }
+                Action::Tag(tag) => {
+                    if !route.labels.tags.contains(tag) {
+                        route.labels.tags.push(tag.clone());
+                    }
+                }
+                Action::Namespace(namespace) => {
+                    route.labels.namespace.get_or_insert_with(|| namespace.clone());
+                }
+                Action::Forward(relay) => {
+                    route.forward.entry(relay.clone()).or_default().push(recipient.clone());
+                }
+                Action::Relay => {
+                    if !route.relay.contains(recipient) {
+                        route.relay.push(recipient.clone());
+                    }
+                }
+                Action::Webhook(url) => {
+                    if !route.webhooks.contains(url) {
+                        route.webhooks.push(url.clone());
+                    }
+                }
+            }
+        }
+        route.store |= store;
+    }
+    route
+}
+
+/// A copy of a mail to send elsewhere, prepared before the mail is handed over for storage.
+pub enum Delivery {
+    Forward {
+        id: u128,
+        relay: String,
+        from: String,
+        to: Vec<String>,
+        data: Bytes,
+    },
+    Webhook {
+        id: u128,
+        url: String,
+        payload: String,
+    },
+    Relay(Relay),
+}
+
+/// The forwards, relay and webhooks of the route of a mail.
+pub fn deliveries(route: &Route, mail: &Mail) -> Vec<Delivery> {
+    let mut deliveries = Vec::new();
+
+    let mut from: Vec<&String> = mail.from.iter().collect();
+    from.sort();
+    let from = from.first().map(|from| from.to_string()).unwrap_or_default();
+    for (relay, to) in &route.forward {
+        deliveries.push(Delivery::Forward {
+            id: mail.id,
+            relay: relay.clone(),
+            from: from.clone(),
+            to: to.clone(),
+            data: mail.data.clone(),
+        });
+    }
+    if !route.relay.is_empty() {
+        deliveries.push(Delivery::Relay(Relay {
+            id: mail.id,
+            from: from.clone(),
+            to: route.relay.clone(),
+            data: mail.data.clone(),
+            record: route.store,
+        }));
+    }
+
+    if !route.webhooks.is_empty() {
+        let summary = MailSummary {
+            tags: route.labels.tags.clone(),
+            namespace: route.labels.namespace.clone(),
+            ..MailSummary::from_mail(mail)
+        };
+        if let Ok(payload) = serde_json::to_string(&summary) {
+            deliveries.extend(route.webhooks.iter().map(|url| Delivery::Webhook {
+                id: mail.id,
+                url: url.clone(),
+                payload: payload.clone(),
+            }));
+        }
+    }
+
+    deliveries
+}
+
+/// Sends the copies in the background, failures are only reported. The relayed mails go through
+/// the relay queue instead, which records how their delivery goes.
+pub fn dispatch(deliveries: Vec<Delivery>, relays: &relay::Sender) {
+    for delivery in deliveries {
+        match delivery {
+            Delivery::Forward {
+                id,
+                relay,
+                from,
+                to,
+                data,
+            } => {
+                tokio::spawn(async move {
+                    let to: Vec<&str> = to.iter().map(String::as_str).collect();
+                    let sent = bench::send_mail(&relay, &from, &to, &data);
+                    let result = match tokio::time::timeout(DELIVERY_TIMEOUT, sent).await {
+                        Ok(result) => result,
+                        Err(_) => Err("timeout".to_string()),
+                    };
+                    if let Err(e) = result {
+                        metrics::RULE_FAILURES.inc();
+                        let message = format!("Failed to forward mail {} to {}: {}", id, relay, e);
+                        report::report(report::Kind::Delivery, &message);
+                    }
+                });
+            }
+            Delivery::Webhook { id, url, payload } => {
+                webhooks::spawn(url, payload, id);
+            }
+            // only refused once the sink is shut down
+            Delivery::Relay(mail) => {
+                let _ = relays.send(mail);
+            }
+        }
+    }
+}
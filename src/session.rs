@@ -0,0 +1,274 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+pub const COOKIE_NAME: &str = "mail_sink_session";
+
+lazy_static! {
+    // with the id of the API key they were opened with, if any
+    static ref SESSIONS: Mutex<HashMap<String, Session>> = Mutex::new(HashMap::new());
+    // replaced when a sink is started again in the same process
+    static ref SETTINGS: RwLock<Option<Settings>> = RwLock::new(None);
+}
+
+/// What an API key, and the panel sessions opened with it, can do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // the GET routes, the /admin ones aside
+    Read,
+    Admin,
+}
+
+struct Session {
+    expires_at: Instant,
+    role: Role,
+    key: Option<u64>,
+}
+
+struct Key {
+    id: u64,
+    key: String,
+    role: Role,
+    // given on the command line, so back after a restart even if revoked
+    configured: bool,
+}
+
+/// An API key as `GET /admin/keys` lists it, without the key itself.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub id: u64,
+    pub role: Role,
+    // the first characters, to tell the keys apart
+    pub hint: String,
+    pub configured: bool,
+}
+
+impl From<&Key> for KeyInfo {
+    fn from(key: &Key) -> Self {
+        KeyInfo {
+            id: key.id,
+            role: key.role,
+            // a quarter of a short key at most, so that the hint doesn't give it away
+            hint: format!("{}…", key.key.chars().take((key.key.chars().count() / 4).min(4)).collect::<String>()),
+            configured: key.configured,
+        }
+    }
+}
+
+/// Why [`revoke_key`] didn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevokeError {
+    NotFound,
+    // the API would be locked out until a restart
+    LastAdmin,
+}
+
+struct Settings {
+    keys: Vec<Key>,
+    next_key_id: u64,
+    credentials: Option<(String, String)>,
+    ttl: Duration,
+    // the panel is served over HTTPS, cookies must not leak over plain HTTP
+    secure: bool,
+}
+
+/// Configures the API keys and the panel login. `credentials` enables the username/password
+/// form next to the API key one, and logs in as an admin.
+pub fn init(
+    keys: Vec<(String, Role)>,
+    credentials: Option<(String, String)>,
+    ttl: Duration,
+    secure: bool,
+) {
+    let keys = keys
+        .into_iter()
+        .zip(1..)
+        .map(|((key, role), id)| Key {
+            id,
+            key,
+            role,
+            configured: true,
+        })
+        .collect::<Vec<_>>();
+    *SETTINGS.write().unwrap() = Some(Settings {
+        next_key_id: keys.len() as u64 + 1,
+        keys,
+        credentials,
+        ttl,
+        secure,
+    });
+}
+
+// what `read` says of the settings, None before they are set
+fn settings<T>(read: impl FnOnce(&Settings) -> T) -> Option<T> {
+    SETTINGS.read().unwrap().as_ref().map(read)
+}
+
+pub fn ttl() -> Duration {
+    settings(|settings| settings.ttl)
+        .unwrap_or(Duration::from_secs(12 * 60 * 60))
+}
+
+pub fn password_login_enabled() -> bool {
+    settings(|settings| settings.credentials.is_some()).unwrap_or(false)
+}
+
+/// The role of `key`, None when it isn't one of ours.
+pub fn key_role(key: &str) -> Option<Role> {
+    find_key(key).map(|(_, role)| role)
+}
+
+// the id and role of `key`, the admin one if it was given twice
+fn find_key(key: &str) -> Option<(u64, Role)> {
+    // compared to every key, so the timing doesn't tell which one is close
+    settings(|settings| {
+        settings.keys.iter().fold(None, |found: Option<(u64, Role)>, expected| {
+            match constant_time_eq(key.as_bytes(), expected.key.as_bytes()) {
+                true if found.is_none_or(|(_, role)| expected.role > role) => Some((expected.id, expected.role)),
+                _ => found,
+            }
+        })
+    })?
+}
+
+pub fn keys() -> Vec<KeyInfo> {
+    settings(|settings| settings.keys.iter().map(KeyInfo::from).collect()).unwrap_or_default()
+}
+
+/// Creates a random key, returned along with how it's listed. None before the keys are set up.
+pub fn create_key(role: Role) -> Option<(String, KeyInfo)> {
+    let mut settings = SETTINGS.write().unwrap();
+    let settings = settings.as_mut()?;
+    let key = Key {
+        id: settings.next_key_id,
+        key: random_token(),
+        role,
+        configured: false,
+    };
+    settings.next_key_id += 1;
+    let created = (key.key.clone(), KeyInfo::from(&key));
+    settings.keys.push(key);
+    Some(created)
+}
+
+/// Removes a key, along with the panel sessions opened with it. The last admin key stays.
+pub fn revoke_key(id: u64) -> Result<(), RevokeError> {
+    let mut settings = SETTINGS.write().unwrap();
+    let settings = settings.as_mut().ok_or(RevokeError::NotFound)?;
+    let index = settings.keys.iter().position(|key| key.id == id).ok_or(RevokeError::NotFound)?;
+    let admins = settings.keys.iter().filter(|key| key.role == Role::Admin).count();
+    if settings.keys[index].role == Role::Admin && admins == 1 {
+        return Err(RevokeError::LastAdmin);
+    }
+    settings.keys.remove(index);
+    SESSIONS.lock().unwrap().retain(|_, session| session.key != Some(id));
+    Ok(())
+}
+
+/// How many admin and read-only keys there are, for the configuration.
+pub fn key_counts() -> (usize, usize) {
+    let keys = keys();
+    let admins = keys.iter().filter(|key| key.role == Role::Admin).count();
+    (admins, keys.len() - admins)
+}
+
+pub fn check_credentials(username: &str, password: &str) -> bool {
+    match settings(|settings| settings.credentials.clone()).flatten() {
+        Some((expected_username, expected_password)) => {
+            // evaluate both, so the timing doesn't tell which one was wrong
+            let username_ok = constant_time_eq(username.as_bytes(), expected_username.as_bytes());
+            let password_ok = constant_time_eq(password.as_bytes(), expected_password.as_bytes());
+            username_ok && password_ok
+        }
+        None => false,
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).expect("no system randomness available");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Opens a new session and returns its token.
+pub fn create(role: Role) -> String {
+    open(role, None)
+}
+
+/// Opens a new session with an API key of `role`, which ends along with the key if it's revoked.
+pub fn create_with_key(key: &str, role: Role) -> String {
+    open(role, find_key(key).map(|(id, _)| id))
+}
+
+fn open(role: Role, key: Option<u64>) -> String {
+    let token = random_token();
+    let now = Instant::now();
+    let mut sessions = SESSIONS.lock().unwrap();
+    // opportunistic cleanup, sessions are only created on login
+    sessions.retain(|_, session| session.expires_at > now);
+    let session = Session {
+        expires_at: now + ttl(),
+        role,
+        key,
+    };
+    sessions.insert(token.clone(), session);
+
+    token
+}
+
+/// The role of a session, None when it doesn't exist or expired.
+pub fn role(token: &str) -> Option<Role> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    match sessions.get(token) {
+        Some(session) if session.expires_at > Instant::now() => Some(session.role),
+        Some(_) => {
+            sessions.remove(token);
+            None
+        }
+        None => None,
+    }
+}
+
+pub fn remove(token: &str) {
+    SESSIONS.lock().unwrap().remove(token);
+}
+
+/// Extracts our session token from a `Cookie` header value.
+pub fn token_from_cookies(cookies: &str) -> Option<&str> {
+    cookies
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, value)| value)
+}
+
+pub fn set_cookie(token: &str) -> String {
+    format!(
+        "{}={}; {}; Max-Age={}",
+        COOKIE_NAME,
+        token,
+        cookie_attributes(),
+        ttl().as_secs()
+    )
+}
+
+pub fn clear_cookie() -> String {
+    format!("{}=; {}; Max-Age=0", COOKIE_NAME, cookie_attributes())
+}
+
+fn cookie_attributes() -> &'static str {
+    match settings(|settings| settings.secure).unwrap_or(false) {
+        true => "Path=/; HttpOnly; SameSite=Lax; Secure",
+        false => "Path=/; HttpOnly; SameSite=Lax",
+    }
+}
@@ -0,0 +1,101 @@
+use crate::store::Store;
+use crate::{ingest, SharedError};
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+lazy_static! {
+    static ref STOPPING: watch::Sender<bool> = watch::Sender::new(false);
+    // notified each time a connection closes
+    static ref CLOSED: Notify = Notify::new();
+}
+
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+// how often the ingest queue is checked while waiting for it to empty
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// An open SMTP, POP3 or HTTP connection, which the shutdown waits for until dropped.
+pub struct Connection(());
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+        CLOSED.notify_waiters();
+    }
+}
+
+/// Counts a connection until the returned guard is dropped.
+pub fn track() -> Connection {
+    CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    Connection(())
+}
+
+pub fn connections() -> usize {
+    CONNECTIONS.load(Ordering::SeqCst)
+}
+
+pub fn stopping() -> bool {
+    *STOPPING.borrow()
+}
+
+/// Resolves once the shutdown has started, right away if it already has. The listeners stop
+/// accepting then, and idle connections close instead of waiting for their next command.
+pub async fn stopped() {
+    let mut stopping = STOPPING.subscribe();
+    // the sender lives as long as the process, so this can't fail
+    let _ = stopping.wait_for(|stopping| *stopping).await;
+}
+
+/// Lets the listeners of a sink started again in the same process accept connections.
+pub fn reset() {
+    STOPPING.send_replace(false);
+}
+
+/// Waits for SIGINT or SIGTERM and returns its name.
+pub async fn signalled() -> Result<&'static str, SharedError> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    })
+}
+
+/// Stops accepting connections, waits up to `timeout` for the open ones to finish, then for the
+/// mails they handed over to be stored, and flushes the database.
+pub async fn stop(db: &Store, queue: &ingest::Queue, timeout: Duration) -> Result<(), SharedError> {
+    drain(queue, timeout).await;
+    let bytes = db.flush_async().await?;
+    info!(bytes, "Database flushed");
+    Ok(())
+}
+
+/// Like [`stop`], without the flush, which a failed database never completes.
+pub async fn drain(queue: &ingest::Queue, timeout: Duration) {
+    STOPPING.send_replace(true);
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        // registered before counting, so that a connection closing in between isn't missed
+        let closed = CLOSED.notified();
+        let open = connections();
+        if open == 0 {
+            break;
+        }
+        info!(connections = open, "Waiting for {} connections to close", open);
+        if tokio::time::timeout_at(deadline, closed).await.is_err() {
+            warn!(connections = connections(), "Shutdown timeout reached, closing the remaining connections");
+            break;
+        }
+    }
+
+    // the mails already accepted are stored whatever the timeout, SMTP clients were told so
+    while !queue.is_empty() {
+        tokio::time::sleep(QUEUE_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,563 @@
+use crate::cli::Args;
+use crate::events::{self, Event};
+use crate::ingest::Queue;
+use crate::smtp::mail::Mail;
+use crate::store::{Storage, Store};
+use crate::{
+    config, dns, http, jobs, limits, memory, metrics, pop3, relay, retention, rules, session, shutdown,
+    smtp, snowflake, summary, tls, webhooks, SharedError,
+};
+use clap::Parser;
+use futures::Stream;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::{self, AbortHandle, JoinHandle};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+// the configuration, rules and sessions are the process's, so one sink runs at a time
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The error of [`MailSink::start`] while another sink of this process is running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlreadyRunning;
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("A mail sink is already running in this process")
+    }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+/// A mail sink to run inside this process, e.g. in the tests of an application, instead of the
+/// `mail-sink` binary:
+/// ```no_run
+/// # async fn example() -> Result<(), mail_sink::SharedError> {
+/// let sink = mail_sink::MailSink::new().start().await?;
+/// let mut mails = sink.mails();
+/// // the application sends a mail to sink.smtp_addr()
+/// let mail = mails.next().await.unwrap();
+/// assert_eq!(mail.subject.as_deref(), Some("Welcome"));
+/// sink.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+/// The options are the command line's, by their long name. Unlike the command line, the sink
+/// listens on `127.0.0.1`, on free ports, and keeps its mails in memory until told otherwise.
+///
+/// Only one sink runs in a process at a time, since the configuration, rules and API keys are
+/// global: [`MailSink::start`] fails with [`AlreadyRunning`] until the running one is shut down
+/// or dropped. Tests running in parallel share one sink or take turns.
+#[derive(Clone, Debug)]
+pub struct MailSink {
+    // `--name=value`, in order
+    options: Vec<(String, String)>,
+}
+
+impl Default for MailSink {
+    fn default() -> Self {
+        MailSink::new()
+    }
+}
+
+impl MailSink {
+    pub fn new() -> Self {
+        MailSink { options: Vec::new() }
+            .in_memory()
+            .bind(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .smtp_port(0)
+            .http_port(0)
+    }
+
+    /// Sets an option of the command line, replacing the value it had.
+    pub fn set(mut self, name: &str, value: impl ToString) -> Self {
+        self.options.retain(|(option, _)| option != name);
+        self.option(name, value)
+    }
+
+    /// Adds a value to an option of the command line, for those that can be given several
+    /// times, e.g. `.option("rule", "*@noise.test drop")`.
+    pub fn option(mut self, name: &str, value: impl ToString) -> Self {
+        self.options.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn bind(self, address: IpAddr) -> Self {
+        self.set("bind", address)
+    }
+
+    /// `0` for any free port, see [`RunningSink::smtp_addr`].
+    pub fn smtp_port(self, port: u16) -> Self {
+        self.set("smtp-port", port)
+    }
+
+    pub fn smtp_ports(self, ports: &[u16]) -> Self {
+        let ports = ports.iter().map(u16::to_string).collect::<Vec<_>>();
+        self.set("smtp-port", ports.join(","))
+    }
+
+    pub fn smtps_port(self, port: u16) -> Self {
+        self.set("smtps-port", port)
+    }
+
+    pub fn pop3_port(self, port: u16) -> Self {
+        self.set("pop3-port", port)
+    }
+
+    pub fn http_port(self, port: u16) -> Self {
+        self.set("http-ports", port)
+    }
+
+    /// Keeps the mails in a database at `path`, across restarts.
+    pub fn db_path(self, path: impl AsRef<Path>) -> Self {
+        self.set("storage", "disk").set("db-path", path.as_ref().display())
+    }
+
+    /// Keeps the mails in memory, gone once the sink is, which is the default.
+    pub fn in_memory(mut self) -> Self {
+        self.options.retain(|(option, _)| option != "db-path");
+        self.set("storage", "memory")
+    }
+
+    /// An API key with full access, instead of the default one.
+    pub fn key(self, key: &str) -> Self {
+        let keys = self.options.iter().any(|(option, _)| option == "key");
+        match keys {
+            true => self.option("key", key),
+            false => self.set("key", key),
+        }
+    }
+
+    /// Binds the ports and starts serving, once the options are checked like the command line's.
+    /// Fails with [`AlreadyRunning`] while another sink of this process is running.
+    pub async fn start(self) -> Result<RunningSink, SharedError> {
+        let argv = std::iter::once("mail-sink".to_string())
+            .chain(self.options.iter().map(|(name, value)| format!("--{}={}", name, value)));
+        let args = Args::try_parse_from(argv)?;
+        start(&args).await
+    }
+}
+
+/// A started sink, see [`MailSink`]. Dropping it without [`RunningSink::shutdown`] stops the
+/// listeners right away, and leaves the open connections to end with the runtime.
+pub struct RunningSink {
+    db: Store,
+    queue: Queue,
+    smtp_addrs: Vec<SocketAddr>,
+    smtps_addr: Option<SocketAddr>,
+    pop3_addr: Option<SocketAddr>,
+    http_addr: SocketAddr,
+    http: Option<JoinHandle<Result<(), SharedError>>>,
+    listeners: Vec<JoinHandle<Result<(), SharedError>>>,
+    // the jobs, the retention and the relay, which only end when aborted
+    background: Vec<AbortHandle>,
+}
+
+impl RunningSink {
+    /// The address of the first SMTP port, the one applications send to.
+    pub fn smtp_addr(&self) -> SocketAddr {
+        self.smtp_addrs[0]
+    }
+
+    pub fn smtp_addrs(&self) -> &[SocketAddr] {
+        &self.smtp_addrs
+    }
+
+    pub fn smtps_addr(&self) -> Option<SocketAddr> {
+        self.smtps_addr
+    }
+
+    pub fn pop3_addr(&self) -> Option<SocketAddr> {
+        self.pop3_addr
+    }
+
+    /// The address of the API and the panel.
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_addr
+    }
+
+    /// The mails stored from now on, as they are.
+    pub fn mails(&self) -> Mails {
+        Mails {
+            events: events::subscribe(),
+            db: self.db.clone(),
+        }
+    }
+
+    /// Resolves if the HTTP listener stops on its own, with its error.
+    pub(crate) async fn failed(&mut self) -> Result<(), SharedError> {
+        match self.http.as_mut() {
+            Some(http) => http.await?,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Stops accepting connections, waits up to `--shutdown-timeout` for the open ones, then for
+    /// the mails they handed over to be stored.
+    pub async fn shutdown(mut self) -> Result<(), SharedError> {
+        let timeout = Duration::from_secs(config::get().shutdown_timeout);
+        shutdown::stop(&self.db, &self.queue, timeout).await?;
+        // they return as soon as they see the shutdown
+        for listener in self.listeners.drain(..).chain(self.http.take()) {
+            let _ = listener.await;
+        }
+        Ok(())
+    }
+
+    /// Like [`RunningSink::shutdown`], but leaves the database as it is, for a failed one to be
+    /// reopened by a new process, see `storage::reopen_needed`.
+    pub(crate) async fn drain(mut self) {
+        let timeout = Duration::from_secs(config::get().shutdown_timeout);
+        shutdown::drain(&self.queue, timeout).await;
+        for listener in self.listeners.drain(..).chain(self.http.take()) {
+            let _ = listener.await;
+        }
+    }
+}
+
+impl Drop for RunningSink {
+    fn drop(&mut self) {
+        for listener in self.listeners.iter().chain(&self.http) {
+            listener.abort();
+        }
+        for task in &self.background {
+            task.abort();
+        }
+        RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// The mails stored by a sink, in the order they were, see [`RunningSink::mails`]. A subscriber
+/// more than 256 mails behind skips the oldest ones, which can still be listed through the API.
+pub struct Mails {
+    events: broadcast::Receiver<Event>,
+    db: Store,
+}
+
+impl Mails {
+    /// The next mail stored, None once the sink shuts down.
+    pub async fn next(&mut self) -> Option<Mail> {
+        loop {
+            let event = tokio::select! {
+                biased;
+                event = self.events.recv() => event,
+                _ = shutdown::stopped() => return None,
+            };
+            match event {
+                Ok(Event::Mail { id, .. }) => {
+                    // gone if it was deleted right away
+                    if let Ok(Some(mail)) = self.db.get_mail(id) {
+                        return Some(mail);
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Mail> {
+        futures::stream::unfold(self, |mut mails| async move {
+            let mail = mails.next().await?;
+            Some((mail, mails))
+        })
+    }
+}
+
+/// Applies the configuration of `args`, opens the database and binds the ports. The command line
+/// sets up the logging, error reporting and reloading beforehand.
+pub(crate) async fn start(args: &Args) -> Result<RunningSink, SharedError> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Err(AlreadyRunning.into());
+    }
+    // released by the drop of the sink from here
+    let mut sink = match serve(args).await {
+        Ok(sink) => sink,
+        Err(e) => {
+            RUNNING.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+    };
+    sink.background.extend([
+        task::spawn(jobs::run_scheduler(sink.db.clone())).abort_handle(),
+        task::spawn(retention::run_cleaner_service(sink.db.clone())).abort_handle(),
+    ]);
+    Ok(sink)
+}
+
+async fn serve(args: &Args) -> Result<RunningSink, SharedError> {
+    config::init(config::Config::from_args(args)?);
+    jobs::init(args.job.clone());
+    rules::init(args.rule.clone());
+    relay::init(args.relay.clone());
+    smtp::auth::init(args.smtp_user.clone());
+    smtp::chaos::init(args.chaos.clone());
+    smtp::recipients::init(smtp::recipients::Policy {
+        domains: args.accept_domain.clone(),
+        accept: args.accept_recipient.clone(),
+        reject: args.reject_recipient.clone(),
+    });
+    webhooks::set(webhooks::from_args(&args.webhook)?);
+    dns::init(args.dns_server);
+    memory::set_budget(config::get().memory_budget);
+    if let Some(seed) = config::get().deterministic {
+        snowflake::set_deterministic(seed);
+    }
+
+    let credentials = args.panel_user.clone().zip(args.panel_password.clone());
+    let keys = args.key.iter().map(|key| (key.clone(), session::Role::Admin));
+    let read_keys = args.read_key.iter().map(|key| (key.clone(), session::Role::Read));
+    session::init(
+        keys.chain(read_keys).collect(),
+        credentials,
+        Duration::from_secs(args.session_ttl as u64 * 60),
+        config::get().http_tls,
+    );
+
+    let (tls_config, self_signed) = tls::load(args.tls_cert.as_deref(), args.tls_key.as_deref())?;
+    if self_signed {
+        warn!(
+            "No TLS certificate given, using a self-signed one for {}",
+            tls::SELF_SIGNED_NAMES.join(", ")
+        );
+    }
+    config::set_tls_self_signed(self_signed);
+    let tls_config = Arc::new(tls_config);
+    let http_tls = match args.http_tls_cert.as_deref().zip(args.http_tls_key.as_deref()) {
+        Some((cert, key)) => Some(Arc::new(tls::load_files(cert, key)?)),
+        None => None,
+    };
+
+    let db = match args.storage {
+        Storage::Disk => Store::open(&args.db_path)?,
+        Storage::Memory => Store::memory(),
+    };
+    match summary::sync(&db)? {
+        0 => {}
+        count => info!(count, "Updated the summaries of {} emails", count),
+    }
+    // the ids go on from the stored ones, even if the clock went back since
+    if let (None, Some(newest)) = (config::get().deterministic, summary::newest(&db)?) {
+        snowflake::resume(newest);
+    }
+
+    // bound before anything is served, so that a port in use fails the start
+    let bind = args.bind;
+    let config = config::get();
+    let mut smtp_listeners = Vec::new();
+    for port in &config.smtp_ports {
+        smtp_listeners.push(TcpListener::bind(SocketAddr::new(bind, *port)).await?);
+    }
+    let smtps_listener = match config.smtps_port {
+        Some(port) => Some(TcpListener::bind(SocketAddr::new(bind, port)).await?),
+        None => None,
+    };
+    let pop3_listener = match config.pop3_port {
+        Some(port) => Some(TcpListener::bind(SocketAddr::new(bind, port)).await?),
+        None => None,
+    };
+    let http_listener = TcpListener::bind(SocketAddr::new(bind, args.http_ports)).await?;
+
+    let smtp_addrs = smtp_listeners
+        .iter()
+        .map(TcpListener::local_addr)
+        .collect::<Result<Vec<_>, _>>()?;
+    let smtps_addr = smtps_listener.as_ref().map(TcpListener::local_addr).transpose()?;
+    let pop3_addr = pop3_listener.as_ref().map(TcpListener::local_addr).transpose()?;
+    let http_addr = http_listener.local_addr()?;
+    config::set_ports(
+        smtp_addrs.iter().map(SocketAddr::port).collect(),
+        smtps_addr.map(|addr| addr.port()),
+        pop3_addr.map(|addr| addr.port()),
+        http_addr.port(),
+    );
+
+    shutdown::reset();
+    let (relays, relay_queue) = relay::channel();
+    let queue = Queue::start(db.clone(), config.queue_capacity, relays);
+    let relay = task::spawn(relay::run(db.clone(), relay_queue)).abort_handle();
+    let mut listeners = Vec::new();
+    for listener in smtp_listeners {
+        listeners.push(task::spawn(run_smtp_service(listener, tls_config.clone(), queue.clone(), false)));
+    }
+    if let Some(listener) = smtps_listener {
+        listeners.push(task::spawn(run_smtp_service(listener, tls_config.clone(), queue.clone(), true)));
+    }
+    if let Some(listener) = pop3_listener {
+        listeners.push(task::spawn(run_pop3_service(listener, db.clone())));
+    }
+    let http = task::spawn(run_http_service(http_listener, db.clone(), queue.clone(), http_tls));
+
+    Ok(RunningSink {
+        db,
+        queue,
+        smtp_addrs,
+        smtps_addr,
+        pop3_addr,
+        http_addr,
+        http: Some(http),
+        listeners,
+        background: vec![relay],
+    })
+}
+
+async fn run_smtp_service(
+    listener: TcpListener,
+    tls_config: Arc<ServerConfig>,
+    queue: Queue,
+    implicit_tls: bool,
+) -> Result<(), SharedError> {
+    let port = listener.local_addr()?.port();
+    if implicit_tls {
+        info!(port, "SMTPS server running on port {}", port);
+    } else {
+        info!(port, "SMTP server running on port {}", port);
+    }
+
+    loop {
+        // accept a new incoming TCP connection
+        let (socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown::stopped() => return Ok(()),
+        };
+        // the reply would have to wait for a TLS handshake, SMTPS clients just see the connection close
+        let reply = if implicit_tls { &b""[..] } else { smtp::TOO_MANY_CONNECTIONS };
+        let Some((socket, permit)) = admit(socket, addr, reply) else {
+            continue;
+        };
+        let connection = shutdown::track();
+
+        // clone the TLS configuration for the spawned task
+        let tls_config = tls_config.clone();
+        let queue = queue.clone();
+        let session = smtp::sessions::Session::open(addr);
+        // ties every line of the session together, the mails it sends included
+        let span = info_span!("smtp", session = session.id(), peer = %addr, port);
+
+        // spawn a new task to handle the client
+        tokio::spawn(async move {
+            info!("Client connected");
+            // a killed session drops its connection along with the mail in progress
+            let serve = async {
+                if implicit_tls {
+                    smtp::handle_implicit_tls_client(socket, tls_config, &session, &queue).await
+                } else {
+                    smtp::handle_client(socket, tls_config, addr, &session, &queue).await
+                }
+            };
+            let result = tokio::select! {
+                result = serve => result,
+                _ = session.killed() => Err("Session killed".into()),
+            };
+            match result {
+                Ok(()) => info!("Client disconnected"),
+                Err(e) => warn!(error = ?e, "Error handling client"),
+            }
+            drop(connection);
+            drop(permit);
+        }.instrument(span));
+    }
+}
+
+async fn run_pop3_service(listener: TcpListener, db: Store) -> Result<(), SharedError> {
+    let port = listener.local_addr()?.port();
+    info!(port, "POP3 server running on port {}", port);
+
+    loop {
+        let (socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown::stopped() => return Ok(()),
+        };
+        let Some((socket, permit)) = admit(socket, addr, pop3::TOO_MANY_CONNECTIONS) else {
+            continue;
+        };
+        let connection = shutdown::track();
+        let db = db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = pop3::handle_client(socket, db).await {
+                warn!(error = ?e, "Error handling POP3 client");
+            }
+            drop(connection);
+            drop(permit);
+        }.instrument(info_span!("pop3", peer = %addr)));
+    }
+}
+
+async fn run_http_service(
+    listener: TcpListener,
+    db: Store,
+    queue: Queue,
+    tls_config: Option<Arc<ServerConfig>>,
+) -> Result<(), SharedError> {
+    let port = listener.local_addr()?.port();
+    match tls_config {
+        Some(_) => info!(port, "HTTPS server running on port {}", port),
+        None => info!(port, "HTTP server running on port {}", port),
+    }
+    let router = Arc::new(http::Router::new(queue));
+    let mut request_id: u64 = 0;
+
+    loop {
+        // accept a new incoming TCP connection
+        let (socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown::stopped() => return Ok(()),
+        };
+        let reply = if tls_config.is_some() { &b""[..] } else { http::TOO_MANY_CONNECTIONS };
+        let Some((socket, permit)) = admit(socket, addr, reply) else {
+            continue;
+        };
+        let connection = shutdown::track();
+        // the requests of a kept-alive connection share its span
+        request_id += 1;
+        let span = info_span!("http", request = request_id, peer = %addr);
+
+        let db = db.clone();
+        let router = router.clone();
+        let tls_config = tls_config.clone();
+        tokio::spawn(async move {
+            let result = match tls_config {
+                Some(tls_config) => {
+                    // a client stalling the handshake is as slow as one stalling its request
+                    let acceptor = TlsAcceptor::from(tls_config);
+                    match tokio::time::timeout(http::HEAD_TIMEOUT, acceptor.accept(socket)).await {
+                        Ok(Ok(stream)) => http::handle_client(stream, addr.ip(), db, &router).await,
+                        Ok(Err(e)) => Err(e.into()),
+                        Err(_) => {
+                            metrics::HTTP_TIMEOUTS.inc();
+                            Ok(())
+                        }
+                    }
+                }
+                None => http::handle_client(socket, addr.ip(), db, &router).await,
+            };
+            if let Err(e) = result {
+                warn!(error = ?e, "Error handling client");
+            }
+            drop(connection);
+            drop(permit);
+        }.instrument(span));
+    }
+}
+
+// counts a new connection against the limits, or closes it after sending `reply` (if not empty)
+fn admit(socket: TcpStream, addr: SocketAddr, reply: &'static [u8]) -> Option<(TcpStream, limits::Permit)> {
+    match limits::connect(addr.ip()) {
+        Ok(permit) => Some((socket, permit)),
+        Err(refusal) => {
+            metrics::CONNECTIONS_REJECTED.inc();
+            // a client hammering the ports would flood the logs at a higher level
+            debug!(peer = %addr, ?refusal, "Too many connections, closing");
+            task::spawn(limits::refuse(socket, reply));
+            None
+        }
+    }
+}
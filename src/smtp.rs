@@ -1,52 +1,110 @@
+pub(crate) mod auth;
+pub(crate) mod chaos;
+pub(crate) mod dkim;
 pub(crate) mod mail;
-
+pub(crate) mod recipients;
+pub(crate) mod sessions;
+pub(crate) mod spf;
+pub(crate) mod verification;
+
+use crate::ingest::Queue;
+use crate::memory::Reservation;
+use crate::rules::Labels;
+use crate::smtp::chaos::{Fault, Stage};
 use crate::smtp::mail::{get_data_from_to, get_subject, Mail};
-use crate::SharedError;
-use rustls_pemfile::{certs, pkcs8_private_keys};
+use crate::smtp::sessions::{Session, State};
+use crate::smtp::verification::{Authentication, Client};
+use crate::snapshot::Rejection;
+use crate::duplicates::Policy;
+use crate::limits::{self, Rate};
+use crate::{config, memory, metrics, rules, shutdown, snapshot, storage, SharedError};
+use bytes::{Bytes, BytesMut};
 use std::collections::HashSet;
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader as StdBufReader;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
-use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio::sync::oneshot;
+use tokio_rustls::rustls::ServerConfig;
 use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
 
 pub(crate) async fn handle_client(
     stream: TcpStream,
     tls_config: Arc<ServerConfig>,
     peer_addr: SocketAddr,
-) -> Result<Mail, SharedError> {
+    session: &Session,
+    queue: &Queue,
+) -> Result<(), SharedError> {
     let (reader, writer) = stream.into_split();
 
     let mut reader = BufReader::new(reader);
     let mut writer = writer;
 
+    if refuse_connection(&mut writer).await? {
+        return Ok(());
+    }
     // greeting
     writer.write_all(b"220 mail-sink\r\n").await?;
 
     let mut from = HashSet::new();
     let mut to = HashSet::new();
-    let mut body = String::new();
+    // authenticated with AUTH, until the connection (or STARTTLS) ends
+    let mut user = None;
+    // what SPF checks, see `--verify-auth`
+    let mut helo = None;
+    let mut mail_from = None;
 
     loop {
         let mut line = String::new();
 
-        let bytes_read = reader.read_line(&mut line).await?;
+        let bytes_read = tokio::select! {
+            biased;
+            read = reader.read_line(&mut line) => read?,
+            // between two commands, so no mail is cut short
+            _ = shutdown::stopped() => {
+                writer.write_all(SHUTTING_DOWN).await?;
+                break;
+            }
+        };
         if bytes_read == 0 {
             // connection closed :((((
             break;
         }
+        session.add_bytes(bytes_read);
+        if !limits::allow(Rate::SmtpCommands, session.peer().ip()) {
+            metrics::SMTP_THROTTLED.inc();
+            writer.write_all(THROTTLED).await?;
+            break;
+        }
 
         let command = line.trim_end();
         let command_upper = command.to_uppercase();
+        log_command(command, &command_upper);
+
+        let (stage, address) = chaos::stage(command);
+        match chaos::inject(chaos::faults(stage, address.as_deref())).await {
+            Some(Fault::Reply(code)) => {
+                writer.write_all(&chaos::reply(code)).await?;
+                continue;
+            }
+            Some(_) => {
+                if stage == Stage::Data {
+                    drop_mid_data(&mut reader, &mut writer).await?;
+                }
+                break;
+            }
+            None => {}
+        }
 
         if command_upper.starts_with("EHLO") || command_upper.starts_with("HELO") {
+            session.set_state(State::Greeted);
+            helo = command.split_whitespace().nth(1).map(str::to_string);
             writer.write_all(b"250-localhost\r\n").await?;
             // STARTTLS capability
             writer.write_all(b"250-STARTTLS\r\n").await?;
+            writer.write_all(auth::CAPABILITY).await?;
+            writer.write_all(&size_capability()).await?;
             writer.write_all(b"250 OK\r\n").await?;
         } else if command_upper.starts_with("STARTTLS") {
             writer.write_all(b"220 Ready to start TLS\r\n").await?;
@@ -56,71 +114,93 @@ pub(crate) async fn handle_client(
             let stream = reader.into_inner().reunite(writer)?;
             // Upgrade to TLS
             let acceptor = TlsAcceptor::from(tls_config.clone());
+            session.set_state(State::Tls);
             let tls_stream = acceptor.accept(stream).await?;
+            session.set_tls();
+            session.set_state(State::Connected);
 
-            let tls_mail = handle_tls_client(tls_stream).await;
-            match tls_mail {
-                Ok(m) => {
-                    from = m.from;
-                    to = m.to;
-                    body = m.data;
-                }
-                Err(e) => {
-                    println!("Error handling TLS client {}: {:?}", peer_addr, e);
-                }
+            info!("STARTTLS done");
+
+            // the envelope starts over once encrypted
+            if let Err(e) = handle_tls_client(tls_stream, session, queue).await {
+                warn!(peer = %peer_addr, error = ?e, "Error handling TLS client");
             }
             break;
+        } else if command_upper.starts_with("AUTH") {
+            if user.is_some() {
+                writer.write_all(b"503 5.5.1 Already authenticated\r\n").await?;
+            } else {
+                user = auth::authenticate(command, &mut reader, &mut writer, session).await?;
+            }
         } else if command_upper.starts_with("MAIL FROM") {
-            from.insert(
-                command[10..]
-                    .to_string()
-                    .replace("<", "")
-                    .replace(">", "")
-                    .trim()
-                    .to_string(),
-            );
+            let (address, size) = parse_mail_from(command);
+            if size.is_some_and(too_large) {
+                metrics::MESSAGES_TOO_LARGE.inc();
+                snapshot::reject(Rejection::TooLarge);
+                writer.write_all(TOO_LARGE).await?;
+                continue;
+            }
+            session.set_state(State::Mail);
+            mail_from = Some(address.clone());
+            from.insert(address);
             writer.write_all(b"250 OK\r\n").await?;
         } else if command_upper.starts_with("RCPT TO") {
-            to.insert(
-                command[8..]
-                    .to_string()
-                    .replace("<", "")
-                    .replace(">", "")
-                    .trim()
-                    .to_string(),
-            );
+            session.set_state(State::Rcpt);
+            let address = command.get(8..).unwrap_or_default().replace(['<', '>'], "").trim().to_string();
+            if !recipients::check(&address) {
+                writer.write_all(recipients::REJECTED).await?;
+                continue;
+            }
+            to.insert(address);
             writer.write_all(b"250 OK\r\n").await?;
         } else if command_upper == "DATA" {
-            writer
-                .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
-                .await?;
-
-            // email data processing
-            let mut data = String::new();
-            loop {
-                line.clear();
-                let bytes_read = reader.read_line(&mut line).await?;
-                if bytes_read == 0 {
-                    // connection closed unexpectedly
-                    break;
-                }
-                let trimmed_line = line.trim_end();
-                if trimmed_line == "." {
-                    break;
-                }
-                data.push_str(&line);
+            session.set_state(State::Data);
+            if to.is_empty() && recipients::restricted() {
+                writer.write_all(recipients::NO_RECIPIENTS).await?;
+                continue;
             }
+            if storage::failing() {
+                metrics::STORAGE_REJECTED.inc();
+                snapshot::reject(Rejection::Storage);
+                writer.write_all(NO_STORAGE).await?;
+                continue;
+            }
+            if memory::exceeded() {
+                metrics::MEMORY_REJECTED.inc();
+                snapshot::reject(Rejection::Memory);
+                writer.write_all(OVER_BUDGET).await?;
+                continue;
+            }
+            writer.write_all(START_DATA).await?;
+
+            let (data, reservation) = match read_data(&mut reader, session).await? {
+                Data::Received(data, reservation) => (data, reservation),
+                refused => {
+                    from.clear();
+                    to.clear();
+                    mail_from = None;
+                    writer.write_all(refuse(refused)).await?;
+                    continue;
+                }
+            };
 
-            let (f, t) = get_data_from_to(&data);
+            let (f, t) = get_data_from_to(&String::from_utf8_lossy(&data));
             f.iter().for_each(|s| {
                 from.insert(s.clone());
             });
-            t.iter().for_each(|s| {
+            // the headers can't bring back a recipient RCPT TO would have refused
+            t.iter().filter(|s| recipients::accepts(s)).for_each(|s| {
                 to.insert(s.clone());
             });
 
-            body = data.clone();
-            writer.write_all(b"250 OK\r\n").await?;
+            let envelope = (std::mem::take(&mut from), std::mem::take(&mut to));
+            let client = Client {
+                ip: session.peer().ip(),
+                helo: helo.clone(),
+                mail_from: mail_from.take(),
+            };
+            let reply = deliver(queue, envelope, data, reservation, user.clone(), client).await;
+            writer.write_all(reply).await?;
         } else if command_upper == "QUIT" {
             // reunite the read and write halves
             let mut stream = reader.into_inner().reunite(writer)?;
@@ -132,75 +212,173 @@ pub(crate) async fn handle_client(
         }
     }
 
-    let subject = get_subject(&body);
-    Ok(Mail::new(from, to, body, subject))
+    Ok(())
+}
+
+/// Serves a connection to the SMTPS port, encrypted from the first byte.
+pub(crate) async fn handle_implicit_tls_client(
+    stream: TcpStream,
+    tls_config: Arc<ServerConfig>,
+    session: &Session,
+    queue: &Queue,
+) -> Result<(), SharedError> {
+    session.set_state(State::Tls);
+    let mut tls_stream = TlsAcceptor::from(tls_config).accept(stream).await?;
+    session.set_tls();
+    session.set_state(State::Connected);
+
+    if refuse_connection(&mut tls_stream).await? {
+        return Ok(());
+    }
+    // greeting, only once the handshake is done
+    tls_stream.write_all(b"220 mail-sink\r\n").await?;
+    handle_tls_client(tls_stream, session, queue).await
 }
 
 async fn handle_tls_client(
     stream: tokio_rustls::server::TlsStream<TcpStream>,
     //peer_addr: SocketAddr,
-) -> Result<Mail, Box<dyn Error>> {
+    session: &Session,
+    queue: &Queue,
+) -> Result<(), SharedError> {
     let (read_half, write_half) = tokio::io::split(stream);
     let mut reader = BufReader::new(read_half);
     let mut writer = write_half;
 
     let mut from = HashSet::new();
     let mut to = HashSet::new();
-    let mut body = String::new();
+    // authenticated with AUTH, until the connection (or STARTTLS) ends
+    let mut user = None;
+    // what SPF checks, see `--verify-auth`
+    let mut helo = None;
+    let mut mail_from = None;
 
     loop {
         let mut line = String::new();
 
-        let bytes_read = reader.read_line(&mut line).await?;
+        let bytes_read = tokio::select! {
+            biased;
+            read = reader.read_line(&mut line) => read?,
+            // between two commands, so no mail is cut short
+            _ = shutdown::stopped() => {
+                writer.write_all(SHUTTING_DOWN).await?;
+                break;
+            }
+        };
         if bytes_read == 0 {
             // connection closed :((((
             break;
         }
+        session.add_bytes(bytes_read);
+        if !limits::allow(Rate::SmtpCommands, session.peer().ip()) {
+            metrics::SMTP_THROTTLED.inc();
+            writer.write_all(THROTTLED).await?;
+            break;
+        }
 
         let command = line.trim_end();
         let command_upper = command.to_uppercase();
+        log_command(command, &command_upper);
+
+        let (stage, address) = chaos::stage(command);
+        match chaos::inject(chaos::faults(stage, address.as_deref())).await {
+            Some(Fault::Reply(code)) => {
+                writer.write_all(&chaos::reply(code)).await?;
+                continue;
+            }
+            Some(_) => {
+                if stage == Stage::Data {
+                    drop_mid_data(&mut reader, &mut writer).await?;
+                }
+                break;
+            }
+            None => {}
+        }
 
         if command_upper.starts_with("EHLO") || command_upper.starts_with("HELO") {
+            session.set_state(State::Greeted);
+            helo = command.split_whitespace().nth(1).map(str::to_string);
             writer.write_all(b"250-localhost\r\n").await?;
+            writer.write_all(auth::CAPABILITY).await?;
+            writer.write_all(&size_capability()).await?;
             writer.write_all(b"250 OK\r\n").await?;
+        } else if command_upper.starts_with("STARTTLS") {
+            writer.write_all(b"503 5.5.1 TLS already active\r\n").await?;
+        } else if command_upper.starts_with("AUTH") {
+            if user.is_some() {
+                writer.write_all(b"503 5.5.1 Already authenticated\r\n").await?;
+            } else {
+                user = auth::authenticate(command, &mut reader, &mut writer, session).await?;
+            }
         } else if command_upper.starts_with("MAIL FROM") {
-            from.insert(command[10..].to_string().replace("<", "").replace(">", ""));
+            let (address, size) = parse_mail_from(command);
+            if size.is_some_and(too_large) {
+                metrics::MESSAGES_TOO_LARGE.inc();
+                snapshot::reject(Rejection::TooLarge);
+                writer.write_all(TOO_LARGE).await?;
+                continue;
+            }
+            session.set_state(State::Mail);
+            mail_from = Some(address.clone());
+            from.insert(address);
             writer.write_all(b"250 OK\r\n").await?;
         } else if command_upper.starts_with("RCPT TO") {
-            to.insert(command[8..].to_string().replace("<", "").replace(">", ""));
+            session.set_state(State::Rcpt);
+            let address = command.get(8..).unwrap_or_default().replace(['<', '>'], "").trim().to_string();
+            if !recipients::check(&address) {
+                writer.write_all(recipients::REJECTED).await?;
+                continue;
+            }
+            to.insert(address);
             writer.write_all(b"250 OK\r\n").await?;
         } else if command_upper == "DATA" {
-            writer
-                .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
-                .await?;
-
-            // email data processing
-            let mut data = String::new();
-            loop {
-                line.clear();
-                let bytes_read = reader.read_line(&mut line).await?;
-                if bytes_read == 0 {
-                    // connection closed unexpectedly
-                    break;
-                }
-                let trimmed_line = line.trim_end();
-                if trimmed_line == "." {
-                    break;
-                }
-                data.push_str(&line);
+            session.set_state(State::Data);
+            if to.is_empty() && recipients::restricted() {
+                writer.write_all(recipients::NO_RECIPIENTS).await?;
+                continue;
+            }
+            if storage::failing() {
+                metrics::STORAGE_REJECTED.inc();
+                snapshot::reject(Rejection::Storage);
+                writer.write_all(NO_STORAGE).await?;
+                continue;
             }
+            if memory::exceeded() {
+                metrics::MEMORY_REJECTED.inc();
+                snapshot::reject(Rejection::Memory);
+                writer.write_all(OVER_BUDGET).await?;
+                continue;
+            }
+            writer.write_all(START_DATA).await?;
+
+            let (data, reservation) = match read_data(&mut reader, session).await? {
+                Data::Received(data, reservation) => (data, reservation),
+                refused => {
+                    from.clear();
+                    to.clear();
+                    mail_from = None;
+                    writer.write_all(refuse(refused)).await?;
+                    continue;
+                }
+            };
 
-            let (f, t) = get_data_from_to(&data);
+            let (f, t) = get_data_from_to(&String::from_utf8_lossy(&data));
             f.iter().for_each(|s| {
                 from.insert(s.clone());
             });
-            t.iter().for_each(|s| {
+            // the headers can't bring back a recipient RCPT TO would have refused
+            t.iter().filter(|s| recipients::accepts(s)).for_each(|s| {
                 to.insert(s.clone());
             });
 
-            body = data.clone();
-
-            writer.write_all(b"250 OK\r\n").await?;
+            let envelope = (std::mem::take(&mut from), std::mem::take(&mut to));
+            let client = Client {
+                ip: session.peer().ip(),
+                helo: helo.clone(),
+                mail_from: mail_from.take(),
+            };
+            let reply = deliver(queue, envelope, data, reservation, user.clone(), client).await;
+            writer.write_all(reply).await?;
         } else if command_upper == "QUIT" {
             break;
         } else {
@@ -208,37 +386,231 @@ async fn handle_tls_client(
         }
     }
 
-    let subject = get_subject(&body);
-    Ok(Mail::new(from, to, body, subject))
+    Ok(())
+}
+
+// a fault injected by --chaos instead of the greeting, true when the connection is to be closed
+async fn refuse_connection<W: AsyncWrite + Unpin>(writer: &mut W) -> std::io::Result<bool> {
+    match chaos::inject(chaos::faults(Stage::Connect, None)).await {
+        Some(Fault::Reply(code)) => {
+            writer.write_all(&chaos::reply(code)).await?;
+            Ok(true)
+        }
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+// answers DATA, then hangs up once the message starts coming
+async fn drop_mid_data<R, W>(reader: &mut R, writer: &mut W) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(START_DATA).await?;
+    writer.flush().await?;
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line).await?;
+    Ok(())
+}
+
+// AUTH carries credentials, only its mechanism is logged
+fn log_command(command: &str, command_upper: &str) {
+    if command_upper.starts_with("AUTH") {
+        debug!(command = command.split_whitespace().take(2).collect::<Vec<_>>().join(" "), "Command");
+    } else {
+        debug!(command, "Command");
+    }
+}
+
+pub(crate) const START_DATA: &[u8] = b"354 End data with <CR><LF>.<CR><LF>\r\n";
+const OVER_BUDGET: &[u8] = b"452 4.3.1 Insufficient system resources, try again later\r\n";
+const NO_STORAGE: &[u8] = b"452 4.3.1 Insufficient system storage, try again later\r\n";
+const TOO_LARGE: &[u8] = b"552 5.3.4 Message size exceeds fixed maximum message size\r\n";
+const SHUTTING_DOWN: &[u8] = b"421 4.3.2 Service shutting down\r\n";
+const THROTTLED: &[u8] = b"421 4.7.0 Too many commands, slow down\r\n";
+pub(crate) const TOO_MANY_CONNECTIONS: &[u8] = b"421 4.7.0 Too many connections, try again later\r\n";
+
+// the SIZE line of the EHLO reply, without a number when there is no limit (RFC 1870)
+fn size_capability() -> Vec<u8> {
+    match config::max_message_size() {
+        Some(max) => format!("250-SIZE {}\r\n", max).into_bytes(),
+        None => b"250-SIZE\r\n".to_vec(),
+    }
+}
+
+fn too_large(size: usize) -> bool {
+    config::max_message_size().is_some_and(|max| size > max)
+}
+
+// the address of MAIL FROM, and the size the client announced with SIZE=, if any
+pub(crate) fn parse_mail_from(command: &str) -> (String, Option<usize>) {
+    // `MAIL FROM:` is 10 bytes, a shorter or non-ASCII command has no address
+    let mut words = command.get(10..).unwrap_or_default().split_whitespace();
+    let address = words.next().unwrap_or("").replace(['<', '>'], "");
+    let size = words.find_map(|word| {
+        let (name, value) = word.split_once('=')?;
+        name.eq_ignore_ascii_case("SIZE").then(|| value.parse().ok())?
+    });
+    (address, size)
+}
+
+/// How DATA ended.
+enum Data {
+    Received(Bytes, Reservation),
+    // didn't fit in the memory budget
+    OverBudget,
+    // over --max-message-size
+    TooLarge,
+}
+
+// the reply to a message that wasn't received
+fn refuse(data: Data) -> &'static [u8] {
+    match data {
+        Data::TooLarge => {
+            metrics::MESSAGES_TOO_LARGE.inc();
+            snapshot::reject(Rejection::TooLarge);
+            TOO_LARGE
+        }
+        _ => {
+            metrics::MEMORY_REJECTED.inc();
+            snapshot::reject(Rejection::Memory);
+            OVER_BUDGET
+        }
+    }
+}
+
+// reads the message up to the lone `.`
+async fn read_data<R: AsyncBufRead + Unpin>(reader: &mut R, session: &Session) -> std::io::Result<Data> {
+    // raw bytes, 8-bit mails aren't necessarily valid UTF-8
+    let mut data = BytesMut::new();
+    let mut reservation = Reservation::new();
+    let mut size = 0;
+    let mut over_budget = false;
+
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let bytes_read = reader.read_until(b'\n', &mut line).await?;
+        if bytes_read == 0 {
+            // connection closed unexpectedly
+            break;
+        }
+        session.add_bytes(bytes_read);
+        if line.trim_ascii_end() == b"." {
+            break;
+        }
+        size += line.len();
+        if too_large(size) {
+            // nothing more is kept, and what was is let go right away
+            data = BytesMut::new();
+            reservation = Reservation::new();
+            continue;
+        }
+        // past the budget the rest is still read, to stay in sync with the client, but dropped
+        if !over_budget && reservation.grow(line.len()) {
+            data.extend_from_slice(&line);
+        } else {
+            over_budget = true;
+        }
+    }
+
+    Ok(match (too_large(size), over_budget) {
+        (true, _) => Data::TooLarge,
+        (false, true) => Data::OverBudget,
+        (false, false) => Data::Received(data.freeze(), reservation),
+    })
+}
+
+// the reply to the end of DATA, the envelope is over either way
+async fn deliver(
+    queue: &Queue,
+    envelope: (HashSet<String>, HashSet<String>),
+    data: Bytes,
+    reservation: Reservation,
+    user: Option<String>,
+    client: Client,
+) -> &'static [u8] {
+    // incomplete mails are acknowledged but not kept
+    if envelope.0.is_empty() || envelope.1.is_empty() || data.len() <= 20 {
+        return b"250 OK\r\n";
+    }
+
+    // before replying, like a receiving server, so that the results are there once it's stored
+    let authentication = match config::verify_auth() {
+        true => Some(verification::verify(&data, &client).await),
+        false => None,
+    };
+    match submit(queue, envelope, data, reservation, user, authentication) {
+        Submitted::Queued(..) | Submitted::Dropped(_) => b"250 OK\r\n",
+        Submitted::Duplicate(_) => b"550 5.7.1 Duplicate message, already received\r\n",
+        Submitted::Refused => NO_STORAGE,
+    }
+}
+
+/// What became of a mail handed over by SMTP or `POST /mails`.
+pub(crate) enum Submitted {
+    /// Waiting to be stored, with its id and a receiver told once it is
+    Queued(u128, oneshot::Receiver<()>),
+    /// Not kept, by the rules or the duplicates policy, although accepted
+    Dropped(u128),
+    /// Refused by `--duplicates reject`, with the id of the first one
+    Duplicate(u128),
+    /// Refused because the ingestion queue is full, for now
+    Refused,
 }
 
-pub fn load_tls_config() -> Result<ServerConfig, Box<dyn Error + Send + Sync>> {
-    // load the TLS certificate and private key files
-    let cert_file = &mut StdBufReader::new(File::open("cert.pem")?);
-    let key_file = &mut StdBufReader::new(File::open("key.pem")?);
-
-    // cert pem
-    let cert_chain = certs(cert_file)
-        .map_err(|_| "Failed to read certificate file")?
-        .into_iter()
-        .map(Certificate)
-        .collect();
-
-    // keys pem
-    let mut keys = pkcs8_private_keys(key_file)
-        .map_err(|_| "Failed to read key file")?
-        .into_iter()
-        .map(PrivateKey)
-        .collect::<Vec<_>>();
-
-    if keys.is_empty() {
-        return Err(From::from("No private keys found in key.pem"));
+/// Runs a received mail through the rules and the duplicates policy, then queues it for storage
+/// and sends its forwards, relay and webhooks.
+pub(crate) fn submit(
+    queue: &Queue,
+    (from, to): (HashSet<String>, HashSet<String>),
+    data: Bytes,
+    reservation: Reservation,
+    user: Option<String>,
+    authentication: Option<Authentication>,
+) -> Submitted {
+    let route = rules::route(&to);
+    let subject = get_subject(&String::from_utf8_lossy(&data));
+    let mail = Mail::new(from, to, data, subject);
+    let mail_id = mail.id;
+    let deliveries = rules::deliveries(&route, &mail);
+    if !route.store {
+        metrics::RULE_DROPPED.inc();
+        rules::dispatch(deliveries, queue.relays());
+        info!(mail_id, "Mail dropped by the rules");
+        return Submitted::Dropped(mail_id);
     }
 
-    let config = ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, keys.remove(0))?;
+    // failing to tell is no reason to refuse the mail
+    let duplicate_of = queue.find_duplicate(&mail).unwrap_or(None);
+    match (duplicate_of, config::duplicates()) {
+        (Some(original), Policy::Drop) => {
+            info!(mail_id, duplicate_of = original, "Duplicate mail dropped");
+            return Submitted::Dropped(mail_id);
+        }
+        (Some(original), Policy::Reject) => {
+            info!(mail_id, duplicate_of = original, "Duplicate mail rejected");
+            return Submitted::Duplicate(original);
+        }
+        _ => {}
+    }
 
-    Ok(config)
+    // only once accepted, a refused mail comes back
+    let labels = Labels {
+        user,
+        authentication,
+        ..route.labels
+    };
+    match queue.push(mail, reservation, duplicate_of, labels) {
+        Some(stored) => {
+            rules::dispatch(deliveries, queue.relays());
+            info!(mail_id, "Mail accepted");
+            Submitted::Queued(mail_id, stored)
+        }
+        None => {
+            warn!(mail_id, "Mail refused, the ingestion queue is full");
+            Submitted::Refused
+        }
+    }
 }
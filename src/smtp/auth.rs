@@ -0,0 +1,152 @@
+use crate::metrics;
+use crate::smtp::sessions::Session;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+lazy_static! {
+    // username to password, empty to accept any credentials
+    static ref CREDENTIALS: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+/// Advertised in the EHLO reply.
+pub const CAPABILITY: &[u8] = b"250-AUTH PLAIN LOGIN\r\n";
+
+/// A `--smtp-user` value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credential {
+    pub username: String,
+    pub password: String,
+}
+
+/// Parses `USERNAME:PASSWORD`, the password may contain colons.
+pub fn parse(spec: &str) -> Result<Credential, String> {
+    match spec.split_once(':') {
+        Some((username, password)) if !username.is_empty() => Ok(Credential {
+            username: username.to_string(),
+            password: password.to_string(),
+        }),
+        _ => Err(format!("Invalid credential `{}`, expected `<username>:<password>`", spec)),
+    }
+}
+
+pub fn init(credentials: Vec<Credential>) {
+    *CREDENTIALS.write().unwrap() = credentials
+        .into_iter()
+        .map(|credential| (credential.username, credential.password))
+        .collect();
+}
+
+/// Whether these credentials are accepted: any of them without `--smtp-user`.
+pub fn check(username: &str, password: &str) -> bool {
+    let credentials = CREDENTIALS.read().unwrap();
+    credentials.is_empty() || credentials.get(username).is_some_and(|expected| expected == password)
+}
+
+/// Decodes the `authzid\0authcid\0passwd` of AUTH PLAIN into the username and password.
+pub fn decode_plain(response: &str) -> Option<(String, String)> {
+    let decoded = BASE64_STANDARD.decode(response.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.split('\0');
+    let (_authzid, username, password) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || username.is_empty() {
+        return None;
+    }
+    Some((username.to_string(), password.to_string()))
+}
+
+fn decode_text(response: &str) -> Option<String> {
+    let decoded = BASE64_STANDARD.decode(response.trim()).ok()?;
+    String::from_utf8(decoded).ok()
+}
+
+/// Runs an `AUTH` command to its end, asking for whatever the initial response didn't give.
+/// Returns the authenticated username, if any, once the reply is written.
+pub async fn authenticate<R, W>(
+    command: &str,
+    reader: &mut R,
+    writer: &mut W,
+    session: &Session,
+) -> std::io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut words = command.split_whitespace().skip(1);
+    let mechanism = words.next().unwrap_or("").to_uppercase();
+    let initial = words.next().map(str::to_string);
+
+    let credentials = match mechanism.as_str() {
+        "PLAIN" => {
+            let response = match initial {
+                Some(response) => Some(response),
+                None => challenge(b"334 \r\n", reader, writer, session).await?,
+            };
+            response.map(|response| decode_plain(&response))
+        }
+        "LOGIN" => {
+            let username = match initial {
+                Some(response) => Some(response),
+                // "Username:"
+                None => challenge(b"334 VXNlcm5hbWU6\r\n", reader, writer, session).await?,
+            };
+            match username.map(|username| decode_text(&username)) {
+                Some(Some(username)) => {
+                    // "Password:"
+                    let password = challenge(b"334 UGFzc3dvcmQ6\r\n", reader, writer, session).await?;
+                    password.map(|password| decode_text(&password).map(|password| (username, password)))
+                }
+                Some(None) => Some(None),
+                None => None,
+            }
+        }
+        _ => {
+            writer.write_all(b"504 5.5.4 Unrecognized authentication mechanism\r\n").await?;
+            return Ok(None);
+        }
+    };
+
+    match credentials {
+        // cancelled with `*`, or the connection closed
+        None => {
+            writer.write_all(b"501 5.0.0 Authentication cancelled\r\n").await?;
+            Ok(None)
+        }
+        Some(None) => {
+            writer.write_all(b"501 5.5.2 Cannot decode the response\r\n").await?;
+            Ok(None)
+        }
+        Some(Some((username, password))) if check(&username, &password) => {
+            writer.write_all(b"235 2.7.0 Authentication successful\r\n").await?;
+            Ok(Some(username))
+        }
+        Some(Some(_)) => {
+            metrics::SMTP_AUTH_FAILURES.inc();
+            writer.write_all(b"535 5.7.8 Authentication credentials invalid\r\n").await?;
+            Ok(None)
+        }
+    }
+}
+
+// sends a 334 challenge and reads the client's answer, None when cancelled
+async fn challenge<R, W>(
+    prompt: &[u8],
+    reader: &mut R,
+    writer: &mut W,
+    session: &Session,
+) -> std::io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(prompt).await?;
+    writer.flush().await?;
+
+    let mut line = String::new();
+    let bytes_read = reader.read_line(&mut line).await?;
+    session.add_bytes(bytes_read);
+    let line = line.trim();
+    Ok((bytes_read > 0 && line != "*").then(|| line.to_string()))
+}
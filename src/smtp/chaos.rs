@@ -0,0 +1,192 @@
+use crate::metrics;
+use crate::rules::glob;
+use lazy_static::lazy_static;
+use std::sync::RwLock;
+use std::time::Duration;
+
+lazy_static! {
+    static ref RULES: RwLock<Vec<Rule>> = RwLock::new(Vec::new());
+}
+
+/// When a fault is injected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Instead of the greeting
+    Connect,
+    Helo,
+    Mail,
+    Rcpt,
+    Data,
+    /// Any command
+    Any,
+}
+
+/// What the sink does instead of behaving.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Answers with this code instead of handling the command
+    Reply(u16),
+    /// Waits this long before handling the command
+    Delay(Duration),
+    /// Closes the connection, halfway through the message for DATA
+    Drop,
+}
+
+/// A fault and when to inject it, from `--chaos "<stage>[:<pattern>] <fault> [<probability>%]"`.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    // as given, for the admin API
+    pub spec: String,
+    stage: Stage,
+    // lowercased, on the address of MAIL FROM or RCPT TO
+    pattern: Option<String>,
+    pub fault: Fault,
+    // out of 1
+    probability: f64,
+}
+
+/// Parses e.g. `rcpt:reject-*@* 550`, `mail 451 20%`, `* delay=500ms`, `data drop 10%` or
+/// `connect 421`.
+pub fn parse(spec: &str) -> Result<Rule, String> {
+    let spec = spec.trim();
+    let invalid = |reason: &str| format!("Invalid chaos rule `{}`, {}", spec, reason);
+    let mut words = spec.split_whitespace();
+    let (Some(trigger), Some(fault)) = (words.next(), words.next()) else {
+        return Err(invalid("expected `<stage>[:<pattern>] <fault> [<probability>%]`"));
+    };
+    let probability = words.next();
+    if words.next().is_some() {
+        return Err(invalid("too many words"));
+    }
+
+    let (stage, pattern) = match trigger.split_once(':') {
+        Some((stage, pattern)) => (stage, Some(pattern.to_lowercase())),
+        None => (trigger, None),
+    };
+    let stage = match stage.to_lowercase().as_str() {
+        "connect" => Stage::Connect,
+        "helo" | "ehlo" => Stage::Helo,
+        "mail" => Stage::Mail,
+        "rcpt" => Stage::Rcpt,
+        "data" => Stage::Data,
+        "*" => Stage::Any,
+        _ => return Err(invalid("the stage is one of connect, helo, mail, rcpt, data or *")),
+    };
+    if pattern.is_some() && !matches!(stage, Stage::Mail | Stage::Rcpt) {
+        return Err(invalid("only mail and rcpt take an address pattern"));
+    }
+
+    let fault = match fault.to_lowercase().as_str() {
+        "drop" => Fault::Drop,
+        fault => match fault.strip_prefix("delay=") {
+            Some(delay) => Fault::Delay(parse_delay(delay).ok_or_else(|| invalid("expected e.g. delay=500ms or delay=2s"))?),
+            None => match fault.parse::<u16>() {
+                Ok(code) if (400..600).contains(&code) => Fault::Reply(code),
+                _ => return Err(invalid("the fault is a 4xx or 5xx code, delay=<duration> or drop")),
+            },
+        },
+    };
+
+    let probability = match probability {
+        Some(probability) => probability
+            .strip_suffix('%')
+            .and_then(|percent| percent.parse::<f64>().ok())
+            .filter(|percent| (0.0..=100.0).contains(percent))
+            .map(|percent| percent / 100.0)
+            .ok_or_else(|| invalid("expected a probability like 10%"))?,
+        None => 1.0,
+    };
+
+    Ok(Rule {
+        spec: spec.to_string(),
+        stage,
+        pattern,
+        fault,
+        probability,
+    })
+}
+
+// `500ms` or `2s`
+fn parse_delay(value: &str) -> Option<Duration> {
+    match value.strip_suffix("ms") {
+        Some(millis) => millis.parse().ok().map(Duration::from_millis),
+        None => value.strip_suffix('s')?.parse().ok().map(Duration::from_secs),
+    }
+}
+
+pub fn init(rules: Vec<Rule>) {
+    *RULES.write().unwrap() = rules;
+}
+
+pub fn get() -> Vec<String> {
+    RULES.read().unwrap().iter().map(|rule| rule.spec.clone()).collect()
+}
+
+/// The stage of a command, and its address for MAIL FROM and RCPT TO.
+pub fn stage(command: &str) -> (Stage, Option<String>) {
+    let upper = command.to_uppercase();
+    let address = || {
+        let (_, address) = command.split_once(':')?;
+        Some(address.trim().trim_start_matches('<').trim_end_matches('>').to_lowercase())
+    };
+    if upper.starts_with("EHLO") || upper.starts_with("HELO") {
+        (Stage::Helo, None)
+    } else if upper.starts_with("MAIL FROM") {
+        (Stage::Mail, address())
+    } else if upper.starts_with("RCPT TO") {
+        (Stage::Rcpt, address())
+    } else if upper == "DATA" {
+        (Stage::Data, None)
+    } else {
+        (Stage::Any, None)
+    }
+}
+
+/// The faults of the rules matching this stage, in order, each one rolled against its
+/// probability. Delays add up, the first reply or drop ends the list.
+pub fn faults(stage: Stage, address: Option<&str>) -> Vec<Fault> {
+    let rules = RULES.read().unwrap();
+    let mut faults = Vec::new();
+    for rule in rules.iter() {
+        let stage_matches = rule.stage == stage || (rule.stage == Stage::Any && stage != Stage::Connect);
+        let pattern_matches = match (&rule.pattern, address) {
+            (Some(pattern), Some(address)) => glob(pattern.as_bytes(), address.as_bytes()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        if !stage_matches || !pattern_matches || !roll(rule.probability) {
+            continue;
+        }
+
+        metrics::CHAOS_FAULTS.inc();
+        faults.push(rule.fault.clone());
+        if !matches!(rule.fault, Fault::Delay(_)) {
+            break;
+        }
+    }
+    faults
+}
+
+/// Sleeps through the delays of `faults` and returns the reply or drop ending them, if any.
+pub async fn inject(faults: Vec<Fault>) -> Option<Fault> {
+    for fault in faults {
+        match fault {
+            Fault::Delay(delay) => tokio::time::sleep(delay).await,
+            fault => return Some(fault),
+        }
+    }
+    None
+}
+
+/// The line answering with `code`, e.g. `451 4.0.0 Injected failure`.
+pub fn reply(code: u16) -> Vec<u8> {
+    format!("{} {}.0.0 Injected failure\r\n", code, code / 100).into_bytes()
+}
+
+fn roll(probability: f64) -> bool {
+    if probability >= 1.0 {
+        return true;
+    }
+    let random = getrandom::u32().unwrap_or(u32::MAX);
+    (random as f64 / u32::MAX as f64) < probability
+}
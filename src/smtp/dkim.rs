@@ -0,0 +1,406 @@
+use crate::dns::Resolver;
+use crate::smtp::verification::Outcome;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use ring::digest::{self, Algorithm as Hash};
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use serde::{Deserialize, Serialize};
+
+const HEADER: &str = "DKIM-Signature";
+// past this many signatures, the others are ignored rather than each costing a lookup
+const MAX_SIGNATURES: usize = 5;
+
+/// What the verification of a DKIM-Signature header found.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub result: Outcome,
+    // `d=` and `s=`, empty when the signature doesn't say
+    pub domain: String,
+    pub selector: String,
+    // why it didn't pass
+    pub reason: Option<String>,
+}
+
+impl Verdict {
+    fn new(signature: &Signature, result: Outcome, reason: Option<String>) -> Self {
+        Verdict {
+            result,
+            domain: signature.domain.clone(),
+            selector: signature.selector.clone(),
+            reason,
+        }
+    }
+}
+
+/// A message split into its header fields, as received, and its body.
+pub struct Message {
+    // the name, and the whole field with its folding and the final CRLF
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+impl Message {
+    /// Reads the fields and the body, with CRLF line endings even if the lines ended with a
+    /// bare LF, as the signer saw them.
+    pub fn parse(data: &[u8]) -> Self {
+        let mut normalized = Vec::with_capacity(data.len());
+        for (i, byte) in data.iter().enumerate() {
+            if *byte == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+                normalized.push(b'\r');
+            }
+            normalized.push(*byte);
+        }
+
+        let mut headers: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut position = 0;
+        while position < normalized.len() {
+            let end = find(&normalized[position..], b"\r\n").map_or(normalized.len(), |end| position + end + 2);
+            let line = &normalized[position..end];
+            position = end;
+            if line == b"\r\n" {
+                break;
+            }
+            match (line[0], headers.last_mut()) {
+                // folded, the field goes on
+                (b' ' | b'\t', Some((_, field))) => field.extend_from_slice(line),
+                _ => {
+                    let name = line.split(|byte| *byte == b':').next().unwrap_or_default();
+                    headers.push((String::from_utf8_lossy(name).trim().to_string(), line.to_vec()));
+                }
+            }
+        }
+
+        Message {
+            headers,
+            body: normalized[position.min(normalized.len())..].to_vec(),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Canonicalization {
+    Simple,
+    Relaxed,
+}
+
+// the tags of a DKIM-Signature header that the verification needs
+struct Signature {
+    // the index of its field in the message
+    field: usize,
+    domain: String,
+    selector: String,
+    algorithm: String,
+    signature: Vec<u8>,
+    body_hash: Vec<u8>,
+    headers: Vec<String>,
+    canonicalization: (Canonicalization, Canonicalization),
+    length: Option<usize>,
+    expiration: Option<u64>,
+}
+
+// `tag=value` pairs separated by `;`, the whitespace around them left out
+fn tags(list: &str) -> Vec<(&str, &str)> {
+    list.split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect()
+}
+
+fn without_whitespace(value: &str) -> String {
+    value.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn parse_signature(message: &Message, field: usize) -> Result<Signature, Verdict> {
+    let raw = String::from_utf8_lossy(&message.headers[field].1).into_owned();
+    let value = raw.split_once(':').map_or("", |(_, value)| value);
+    let tags = tags(value);
+    let tag = |name: &str| tags.iter().find(|(tag, _)| *tag == name).map(|(_, value)| *value);
+
+    let mut signature = Signature {
+        field,
+        domain: tag("d").unwrap_or_default().to_lowercase(),
+        selector: tag("s").unwrap_or_default().to_string(),
+        algorithm: tag("a").unwrap_or_default().to_lowercase(),
+        signature: Vec::new(),
+        body_hash: Vec::new(),
+        headers: Vec::new(),
+        canonicalization: (Canonicalization::Simple, Canonicalization::Simple),
+        length: None,
+        expiration: None,
+    };
+    let error = |signature: &Signature, reason: &str| Verdict::new(signature, Outcome::PermError, Some(reason.to_string()));
+
+    if tag("v") != Some("1") {
+        return Err(error(&signature, "unsupported version"));
+    }
+    for required in ["a", "b", "bh", "d", "h", "s"] {
+        if tag(required).is_none_or(str::is_empty) {
+            return Err(error(&signature, &format!("missing {}= tag", required)));
+        }
+    }
+    let decode = |value: Option<&str>| BASE64_STANDARD.decode(without_whitespace(value.unwrap_or_default()));
+    signature.signature = decode(tag("b")).map_err(|_| error(&signature, "invalid b= tag"))?;
+    signature.body_hash = decode(tag("bh")).map_err(|_| error(&signature, "invalid bh= tag"))?;
+    signature.headers = tag("h")
+        .unwrap_or_default()
+        .split(':')
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    if !signature.headers.iter().any(|name| name == "from") {
+        return Err(error(&signature, "From is not signed"));
+    }
+
+    let parse = |value: &str| match value.to_lowercase().as_str() {
+        "simple" => Some(Canonicalization::Simple),
+        "relaxed" => Some(Canonicalization::Relaxed),
+        _ => None,
+    };
+    if let Some(value) = tag("c") {
+        let (headers, body) = value.split_once('/').unwrap_or((value, "simple"));
+        signature.canonicalization = parse(headers)
+            .zip(parse(body))
+            .ok_or_else(|| error(&signature, "unknown canonicalization"))?;
+    }
+    if let Some(length) = tag("l") {
+        signature.length = Some(length.parse().map_err(|_| error(&signature, "invalid l= tag"))?);
+    }
+    if let Some(expiration) = tag("x") {
+        signature.expiration = Some(expiration.parse().map_err(|_| error(&signature, "invalid x= tag"))?);
+    }
+    // the signing identity has to be of the signing domain
+    if let Some(identity) = tag("i") {
+        let domain = identity.rsplit_once('@').map_or(identity, |(_, domain)| domain).to_lowercase();
+        if domain != signature.domain && !domain.ends_with(&format!(".{}", signature.domain)) {
+            return Err(error(&signature, "i= is not in d="));
+        }
+    }
+    Ok(signature)
+}
+
+/// The body as hashed, RFC 6376 section 3.4.3 and 3.4.4.
+pub fn canonical_body(body: &[u8], relaxed: bool) -> Vec<u8> {
+    let mut lines: Vec<Vec<u8>> = body
+        .split(|byte| *byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect();
+    if body.ends_with(b"\n") {
+        // what follows the last CRLF isn't a line
+        lines.pop();
+    }
+    if relaxed {
+        for line in lines.iter_mut() {
+            *line = compress_whitespace(line);
+            while line.last() == Some(&b' ') {
+                line.pop();
+            }
+        }
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut canonical = Vec::with_capacity(body.len());
+    for line in &lines {
+        canonical.extend_from_slice(line);
+        canonical.extend_from_slice(b"\r\n");
+    }
+    // an empty body is a lone CRLF in simple, nothing in relaxed
+    if canonical.is_empty() && !relaxed {
+        canonical.extend_from_slice(b"\r\n");
+    }
+    canonical
+}
+
+/// A header field as hashed, RFC 6376 section 3.4.1 and 3.4.2.
+pub fn canonical_header(field: &[u8], relaxed: bool) -> Vec<u8> {
+    if !relaxed {
+        return field.to_vec();
+    }
+    let (name, value) = match field.iter().position(|byte| *byte == b':') {
+        Some(colon) => (&field[..colon], &field[colon + 1..]),
+        None => (field, &[][..]),
+    };
+    let unfolded: Vec<u8> = value.iter().copied().filter(|byte| *byte != b'\r' && *byte != b'\n').collect();
+    let value = compress_whitespace(&unfolded);
+
+    let mut canonical = String::from_utf8_lossy(name).trim().to_lowercase().into_bytes();
+    canonical.push(b':');
+    canonical.extend_from_slice(value.trim_ascii());
+    canonical.extend_from_slice(b"\r\n");
+    canonical
+}
+
+// runs of spaces and tabs as a single space
+fn compress_whitespace(line: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::with_capacity(line.len());
+    for byte in line {
+        let byte = if *byte == b'\t' { b' ' } else { *byte };
+        if byte == b' ' && compressed.last() == Some(&b' ') {
+            continue;
+        }
+        compressed.push(byte);
+    }
+    compressed
+}
+
+// the DKIM-Signature field with the value of its b= tag left out
+fn without_signature(field: &[u8]) -> Vec<u8> {
+    let field = String::from_utf8_lossy(field);
+    let Some((name, value)) = field.split_once(':') else {
+        return field.as_bytes().to_vec();
+    };
+    let tags = value
+        .split(';')
+        .map(|tag| match tag.split_once('=') {
+            Some((tag_name, _)) if tag_name.trim() == "b" => format!("{}=", tag_name),
+            _ => tag.to_string(),
+        })
+        .collect::<Vec<_>>();
+    format!("{}:{}", name, tags.join(";")).into_bytes()
+}
+
+// what the signature signs: the fields listed in h=, from the last occurrence up, then the
+// signature field itself without its final CRLF
+fn signed_headers(message: &Message, signature: &Signature) -> Vec<u8> {
+    let relaxed = signature.canonicalization.0 == Canonicalization::Relaxed;
+    let mut used = vec![false; message.headers.len()];
+    let mut data = Vec::new();
+    for name in &signature.headers {
+        let field = (0..message.headers.len())
+            .rev()
+            .find(|i| !used[*i] && message.headers[*i].0.eq_ignore_ascii_case(name));
+        // a missing field is signed as absent, it contributes nothing
+        if let Some(i) = field {
+            used[i] = true;
+            data.extend_from_slice(&canonical_header(&message.headers[i].1, relaxed));
+        }
+    }
+    let own = canonical_header(&without_signature(&message.headers[signature.field].1), relaxed);
+    data.extend_from_slice(own.strip_suffix(b"\r\n").unwrap_or(&own));
+    data
+}
+
+// the key of a DKIM record: `k=` and the decoded `p=`
+fn parse_key(record: &str) -> Result<(String, Vec<u8>), String> {
+    let tags = tags(record);
+    let tag = |name: &str| tags.iter().find(|(tag, _)| *tag == name).map(|(_, value)| *value);
+    if tag("v").is_some_and(|version| version != "DKIM1") {
+        return Err("unsupported key record version".to_string());
+    }
+    let kind = tag("k").unwrap_or("rsa").to_lowercase();
+    let key = without_whitespace(tag("p").ok_or("no p= in the key record")?);
+    if key.is_empty() {
+        return Err("key revoked".to_string());
+    }
+    let key = BASE64_STANDARD.decode(key).map_err(|_| "invalid p= in the key record".to_string())?;
+    Ok((kind, key))
+}
+
+// the RSAPublicKey of a SubjectPublicKeyInfo, which DKIM records hold, or the key as is if it's
+// one already
+fn rsa_public_key(der: &[u8]) -> &[u8] {
+    // a tag and a length, then the content
+    fn element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, rest) = der.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (length, rest) = match first {
+            0..=0x7f => (first as usize, rest),
+            _ => {
+                let count = (first & 0x7f) as usize;
+                let bytes = rest.get(..count)?;
+                (bytes.iter().fold(0, |length, byte| (length << 8) | *byte as usize), &rest[count..])
+            }
+        };
+        Some((tag, rest.get(..length)?, &rest[length..]))
+    }
+    const SEQUENCE: u8 = 0x30;
+    const BIT_STRING: u8 = 0x03;
+
+    let key = element(der).and_then(|(tag, content, _)| {
+        let (algorithm, _, rest) = element(content).filter(|_| tag == SEQUENCE)?;
+        let (tag, bits, _) = element(rest).filter(|_| algorithm == SEQUENCE)?;
+        // after the count of unused bits, always 0 here
+        bits.get(1..).filter(|_| tag == BIT_STRING)
+    });
+    key.unwrap_or(der)
+}
+
+/// Checks a signature of `message` with the DKIM record of its selector, given as fetched from
+/// DNS. `now` is in seconds since the epoch, for `x=`.
+fn check(message: &Message, signature: &Signature, record: &str, now: u64) -> Verdict {
+    let fail = |result, reason: &str| Verdict::new(signature, result, Some(reason.to_string()));
+
+    let (hash, rsa): (&'static Hash, &'static dyn VerificationAlgorithm) = match signature.algorithm.as_str() {
+        "rsa-sha256" => (&digest::SHA256, &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY),
+        "rsa-sha1" => (
+            &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            &signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY,
+        ),
+        "ed25519-sha256" => (&digest::SHA256, &signature::ED25519),
+        _ => return fail(Outcome::PermError, "unsupported algorithm"),
+    };
+    if signature.expiration.is_some_and(|expiration| expiration < now) {
+        return fail(Outcome::Fail, "signature expired");
+    }
+
+    let mut body = canonical_body(&message.body, signature.canonicalization.1 == Canonicalization::Relaxed);
+    if let Some(length) = signature.length {
+        if length > body.len() {
+            return fail(Outcome::Fail, "l= is past the end of the body");
+        }
+        body.truncate(length);
+    }
+    if digest::digest(hash, &body).as_ref() != signature.body_hash {
+        return fail(Outcome::Fail, "body hash mismatch");
+    }
+
+    let (kind, key) = match parse_key(record) {
+        Ok(key) => key,
+        Err(reason) => return fail(Outcome::PermError, &reason),
+    };
+    let headers = signed_headers(message, signature);
+    let verified = match (kind.as_str(), signature.algorithm.starts_with("rsa-")) {
+        ("rsa", true) => UnparsedPublicKey::new(rsa, rsa_public_key(&key)).verify(&headers, &signature.signature),
+        // Ed25519 signs the hash of the headers rather than the headers themselves, RFC 8463
+        ("ed25519", false) => UnparsedPublicKey::new(&signature::ED25519, &key)
+            .verify(digest::digest(hash, &headers).as_ref(), &signature.signature),
+        _ => return fail(Outcome::PermError, "the key doesn't go with the algorithm"),
+    };
+    match verified {
+        Ok(()) => Verdict::new(signature, Outcome::Pass, None),
+        Err(_) => fail(Outcome::Fail, "signature mismatch"),
+    }
+}
+
+/// Verifies the DKIM signatures of a message, fetching their keys from DNS.
+pub async fn verify(data: &[u8], resolver: &dyn Resolver, now: u64) -> Vec<Verdict> {
+    let message = Message::parse(data);
+    let fields = (0..message.headers.len())
+        .filter(|i| message.headers[*i].0.eq_ignore_ascii_case(HEADER))
+        .take(MAX_SIGNATURES)
+        .collect::<Vec<_>>();
+
+    let mut verdicts = Vec::new();
+    for field in fields {
+        let signature = match parse_signature(&message, field) {
+            Ok(signature) => signature,
+            Err(verdict) => {
+                verdicts.push(verdict);
+                continue;
+            }
+        };
+        let name = format!("{}._domainkey.{}", signature.selector, signature.domain);
+        let verdict = match resolver.txt(&name).await {
+            Ok(records) => match records.first() {
+                Some(record) => check(&message, &signature, record, now),
+                None => Verdict::new(&signature, Outcome::PermError, Some(format!("no key at {}", name))),
+            },
+            Err(e) => Verdict::new(&signature, Outcome::TempError, Some(e)),
+        };
+        verdicts.push(verdict);
+    }
+    verdicts
+}
@@ -1,29 +1,102 @@
-use mailparse::parse_mail;
+use bytes::Bytes;
+use mailparse::{parse_mail, DispositionType, MailHeaderMap, ParsedMail};
 use rfc2047_decoder::decode;
-use serde::{Deserialize, Serialize};
+use serde::de::{Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
 use std::collections::HashSet;
+use std::fmt;
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Mail {
     pub from: HashSet<String>,
     pub to: HashSet<String>,
     pub subject: Option<String>,
-    pub data: String,
+    // the raw message, shared rather than copied from SMTP to the database and HTTP
+    #[serde(with = "raw_data")]
+    pub data: Bytes,
     pub id: u128,
 }
 
+/// Bytes in the database, which bincode lays out like the `String` mails used to be stored as,
+/// and text in JSON.
+mod raw_data {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(data: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&String::from_utf8_lossy(data))
+        } else {
+            serializer.serialize_bytes(data)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        struct RawVisitor;
+
+        impl<'de> Visitor<'de> for RawVisitor {
+            type Value = Bytes;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("the raw message")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, value: &[u8]) -> Result<Bytes, E> {
+                Ok(Bytes::copy_from_slice(value))
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, value: Vec<u8>) -> Result<Bytes, E> {
+                Ok(Bytes::from(value))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Bytes, E> {
+                Ok(Bytes::copy_from_slice(value.as_bytes()))
+            }
+
+            fn visit_string<E: serde::de::Error>(self, value: String) -> Result<Bytes, E> {
+                Ok(Bytes::from(value))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_string(RawVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(RawVisitor)
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub size: usize,
+    pub content_id: Option<String>,
+    // of the decoded content, in hex, so that tests can compare it without downloading it
+    pub sha256: String,
+    // base64, only when asked for with `?content=1`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
 impl Mail {
     pub fn parse_body(&self) -> String {
-        let mail = match parse_mail(self.data.as_bytes()) {
+        let mail = match parse_mail(&self.data) {
             Ok(parsed) => parsed,
             // return raw body if parsing fails
             Err(_) => {
-                let mut body = self.data.clone();
+                let data = self.data_str();
                 // after the headers
-                if let Some(index) = body.find("\r\n\r\n") {
-                    body = body[index + 4..].to_string();
-                }
-                return body;
+                return match data.find("\r\n\r\n") {
+                    Some(index) => data[index + 4..].to_string(),
+                    None => data.into_owned(),
+                };
             }
         };
 
@@ -32,21 +105,100 @@ impl Mail {
             // not multipart, return the body as is
             mail.get_body().unwrap_or_else(|_| String::new())
         } else {
-            // prioritize 'text/html' parts
-            for part in &mail.subparts {
-                let content_type = part.ctype.mimetype.to_lowercase();
-                if content_type == "text/html" {
-                    // Return the HTML part's body
-                    return part.get_body().unwrap_or_else(|_| String::new());
-                }
-            }
-            // no 'text/html' part found, return the first multipart's body
-            mail.subparts[0]
+            // prioritize 'text/html' parts, then 'text/plain' ones, looking into nested multiparts
+            // (e.g. an alternative inside a mixed one when the mail has attachments)
+            find_part(&mail, "text/html")
+                .or_else(|| find_part(&mail, "text/plain"))
+                .unwrap_or(&mail.subparts[0])
                 .get_body()
                 .unwrap_or_else(|_| String::new())
         }
     }
 
+    /// The `text/html` alternative, if the mail has one.
+    pub fn html_body(&self) -> Option<String> {
+        self.body_part("text/html")
+    }
+
+    /// The `text/plain` alternative, if the mail has one.
+    pub fn text_body(&self) -> Option<String> {
+        self.body_part("text/plain")
+    }
+
+    /// The `text/html` alternative, or else the text one (or the whole body) in a `<pre>`.
+    pub fn html_preview(&self) -> String {
+        if let Some(html) = self.html_body() {
+            return html;
+        }
+        let text = self.text_body().unwrap_or_else(|| self.parse_body());
+        format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"></head><body><pre>{}</pre></body></html>",
+            escape_html(&text)
+        )
+    }
+
+    fn body_part(&self, mimetype: &str) -> Option<String> {
+        let mail = parse_mail(&self.data).ok()?;
+        find_part(&mail, mimetype).and_then(|part| part.get_body().ok())
+    }
+
+    /// Top-level headers in their original order, with encoded words decoded.
+    pub fn headers(&self) -> Vec<Header> {
+        match parse_mail(&self.data) {
+            Ok(mail) => mail
+                .headers
+                .iter()
+                .map(|header| Header {
+                    name: header.get_key(),
+                    value: header.get_value(),
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn attachments(&self) -> Vec<Attachment> {
+        self.attachments_with_content()
+            .into_iter()
+            .map(|(attachment, _)| attachment)
+            .collect()
+    }
+
+    /// Attachments along with their decoded content, in MIME tree order.
+    pub fn attachments_with_content(&self) -> Vec<(Attachment, Vec<u8>)> {
+        let mail = match parse_mail(&self.data) {
+            Ok(parsed) => parsed,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut parts = Vec::new();
+        collect_attachment_parts(&mail, &mut parts);
+
+        parts
+            .into_iter()
+            .map(|(filename, part)| {
+                let content = part.get_body_raw().unwrap_or_default();
+                let attachment = Attachment {
+                    filename,
+                    content_type: part.ctype.mimetype.clone(),
+                    size: content.len(),
+                    sha256: sha256(&content),
+                    content_id: part
+                        .headers
+                        .get_first_value("Content-ID")
+                        .map(|id| id.trim().trim_matches(['<', '>']).to_string()),
+                    content: None,
+                };
+                (attachment, content)
+            })
+            .collect()
+    }
+
+    /// The raw message as text, only copied when it isn't valid UTF-8.
+    pub fn data_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.data)
+    }
+
     pub fn timestamp(&self) -> u128 {
         crate::snowflake::to_timestamp(self.id)
     }
@@ -54,19 +206,71 @@ impl Mail {
     pub fn new(
         from: HashSet<String>,
         to: HashSet<String>,
-        data: String,
+        data: impl Into<Bytes>,
         subject: Option<String>,
     ) -> Self {
         Self {
             from,
             to,
             subject,
-            data,
+            data: data.into(),
             id: crate::snowflake::next(),
         }
     }
 }
 
+fn sha256(data: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn find_part<'a>(part: &'a ParsedMail<'a>, mimetype: &str) -> Option<&'a ParsedMail<'a>> {
+    if part.subparts.is_empty() {
+        let is_attachment = part.get_content_disposition().disposition == DispositionType::Attachment;
+        return (part.ctype.mimetype == mimetype && !is_attachment).then_some(part);
+    }
+
+    part.subparts
+        .iter()
+        .find_map(|subpart| find_part(subpart, mimetype))
+}
+
+// walks the MIME tree and collects the leaf parts that are attachments, in order
+fn collect_attachment_parts<'a>(part: &'a ParsedMail<'a>, parts: &mut Vec<(String, &'a ParsedMail<'a>)>) {
+    if !part.subparts.is_empty() {
+        for subpart in &part.subparts {
+            collect_attachment_parts(subpart, parts);
+        }
+        return;
+    }
+
+    let disposition = part.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .cloned();
+
+    match (disposition.disposition, filename) {
+        // inline parts without a name are the body itself
+        (DispositionType::Attachment, filename) => {
+            parts.push((filename.unwrap_or_else(|| "attachment".to_string()), part))
+        }
+        (_, Some(filename)) => parts.push((filename, part)),
+        _ => {}
+    }
+}
+
 pub fn get_subject(data: &str) -> Option<String> {
     for line in data.lines() {
         if line.to_lowercase().starts_with("subject:") {
@@ -145,11 +349,8 @@ fn extract_emails(header_value: &str, email_set: &mut HashSet<String>) {
 
 fn extract_email_address(s: &str) -> Option<String> {
     if let Some(start) = s.find('<') {
-        if let Some(end) = s.find('>') {
-            Some(s[start + 1..end].to_string())
-        } else {
-            None // malformed email
-        }
+        // malformed email if there is no closing bracket
+        s.find('>').map(|end| s[start + 1..end].to_string())
     } else {
         Some(s.to_string())
     }
@@ -0,0 +1,102 @@
+use crate::metrics;
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref POLICY: RwLock<Policy> = RwLock::new(Policy::default());
+}
+
+/// Refused at RCPT TO, like a production server does for an unknown mailbox.
+pub const REJECTED: &[u8] = b"550 5.1.1 Recipient address rejected\r\n";
+/// Answered to DATA when none of the recipients was accepted.
+pub const NO_RECIPIENTS: &[u8] = b"554 5.5.1 No valid recipients\r\n";
+
+/// A `--accept-recipient` or `--reject-recipient` regular expression.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    // as given, for the configuration page
+    pub spec: String,
+    regex: Regex,
+}
+
+/// Parses a regular expression matched against whole addresses, case-insensitively.
+pub fn parse_pattern(spec: &str) -> Result<Pattern, String> {
+    let regex = RegexBuilder::new(&format!("^(?:{})$", spec))
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| format!("Invalid recipient pattern `{}`: {}", spec, e))?;
+    Ok(Pattern {
+        spec: spec.to_string(),
+        regex,
+    })
+}
+
+/// Parses `example.com`, or `*.example.com` for its subdomains.
+pub fn parse_domain(spec: &str) -> Result<String, String> {
+    let domain = spec.trim().trim_start_matches('@').to_lowercase();
+    let name = domain.strip_prefix("*.").unwrap_or(&domain);
+    if name.is_empty() || name.contains(['@', '*']) || name.contains(char::is_whitespace) {
+        return Err(format!("Invalid domain `{}`, expected e.g. example.com or *.example.com", spec));
+    }
+    Ok(domain)
+}
+
+/// Which recipients RCPT TO accepts. Without any domain nor pattern, all of them.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    // lowercased, `*.example.com` for the subdomains of `example.com`
+    pub domains: Vec<String>,
+    pub accept: Vec<Pattern>,
+    pub reject: Vec<Pattern>,
+}
+
+impl Policy {
+    /// Rejected when matching a `reject` pattern, whatever the rest. Otherwise accepted if
+    /// there's nothing to accept from, or if its domain or an `accept` pattern matches.
+    pub fn accepts(&self, address: &str) -> bool {
+        if self.reject.iter().any(|pattern| pattern.regex.is_match(address)) {
+            return false;
+        }
+        if !self.restricted() {
+            return true;
+        }
+        let domain = address.rsplit_once('@').map(|(_, domain)| domain.to_lowercase());
+        let domain_matches = domain.is_some_and(|domain| {
+            self.domains.iter().any(|accepted| match accepted.strip_prefix("*.") {
+                Some(parent) => domain.ends_with(&format!(".{}", parent)),
+                None => domain == *accepted,
+            })
+        });
+        domain_matches || self.accept.iter().any(|pattern| pattern.regex.is_match(address))
+    }
+
+    /// Whether only some recipients are accepted, so that a mail may have none left.
+    pub fn restricted(&self) -> bool {
+        !self.domains.is_empty() || !self.accept.is_empty()
+    }
+}
+
+pub fn init(policy: Policy) {
+    *POLICY.write().unwrap() = policy;
+}
+
+/// Whether a RCPT TO address is accepted, counting it in `mail_sink_recipients_rejected_total`
+/// if not.
+pub fn check(address: &str) -> bool {
+    let accepted = POLICY.read().unwrap().accepts(address);
+    if !accepted {
+        metrics::RECIPIENTS_REJECTED.inc();
+    }
+    accepted
+}
+
+/// Whether an address taken from the headers of a mail is one it can be captured for.
+pub fn accepts(address: &str) -> bool {
+    POLICY.read().unwrap().accepts(address)
+}
+
+/// Whether a mail needs an accepted recipient, see [`Policy::restricted`].
+pub fn restricted() -> bool {
+    POLICY.read().unwrap().restricted()
+}
@@ -0,0 +1,138 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<u64, Arc<Entry>>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Where a SMTP conversation is at.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Connected,
+    Greeted,
+    Mail,
+    Rcpt,
+    Data,
+    Tls,
+}
+
+struct Entry {
+    peer: SocketAddr,
+    started: Instant,
+    started_at: u128,
+    state: Mutex<State>,
+    tls: AtomicBool,
+    bytes: AtomicU64,
+    kill: Notify,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub peer: String,
+    pub state: State,
+    // through STARTTLS or on the SMTPS port
+    pub tls: bool,
+    // received from the client, TLS included
+    pub bytes: u64,
+    pub started_at: u128,
+    pub duration_ms: u128,
+}
+
+/// A live SMTP connection, unregistered when dropped.
+pub struct Session {
+    id: u64,
+    entry: Arc<Entry>,
+}
+
+impl Session {
+    pub fn open(peer: SocketAddr) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let entry = Arc::new(Entry {
+            peer,
+            started: Instant::now(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis())
+                .unwrap_or(0),
+            state: Mutex::new(State::Connected),
+            tls: AtomicBool::new(false),
+            bytes: AtomicU64::new(0),
+            kill: Notify::new(),
+        });
+        SESSIONS.lock().unwrap().insert(id, entry.clone());
+        Session { id, entry }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.entry.peer
+    }
+
+    pub fn set_state(&self, state: State) {
+        *self.entry.state.lock().unwrap() = state;
+    }
+
+    /// Marks the connection as encrypted, once the TLS handshake is done.
+    pub fn set_tls(&self) {
+        self.entry.tls.store(true, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: usize) {
+        self.entry.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Resolves once the session has been killed through [`kill`].
+    pub async fn killed(&self) {
+        self.entry.kill.notified().await
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        SESSIONS.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// The open sessions, oldest first.
+pub fn list() -> Vec<SessionInfo> {
+    let sessions = SESSIONS.lock().unwrap();
+    let mut list: Vec<SessionInfo> = sessions
+        .iter()
+        .map(|(id, entry)| SessionInfo {
+            id: *id,
+            peer: entry.peer.to_string(),
+            state: *entry.state.lock().unwrap(),
+            tls: entry.tls.load(Ordering::Relaxed),
+            bytes: entry.bytes.load(Ordering::Relaxed),
+            started_at: entry.started_at,
+            duration_ms: entry.started.elapsed().as_millis(),
+        })
+        .collect();
+    list.sort_by_key(|session| session.id);
+    list
+}
+
+/// Asks a session to drop its connection, false if there's no such session.
+pub fn kill(id: u64) -> bool {
+    match SESSIONS.lock().unwrap().get(&id) {
+        Some(entry) => {
+            // notify_one keeps the permit if the session isn't awaiting yet
+            entry.kill.notify_one();
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,334 @@
+use crate::dns::Resolver;
+use crate::smtp::verification::Outcome;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+
+// the mechanisms and modifiers that cost a lookup, past which the record is in error (RFC 7208
+// section 4.6.4)
+const MAX_LOOKUPS: usize = 10;
+const MAX_MX: usize = 10;
+
+/// What the SPF policy of the sender's domain says of the client that sent a mail.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub result: Outcome,
+    // of MAIL FROM, or the HELO name when MAIL FROM is empty
+    pub domain: String,
+    pub ip: IpAddr,
+    // why it didn't pass
+    pub reason: Option<String>,
+}
+
+// an outcome of check_host(), with why
+type Evaluated = (Outcome, Option<String>);
+
+/// Evaluates the SPF record of the domain of `mail_from`, or of `helo` for a bounce.
+pub async fn verify(ip: IpAddr, helo: Option<&str>, mail_from: Option<&str>, resolver: &dyn Resolver) -> Verdict {
+    let ip = ip.to_canonical();
+    let helo = helo.unwrap_or_default().trim().trim_end_matches('.').to_lowercase();
+    let sender = match mail_from.map(str::trim).filter(|address| address.contains('@')) {
+        Some(address) => address.to_string(),
+        None => format!("postmaster@{}", helo),
+    };
+    let domain = sender.rsplit_once('@').map_or("", |(_, domain)| domain).trim_end_matches('.').to_lowercase();
+
+    let (result, reason) = if valid_domain(&domain) {
+        let mut evaluation = Evaluation {
+            resolver,
+            ip,
+            sender,
+            helo,
+            lookups: 0,
+        };
+        evaluation.check_host(domain.clone()).await
+    } else {
+        (Outcome::None, Some("no domain to check".to_string()))
+    };
+    Verdict {
+        result,
+        domain,
+        ip,
+        reason,
+    }
+}
+
+// at least two labels, none empty
+fn valid_domain(domain: &str) -> bool {
+    domain.contains('.') && domain.split('.').all(|label| !label.is_empty() && label.len() <= 63)
+}
+
+struct Evaluation<'a> {
+    resolver: &'a dyn Resolver,
+    ip: IpAddr,
+    sender: String,
+    helo: String,
+    lookups: usize,
+}
+
+impl Evaluation<'_> {
+    // check_host() of RFC 7208 section 4, boxed for include: and redirect= to recurse
+    fn check_host(&mut self, domain: String) -> Pin<Box<dyn Future<Output = Evaluated> + Send + '_>> {
+        Box::pin(async move {
+            let records = match self.resolver.txt(&domain).await {
+                Ok(records) => records,
+                Err(e) => return (Outcome::TempError, Some(e)),
+            };
+            let records: Vec<String> = records
+                .into_iter()
+                .filter(|record| record.split(' ').next().unwrap_or_default().eq_ignore_ascii_case("v=spf1"))
+                .collect();
+            let record = match records.as_slice() {
+                [record] => record,
+                [] => return (Outcome::None, Some(format!("no SPF record at {}", domain))),
+                _ => return permerror(&format!("several SPF records at {}", domain)),
+            };
+
+            let mut redirect = None;
+            let mut mechanisms = Vec::new();
+            for term in record.split(' ').skip(1).filter(|term| !term.is_empty()) {
+                match modifier(term) {
+                    Some(("redirect", target)) if redirect.is_none() => redirect = Some(target.to_string()),
+                    Some(("redirect", _)) => return permerror("several redirect= modifiers"),
+                    // exp= explains a failure to the sender, and unknown modifiers are ignored
+                    Some(_) => {}
+                    None => mechanisms.push(term.to_string()),
+                }
+            }
+
+            for term in mechanisms {
+                let (qualifier, mechanism) = match term.chars().next() {
+                    Some('+') => (Outcome::Pass, &term[1..]),
+                    Some('-') => (Outcome::Fail, &term[1..]),
+                    Some('~') => (Outcome::SoftFail, &term[1..]),
+                    Some('?') => (Outcome::Neutral, &term[1..]),
+                    _ => (Outcome::Pass, term.as_str()),
+                };
+                match self.matches(mechanism, &domain).await {
+                    Ok(true) => {
+                        let reason = (qualifier != Outcome::Pass).then(|| format!("{} matched {}", domain, term));
+                        return (qualifier, reason);
+                    }
+                    Ok(false) => {}
+                    Err(error) => return error,
+                }
+            }
+
+            match redirect {
+                Some(target) => {
+                    if let Err(error) = self.count_lookup() {
+                        return error;
+                    }
+                    let target = match self.expand(&target, &domain) {
+                        Ok(target) => target,
+                        Err(error) => return error,
+                    };
+                    match self.check_host(target).await {
+                        (Outcome::None, reason) => (Outcome::PermError, reason),
+                        evaluated => evaluated,
+                    }
+                }
+                None => (Outcome::Neutral, Some(format!("no mechanism of {} matched", domain))),
+            }
+        })
+    }
+
+    // whether the client matches a mechanism, an error ends the evaluation
+    async fn matches(&mut self, mechanism: &str, domain: &str) -> Result<bool, Evaluated> {
+        let (name, argument) = match mechanism.find([':', '/']) {
+            Some(end) => (&mechanism[..end], &mechanism[end..]),
+            None => (mechanism, ""),
+        };
+        match name.to_lowercase().as_str() {
+            "all" if argument.is_empty() => Ok(true),
+            "include" => {
+                self.count_lookup()?;
+                let target = self.expand(argument.strip_prefix(':').ok_or_else(|| invalid(mechanism))?, domain)?;
+                match self.check_host(target).await {
+                    (Outcome::Pass, _) => Ok(true),
+                    (Outcome::Fail | Outcome::SoftFail | Outcome::Neutral, _) => Ok(false),
+                    (Outcome::TempError, reason) => Err((Outcome::TempError, reason)),
+                    (_, reason) => Err((Outcome::PermError, reason)),
+                }
+            }
+            "a" | "mx" => {
+                self.count_lookup()?;
+                let (target, prefixes) = split_cidr(argument).ok_or_else(|| invalid(mechanism))?;
+                let target = match target.strip_prefix(':') {
+                    Some(target) => self.expand(target, domain)?,
+                    None => domain.to_string(),
+                };
+                let hosts = match name.eq_ignore_ascii_case("mx") {
+                    true => self.lookup(self.resolver.mx(&target).await)?,
+                    false => vec![target],
+                };
+                if hosts.len() > MAX_MX {
+                    return Err((Outcome::PermError, Some(format!("more than {} MX records", MAX_MX))));
+                }
+                for host in hosts {
+                    let ips = self.lookup(self.resolver.ips(&host).await)?;
+                    if ips.into_iter().any(|ip| in_network(self.ip, ip, prefixes)) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            "ip4" | "ip6" => {
+                let argument = argument.strip_prefix(':').ok_or_else(|| invalid(mechanism))?;
+                let (network, prefix) = match argument.split_once('/') {
+                    Some((network, prefix)) => (network, Some(prefix)),
+                    None => (argument, None),
+                };
+                let network: IpAddr = network.parse().map_err(|_| invalid(mechanism))?;
+                if network.is_ipv4() != name.eq_ignore_ascii_case("ip4") {
+                    return Err(invalid(mechanism));
+                }
+                let max = if network.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max).ok_or_else(|| invalid(mechanism))?,
+                    None => max,
+                };
+                Ok(in_network(self.ip, network, (prefix, prefix)))
+            }
+            "exists" => {
+                self.count_lookup()?;
+                let target = self.expand(argument.strip_prefix(':').ok_or_else(|| invalid(mechanism))?, domain)?;
+                let ips = self.lookup(self.resolver.ips(&target).await)?;
+                Ok(ips.iter().any(IpAddr::is_ipv4))
+            }
+            // deprecated, and needs reverse lookups the sink doesn't make: never matches
+            "ptr" => {
+                self.count_lookup()?;
+                Ok(false)
+            }
+            _ => Err(invalid(mechanism)),
+        }
+    }
+
+    fn count_lookup(&mut self) -> Result<(), Evaluated> {
+        self.lookups += 1;
+        match self.lookups > MAX_LOOKUPS {
+            true => Err((Outcome::PermError, Some(format!("more than {} DNS lookups", MAX_LOOKUPS)))),
+            false => Ok(()),
+        }
+    }
+
+    fn lookup<T>(&self, result: Result<T, String>) -> Result<T, Evaluated> {
+        result.map_err(|e| (Outcome::TempError, Some(e)))
+    }
+
+    /// Expands the macros of a domain-spec, RFC 7208 section 7.
+    fn expand(&self, spec: &str, domain: &str) -> Result<String, Evaluated> {
+        let mut expanded = String::new();
+        let mut chars = spec.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => expanded.push('%'),
+                Some('_') => expanded.push(' '),
+                Some('-') => expanded.push_str("%20"),
+                Some('{') => {
+                    let body: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    expanded.push_str(&self.expand_macro(&body, domain).ok_or_else(|| invalid(spec))?);
+                }
+                _ => return Err(invalid(spec)),
+            }
+        }
+        Ok(expanded)
+    }
+
+    // a letter, then how many parts to keep, `r` to reverse them, and the delimiters to split on
+    fn expand_macro(&self, body: &str, domain: &str) -> Option<String> {
+        let mut chars = body.chars();
+        let letter = chars.next()?.to_ascii_lowercase();
+        let value = match letter {
+            's' => self.sender.clone(),
+            'l' => self.sender.rsplit_once('@').map_or("postmaster", |(local, _)| local).to_string(),
+            'o' => self.sender.rsplit_once('@').map_or("", |(_, domain)| domain).to_string(),
+            'd' => domain.to_string(),
+            'i' => match self.ip {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => ip
+                    .octets()
+                    .iter()
+                    .flat_map(|octet| [octet >> 4, octet & 0xf])
+                    .map(|nibble| format!("{:x}", nibble))
+                    .collect::<Vec<_>>()
+                    .join("."),
+            },
+            'v' => if self.ip.is_ipv4() { "in-addr" } else { "ip6" }.to_string(),
+            'h' => self.helo.clone(),
+            'p' => "unknown".to_string(),
+            _ => return None,
+        };
+        let rest: String = chars.collect();
+        let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        let rest = &rest[digits.len()..];
+        let (reverse, delimiters) = match rest.strip_prefix(['r', 'R']) {
+            Some(delimiters) => (true, delimiters),
+            None => (false, rest),
+        };
+        if delimiters.chars().any(|c| !".-+,/_=".contains(c)) {
+            return None;
+        }
+        let delimiters = if delimiters.is_empty() { "." } else { delimiters };
+
+        let mut parts: Vec<&str> = value.split(|c| delimiters.contains(c)).collect();
+        if reverse {
+            parts.reverse();
+        }
+        if !digits.is_empty() {
+            let keep = digits.parse::<usize>().ok().filter(|keep| *keep > 0)?;
+            parts = parts.split_off(parts.len().saturating_sub(keep));
+        }
+        Some(parts.join("."))
+    }
+}
+
+fn invalid(term: &str) -> Evaluated {
+    (Outcome::PermError, Some(format!("invalid term `{}`", term)))
+}
+
+fn permerror(reason: &str) -> Evaluated {
+    (Outcome::PermError, Some(reason.to_string()))
+}
+
+// `name=value`, unlike a mechanism whose name is followed by `:` or `/`
+fn modifier(term: &str) -> Option<(&str, &str)> {
+    let (name, value) = term.split_once('=')?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    valid.then_some((name, value))
+}
+
+// `target/24//64` into the target and the IPv4 and IPv6 prefix lengths
+fn split_cidr(argument: &str) -> Option<(&str, (u8, u8))> {
+    let (rest, ipv6) = match argument.split_once("//") {
+        Some((rest, ipv6)) => (rest, ipv6.parse().ok().filter(|prefix| *prefix <= 128)?),
+        None => (argument, 128),
+    };
+    // a `/` inside an IPv6 address can't happen, the last one is the prefix
+    let (target, ipv4) = match rest.rsplit_once('/') {
+        Some((target, ipv4)) => (target, ipv4.parse().ok().filter(|prefix| *prefix <= 32)?),
+        None => (rest, 32),
+    };
+    Some((target, (ipv4, ipv6)))
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, (ipv4, ipv6): (u8, u8)) -> bool {
+    match (ip, network.to_canonical()) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - ipv4 as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - ipv6 as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
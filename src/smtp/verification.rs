@@ -0,0 +1,84 @@
+use crate::dns::{self, Resolver};
+use crate::smtp::{dkim, spf};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The result of a check, named as in an Authentication-Results header (RFC 8601).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    Fail,
+    // SPF only, the domain doesn't quite disallow the client
+    SoftFail,
+    Neutral,
+    // nothing to check: no signature, no SPF record
+    None,
+    // DNS failed, trying again later may do
+    TempError,
+    // the signature or the record is broken
+    PermError,
+}
+
+impl Outcome {
+    /// As given to `?dkim` and `?spf`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "pass" => Some(Outcome::Pass),
+            "fail" => Some(Outcome::Fail),
+            "softfail" => Some(Outcome::SoftFail),
+            "neutral" => Some(Outcome::Neutral),
+            "none" => Some(Outcome::None),
+            "temperror" => Some(Outcome::TempError),
+            "permerror" => Some(Outcome::PermError),
+            _ => None,
+        }
+    }
+}
+
+/// What the DKIM signatures and the SPF policy say of a mail received over SMTP, see
+/// `--verify-auth`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Authentication {
+    // one per DKIM-Signature header, none when the mail isn't signed
+    pub dkim: Vec<dkim::Verdict>,
+    pub spf: spf::Verdict,
+}
+
+impl Authentication {
+    /// The result of the DKIM signatures, `none` for a mail without any. A mail has several
+    /// results when it has several signatures.
+    pub fn dkim_results(&self) -> Vec<Outcome> {
+        match self.dkim.is_empty() {
+            true => vec![Outcome::None],
+            false => self.dkim.iter().map(|verdict| verdict.result).collect(),
+        }
+    }
+}
+
+/// Who handed a mail over, as SPF sees it.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub ip: IpAddr,
+    pub helo: Option<String>,
+    pub mail_from: Option<String>,
+}
+
+/// Verifies the DKIM signatures of a mail and the SPF policy of its sender, through DNS.
+pub async fn verify(data: &[u8], client: &Client) -> Authentication {
+    let resolver = dns::resolver();
+    verify_with(data, client, &resolver).await
+}
+
+pub async fn verify_with(data: &[u8], client: &Client, resolver: &dyn Resolver) -> Authentication {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (dkim, spf) = tokio::join!(
+        dkim::verify(data, resolver, now),
+        spf::verify(client.ip, client.helo.as_deref(), client.mail_from.as_deref(), resolver),
+    );
+    Authentication { dkim, spf }
+}
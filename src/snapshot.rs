@@ -0,0 +1,144 @@
+use crate::store::Store;
+use crate::summary::{self, MailSummary};
+use crate::SharedError;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// past this, the oldest rejections are forgotten
+const REJECTIONS_KEPT: usize = 10_000;
+
+lazy_static! {
+    static ref REJECTIONS: Mutex<VecDeque<(u128, Rejection)>> = Mutex::new(VecDeque::new());
+}
+
+/// Why a SMTP transaction was answered 452.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    QueueFull,
+    Memory,
+    Storage,
+    // over --max-message-size, answered 552
+    TooLarge,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq, Eq)]
+pub struct Rejections {
+    pub queue_full: u64,
+    pub memory: u64,
+    pub storage: u64,
+    pub too_large: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AddressState {
+    pub address: String,
+    pub count: u64,
+    pub last_id: u128,
+}
+
+/// What happened since a point in time, for tests to assert on in one call.
+#[derive(Serialize, Debug)]
+pub struct Snapshot {
+    pub since: u128,
+    // to pass as `since` to the next snapshot
+    pub now: u128,
+    pub count: u64,
+    pub bytes: u64,
+    pub last_id: Option<u128>,
+    pub senders: Vec<AddressState>,
+    pub recipients: Vec<AddressState>,
+    pub rejections: Rejections,
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0)
+}
+
+/// Remembers a refused transaction, alongside the matching metric.
+pub fn reject(rejection: Rejection) {
+    let mut rejections = REJECTIONS.lock().unwrap();
+    if rejections.len() == REJECTIONS_KEPT {
+        rejections.pop_front();
+    }
+    rejections.push_back((now(), rejection));
+}
+
+/// Forgets the rejections, returns how many there were.
+pub fn clear() -> usize {
+    let mut rejections = REJECTIONS.lock().unwrap();
+    let count = rejections.len();
+    rejections.clear();
+    count
+}
+
+/// The mails still stored and the rejections since `since` (milliseconds), most active
+/// addresses first.
+pub fn query(db: &Store, since: u128) -> Result<Snapshot, SharedError> {
+    let now = now();
+    let mut snapshot = Snapshot {
+        since,
+        now,
+        count: 0,
+        bytes: 0,
+        last_id: None,
+        senders: Vec::new(),
+        recipients: Vec::new(),
+        rejections: Rejections::default(),
+    };
+
+    let mut senders: HashMap<String, AddressState> = HashMap::new();
+    let mut recipients: HashMap<String, AddressState> = HashMap::new();
+    for result in summary::tree(db)?.iter() {
+        let (_, data) = result?;
+        let Ok(summary) = bincode::deserialize::<MailSummary>(&data) else {
+            continue;
+        };
+        if summary.timestamp < since {
+            continue;
+        }
+
+        snapshot.count += 1;
+        snapshot.bytes += summary.size;
+        snapshot.last_id = snapshot.last_id.max(Some(summary.id));
+        for (addresses, states) in [(&summary.from, &mut senders), (&summary.to, &mut recipients)] {
+            for address in addresses {
+                let address = address.to_lowercase();
+                let state = states.entry(address.clone()).or_insert(AddressState {
+                    address,
+                    count: 0,
+                    last_id: 0,
+                });
+                state.count += 1;
+                state.last_id = state.last_id.max(summary.id);
+            }
+        }
+    }
+    snapshot.senders = sorted(senders);
+    snapshot.recipients = sorted(recipients);
+
+    for (timestamp, rejection) in REJECTIONS.lock().unwrap().iter() {
+        if *timestamp < since {
+            continue;
+        }
+        match rejection {
+            Rejection::QueueFull => snapshot.rejections.queue_full += 1,
+            Rejection::Memory => snapshot.rejections.memory += 1,
+            Rejection::Storage => snapshot.rejections.storage += 1,
+            Rejection::TooLarge => snapshot.rejections.too_large += 1,
+        }
+    }
+
+    Ok(snapshot)
+}
+
+fn sorted(states: HashMap<String, AddressState>) -> Vec<AddressState> {
+    let mut states: Vec<AddressState> = states.into_values().collect();
+    states.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.address.cmp(&b.address)));
+    states
+}
@@ -5,38 +5,69 @@ use std::time::{SystemTime, UNIX_EPOCH};
 const EPOCH: u128 = 1704067200000; // January 1, 2024, 00:00:00 UTC in milliseconds
 const SEQUENCE_BITS: u32 = 12;
 const SEQUENCE_MASK: u128 = (1 << SEQUENCE_BITS) - 1;
+// between two mails in deterministic mode
+const DETERMINISTIC_STEP: u128 = 1000;
 
 lazy_static! {
     static ref SNOWFLAKE: Mutex<Snowflake> = Mutex::new(Snowflake::new());
 }
 
-struct Snowflake {
+pub struct Snowflake {
     sequence: u128,
     last_timestamp: u128,
+    // the next timestamp handed out in deterministic mode, instead of the wall clock
+    clock: Option<u128>,
 }
 
 impl Snowflake {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Snowflake {
             sequence: 0,
             last_timestamp: 0,
+            clock: None,
         }
     }
 
-    fn next_id(&mut self) -> u128 {
+    /// Ids from a virtual clock starting `seed` seconds after the epoch and moving one second
+    /// per id, so that the same mails get the same ids and timestamps on every run.
+    pub fn deterministic(seed: u64) -> Self {
+        Snowflake {
+            clock: Some(EPOCH + seed as u128 * 1000),
+            ..Snowflake::new()
+        }
+    }
+
+    /// Ids after `last`, e.g. the newest stored one, even if the clock went back since it was
+    /// handed out.
+    pub fn after(last: u128) -> Self {
+        Snowflake {
+            sequence: last & SEQUENCE_MASK,
+            last_timestamp: to_timestamp(last),
+            clock: None,
+        }
+    }
+
+    /// Always greater than the previous one, so that ids sort the mails in the order they were
+    /// received.
+    pub fn next_id(&mut self) -> u128 {
+        if let Some(clock) = self.clock.as_mut() {
+            self.last_timestamp = *clock;
+            *clock += DETERMINISTIC_STEP;
+            return (self.last_timestamp - EPOCH) << SEQUENCE_BITS;
+        }
+
         let timestamp = Self::current_timestamp();
-        if timestamp != self.last_timestamp {
+        if timestamp > self.last_timestamp {
             self.sequence = 0;
             self.last_timestamp = timestamp;
         } else {
+            // same millisecond, or the clock went back: the ids keep going up from the last one
             self.sequence += 1;
             if self.sequence > SEQUENCE_MASK {
-                // sequence overflow, wait for the next timestamp
-                while Self::current_timestamp() == self.last_timestamp {
-                    // busy wait, not ideal, but simple
-                }
+                // sequence overflow, the next millisecond is taken ahead of time rather than
+                // waiting for a clock that may be far behind
                 self.sequence = 0;
-                self.last_timestamp = Self::current_timestamp();
+                self.last_timestamp += 1;
             }
         }
         (self.last_timestamp - EPOCH) << SEQUENCE_BITS | self.sequence
@@ -50,6 +81,16 @@ impl Snowflake {
     }
 }
 
+/// Switches to deterministic ids and timestamps, see [`Snowflake::deterministic`].
+pub fn set_deterministic(seed: u64) {
+    *SNOWFLAKE.lock().unwrap() = Snowflake::deterministic(seed);
+}
+
+/// Carries on from the newest stored id, see [`Snowflake::after`].
+pub fn resume(last: u128) {
+    *SNOWFLAKE.lock().unwrap() = Snowflake::after(last);
+}
+
 pub fn next() -> u128 {
     SNOWFLAKE.lock().unwrap().next_id()
 }
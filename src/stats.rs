@@ -0,0 +1,159 @@
+use crate::smtp::mail::Mail;
+use crate::store::{self, Store};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// counters are kept per hour, which is also the precision of the requested time window
+const BUCKET_MS: u128 = 60 * 60 * 1000;
+const TREE: &str = "stats";
+
+const TOTAL: u8 = b'm';
+const SENDER: u8 = b'f';
+const RECIPIENT: u8 = b't';
+const DUPLICATE: u8 = b'd';
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counter {
+    pub count: u64,
+    pub bytes: u64,
+    pub last_received: u128,
+}
+
+impl Counter {
+    fn add(&mut self, other: &Counter) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+        self.last_received = self.last_received.max(other.last_received);
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AddressStats {
+    pub address: String,
+    #[serde(flatten)]
+    pub counter: Counter,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub total: Counter,
+    // mails received again, stored or not depending on the duplicates policy
+    pub duplicates: Counter,
+    pub senders: Vec<AddressStats>,
+    pub recipients: Vec<AddressStats>,
+}
+
+fn key(kind: u8, bucket: u64, address: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(9 + address.len());
+    key.push(kind);
+    key.extend_from_slice(&bucket.to_be_bytes());
+    key.extend_from_slice(address.as_bytes());
+    key
+}
+
+/// Counts a stored mail. Stats are history: deleting the mail later doesn't change them.
+pub fn record(db: &Store, mail: &Mail) -> store::Result<()> {
+    let tree = db.open_tree(TREE)?;
+    let bucket = (mail.timestamp() / BUCKET_MS) as u64;
+    let received = Counter {
+        count: 1,
+        bytes: mail.data.len() as u64,
+        last_received: mail.timestamp(),
+    };
+
+    let mut keys = vec![key(TOTAL, bucket, "")];
+    keys.extend(mail.from.iter().map(|address| key(SENDER, bucket, &address.to_lowercase())));
+    keys.extend(mail.to.iter().map(|address| key(RECIPIENT, bucket, &address.to_lowercase())));
+
+    for key in keys {
+        tree.fetch_and_update(key, |current| {
+            let mut counter = current
+                .and_then(|data| bincode::deserialize::<Counter>(data).ok())
+                .unwrap_or_default();
+            counter.add(&received);
+            bincode::serialize(&counter).ok()
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Counts a mail received again, see [`crate::duplicates`].
+pub fn record_duplicate(db: &Store, mail: &Mail) -> store::Result<()> {
+    let tree = db.open_tree(TREE)?;
+    let bucket = (mail.timestamp() / BUCKET_MS) as u64;
+    let received = Counter {
+        count: 1,
+        bytes: mail.data.len() as u64,
+        last_received: mail.timestamp(),
+    };
+    tree.fetch_and_update(key(DUPLICATE, bucket, ""), |current| {
+        let mut counter = current
+            .and_then(|data| bincode::deserialize::<Counter>(data).ok())
+            .unwrap_or_default();
+        counter.add(&received);
+        bincode::serialize(&counter).ok()
+    })?;
+    Ok(())
+}
+
+/// Forgets every counter, returns how many there were.
+pub fn clear(db: &Store) -> store::Result<usize> {
+    let tree = db.open_tree(TREE)?;
+    let count = tree.len();
+    tree.clear()?;
+    Ok(count)
+}
+
+/// Aggregates the counters of the hours overlapping `[since, until)`, most active addresses first.
+pub fn query(db: &Store, since: Option<u128>, until: Option<u128>) -> store::Result<Stats> {
+    let tree = db.open_tree(TREE)?;
+    let first = since.map(|since| (since / BUCKET_MS) as u64).unwrap_or(0);
+    let last = until
+        .map(|until| (until.saturating_sub(1) / BUCKET_MS) as u64)
+        .unwrap_or(u64::MAX);
+
+    let aggregate = |kind: u8| -> store::Result<Vec<AddressStats>> {
+        let mut counters: HashMap<String, Counter> = HashMap::new();
+        let start = key(kind, first, "");
+        let end = key(kind, last.saturating_add(1), "");
+        if start >= end {
+            return Ok(Vec::new());
+        }
+
+        for result in tree.range(start..end) {
+            let (key, data) = result?;
+            let address = String::from_utf8_lossy(&key[9..]).to_string();
+            if let Ok(counter) = bincode::deserialize::<Counter>(&data) {
+                counters.entry(address).or_default().add(&counter);
+            }
+        }
+
+        let mut stats: Vec<AddressStats> = counters
+            .into_iter()
+            .map(|(address, counter)| AddressStats { address, counter })
+            .collect();
+        stats.sort_by(|a, b| {
+            b.counter
+                .count
+                .cmp(&a.counter.count)
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        Ok(stats)
+    };
+
+    let sum = |kind: u8| -> store::Result<Counter> {
+        Ok(aggregate(kind)?
+            .into_iter()
+            .map(|stats| stats.counter)
+            .next()
+            .unwrap_or_default())
+    };
+
+    Ok(Stats {
+        total: sum(TOTAL)?,
+        duplicates: sum(DUPLICATE)?,
+        senders: aggregate(SENDER)?,
+        recipients: aggregate(RECIPIENT)?,
+    })
+}
@@ -0,0 +1,140 @@
+use crate::store::{self, Store};
+use crate::{events, metrics, report, SharedError};
+use lazy_static::lazy_static;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+pub const DEFAULT_PATH: &str = "db";
+
+// only the command line's, a process embedding the sink isn't restarted
+static PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+// how often the database is tried again once writes have failed
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// free space needed before starting over, a few of sled's segments
+const SPACE_NEEDED: usize = 4 * 1024 * 1024;
+const TREE: &str = "health";
+
+static FAILING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // the failed database has room again, see `reopen_needed`
+    static ref REOPEN: Notify = Notify::new();
+}
+
+/// Sets where the database lives, `--db-path`, so that a failing one can be reopened by
+/// restarting the process once there's free space again, see [`reopen_needed`].
+pub fn set_path(path: PathBuf) {
+    *PATH.write().unwrap() = Some(path);
+}
+
+/// Whether the database refuses writes, in which case mails are turned away instead of lost.
+pub fn failing() -> bool {
+    FAILING.load(Ordering::Relaxed)
+}
+
+/// Switches to refusing mails after a failed write, and keeps trying the database in the
+/// background until it takes writes again.
+pub fn failed(db: &Store, error: &store::Error) {
+    metrics::STORAGE_FAILURES.inc();
+    if FAILING.swap(true, Ordering::Relaxed) {
+        // already failing and being checked
+        return;
+    }
+
+    metrics::STORAGE_FAILING.set(1);
+    let error = describe(error);
+    report::report(
+        report::Kind::Storage,
+        &format!("Storage is failing ({}), refusing mails until it recovers", error),
+    );
+    events::publish(events::Event::StorageFailing { error });
+
+    let db = db.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(PROBE_INTERVAL).await;
+            if check(&db).await {
+                break;
+            }
+            let path = PATH.read().unwrap().clone();
+            if path.is_some_and(|path| has_space(&path)) {
+                // sled keeps failing once it ran into an IO error, and hangs when dropped while
+                // holding the lock on its files, so only a new process can open the database again
+                warn!("Storage has free space again, restarting to reopen the database");
+                REOPEN.notify_one();
+                break;
+            }
+        }
+    });
+}
+
+/// Tries a write, and accepts mails again if it went through. True when the storage is healthy.
+pub async fn check(db: &Store) -> bool {
+    if probe(db).await.is_err() {
+        return false;
+    }
+    if FAILING.swap(false, Ordering::Relaxed) {
+        metrics::STORAGE_FAILING.set(0);
+        info!("Storage recovered, accepting mails again");
+        events::publish(events::Event::StorageRecovered);
+    }
+    true
+}
+
+// sled buffers writes, a full disk only shows once they are flushed
+async fn probe(db: &Store) -> Result<(), SharedError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    db.open_tree(TREE)?
+        .insert("probe", timestamp.to_le_bytes())?;
+    // a failed instance never completes the flush
+    tokio::time::timeout(PROBE_TIMEOUT, db.flush_async()).await??;
+    Ok(())
+}
+
+// actually writes the bytes, a sparse file wouldn't tell anything
+fn has_space(dir: &Path) -> bool {
+    let path = dir.join("space_probe");
+    let written = File::create(&path).and_then(|mut file| {
+        file.write_all(&vec![0; SPACE_NEEDED])?;
+        file.sync_all()
+    });
+    let _ = fs::remove_file(&path);
+    written.is_ok()
+}
+
+/// Resolves once a failed database has free space again. The command line then drains the
+/// connections and the ingest queue, and calls [`restart`].
+pub async fn reopen_needed() {
+    REOPEN.notified().await
+}
+
+/// Replaces the process with a new one, with the same arguments. Files and sockets are opened
+/// close-on-exec, which also releases the database lock. Only returns on failure.
+pub fn restart() -> std::io::Error {
+    match std::env::current_exe() {
+        Ok(exe) => Command::new(exe).args(std::env::args_os().skip(1)).exec(),
+        Err(e) => e,
+    }
+}
+
+fn describe(error: &store::Error) -> String {
+    match error {
+        store::Error::Io(e) if matches!(e.kind(), ErrorKind::StorageFull | ErrorKind::QuotaExceeded) => {
+            "disk full".to_string()
+        }
+        e => e.to_string(),
+    }
+}
@@ -0,0 +1,389 @@
+pub(crate) mod disk;
+pub(crate) mod memory;
+
+use crate::smtp::mail::Mail;
+use crate::relay;
+use crate::summary::{self, MailSummary, Order};
+use crate::SharedError;
+use clap::ValueEnum;
+use bytes::Bytes;
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::fmt;
+use std::future::Future;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Where the mails are kept, `--storage`.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Storage {
+    /// A sled database in `--db-path`, kept across restarts
+    #[default]
+    Disk,
+    /// Lost when the process exits, for ephemeral runs
+    Memory,
+}
+
+/// A failure of a [`Backend`], which maps its own errors to it.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the files failed, e.g. on a full disk
+    Io(std::io::Error),
+    /// Anything else, e.g. a corrupted database, described by the backend
+    Backend(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::Backend(e) => f.write_str(e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Backend(_) => None,
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The bytes of a key or a value read from a tree, cheap to clone.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Value(Bytes);
+
+impl Deref for Value {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Value {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// so that the memory backend looks its maps up by `&[u8]`
+impl Borrow<[u8]> for Value {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value(Bytes::copy_from_slice(bytes))
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value(Bytes::from(bytes))
+    }
+}
+
+/// The value found by a [`BackendTree::compare_and_swap`] that didn't swap, instead of the one
+/// expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompareAndSwapError {
+    pub current: Option<Value>,
+}
+
+/// What a [`Store`] keeps its trees in. Keys and values are bytes and errors the store's whatever
+/// the backend, so that the modules work the same on any.
+pub trait Backend: Send + Sync {
+    /// The tree of the mails themselves, keyed by `id.to_le_bytes()`.
+    fn mails(&self) -> Arc<dyn BackendTree>;
+
+    /// The tree named `name`, created empty the first time.
+    fn open_tree(&self, name: &str) -> Result<Arc<dyn BackendTree>>;
+
+    fn size_on_disk(&self) -> Result<u64>;
+
+    /// Waits for the writes so far to be durable, returns how many bytes that took.
+    fn flush(&self) -> Pin<Box<dyn Future<Output = Result<usize>> + Send + '_>>;
+}
+
+/// The entries of a tree from a [`BackendTree::range`], in key order from either end.
+pub type Entries = Box<dyn DoubleEndedIterator<Item = Result<(Value, Value)>> + Send>;
+
+/// A sorted map of bytes to bytes, see [`Backend`].
+pub trait BackendTree: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Value>>;
+
+    /// Returns the value replaced, if any.
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Value>>;
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Value>>;
+
+    /// Sets `new` (or removes the entry if None) only if the current value is `old`, atomically.
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<Result<(), CompareAndSwapError>>;
+
+    fn range(&self, start: Bound<Value>, end: Bound<Value>) -> Entries;
+
+    fn len(&self) -> usize;
+
+    fn clear(&self) -> Result<()>;
+}
+
+/// The database, shared by the SMTP writers, the HTTP handlers and the jobs without a lock: the
+/// backends can be used from any number of tasks at once, so a long listing doesn't hold up
+/// ingestion. It derefs to the tree of the mails, the trees of the other modules (`summary`,
+/// `stats`, ...) are reached through [`Store::open_tree`].
+#[derive(Clone)]
+pub struct Store {
+    backend: Arc<dyn Backend>,
+    mails: Tree,
+    // taken shared while a mail is being written, alone by what has to see no write at all
+    writes: Arc<RwLock<()>>,
+}
+
+impl Deref for Store {
+    type Target = Tree;
+
+    fn deref(&self) -> &Tree {
+        &self.mails
+    }
+}
+
+impl Store {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Store::new(sled::open(path).map_err(Error::from)?))
+    }
+
+    pub fn new(db: sled::Db) -> Self {
+        Store::with_backend(Arc::new(disk::Disk::new(db)))
+    }
+
+    /// An empty store in memory, see [`Storage::Memory`].
+    pub fn memory() -> Self {
+        Store::with_backend(Arc::new(memory::Memory::default()))
+    }
+
+    pub fn with_backend(backend: Arc<dyn Backend>) -> Self {
+        Store {
+            mails: Tree(backend.mails()),
+            backend,
+            writes: Arc::new(RwLock::new(())),
+        }
+    }
+
+    pub fn open_tree(&self, name: &str) -> Result<Tree> {
+        Ok(Tree(self.backend.open_tree(name)?))
+    }
+
+    /// What the database takes on disk, 0 in memory.
+    pub fn size_on_disk(&self) -> Result<u64> {
+        self.backend.size_on_disk()
+    }
+
+    pub async fn flush_async(&self) -> Result<usize> {
+        self.backend.flush().await
+    }
+
+    /// Writes the raw mail, its summary is up to the caller.
+    pub fn put_mail(&self, mail: &Mail) -> Result<()> {
+        let bytes = bincode::serialize(mail).expect("a mail always serializes");
+        self.mails.insert(mail.id.to_le_bytes(), bytes)?;
+        Ok(())
+    }
+
+    pub fn get_mail(&self, id: u128) -> Result<Option<Mail>, SharedError> {
+        match self.mails.get(id.to_le_bytes())? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the mail along with its summary and relay status, false if it wasn't there.
+    pub fn delete(&self, id: u128) -> Result<bool> {
+        let existed = self.mails.remove(id.to_le_bytes())?.is_some();
+        summary::remove(self, id)?;
+        relay::remove(self, id)?;
+        Ok(existed)
+    }
+
+    /// The summaries of the mails in the order `GET /mails` lists them, from right after the
+    /// mail `after` and skipping `offset` more.
+    pub fn iter_page(
+        &self,
+        order: Order,
+        after: Option<u128>,
+        offset: usize,
+    ) -> Result<impl Iterator<Item = Result<MailSummary, SharedError>>> {
+        let db = self.clone();
+        Ok(summary::ids(self, order, after)?
+            .skip(offset)
+            // gone if it was deleted since it was listed
+            .filter_map(move |id| match id {
+                Ok(id) => summary::get(&db, id).transpose(),
+                Err(e) => Some(Err(e.into())),
+            }))
+    }
+
+    /// Held while storing a mail and its summary, several writers at once.
+    pub async fn writing(&self) -> RwLockReadGuard<'_, ()> {
+        self.writes.read().await
+    }
+
+    /// Waits for the mails being written and holds the next ones back until dropped, e.g. to
+    /// clear everything at once.
+    pub async fn exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        self.writes.write().await
+    }
+}
+
+/// A tree of a [`Store`], with the methods of a sled tree the modules use.
+#[derive(Clone)]
+pub struct Tree(Arc<dyn BackendTree>);
+
+impl Tree {
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Value>> {
+        self.0.get(key.as_ref())
+    }
+
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> Result<bool> {
+        Ok(self.0.get(key.as_ref())?.is_some())
+    }
+
+    pub fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<Option<Value>> {
+        self.0.insert(key.as_ref(), value.as_ref())
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Value>> {
+        self.0.remove(key.as_ref())
+    }
+
+    pub fn compare_and_swap<K, OV, NV>(
+        &self,
+        key: K,
+        old: Option<OV>,
+        new: Option<NV>,
+    ) -> Result<Result<(), CompareAndSwapError>>
+    where
+        K: AsRef<[u8]>,
+        OV: AsRef<[u8]>,
+        NV: AsRef<[u8]>,
+    {
+        self.0.compare_and_swap(
+            key.as_ref(),
+            old.as_ref().map(AsRef::as_ref),
+            new.as_ref().map(AsRef::as_ref),
+        )
+    }
+
+    /// Replaces the value with what `f` makes of it (removes it on None), returns the new one.
+    pub fn update_and_fetch<K, V, F>(&self, key: K, f: F) -> Result<Option<Value>>
+    where
+        K: AsRef<[u8]>,
+        V: Into<Value>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+    {
+        Ok(self.update(key.as_ref(), f)?.1)
+    }
+
+    /// Replaces the value with what `f` makes of it (removes it on None), returns the old one.
+    pub fn fetch_and_update<K, V, F>(&self, key: K, f: F) -> Result<Option<Value>>
+    where
+        K: AsRef<[u8]>,
+        V: Into<Value>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+    {
+        Ok(self.update(key.as_ref(), f)?.0)
+    }
+
+    // tried again until no other write came in between, `f` may be called more than once
+    fn update<V, F>(&self, key: &[u8], mut f: F) -> Result<(Option<Value>, Option<Value>)>
+    where
+        V: Into<Value>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+    {
+        let mut current = self.0.get(key)?;
+        loop {
+            let new = f(current.as_deref()).map(Into::into);
+            match self.0.compare_and_swap(key, current.as_deref(), new.as_deref())? {
+                Ok(()) => return Ok((current, new)),
+                Err(swap) => current = swap.current,
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Iter {
+        Iter(self.0.range(Bound::Unbounded, Bound::Unbounded))
+    }
+
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Iter {
+        let bound = |bound: Bound<&K>| bound.map(|key| Value::from(key.as_ref()));
+        Iter(self.0.range(bound(range.start_bound()), bound(range.end_bound())))
+    }
+
+    /// The entries whose key starts with `prefix`.
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Iter {
+        let prefix = prefix.as_ref();
+        // the first key past them: the prefix without its trailing 0xff, last byte incremented
+        let mut end = prefix.to_vec();
+        while end.last() == Some(&u8::MAX) {
+            end.pop();
+        }
+        let end = match end.last_mut() {
+            Some(last) => {
+                *last += 1;
+                Bound::Excluded(Value::from(end))
+            }
+            None => Bound::Unbounded,
+        };
+        Iter(self.0.range(Bound::Included(Value::from(prefix)), end))
+    }
+
+    pub fn last(&self) -> Result<Option<(Value, Value)>> {
+        self.iter().next_back().transpose()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.0.clear()
+    }
+}
+
+/// The entries of a [`Tree`], in key order.
+pub struct Iter(Entries);
+
+impl Iter {
+    pub fn keys(self) -> impl DoubleEndedIterator<Item = Result<Value>> + Send {
+        self.map(|entry| entry.map(|(key, _)| key))
+    }
+}
+
+impl Iterator for Iter {
+    type Item = Result<(Value, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
@@ -0,0 +1,91 @@
+use crate::store::{Backend, BackendTree, CompareAndSwapError, Entries, Error, Result, Value};
+use sled::{Db, IVec, Tree};
+use std::future::Future;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A sled database, `--storage disk`.
+pub struct Disk {
+    db: Db,
+}
+
+impl Disk {
+    pub fn new(db: Db) -> Self {
+        Disk { db }
+    }
+}
+
+impl From<sled::Error> for Error {
+    fn from(error: sled::Error) -> Self {
+        match error {
+            sled::Error::Io(e) => Error::Io(e),
+            e => Error::Backend(e.to_string()),
+        }
+    }
+}
+
+fn value(bytes: IVec) -> Value {
+    Value::from(bytes.as_ref())
+}
+
+impl Backend for Disk {
+    fn mails(&self) -> Arc<dyn BackendTree> {
+        // the default tree, where the mails have always been
+        Arc::new(Tree::clone(&self.db))
+    }
+
+    fn open_tree(&self, name: &str) -> Result<Arc<dyn BackendTree>> {
+        Ok(Arc::new(self.db.open_tree(name)?))
+    }
+
+    fn size_on_disk(&self) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    fn flush(&self) -> Pin<Box<dyn Future<Output = Result<usize>> + Send + '_>> {
+        Box::pin(async { Ok(self.db.flush_async().await?) })
+    }
+}
+
+impl BackendTree for Tree {
+    fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        Ok(Tree::get(self, key)?.map(value))
+    }
+
+    fn insert(&self, key: &[u8], data: &[u8]) -> Result<Option<Value>> {
+        Ok(Tree::insert(self, key, data)?.map(value))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Value>> {
+        Ok(Tree::remove(self, key)?.map(value))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<Result<(), CompareAndSwapError>> {
+        Ok(Tree::compare_and_swap(self, key, old, new)?.map_err(|e| CompareAndSwapError {
+            current: e.current.map(value),
+        }))
+    }
+
+    fn range(&self, start: Bound<Value>, end: Bound<Value>) -> Entries {
+        let bound = |bound: Bound<Value>| bound.map(|key| IVec::from(key.as_ref()));
+        let entries = Tree::range(self, (bound(start), bound(end)));
+        Box::new(entries.map(|entry| {
+            let (key, data) = entry?;
+            Ok((value(key), value(data)))
+        }))
+    }
+
+    fn len(&self) -> usize {
+        Tree::len(self)
+    }
+
+    fn clear(&self) -> Result<()> {
+        Ok(Tree::clear(self)?)
+    }
+}
@@ -0,0 +1,143 @@
+use crate::store::{Backend, BackendTree, CompareAndSwapError, Entries, Result, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Trees kept in the memory of the process, `--storage memory`.
+#[derive(Default)]
+pub struct Memory {
+    mails: Arc<MemoryTree>,
+    trees: Mutex<HashMap<String, Arc<MemoryTree>>>,
+}
+
+impl Backend for Memory {
+    fn mails(&self) -> Arc<dyn BackendTree> {
+        self.mails.clone()
+    }
+
+    fn open_tree(&self, name: &str) -> Result<Arc<dyn BackendTree>> {
+        let mut trees = self.trees.lock().unwrap();
+        Ok(trees.entry(name.to_string()).or_default().clone())
+    }
+
+    fn size_on_disk(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    fn flush(&self) -> Pin<Box<dyn Future<Output = Result<usize>> + Send + '_>> {
+        Box::pin(async { Ok(0) })
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryTree {
+    // shared with the iterators, which lock it again for each entry
+    entries: Arc<RwLock<BTreeMap<Value, Value>>>,
+}
+
+impl BackendTree for MemoryTree {
+    fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        Ok(self.entries.read().unwrap().get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Value>> {
+        Ok(self.entries.write().unwrap().insert(key.into(), value.into()))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Value>> {
+        Ok(self.entries.write().unwrap().remove(key))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<Result<(), CompareAndSwapError>> {
+        let mut entries = self.entries.write().unwrap();
+        let current = entries.get(key);
+        if current.map(AsRef::as_ref) != old {
+            return Ok(Err(CompareAndSwapError {
+                current: current.cloned(),
+            }));
+        }
+        match new {
+            Some(new) => entries.insert(key.into(), new.into()),
+            None => entries.remove(key),
+        };
+        Ok(Ok(()))
+    }
+
+    fn range(&self, start: Bound<Value>, end: Bound<Value>) -> Entries {
+        Box::new(Range {
+            entries: self.entries.clone(),
+            start,
+            end,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.entries.write().unwrap().clear();
+        Ok(())
+    }
+}
+
+// like sled's, it goes on from where it is and sees the writes made meanwhile, without holding
+// the tree between two entries
+struct Range {
+    entries: Arc<RwLock<BTreeMap<Value, Value>>>,
+    // narrowed to exclude each entry returned from either end
+    start: Bound<Value>,
+    end: Bound<Value>,
+}
+
+impl Range {
+    // `BTreeMap::range` panics on those
+    fn is_empty(&self) -> bool {
+        match (&self.start, &self.end) {
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => {
+                start > end
+                    || (start == end && matches!((&self.start, &self.end), (Bound::Excluded(_), Bound::Excluded(_))))
+            }
+            _ => false,
+        }
+    }
+
+    fn next_entry(&mut self, back: bool) -> Option<(Value, Value)> {
+        if self.is_empty() {
+            return None;
+        }
+        let entries = self.entries.read().unwrap();
+        let mut range = entries.range((self.start.clone(), self.end.clone()));
+        let (key, value) = match back {
+            true => range.next_back()?,
+            false => range.next()?,
+        };
+        let bound = Bound::Excluded(key.clone());
+        match back {
+            true => self.end = bound,
+            false => self.start = bound,
+        }
+        Some((key.clone(), value.clone()))
+    }
+}
+
+impl Iterator for Range {
+    type Item = Result<(Value, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry(false).map(Ok)
+    }
+}
+
+impl DoubleEndedIterator for Range {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_entry(true).map(Ok)
+    }
+}
@@ -0,0 +1,371 @@
+use crate::smtp::mail::Mail;
+use crate::smtp::verification::Authentication;
+use crate::store::{self, Store, Tree};
+use crate::SharedError;
+use serde::{Deserialize, Serialize};
+use std::ops::Bound;
+
+// keyed like the mails themselves, by `id.to_le_bytes()`
+const TREE: &str = "summaries";
+// `to:` or `from:`, the lowercased address, a 0 and `id.to_be_bytes()`, so that the mails of an
+// address are next to each other, oldest first
+const ADDRESSES: &str = "addresses";
+// `id.to_be_bytes()` to the size of the mail (`u64`, little endian), so that the mails are in the
+// order they were received, oldest first, for the retention to evict
+const TIMELINE: &str = "timeline";
+
+/// What the list endpoints return, kept next to each mail so that listing doesn't have to
+/// deserialize (nor parse) the whole message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MailSummary {
+    pub id: u128,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub subject: Option<String>,
+    // of the raw data, in bytes
+    pub size: u64,
+    pub timestamp: u128,
+    pub has_attachment: bool,
+    // from the end of DATA to the mail being written, unknown for mails stored by older versions
+    pub ingest_latency_us: Option<u64>,
+    // the first mail received with the same Message-ID (or content), see `--duplicates`
+    pub duplicate_of: Option<u128>,
+    // given by the rules (see `--rule`), or through `PATCH /mails/<mail_id>`
+    pub tags: Vec<String>,
+    pub namespace: Option<String>,
+    // the SMTP AUTH username the mail was sent with
+    pub user: Option<String>,
+    // set once fetched with `GET /mails/<mail_id>`, or through `PATCH /mails/<mail_id>`
+    pub read: bool,
+    // only for the mails received over SMTP with `--verify-auth`
+    pub authentication: Option<Authentication>,
+}
+
+impl MailSummary {
+    pub fn from_mail(mail: &Mail) -> Self {
+        let mut from: Vec<String> = mail.from.iter().cloned().collect();
+        from.sort();
+        let mut to: Vec<String> = mail.to.iter().cloned().collect();
+        to.sort();
+
+        MailSummary {
+            id: mail.id,
+            from,
+            to,
+            subject: mail.subject.clone(),
+            size: mail.data.len() as u64,
+            timestamp: mail.timestamp(),
+            has_attachment: !mail.attachments().is_empty(),
+            ingest_latency_us: None,
+            duplicate_of: None,
+            tags: Vec::new(),
+            namespace: None,
+            user: None,
+            read: false,
+            authentication: None,
+        }
+    }
+}
+
+pub fn tree(db: &Store) -> store::Result<Tree> {
+    db.open_tree(TREE)
+}
+
+fn addresses(db: &Store) -> store::Result<Tree> {
+    db.open_tree(ADDRESSES)
+}
+
+fn timeline(db: &Store) -> store::Result<Tree> {
+    db.open_tree(TIMELINE)
+}
+
+fn address_prefix(to: bool, address: &str) -> Vec<u8> {
+    let mut prefix = if to { b"to:".to_vec() } else { b"from:".to_vec() };
+    prefix.extend_from_slice(address.to_lowercase().as_bytes());
+    prefix.push(0);
+    prefix
+}
+
+fn address_keys(summary: &MailSummary) -> Vec<Vec<u8>> {
+    let to = summary.to.iter().map(|address| (true, address));
+    let from = summary.from.iter().map(|address| (false, address));
+    to.chain(from)
+        .map(|(to, address)| {
+            let mut key = address_prefix(to, address);
+            key.extend_from_slice(&summary.id.to_be_bytes());
+            key
+        })
+        .collect()
+}
+
+/// To be called along with every insertion in the mail tree.
+pub fn insert(db: &Store, summary: &MailSummary) -> Result<(), SharedError> {
+    tree(db)?.insert(summary.id.to_le_bytes(), bincode::serialize(summary)?)?;
+    let addresses = addresses(db)?;
+    for key in address_keys(summary) {
+        addresses.insert(key, [])?;
+    }
+    timeline(db)?.insert(summary.id.to_be_bytes(), summary.size.to_le_bytes())?;
+    Ok(())
+}
+
+/// The ids and sizes of the mails, oldest first.
+pub fn oldest(db: &Store) -> store::Result<impl Iterator<Item = store::Result<(u128, u64)>>> {
+    Ok(timeline(db)?.iter().map(|result| {
+        let (key, value) = result?;
+        let mut id = [0; 16];
+        id.copy_from_slice(&key);
+        let mut size = [0; 8];
+        size.copy_from_slice(&value);
+        Ok((u128::from_be_bytes(id), u64::from_le_bytes(size)))
+    }))
+}
+
+/// The order of the mails in a list, by id and so by the time they were received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    Asc,
+    // newest first
+    #[default]
+    Desc,
+}
+
+impl Order {
+    /// `asc` or `desc`, as given to `?order`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "asc" => Ok(Order::Asc),
+            "desc" => Ok(Order::Desc),
+            _ => Err(format!("Invalid order: {}, expected asc or desc", value)),
+        }
+    }
+}
+
+/// The ids of the mails in `order`, starting right after `after` (excluded) if given, so that a
+/// page starts where the previous one ended whatever was received or deleted in between.
+pub fn ids(
+    db: &Store,
+    order: Order,
+    after: Option<u128>,
+) -> store::Result<Box<dyn Iterator<Item = store::Result<u128>> + Send>> {
+    let timeline = timeline(db)?;
+    let after = after.map(|id| id.to_be_bytes());
+    let keys: Box<dyn Iterator<Item = store::Result<store::Value>> + Send> = match (order, after) {
+        (Order::Asc, Some(after)) => Box::new(timeline.range((Bound::Excluded(after), Bound::Unbounded)).keys()),
+        (Order::Desc, Some(after)) => Box::new(timeline.range(..after).keys().rev()),
+        (Order::Asc, None) => Box::new(timeline.iter().keys()),
+        (Order::Desc, None) => Box::new(timeline.iter().keys().rev()),
+    };
+    Ok(Box::new(keys.map(|key| {
+        let key = key?;
+        let mut id = [0; 16];
+        id.copy_from_slice(&key);
+        Ok(u128::from_be_bytes(id))
+    })))
+}
+
+/// The id of the last mail received, that the next ones have to come after.
+pub fn newest(db: &Store) -> store::Result<Option<u128>> {
+    Ok(timeline(db)?.last()?.map(|(key, _)| {
+        let mut id = [0; 16];
+        id.copy_from_slice(&key);
+        u128::from_be_bytes(id)
+    }))
+}
+
+/// How many of the stored mails were received at or after `since` (milliseconds), newest first
+/// so that only those are read.
+pub fn received_since(db: &Store, since: u128) -> store::Result<usize> {
+    let mut count = 0;
+    for key in timeline(db)?.iter().keys().rev() {
+        let key = key?;
+        let mut id = [0; 16];
+        id.copy_from_slice(&key);
+        if crate::snowflake::to_timestamp(u128::from_be_bytes(id)) < since {
+            break;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// How many mails are stored and how many bytes their raw data takes, without reading them.
+pub fn usage(db: &Store) -> store::Result<(usize, u64)> {
+    let mut usage = (0, 0);
+    for result in oldest(db)? {
+        let (_, size) = result?;
+        usage = (usage.0 + 1, usage.1 + size);
+    }
+    Ok(usage)
+}
+
+/// The ids of the mails sent to (or from) an address, whatever its case, newest first.
+pub fn ids_by_address(
+    db: &Store,
+    to: bool,
+    address: &str,
+) -> store::Result<impl Iterator<Item = store::Result<u128>>> {
+    let prefix = address_prefix(to, address);
+    Ok(addresses(db)?.scan_prefix(&prefix).keys().rev().map(move |key| {
+        let key = key?;
+        let mut id = [0; 16];
+        id.copy_from_slice(&key[prefix.len()..]);
+        Ok(u128::from_be_bytes(id))
+    }))
+}
+
+/// A recipient address and its mails, see [`mailboxes`].
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Mailbox {
+    pub address: String,
+    pub count: usize,
+    pub last_received: u128,
+}
+
+/// Every recipient address (lowercased) with mails, in alphabetical order, read from the index
+/// alone.
+pub fn mailboxes(db: &Store) -> store::Result<Vec<Mailbox>> {
+    let mut mailboxes: Vec<Mailbox> = Vec::new();
+    for key in addresses(db)?.scan_prefix(b"to:").keys() {
+        let key = key?;
+        // `to:`, the address, a 0 and the id
+        let Some(end) = key.len().checked_sub(17).filter(|end| *end >= 3) else {
+            continue;
+        };
+        let address = String::from_utf8_lossy(&key[3..end]);
+        let mut id = [0; 16];
+        id.copy_from_slice(&key[end + 1..]);
+        let timestamp = crate::snowflake::to_timestamp(u128::from_be_bytes(id));
+
+        // the mails of an address are next to each other, oldest first
+        match mailboxes.last_mut() {
+            Some(mailbox) if mailbox.address == address => {
+                mailbox.count += 1;
+                mailbox.last_received = timestamp;
+            }
+            _ => mailboxes.push(Mailbox {
+                address: address.into_owned(),
+                count: 1,
+                last_received: timestamp,
+            }),
+        }
+    }
+    Ok(mailboxes)
+}
+
+pub fn get(db: &Store, id: u128) -> Result<Option<MailSummary>, SharedError> {
+    match tree(db)?.get(id.to_le_bytes())? {
+        Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Changes what the API can change of a summary (`read`, `tags`), None if the mail is gone.
+pub fn update(
+    db: &Store,
+    id: u128,
+    change: impl Fn(&mut MailSummary),
+) -> Result<Option<MailSummary>, SharedError> {
+    let tree = tree(db)?;
+    loop {
+        let Some(current) = tree.get(id.to_le_bytes())? else {
+            return Ok(None);
+        };
+        let mut summary: MailSummary = bincode::deserialize(&current)?;
+        change(&mut summary);
+        // two changes at once are both applied, and a deleted mail isn't brought back
+        let swapped = tree.compare_and_swap(
+            id.to_le_bytes(),
+            Some(current),
+            Some(bincode::serialize(&summary)?),
+        )?;
+        if swapped.is_ok() {
+            return Ok(Some(summary));
+        }
+    }
+}
+
+/// To be called along with every removal from the mail tree.
+pub fn remove(db: &Store, id: u128) -> store::Result<()> {
+    timeline(db)?.remove(id.to_be_bytes())?;
+    let Some(data) = tree(db)?.remove(id.to_le_bytes())? else {
+        return Ok(());
+    };
+    // an unreadable summary leaves entries behind, until the next sync
+    if let Ok(summary) = bincode::deserialize::<MailSummary>(&data) {
+        let addresses = addresses(db)?;
+        for key in address_keys(&summary) {
+            addresses.remove(key)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn clear(db: &Store) -> store::Result<()> {
+    tree(db)?.clear()?;
+    timeline(db)?.clear()?;
+    addresses(db)?.clear()
+}
+
+/// Adds the summaries missing from a database written by an older version (or after a failed
+/// write), rewrites those in an older format and removes those of mails that are gone, then
+/// does the same for the address index and the timeline. Returns how many were fixed.
+pub fn sync(db: &Store) -> Result<usize, SharedError> {
+    let tree = tree(db)?;
+    let addresses = addresses(db)?;
+    let timeline = timeline(db)?;
+    let mut fixed = 0;
+
+    for result in db.iter() {
+        let (key, data) = result?;
+        let current = tree.get(&key)?;
+        if current.is_none_or(|data| bincode::deserialize::<MailSummary>(&data).is_err()) {
+            let mail: Mail = bincode::deserialize(&data)?;
+            insert(db, &MailSummary::from_mail(&mail))?;
+            fixed += 1;
+        }
+    }
+
+    for result in tree.iter() {
+        let (key, data) = result?;
+        if !db.contains_key(&key)? {
+            tree.remove(key)?;
+            fixed += 1;
+            continue;
+        }
+        let summary: MailSummary = bincode::deserialize(&data)?;
+        let mut indexed = true;
+        for key in address_keys(&summary) {
+            indexed &= addresses.insert(key, [])?.is_some();
+        }
+        indexed &= timeline
+            .insert(summary.id.to_be_bytes(), summary.size.to_le_bytes())?
+            .is_some();
+        if !indexed {
+            fixed += 1;
+        }
+    }
+
+    for result in timeline.iter() {
+        let (key, _) = result?;
+        let id = u128::from_be_bytes(key.as_ref().try_into()?);
+        if !tree.contains_key(id.to_le_bytes())? {
+            timeline.remove(key)?;
+            fixed += 1;
+        }
+    }
+
+    for result in addresses.iter() {
+        let (key, _) = result?;
+        let Some(id) = key.len().checked_sub(16).map(|start| &key[start..]) else {
+            continue;
+        };
+        let id = u128::from_be_bytes(id.try_into()?);
+        if !tree.contains_key(id.to_le_bytes())? {
+            addresses.remove(key)?;
+            fixed += 1;
+        }
+    }
+
+    Ok(fixed)
+}
@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod auth_tester {
+    use crate::smtp::auth::{self, Credential};
+    use crate::smtp::sessions::Session;
+    use base64::prelude::{Engine, BASE64_STANDARD};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            auth::parse("app:s3cr:et").unwrap(),
+            Credential {
+                username: "app".to_string(),
+                password: "s3cr:et".to_string(),
+            }
+        );
+        assert!(auth::parse("app").is_err());
+        assert!(auth::parse(":password").is_err());
+    }
+
+    #[test]
+    fn test_decode_plain() {
+        let response = BASE64_STANDARD.encode("\0app\0secret");
+        assert_eq!(
+            auth::decode_plain(&response),
+            Some(("app".to_string(), "secret".to_string()))
+        );
+        assert_eq!(auth::decode_plain(&BASE64_STANDARD.encode("app\0secret")), None);
+        assert_eq!(auth::decode_plain("not base64!"), None);
+    }
+
+    // the replies written while authenticating with the given client lines
+    async fn run(command: &str, lines: &str) -> (Option<String>, String) {
+        let session = Session::open("127.0.0.1:45400".parse().unwrap());
+        let mut reader = lines.as_bytes();
+        let mut written = Vec::new();
+        let user = auth::authenticate(command, &mut reader, &mut written, &session)
+            .await
+            .unwrap();
+        (user, String::from_utf8(written).unwrap())
+    }
+
+    // the credentials are global, so everything depending on them runs in one test
+    #[tokio::test]
+    async fn test_authenticate() {
+        let plain = format!("AUTH PLAIN {}", BASE64_STANDARD.encode("\0app\0secret"));
+        let login = format!(
+            "{}\r\n{}\r\n",
+            BASE64_STANDARD.encode("app"),
+            BASE64_STANDARD.encode("secret")
+        );
+
+        // sink mode, anything goes
+        auth::init(Vec::new());
+        let (user, replies) = run(&plain, "").await;
+        assert_eq!(user.as_deref(), Some("app"));
+        assert_eq!(replies, "235 2.7.0 Authentication successful\r\n");
+
+        auth::init(vec![auth::parse("app:secret").unwrap()]);
+        let (user, replies) = run("AUTH LOGIN", &login).await;
+        assert_eq!(user.as_deref(), Some("app"));
+        assert!(replies.starts_with("334 VXNlcm5hbWU6\r\n334 UGFzc3dvcmQ6\r\n235"));
+
+        let wrong = format!("{}\r\n", BASE64_STANDARD.encode("\0app\0wrong"));
+        let (user, replies) = run("AUTH PLAIN", &wrong).await;
+        assert_eq!(user, None);
+        assert!(replies.ends_with("535 5.7.8 Authentication credentials invalid\r\n"));
+
+        let (user, replies) = run("AUTH LOGIN", "*\r\n").await;
+        assert_eq!(user, None);
+        assert!(replies.ends_with("501 5.0.0 Authentication cancelled\r\n"));
+
+        let (_, replies) = run("AUTH CRAM-MD5", "").await;
+        assert!(replies.starts_with("504"));
+        auth::init(Vec::new());
+    }
+}
@@ -0,0 +1,37 @@
+#[cfg(test)]
+mod bench_tester {
+    use crate::bench;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_size_and_duration() {
+        assert_eq!(bench::parse_size("512"), Ok(512));
+        assert_eq!(bench::parse_size("10k"), Ok(10 * 1024));
+        assert_eq!(bench::parse_size("2M"), Ok(2 * 1024 * 1024));
+        assert!(bench::parse_size("10x").is_err());
+
+        assert_eq!(bench::parse_duration("60"), Ok(Duration::from_secs(60)));
+        assert_eq!(bench::parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(bench::parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(bench::parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(bench::parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(bench::percentile(&samples, 50.0), Duration::from_millis(50));
+        assert_eq!(bench::percentile(&samples, 99.0), Duration::from_millis(99));
+        assert_eq!(bench::percentile(&samples, 100.0), Duration::from_millis(100));
+        assert_eq!(bench::percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_generate_mail() {
+        let (from, to, data) = bench::generate_mail(7, 10 * 1024);
+        assert!(data.contains(&format!("From: <{}>", from)));
+        assert!(data.contains(&format!("To: <{}>", to)));
+        assert!(data.len() >= 10 * 1024 && data.len() < 10 * 1024 + 100);
+        assert!(data.lines().all(|line| line.len() < 100));
+    }
+}
@@ -0,0 +1,51 @@
+#[cfg(test)]
+mod chaos_tester {
+    use crate::smtp::chaos::{self, Fault, Stage};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(chaos::parse("rcpt:reject-*@* 550").unwrap().fault, Fault::Reply(550));
+        assert_eq!(
+            chaos::parse("* delay=500ms").unwrap().fault,
+            Fault::Delay(Duration::from_millis(500))
+        );
+        assert_eq!(chaos::parse("data drop 10%").unwrap().fault, Fault::Drop);
+        assert!(chaos::parse("rcpt 250").is_err());
+        assert!(chaos::parse("data:*@* drop").is_err());
+        assert!(chaos::parse("mail 451 150%").is_err());
+        assert!(chaos::parse("quit drop").is_err());
+        assert!(chaos::parse("* delay=soon").is_err());
+    }
+
+    #[test]
+    fn test_stage() {
+        assert_eq!(
+            chaos::stage("RCPT TO:<Reject-1@Example.com>"),
+            (Stage::Rcpt, Some("reject-1@example.com".to_string()))
+        );
+        assert_eq!(chaos::stage("ehlo client"), (Stage::Helo, None));
+        assert_eq!(chaos::stage("NOOP"), (Stage::Any, None));
+    }
+
+    // the rules are global, and other tests hold SMTP sessions, so they only match addresses
+    // nothing else sends to
+    #[test]
+    fn test_faults() {
+        let rules = ["rcpt:chaos-slow-*@* delay=1ms", "rcpt:chaos-*@* 550", "rcpt:chaos-*@* 451"];
+        chaos::init(rules.iter().map(|spec| chaos::parse(spec).unwrap()).collect());
+
+        assert_eq!(
+            chaos::faults(Stage::Rcpt, Some("chaos-slow-1@example.com")),
+            vec![Fault::Delay(Duration::from_millis(1)), Fault::Reply(550)]
+        );
+        // the first reply wins
+        assert_eq!(chaos::faults(Stage::Rcpt, Some("chaos-1@example.com")), vec![Fault::Reply(550)]);
+        assert!(chaos::faults(Stage::Rcpt, Some("user@example.com")).is_empty());
+        assert!(chaos::faults(Stage::Mail, Some("chaos-1@example.com")).is_empty());
+        assert_eq!(chaos::get(), rules);
+
+        chaos::init(Vec::new());
+        assert_eq!(chaos::reply(451), b"451 4.0.0 Injected failure\r\n");
+    }
+}
@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod config_file_tester {
+    use crate::cli::Args;
+    use crate::{config, config_file};
+    use clap::Parser;
+    use std::ffi::OsString;
+
+    fn argv(args: &[&str]) -> Vec<OsString> {
+        std::iter::once("mail-sink").chain(args.iter().copied()).map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_deterministic_needs_memory() {
+        let args = Args::try_parse_from(argv(&["--deterministic", "1"])).unwrap();
+        let error = config::Config::from_args(&args).unwrap_err();
+        assert!(error.contains("--storage memory"));
+
+        let args = Args::try_parse_from(argv(&["--deterministic", "1", "--storage", "memory"])).unwrap();
+        assert!(config::Config::from_args(&args).is_ok());
+    }
+
+    // the variables are global, so everything depending on them runs in one test
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("mail-sink-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+smtp_port = [25, 587]
+key = ["admin-1", "admin-2"]
+max-mail-age = 60
+max_mails = 10
+session_ttl = 30
+webhook = "https://ci.example.com/hook"
+verify_auth = true
+"#,
+        )
+        .unwrap();
+        let config = path.to_str().unwrap();
+
+        std::env::set_var("MAILSINK_SESSION_TTL", "5");
+        std::env::set_var("MAILSINK_READ_KEY", "read-1, read-2");
+        std::env::set_var("MAILSINK_WEBHOOK", "https://a.test/hook,https://b.test/hook");
+        let args = config_file::load(argv(&["--max-mails", "20", "--config", config])).unwrap();
+        std::env::remove_var("MAILSINK_SESSION_TTL");
+        std::env::remove_var("MAILSINK_READ_KEY");
+        std::env::remove_var("MAILSINK_WEBHOOK");
+
+        assert_eq!(args.smtp_port, "25,587");
+        assert_eq!(args.key, vec!["admin-1", "admin-2"]);
+        assert_eq!(args.read_key, vec!["read-1", "read-2"]);
+        assert_eq!(args.lifetime, Some(60));
+        // the command line wins over the variables, which win over the file
+        assert_eq!(args.max_mails, Some(20));
+        assert_eq!(args.session_ttl, 5);
+        assert_eq!(args.webhook, vec!["https://a.test/hook", "https://b.test/hook"]);
+        assert_eq!(args.config.as_deref(), Some(path.as_path()));
+        assert!(args.verify_auth);
+
+        // defaults stay when neither sets them
+        let args = config_file::load(argv(&[])).unwrap();
+        assert_eq!(args.key, vec!["prouteur"]);
+        assert!(args.webhook.is_empty());
+        assert!(!args.verify_auth);
+
+        // a switch takes a value in a variable, and stays one on the command line
+        std::env::set_var("MAILSINK_VERIFY_AUTH", "1");
+        assert!(config_file::load(argv(&[])).unwrap().verify_auth);
+        std::env::set_var("MAILSINK_VERIFY_AUTH", "false");
+        assert!(!config_file::load(argv(&[])).unwrap().verify_auth);
+        assert!(config_file::load(argv(&["--verify-auth"])).unwrap().verify_auth);
+        std::env::remove_var("MAILSINK_VERIFY_AUTH");
+        assert_eq!(args.db_path.to_str(), Some("db"));
+
+        std::fs::write(&path, "max_mails = 10\nmax_mail = 20\n").unwrap();
+        let error = config_file::load(argv(&["--config", config])).unwrap_err();
+        assert!(error.to_string().contains("Unknown option `max_mail`"));
+
+        std::fs::write(&path, "max_mails = \"many\"\n").unwrap();
+        assert!(config_file::load(argv(&["--config", config])).is_err());
+
+        std::fs::write(&path, "max_mails = { value = 10 }\n").unwrap();
+        let error = config_file::load(argv(&["--config", config])).unwrap_err();
+        assert!(error.to_string().contains("should be a string"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
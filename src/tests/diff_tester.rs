@@ -0,0 +1,60 @@
+#[cfg(test)]
+mod diff_tester {
+    use crate::diff::*;
+    use crate::smtp::mail::Header;
+
+    fn header(name: &str, value: &str) -> Header {
+        Header {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diff_headers() {
+        let a = [
+            header("Subject", "Welcome"),
+            header("From", "noreply@shop.test"),
+            header("Received", "from a"),
+            header("Received", "from b"),
+            header("X-Build", "1042"),
+        ];
+        let b = [
+            header("subject", "Welcome!"),
+            header("From", "noreply@shop.test"),
+            header("Received", "from a"),
+            header("Received", "from b"),
+            header("List-Unsubscribe", "<mailto:unsubscribe@shop.test>"),
+        ];
+
+        let diffs = diff_headers(&a, &b);
+        let changes: Vec<_> = diffs.iter().map(|d| (d.name.as_str(), d.change)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("Subject", Change::Changed),
+                ("From", Change::Equal),
+                ("Received", Change::Equal),
+                ("X-Build", Change::Removed),
+                ("List-Unsubscribe", Change::Added),
+            ]
+        );
+        assert_eq!(diffs[0].b.as_deref(), Some("Welcome!"));
+        assert_eq!(diffs[2].a.as_deref(), Some("from a\nfrom b"));
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let diffs = diff_lines("Hello\r\nYour code is 1234\r\nBye\r\n", "Hello\r\nYour code is 5678\r\nBye\r\n");
+        let changes: Vec<_> = diffs.iter().map(|d| (d.change, d.value.as_str())).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (Change::Equal, "Hello"),
+                (Change::Removed, "Your code is 1234"),
+                (Change::Added, "Your code is 5678"),
+                (Change::Equal, "Bye"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+#[cfg(test)]
+mod dns_tester {
+    use crate::dns::{decode_answer, encode_query, first_nameserver, parse_server, Answer, Record};
+    use std::net::{IpAddr, SocketAddr};
+
+    const TXT: u16 = 16;
+    const MX: u16 = 15;
+    const A: u16 = 1;
+
+    // a header with `flags`, the question of `encode_query` and the given answer records
+    fn answer(flags: [u8; 2], kind: u16, records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut message = encode_query(0x1234, "example.com", kind).unwrap();
+        message[2..4].copy_from_slice(&flags);
+        message[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for (record_kind, data) in records {
+            // a pointer to the name of the question
+            message.extend_from_slice(&[0xc0, 12]);
+            message.extend_from_slice(&record_kind.to_be_bytes());
+            message.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(data);
+        }
+        message
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query(0x1234, "mail._domainkey.example.com.", TXT).unwrap();
+        assert_eq!(&query[..12], &[0x12, 0x34, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[12..], b"\x04mail\x0a_domainkey\x07example\x03com\x00\x00\x10\x00\x01");
+
+        assert!(encode_query(1, "example..com", TXT).is_err());
+        assert!(encode_query(1, &format!("{}.com", "a".repeat(64)), TXT).is_err());
+    }
+
+    #[test]
+    fn test_decode_answer() {
+        let txt = answer(
+            [0x81, 0x80],
+            TXT,
+            &[(TXT, b"\x07v=spf1 \x04-all".to_vec()), (TXT, b"\x03abc".to_vec())],
+        );
+        assert_eq!(
+            decode_answer(&txt, TXT).unwrap(),
+            Answer::Records(vec![Record::Txt("v=spf1 -all".to_string()), Record::Txt("abc".to_string())])
+        );
+
+        // the exchange ends with a pointer to `example.com`
+        let mx = answer([0x81, 0x80], MX, &[(MX, b"\x00\x0a\x02mx\xc0\x0c".to_vec())]);
+        assert_eq!(
+            decode_answer(&mx, MX).unwrap(),
+            Answer::Records(vec![Record::Mx(10, "mx.example.com".to_string())])
+        );
+
+        // a CNAME along the records asked for
+        let a = answer([0x81, 0x80], A, &[(5, b"\xc0\x0c".to_vec()), (A, vec![192, 0, 2, 1])]);
+        assert_eq!(
+            decode_answer(&a, A).unwrap(),
+            Answer::Records(vec![Record::Ip(IpAddr::from([192, 0, 2, 1]))])
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let nxdomain = answer([0x81, 0x83], TXT, &[]);
+        assert_eq!(decode_answer(&nxdomain, TXT).unwrap(), Answer::Records(vec![]));
+
+        let servfail = answer([0x81, 0x82], TXT, &[]);
+        assert!(decode_answer(&servfail, TXT).is_err());
+
+        let truncated = answer([0x83, 0x80], TXT, &[]);
+        assert_eq!(decode_answer(&truncated, TXT).unwrap(), Answer::Truncated);
+
+        // a pointer to itself
+        let looping = answer([0x81, 0x80], MX, &[(MX, b"\x00\x0a\xc0\x2b".to_vec())]);
+        assert!(decode_answer(&looping, MX).is_err());
+
+        let short = answer([0x81, 0x80], A, &[(A, vec![192, 0, 2])]);
+        assert!(decode_answer(&short, A).is_err());
+        assert!(decode_answer(&short[..20], A).is_err());
+    }
+
+    #[test]
+    fn test_servers() {
+        assert_eq!(parse_server("1.1.1.1").unwrap(), "1.1.1.1:53".parse::<SocketAddr>().unwrap());
+        assert_eq!(parse_server("127.0.0.1:5353").unwrap(), "127.0.0.1:5353".parse::<SocketAddr>().unwrap());
+        assert_eq!(parse_server("::1").unwrap(), "[::1]:53".parse::<SocketAddr>().unwrap());
+        assert!(parse_server("dns.example").is_err());
+
+        let conf = "# generated\nsearch example.com\nnameserver 10.0.0.2\nnameserver 10.0.0.3\n";
+        assert_eq!(first_nameserver(conf), Some("10.0.0.2:53".parse().unwrap()));
+        assert_eq!(first_nameserver("search example.com\n"), None);
+    }
+}
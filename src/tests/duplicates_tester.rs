@@ -0,0 +1,18 @@
+#[cfg(test)]
+mod duplicates_tester {
+    use crate::duplicates;
+
+    #[test]
+    fn test_key() {
+        let first = b"Message-ID: <42@shop.test>\r\nSubject: hello\r\n\r\nHello!\r\n";
+        let resent = b"Message-ID:  <42@shop.test> \r\nSubject: hello again\r\n\r\nHello again!\r\n";
+        assert_eq!(duplicates::key(first), b"id:<42@shop.test>");
+        assert_eq!(duplicates::key(first), duplicates::key(resent));
+
+        // without a Message-ID, only the same content matches
+        let anonymous = b"Subject: hello\r\n\r\nHello!\r\n";
+        assert_eq!(duplicates::key(anonymous), duplicates::key(anonymous));
+        assert!(duplicates::key(anonymous).starts_with(b"hash:"));
+        assert_ne!(duplicates::key(anonymous), duplicates::key(b"Subject: hello\r\n\r\nHi!\r\n"));
+    }
+}
@@ -0,0 +1,35 @@
+#[cfg(test)]
+mod events_tester {
+    use crate::events::Event;
+    use crate::filter::MailFilter;
+    use crate::smtp::mail::Mail;
+    use std::collections::HashMap;
+
+    fn filter(pairs: &[(&str, &str)]) -> MailFilter {
+        let query: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        MailFilter::from_query(&query).unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let mail = Mail::new(
+            ["noreply@shop.test".to_string()].into(),
+            ["Alice@example.com".to_string()].into(),
+            "Subject: hello\r\n\r\nHello!\r\n".to_string(),
+            Some("hello".to_string()),
+        );
+        let event = Event::mail_received(&mail);
+
+        assert!(event.matches(&filter(&[])));
+        assert!(event.matches(&filter(&[("to", "alice@example.com")])));
+        assert!(event.matches(&filter(&[("to", "alice"), ("from", "shop.test")])));
+        assert!(!event.matches(&filter(&[("to", "bob@example.com")])));
+        assert!(!event.matches(&filter(&[("from", "billing")])));
+
+        // everyone hears about the storage
+        assert!(Event::StorageRecovered.matches(&filter(&[("to", "bob@example.com")])));
+    }
+}
@@ -0,0 +1,54 @@
+#[cfg(test)]
+mod export_tester {
+    use crate::export::{self, EmlZip, Format};
+    use crate::smtp::mail::Mail;
+    use std::collections::HashSet;
+    use std::io::Read;
+
+    fn mail(id: u128, from: &str, data: &str) -> Mail {
+        Mail {
+            from: HashSet::from([from.to_string()]),
+            to: HashSet::from(["bob@example.com".to_string()]),
+            subject: None,
+            data: data.to_string().into(),
+            id,
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Format::parse("mbox"), Ok(Format::Mbox));
+        assert_eq!(Format::parse("eml-zip"), Ok(Format::EmlZip));
+        assert!(Format::parse("").is_err());
+        assert!(Format::parse("pst").is_err());
+    }
+
+    #[test]
+    fn test_mbox_entry() {
+        // id 1 is received at the snowflake epoch, a monday
+        let received = mail(1, "alice@example.com", "Subject: hi\r\n\r\nFrom here\r\n>From there\r\nFrom: no");
+        assert_eq!(
+            String::from_utf8(export::mbox_entry(&received)).unwrap(),
+            "From alice@example.com Mon Jan  1 00:00:00 2024\n\
+             Subject: hi\n\n>From here\n>>From there\nFrom: no\n\n"
+        );
+
+        let anonymous = mail(1, "", "a\r\n");
+        assert!(export::mbox_entry(&anonymous).starts_with(b"From MAILER-DAEMON "));
+    }
+
+    #[test]
+    fn test_eml_zip() {
+        let mut zip = EmlZip::new();
+        let mut archive = Vec::new();
+        archive.extend(zip.add(&mail(1, "alice@example.com", "Subject: one\r\n\r\n1\r\n")).unwrap());
+        archive.extend(zip.add(&mail(2, "alice@example.com", "Subject: two\r\n\r\n2\r\n")).unwrap());
+        archive.extend(zip.finish().unwrap());
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(archive)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut content = String::new();
+        archive.by_name("2.eml").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "Subject: two\r\n\r\n2\r\n");
+    }
+}
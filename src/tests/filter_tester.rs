@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod filter_tester {
+    use crate::filter::MailFilter;
+    use crate::smtp::dkim;
+    use crate::smtp::mail::*;
+    use crate::smtp::spf;
+    use crate::smtp::verification::{Authentication, Outcome};
+    use crate::summary::MailSummary;
+    use std::collections::HashMap;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn sample_mail(path: &str) -> Mail {
+        let body = std::fs::read_to_string(path).unwrap();
+        let (from, to) = get_data_from_to(&body);
+        let subject = get_subject(&body);
+        Mail::new(from, to, body, subject)
+    }
+
+    #[test]
+    fn test_address_filters() {
+        let mail = sample_mail("test/samples/attachment.body");
+
+        let filter = MailFilter::from_query(&query(&[("to", "ALICE@")])).unwrap();
+        assert!(filter.matches(&mail));
+
+        let filter = MailFilter::from_query(&query(&[("from", "shop.test"), ("to", "bob")])).unwrap();
+        assert!(!filter.matches(&mail));
+
+        // empty values mean no filter
+        let filter = MailFilter::from_query(&query(&[("to", ""), ("search", " ")])).unwrap();
+        assert!(filter.matches(&mail));
+    }
+
+    #[test]
+    fn test_time_and_attachment_filters() {
+        let with_attachments = sample_mail("test/samples/attachment.body");
+        let without_attachments = sample_mail("test/samples/raw.body");
+        let timestamp = with_attachments.timestamp().to_string();
+
+        let filter = MailFilter::from_query(&query(&[("since", &timestamp)])).unwrap();
+        assert!(filter.matches(&with_attachments));
+        let filter = MailFilter::from_query(&query(&[("until", &timestamp)])).unwrap();
+        assert!(!filter.matches(&with_attachments));
+        let filter = MailFilter::from_query(&query(&[("before", &timestamp)])).unwrap();
+        assert!(!filter.matches(&with_attachments));
+
+        let filter = MailFilter::from_query(&query(&[("has_attachment", "true")])).unwrap();
+        assert!(filter.matches(&with_attachments));
+        assert!(!filter.matches(&without_attachments));
+
+        assert!(MailFilter::from_query(&query(&[("since", "yesterday")])).is_err());
+        assert!(MailFilter::from_query(&query(&[("has_attachment", "maybe")])).is_err());
+    }
+
+    #[test]
+    fn test_authentication_filters() {
+        let mut summary = MailSummary::from_mail(&sample_mail("test/samples/raw.body"));
+        let filter = |pairs: &[(&str, &str)]| MailFilter::from_query(&query(pairs)).unwrap();
+
+        // not verified, see `--verify-auth`
+        assert!(!filter(&[("dkim", "none")]).matches_labels(&summary));
+        assert!(filter(&[("dkim", "")]).matches_labels(&summary));
+
+        let verdict = |result| dkim::Verdict {
+            result,
+            domain: "example.com".to_string(),
+            selector: "mail".to_string(),
+            reason: None,
+        };
+        summary.authentication = Some(Authentication {
+            dkim: vec![verdict(Outcome::Pass), verdict(Outcome::Fail)],
+            spf: spf::Verdict {
+                result: Outcome::SoftFail,
+                domain: "example.com".to_string(),
+                ip: "192.0.2.1".parse().unwrap(),
+                reason: None,
+            },
+        });
+        assert!(filter(&[("dkim", "fail")]).matches_labels(&summary));
+        assert!(filter(&[("dkim", "PASS"), ("spf", "softfail")]).matches_labels(&summary));
+        assert!(!filter(&[("dkim", "none")]).matches_labels(&summary));
+        assert!(!filter(&[("spf", "pass")]).matches_labels(&summary));
+
+        summary.authentication.as_mut().unwrap().dkim.clear();
+        assert!(filter(&[("dkim", "none")]).matches_labels(&summary));
+
+        assert!(MailFilter::from_query(&query(&[("dkim", "ok")])).is_err());
+    }
+}
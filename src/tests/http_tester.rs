@@ -0,0 +1,382 @@
+#[cfg(test)]
+mod http_tester {
+    use crate::http::{self, Router};
+    use crate::ingest::Queue;
+    use crate::limits::{self, Rate};
+    use crate::session::{self, Role};
+    use crate::relay;
+    use crate::store::Store;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Instant;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+    // the status line and the lowercased headers of a response, its body skipped
+    async fn response(stream: &mut BufReader<DuplexStream>) -> (String, Vec<String>) {
+        let (status, headers, _) = response_with_body(stream).await;
+        (status, headers)
+    }
+
+    async fn response_with_body(stream: &mut BufReader<DuplexStream>) -> (String, Vec<String>, Vec<u8>) {
+        let mut status = String::new();
+        stream.read_line(&mut status).await.unwrap();
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            headers.push(line.trim_end().to_lowercase());
+        }
+        let length = headers
+            .iter()
+            .find_map(|header| header.strip_prefix("content-length: "))
+            .map(|length| length.parse::<usize>().unwrap())
+            .unwrap();
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        (status.trim_end().to_string(), headers, body)
+    }
+
+    fn serve() -> (BufReader<DuplexStream>, tokio::task::JoinHandle<()>) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let handle = tokio::spawn(async move {
+            let queue = Queue::start(db.clone(), 10, relay::channel().0);
+            http::handle_client(server, Ipv4Addr::LOCALHOST.into(), db, &Router::new(queue)).await.unwrap();
+        });
+        (BufReader::new(client), handle)
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let (mut stream, handle) = serve();
+        let request = b"GET /login HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        stream.get_mut().write_all(request).await.unwrap();
+        let (status, headers) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(headers.contains(&"connection: keep-alive".to_string()));
+        assert!(headers.iter().any(|header| header.starts_with("date: ") && header.ends_with(" gmt")));
+
+        // a body is read whole, the next request starts right after it
+        let request = b"POST /login HTTP/1.1\r\nContent-Length: 9\r\n\r\nkey=wrongGET /login HTTP/1.1\r\nConnection: close\r\n\r\n";
+        stream.get_mut().write_all(request).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 303 See Other");
+        let (status, headers) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(headers.contains(&"connection: close".to_string()));
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_1_0_closes() {
+        let (mut stream, handle) = serve();
+        stream.get_mut().write_all(b"GET /login HTTP/1.0\r\n\r\n").await.unwrap();
+        let (_, headers) = response(&mut stream).await;
+        assert!(headers.contains(&"connection: close".to_string()));
+        handle.await.unwrap();
+
+        let (mut stream, handle) = serve();
+        let request = b"POST /login HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        stream.get_mut().write_all(request).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 411 Length Required");
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_post_mail() {
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Admin));
+        let mail = "From: sender@example.com\nTo: rcpt@example.com\nSubject: Posted\n\nHello there";
+        let request = format!(
+            "POST /mails HTTP/1.1\r\nCookie: {}\r\nContent-Type: message/rfc822\r\nContent-Length: {}\r\n\r\n{}",
+            cookie,
+            mail.len(),
+            mail
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, headers) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 201 Created");
+        let location = headers
+            .iter()
+            .find_map(|header| header.strip_prefix("location: "))
+            .unwrap()
+            .to_string();
+
+        // stored by the time it's answered
+        let request = format!("GET {} HTTP/1.1\r\nCookie: {}\r\n\r\n", location, cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+
+        let body = r#"{"from": "sender@example.com", "to": []}"#;
+        let request = format!(
+            "PUT /mails HTTP/1.1\r\nCookie: {}\r\nContent-Length: {}\r\n\r\n{}",
+            cookie,
+            body.len(),
+            body
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+        let request = format!(
+            "POST /mails HTTP/1.1\r\nCookie: {}\r\nContent-Type: text/plain\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi",
+            cookie
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 415 Unsupported Media Type");
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_body_limits() {
+        // not authenticated: dropped before the body is read, let alone allocated
+        let (mut stream, handle) = serve();
+        let request = b"POST /mails HTTP/1.1\r\nContent-Type: message/rfc822\r\nContent-Length: 100000000\r\n\r\n";
+        stream.get_mut().write_all(request).await.unwrap();
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        handle.await.unwrap();
+
+        // past any --max-message-size, there is always a limit
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Admin));
+        let request = format!(
+            "POST /mails HTTP/1.1\r\nCookie: {}\r\nContent-Type: message/rfc822\r\nContent-Length: 1000000000000\r\n\r\n",
+            cookie
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, headers) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+        assert!(headers.contains(&"connection: close".to_string()));
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keys_requests() {
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Admin));
+        let requests = [
+            ("POST /admin/keys", "{\"role\": \"owner\"}", "HTTP/1.1 400 Bad Request"),
+            ("DELETE /admin/keys/none", "", "HTTP/1.1 404 Not Found"),
+            ("DELETE /admin/keys/999999", "", "HTTP/1.1 404 Not Found"),
+        ];
+        for (line, body, expected) in requests {
+            let (mut stream, handle) = serve();
+            let request = format!(
+                "{} HTTP/1.1\r\nCookie: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                line,
+                cookie,
+                body.len(),
+                body
+            );
+            stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+            let (status, _) = response(&mut stream).await;
+            assert_eq!(status, expected);
+            handle.await.unwrap();
+        }
+
+        // a read-only session can't manage them
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Read));
+        let request = format!("GET /admin/keys HTTP/1.1\r\nCookie: {}\r\nConnection: close\r\n\r\n", cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_and_tags() {
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Admin));
+        let mail = "From: sender@example.com\r\nTo: rcpt@example.com\r\nSubject: Tagged\r\n\r\nHello there";
+        let request = format!(
+            "POST /mails HTTP/1.1\r\nCookie: {}\r\nContent-Type: message/rfc822\r\nContent-Length: {}\r\n\r\n{}",
+            cookie,
+            mail.len(),
+            mail
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (_, headers) = response(&mut stream).await;
+        let location = headers
+            .iter()
+            .find_map(|header| header.strip_prefix("location: "))
+            .unwrap()
+            .to_string();
+
+        let patch = r#"{"add_tags": ["run-42", "RUN-42", " "]}"#;
+        let request = format!(
+            "PATCH {} HTTP/1.1\r\nCookie: {}\r\nContent-Length: {}\r\n\r\n{}",
+            location,
+            cookie,
+            patch.len(),
+            patch
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _, body) = response_with_body(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(summary["tags"], serde_json::json!(["run-42"]));
+        assert_eq!(summary["read"], false);
+
+        // fetching it marks it as read
+        let request = format!("GET {} HTTP/1.1\r\nCookie: {}\r\n\r\n", location, cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (_, _, body) = response_with_body(&mut stream).await;
+        let mail: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(mail["read"], true);
+        assert_eq!(mail["tags"], serde_json::json!(["run-42"]));
+
+        let patch = r#"{"read": "yes"}"#;
+        let request = format!(
+            "PATCH {} HTTP/1.1\r\nCookie: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            location,
+            cookie,
+            patch.len(),
+            patch
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_attachments() {
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Admin));
+        let mail = std::fs::read("test/samples/attachment.body").unwrap();
+        let request = format!(
+            "POST /mails?from=shop@shop.test&to=buyer@example.com HTTP/1.1\r\nCookie: {}\r\nContent-Type: message/rfc822\r\nContent-Length: {}\r\n\r\n",
+            cookie,
+            mail.len()
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        stream.get_mut().write_all(&mail).await.unwrap();
+        let (status, headers) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 201 Created");
+        let location = headers
+            .iter()
+            .find_map(|header| header.strip_prefix("location: "))
+            .unwrap()
+            .to_string();
+
+        let request = format!("GET {}/attachments HTTP/1.1\r\nCookie: {}\r\n\r\n", location, cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _, body) = response_with_body(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let attachments: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(attachments[0]["filename"], "invoice-1042.pdf");
+        assert_eq!(attachments[0]["size"], 77);
+        assert_eq!(
+            attachments[0]["sha256"],
+            "56c2ac043c28d3543275a6f50b593a6beb0b6888ed8159ac02e3e7f4a32ccb26"
+        );
+        assert_eq!(attachments[1]["content_id"], "logo@shop.test");
+
+        let request = format!(
+            "GET /mails/1/attachments HTTP/1.1\r\nCookie: {}\r\nConnection: close\r\n\r\n",
+            cookie
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_info() {
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Read));
+        let request = format!("GET /info HTTP/1.1\r\nCookie: {}\r\nConnection: close\r\n\r\n", cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _, body) = response_with_body(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["mail_count"], 0);
+        assert_eq!(info["received_last_day"], 0);
+        assert!(info["uptime_seconds"].is_u64());
+        assert!(info["listeners"]["smtp"].is_array());
+        assert!(info["config"].is_object());
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reset() {
+        // a client throttled by --http-rate, from an address no other test uses
+        let throttled = IpAddr::from([192, 0, 2, 77]);
+        let now = Instant::now();
+        {
+            let mut buckets = limits::BUCKETS.lock().unwrap();
+            buckets.take(Rate::HttpRequests, throttled, 1, now);
+            assert!(!buckets.take(Rate::HttpRequests, throttled, 1, now));
+        }
+
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Admin));
+        let request = format!("POST /reset HTTP/1.1\r\nCookie: {}\r\nConnection: close\r\n\r\n", cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, _, body) = response_with_body(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let cleared: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(cleared["mails"], 0);
+        // at least the throttled client, the other tests' deliveries may be counted too
+        assert!(cleared["rate_limits"].as_u64().unwrap() >= 1);
+        for count in ["duplicates", "relay_statuses", "webhooks", "relays"] {
+            assert!(cleared[count].is_u64(), "{} missing", count);
+        }
+        handle.await.unwrap();
+
+        assert!(limits::BUCKETS.lock().unwrap().take(Rate::HttpRequests, throttled, 1, now));
+    }
+
+    #[tokio::test]
+    async fn test_preview() {
+        let (mut stream, handle) = serve();
+        let cookie = format!("{}={}", session::COOKIE_NAME, session::create(Role::Read));
+        let admin = format!("{}={}", session::COOKIE_NAME, session::create(Role::Admin));
+        let mail = "From: a@example.com\nTo: b@example.com\nContent-Type: text/html\n\n<p>Hi</p>";
+        let request = format!(
+            "POST /mails HTTP/1.1\r\nCookie: {}\r\nContent-Type: message/rfc822\r\nContent-Length: {}\r\n\r\n{}",
+            admin,
+            mail.len(),
+            mail
+        );
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (_, headers) = response(&mut stream).await;
+        let id = headers
+            .iter()
+            .find_map(|header| header.strip_prefix("location: /mails/"))
+            .unwrap()
+            .to_string();
+
+        let request = format!("GET /preview/{} HTTP/1.1\r\nCookie: {}\r\n\r\n", id, cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, headers, body) = response_with_body(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(headers.contains(&"content-type: text/html; charset=utf-8".to_string()));
+        assert!(headers.iter().any(|header| header.starts_with("content-security-policy: sandbox")));
+        assert!(String::from_utf8_lossy(&body).contains("<p>Hi</p>"));
+
+        // the old path of the rendered mail
+        let request = format!("GET /mails/{}/html HTTP/1.1\r\nCookie: {}\r\n\r\n", id, cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, headers) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 303 See Other");
+        assert!(headers.contains(&format!("location: /preview/{}", id)));
+
+        // the panel's page around it
+        let request = format!("GET /open/{} HTTP/1.1\r\nCookie: {}\r\nConnection: close\r\n\r\n", id, cookie);
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let (status, headers) = response(&mut stream).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(headers.contains(&"content-type: text/html".to_string()));
+        handle.await.unwrap();
+    }
+}
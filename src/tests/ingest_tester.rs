@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod ingest_tester {
+    use crate::ingest::Queue;
+    use crate::memory::Reservation;
+    use crate::rules::Labels;
+    use crate::smtp::mail::Mail;
+    use crate::relay;
+    use crate::store::Store;
+    use std::time::Duration;
+
+    fn mail() -> Mail {
+        Mail::new(
+            ["noreply@shop.test".to_string()].into(),
+            ["alice@example.com".to_string()].into(),
+            "Subject: hello\r\n\r\nHello Alice!\r\n".to_string(),
+            Some("hello".to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_refuses_mails() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let queue = Queue::start(db.clone(), 1, relay::channel().0);
+
+        // writers are held back, so the queue fills up
+        let guard = db.exclusive().await;
+        let mut accepted = 0;
+        for _ in 0..10 {
+            if queue.push(mail(), Reservation::new(), None, Labels::default()).is_some() {
+                accepted += 1;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(accepted < 10);
+        drop(guard);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while db.len() < accepted {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(queue.push(mail(), Reservation::new(), None, Labels::default()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stored_is_told() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let queue = Queue::start(db.clone(), 10, relay::channel().0);
+
+        let guard = db.exclusive().await;
+        let mail = mail();
+        let id = mail.id;
+        let mut stored = queue.push(mail, Reservation::new(), None, Labels::default()).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(stored.try_recv().is_err());
+        drop(guard);
+
+        tokio::time::timeout(Duration::from_secs(5), stored).await.unwrap().unwrap();
+        assert!(db.get_mail(id).unwrap().is_some());
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_find_duplicate() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let queue = Queue::start(db.clone(), 10, relay::channel().0);
+        let with_id = |id: &str| {
+            Mail::new(
+                ["noreply@shop.test".to_string()].into(),
+                ["alice@example.com".to_string()].into(),
+                format!("Message-ID: <{}@shop.test>\r\nSubject: hello\r\n\r\nHello Alice!\r\n", id),
+                Some("hello".to_string()),
+            )
+        };
+
+        let first = with_id("1");
+        assert_eq!(queue.find_duplicate(&first).unwrap(), None);
+        // waiting to be stored counts already
+        let first_id = first.id;
+        assert!(queue.push(first, Reservation::new(), None, Labels::default()).is_some());
+        assert_eq!(queue.find_duplicate(&with_id("1")).unwrap(), Some(first_id));
+        assert_eq!(queue.find_duplicate(&with_id("2")).unwrap(), None);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !db.contains_key(first_id.to_le_bytes()).unwrap() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(queue.find_duplicate(&with_id("1")).unwrap(), Some(first_id));
+
+        // once the first one is deleted, the next copy takes its place
+        db.remove(first_id.to_le_bytes()).unwrap();
+        let again = with_id("1");
+        assert_eq!(queue.find_duplicate(&again).unwrap(), None);
+        let again_id = again.id;
+        assert!(queue.push(again, Reservation::new(), None, Labels::default()).is_some());
+        assert_eq!(queue.find_duplicate(&with_id("1")).unwrap(), Some(again_id));
+    }
+}
@@ -0,0 +1,80 @@
+#[cfg(test)]
+mod jobs_tester {
+    use crate::jobs::{self, Schedule};
+    use crate::smtp::mail::Mail;
+    use crate::store::Store;
+    use crate::summary::{self, MailSummary};
+
+    // 2024-01-01 00:00 UTC, a monday
+    const NEW_YEAR: u64 = 1_704_067_200;
+    // 2024-02-29 12:30 UTC, a thursday
+    const LEAP_DAY: u64 = 1_709_209_800;
+
+    #[test]
+    fn test_schedule() {
+        let daily = Schedule::parse("0 0 * * *").unwrap();
+        assert!(daily.matches(NEW_YEAR));
+        assert!(!daily.matches(NEW_YEAR + 60));
+        assert_eq!(daily.next(NEW_YEAR), Some(NEW_YEAR + 86_400));
+        assert_eq!(Schedule::parse("@daily").unwrap(), daily);
+
+        let schedule = Schedule::parse("*/15 9-17 * * 1-5").unwrap();
+        assert!(schedule.matches(LEAP_DAY - 3 * 3600));
+        assert!(!schedule.matches(LEAP_DAY + 6 * 3600));
+        assert!(!schedule.matches(LEAP_DAY - 3 * 3600 + 5 * 60));
+
+        // sunday is 0 and 7, and either day matches when both are restricted
+        assert_eq!(Schedule::parse("0 0 * * 7").unwrap(), Schedule::parse("0 0 * * 0").unwrap());
+        let either = Schedule::parse("30 12 29 * 1").unwrap();
+        assert!(either.matches(LEAP_DAY));
+        assert!(!either.matches(LEAP_DAY + 86_400));
+        assert_eq!(either.next(LEAP_DAY), Some(LEAP_DAY + 4 * 86_400));
+
+        assert!(Schedule::parse("0 0 * *").is_err());
+        assert!(Schedule::parse("60 * * * *").is_err());
+        assert!(Schedule::parse("*/0 * * * *").is_err());
+        assert!(Schedule::parse("0 5-1 * * *").is_err());
+        assert_eq!(Schedule::parse("0 0 30 2 *").unwrap().next(NEW_YEAR), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let job = jobs::parse("*/30  * * * *   purge older_than=60&to=example.com").unwrap();
+        assert!(matches!(job.action, jobs::Action::Purge(_)));
+        assert!(matches!(jobs::parse("@weekly flush").unwrap().action, jobs::Action::Flush));
+        match jobs::parse("@daily archive /backups since=0").unwrap().action {
+            jobs::Action::Archive(dir, _) => assert_eq!(dir.to_str(), Some("/backups")),
+            action => panic!("unexpected action {:?}", action),
+        }
+
+        assert!(jobs::parse("* * * * *").is_err());
+        assert!(jobs::parse("@daily archive").is_err());
+        assert!(jobs::parse("@daily flush now").is_err());
+        assert!(jobs::parse("@daily purge older_than=soon").is_err());
+        assert!(jobs::parse("@daily restart").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        for to in ["alice@example.com", "bob@example.com"] {
+            let mail = Mail::new(
+                ["noreply@shop.test".to_string()].into(),
+                [to.to_string()].into(),
+                "Subject: hello\r\n\r\nHello!\r\n".to_string(),
+                Some("hello".to_string()),
+            );
+            db.insert(mail.id.to_le_bytes(), bincode::serialize(&mail).unwrap()).unwrap();
+            summary::insert(&db, &MailSummary::from_mail(&mail)).unwrap();
+        }
+
+        // the mails are brand new
+        let job = jobs::parse("@hourly purge older_than=60").unwrap();
+        assert_eq!(jobs::run(&job, &db).await.unwrap(), "deleted 0 mails");
+
+        let job = jobs::parse("@hourly purge to=bob").unwrap();
+        assert_eq!(jobs::run(&job, &db).await.unwrap(), "deleted 1 mails");
+        assert_eq!(db.len(), 1);
+        assert_eq!(summary::tree(&db).unwrap().len(), 1);
+    }
+}
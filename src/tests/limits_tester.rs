@@ -0,0 +1,61 @@
+#[cfg(test)]
+mod limits_tester {
+    use crate::limits::{Buckets, Connections, Rate, Refusal};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    const FIRST: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const SECOND: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn test_connections() {
+        let mut connections = Connections::default();
+        assert_eq!(connections.open(FIRST, Some(3), Some(2)), Ok(()));
+        assert_eq!(connections.open(FIRST, Some(3), Some(2)), Ok(()));
+        assert_eq!(connections.open(FIRST, Some(3), Some(2)), Err(Refusal::PerIp));
+        assert_eq!(connections.open(SECOND, Some(3), Some(2)), Ok(()));
+        assert_eq!(connections.open(SECOND, Some(3), Some(2)), Err(Refusal::Total));
+
+        connections.close(FIRST);
+        assert_eq!(connections.open(SECOND, Some(3), Some(2)), Ok(()));
+        assert_eq!(connections.open(SECOND, Some(3), Some(2)), Err(Refusal::Total));
+        // without limits, only counted
+        assert_eq!(connections.open(SECOND, None, None), Ok(()));
+        assert_eq!(connections.open(FIRST, Some(5), None), Ok(()));
+        assert_eq!(connections.open(FIRST, Some(5), None), Err(Refusal::Total));
+    }
+
+    #[test]
+    fn test_buckets() {
+        let mut buckets = Buckets::default();
+        let start = Instant::now();
+        // a second worth at once
+        for _ in 0..5 {
+            assert!(buckets.take(Rate::SmtpCommands, FIRST, 5, start));
+        }
+        assert!(!buckets.take(Rate::SmtpCommands, FIRST, 5, start));
+        // other clients and the other rate have their own
+        assert!(buckets.take(Rate::SmtpCommands, SECOND, 5, start));
+        assert!(buckets.take(Rate::HttpRequests, FIRST, 5, start));
+
+        // refilled at the rate, never past a second worth
+        let later = start + Duration::from_millis(200);
+        assert!(buckets.take(Rate::SmtpCommands, FIRST, 5, later));
+        assert!(!buckets.take(Rate::SmtpCommands, FIRST, 5, later));
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..5 {
+            assert!(buckets.take(Rate::SmtpCommands, FIRST, 5, much_later));
+        }
+        assert!(!buckets.take(Rate::SmtpCommands, FIRST, 5, much_later));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut buckets = Buckets::default();
+        let now = Instant::now();
+        assert!(buckets.take(Rate::HttpRequests, FIRST, 1, now));
+        assert!(!buckets.take(Rate::HttpRequests, FIRST, 1, now));
+        buckets.clear();
+        assert!(buckets.take(Rate::HttpRequests, FIRST, 1, now));
+    }
+}
@@ -0,0 +1,10 @@
+#[cfg(test)]
+mod logging_tester {
+    use crate::logging::{self, Format};
+
+    #[test]
+    fn test_init_rejects_invalid_level() {
+        // checked before installing anything, so this doesn't touch the global subscriber
+        assert!(logging::init("info,mail_sink=loud", Format::Text).is_err());
+    }
+}
@@ -0,0 +1,26 @@
+#[cfg(test)]
+mod memory_tester {
+    use crate::memory::{self, Reservation};
+
+    #[test]
+    fn test_budget() {
+        let used = memory::used();
+        memory::set_budget(Some(used + 100));
+
+        let mut first = Reservation::new();
+        assert!(first.grow(60));
+        let mut second = Reservation::new();
+        assert!(!second.grow(50));
+        assert!(!memory::exceeded());
+        assert!(second.grow(40));
+        assert!(memory::exceeded());
+
+        drop(first);
+        drop(second);
+        assert_eq!(memory::used(), used);
+        assert!(!memory::exceeded());
+
+        memory::set_budget(None);
+        assert!(Reservation::new().grow(usize::MAX / 2));
+    }
+}
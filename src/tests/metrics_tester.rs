@@ -0,0 +1,27 @@
+#[cfg(test)]
+mod metrics_tester {
+    use crate::metrics::Histogram;
+    use std::time::Duration;
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new(&[0.01, 0.1]);
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_millis(10));
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_secs(2));
+
+        let mut text = String::new();
+        histogram.render(&mut text, "latency_seconds", "Latency");
+        // buckets are cumulative, bounds inclusive
+        assert_eq!(
+            text,
+            "# HELP latency_seconds Latency\n# TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{le=\"0.01\"} 2\n\
+             latency_seconds_bucket{le=\"0.1\"} 3\n\
+             latency_seconds_bucket{le=\"+Inf\"} 4\n\
+             latency_seconds_sum 2.065\n\
+             latency_seconds_count 4\n"
+        );
+    }
+}
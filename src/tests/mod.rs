@@ -1 +1,74 @@
+#[allow(clippy::module_inception)]
+mod filter_tester;
+#[allow(clippy::module_inception)]
 mod parsing_tester;
+#[allow(clippy::module_inception)]
+mod session_tester;
+#[allow(clippy::module_inception)]
+mod diff_tester;
+#[allow(clippy::module_inception)]
+mod stats_tester;
+#[allow(clippy::module_inception)]
+mod smtp_sessions_tester;
+#[allow(clippy::module_inception)]
+mod bench_tester;
+#[allow(clippy::module_inception)]
+mod summary_tester;
+#[allow(clippy::module_inception)]
+mod ingest_tester;
+#[allow(clippy::module_inception)]
+mod memory_tester;
+#[allow(clippy::module_inception)]
+mod storage_tester;
+#[allow(clippy::module_inception)]
+mod snowflake_tester;
+#[allow(clippy::module_inception)]
+mod snapshot_tester;
+#[allow(clippy::module_inception)]
+mod metrics_tester;
+#[allow(clippy::module_inception)]
+mod duplicates_tester;
+#[allow(clippy::module_inception)]
+mod jobs_tester;
+#[allow(clippy::module_inception)]
+mod rules_tester;
+#[allow(clippy::module_inception)]
+mod events_tester;
+#[allow(clippy::module_inception)]
+mod webhooks_tester;
+#[allow(clippy::module_inception)]
+mod tls_tester;
+#[allow(clippy::module_inception)]
+mod auth_tester;
+#[allow(clippy::module_inception)]
+mod retention_tester;
+#[allow(clippy::module_inception)]
+mod logging_tester;
+#[allow(clippy::module_inception)]
+mod config_file_tester;
+#[allow(clippy::module_inception)]
+mod pop3_tester;
+#[allow(clippy::module_inception)]
+mod chaos_tester;
+#[allow(clippy::module_inception)]
+mod smtp_tester;
+#[allow(clippy::module_inception)]
+mod export_tester;
+#[allow(clippy::module_inception)]
+mod http_tester;
+#[allow(clippy::module_inception)]
+mod shutdown_tester;
+#[allow(clippy::module_inception)]
+mod store_tester;
+#[allow(clippy::module_inception)]
+mod relay_tester;
+#[allow(clippy::module_inception)]
+mod upload_tester;
+#[allow(clippy::module_inception)]
+mod limits_tester;
+#[allow(clippy::module_inception)]
+mod recipients_tester;
+#[allow(clippy::module_inception)]
+mod verification_tester;
+#[allow(clippy::module_inception)]
+mod dns_tester;
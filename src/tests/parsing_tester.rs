@@ -10,7 +10,7 @@ mod parsing_tester {
         let mail = Mail {
             from: Default::default(),
             to: Default::default(),
-            data: body,
+            data: body.into(),
             subject,
             id: 0,
         };
@@ -18,7 +18,7 @@ mod parsing_tester {
         let parsed = mail.parse_body();
         assert!(parsed.starts_with("<!doctype html>"));
 
-        let (from, _) = get_data_from_to(&mail.data);
+        let (from, _) = get_data_from_to(&mail.data_str());
         assert!(from.contains("noreply@discord.com"));
 
         //should've decoded the subject with rfc2047 decoder
@@ -32,7 +32,7 @@ mod parsing_tester {
         let mail = Mail {
             from: Default::default(),
             to: Default::default(),
-            data: body,
+            data: body.into(),
             subject,
             id: 0,
         };
@@ -40,10 +40,137 @@ mod parsing_tester {
         let parsed = mail.parse_body();
         assert_eq!(parsed.len(), 1809);
 
-        let (from, to) = get_data_from_to(&mail.data);
+        let (from, to) = get_data_from_to(&mail.data_str());
         assert!(from.contains("test@test.com"));
         assert_eq!(to.len(), 8);
 
         assert_eq!(mail.subject.unwrap(), "test smtp--");
     }
+
+    #[test]
+    fn test_attachments() {
+        let body = std::fs::read_to_string("test/samples/attachment.body").unwrap();
+        let subject = get_subject(&body);
+        let mail = Mail {
+            from: Default::default(),
+            to: Default::default(),
+            data: body.into(),
+            subject,
+            id: 0,
+        };
+
+        let attachments = mail.attachments();
+        assert_eq!(attachments.len(), 2);
+
+        assert_eq!(attachments[0].filename, "invoice-1042.pdf");
+        assert_eq!(attachments[0].content_type, "application/pdf");
+        assert_eq!(attachments[0].size, 77);
+        assert_eq!(
+            attachments[0].sha256,
+            "56c2ac043c28d3543275a6f50b593a6beb0b6888ed8159ac02e3e7f4a32ccb26"
+        );
+
+        assert_eq!(attachments[1].filename, "logo.png");
+        assert_eq!(attachments[1].content_type, "image/png");
+        assert_eq!(attachments[1].size, 70);
+        assert_eq!(attachments[1].content_id.as_deref(), Some("logo@shop.test"));
+
+        let (_, content) = &mail.attachments_with_content()[0];
+        assert!(content.starts_with(b"%PDF-1.4"));
+
+        // the html alternative should still be picked as the body
+        assert!(mail.parse_body().starts_with("<html>"));
+    }
+
+    #[test]
+    fn test_body_alternatives() {
+        let body = std::fs::read_to_string("test/samples/attachment.body").unwrap();
+        let mail = Mail {
+            data: body.into(),
+            ..Default::default()
+        };
+
+        assert!(mail.html_body().unwrap().starts_with("<html>"));
+        assert_eq!(mail.text_body().unwrap().trim(), "Hello Alice, your invoice is attached.");
+
+        let body = std::fs::read_to_string("test/samples/raw.body").unwrap();
+        let mail = Mail {
+            data: body.into(),
+            ..Default::default()
+        };
+
+        // single part mail declared as html, even though its content is plain text
+        assert!(mail.html_body().is_some());
+        assert!(mail.text_body().is_none());
+    }
+
+    #[test]
+    fn test_html_preview() {
+        let body = std::fs::read_to_string("test/samples/attachment.body").unwrap();
+        let mail = Mail {
+            data: body.into(),
+            ..Default::default()
+        };
+        assert!(mail.html_preview().starts_with("<html>"));
+
+        // no html alternative, the text one is escaped into a <pre>
+        let mail = Mail {
+            data: "Content-Type: text/plain\r\n\r\n1 < 2 & <b>bold</b>\r\n".into(),
+            ..Default::default()
+        };
+        assert!(mail
+            .html_preview()
+            .contains("<pre>1 &lt; 2 &amp; &lt;b&gt;bold&lt;/b&gt;"));
+    }
+
+    #[test]
+    fn test_headers() {
+        let body = std::fs::read_to_string("test/samples/discord_mail.body").unwrap();
+        let mail = Mail {
+            from: Default::default(),
+            to: Default::default(),
+            data: body.into(),
+            subject: None,
+            id: 0,
+        };
+
+        let headers = mail.headers();
+        assert_eq!(headers[0].name, "DKIM-Signature");
+
+        // order is kept and encoded words are decoded
+        let subject = headers.iter().find(|h| h.name == "Subject").unwrap();
+        assert_eq!(subject.value, "Vérifie ton adresse e-mail Discord");
+        let received = headers.iter().filter(|h| h.name == "Received").count();
+        assert_eq!(received, 2);
+    }
+
+    #[test]
+    fn test_data_encoding() {
+        // how mails were stored when their data was a String
+        #[derive(serde::Serialize)]
+        struct StringMail {
+            from: std::collections::HashSet<String>,
+            to: std::collections::HashSet<String>,
+            subject: Option<String>,
+            data: String,
+            id: u128,
+        }
+
+        let stored = bincode::serialize(&StringMail {
+            from: ["a@b.c".to_string()].into(),
+            to: Default::default(),
+            subject: None,
+            data: "Subject: hé\r\n\r\nhello".to_string(),
+            id: 42,
+        })
+        .unwrap();
+        let mail: Mail = bincode::deserialize(&stored).unwrap();
+        assert_eq!(mail.data_str(), "Subject: hé\r\n\r\nhello");
+        assert_eq!(bincode::serialize(&mail).unwrap(), stored);
+
+        // text in JSON, even when it isn't valid UTF-8
+        let mail = Mail::new(Default::default(), Default::default(), &b"caf\xe9"[..], None);
+        let json = serde_json::to_value(&mail).unwrap();
+        assert_eq!(json["data"], "caf\u{fffd}");
+    }
 }
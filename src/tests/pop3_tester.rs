@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod pop3_tester {
+    use crate::pop3;
+    use crate::smtp::mail::Mail;
+    use crate::store::Store;
+    use crate::summary::{self, MailSummary};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+    #[test]
+    fn test_multiline() {
+        assert_eq!(pop3::multiline(b"a\n.b\r\nc"), b"a\r\n..b\r\nc\r\n.\r\n");
+        assert_eq!(pop3::multiline(b""), b".\r\n");
+    }
+
+    async fn command(stream: &mut BufReader<DuplexStream>, line: &str) -> String {
+        stream.get_mut().write_all(format!("{}\r\n", line).as_bytes()).await.unwrap();
+        let mut reply = String::new();
+        stream.read_line(&mut reply).await.unwrap();
+        reply
+    }
+
+    // the rest of a multi-line reply, up to the lone dot
+    async fn lines(stream: &mut BufReader<DuplexStream>) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            if line == ".\r\n" {
+                return lines;
+            }
+            lines.push(line.trim_end().to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        for (id, data) in [(1u128, "Subject: first\r\n\r\nhello\r\n"), (2, "Subject: second\r\n\r\n.dot\r\n")] {
+            let mail = Mail {
+                data: data.into(),
+                id,
+                ..Default::default()
+            };
+            db.insert(id.to_le_bytes(), bincode::serialize(&mail).unwrap()).unwrap();
+            summary::insert(&db, &MailSummary::from_mail(&mail)).unwrap();
+        }
+
+        let (client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(pop3::handle_client(server, db.clone()));
+        let mut stream = BufReader::new(client);
+        let mut greeting = String::new();
+        stream.read_line(&mut greeting).await.unwrap();
+        assert!(greeting.starts_with("+OK"));
+
+        assert!(command(&mut stream, "STAT").await.starts_with("-ERR"));
+        assert!(command(&mut stream, "USER tester").await.starts_with("+OK"));
+        assert_eq!(command(&mut stream, "PASS anything").await, "+OK 2 messages\r\n");
+        assert_eq!(command(&mut stream, "STAT").await, "+OK 2 50\r\n");
+
+        command(&mut stream, "UIDL").await;
+        assert_eq!(lines(&mut stream).await, vec!["1 1", "2 2"]);
+
+        assert!(command(&mut stream, "RETR 2").await.starts_with("+OK"));
+        assert_eq!(lines(&mut stream).await, vec!["Subject: second", "", "..dot"]);
+
+        assert_eq!(command(&mut stream, "DELE 1").await, "+OK Deleted\r\n");
+        assert!(command(&mut stream, "RETR 1").await.starts_with("-ERR"));
+        command(&mut stream, "LIST").await;
+        assert_eq!(lines(&mut stream).await, vec!["2 25"]);
+
+        assert_eq!(command(&mut stream, "QUIT").await, "+OK 1 messages deleted\r\n");
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        handle.await.unwrap().unwrap();
+
+        assert!(db.get(1u128.to_le_bytes()).unwrap().is_none());
+        assert_eq!(summary::usage(&db).unwrap(), (1, 25));
+    }
+}
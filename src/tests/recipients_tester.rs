@@ -0,0 +1,64 @@
+#[cfg(test)]
+mod recipients_tester {
+    use crate::smtp::recipients::{self, Policy};
+
+    // the policy is global and other tests hold SMTP sessions, so only its struct is tested
+    fn build(domains: &[&str], accept: &[&str], reject: &[&str]) -> Policy {
+        Policy {
+            domains: domains.iter().map(|domain| recipients::parse_domain(domain).unwrap()).collect(),
+            accept: accept.iter().map(|spec| recipients::parse_pattern(spec).unwrap()).collect(),
+            reject: reject.iter().map(|spec| recipients::parse_pattern(spec).unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(recipients::parse_domain("@Example.com").unwrap(), "example.com");
+        assert_eq!(recipients::parse_domain("*.example.com").unwrap(), "*.example.com");
+        assert!(recipients::parse_domain("").is_err());
+        assert!(recipients::parse_domain("user@example.com").is_err());
+        assert!(recipients::parse_domain("*.*.example.com").is_err());
+        assert!(recipients::parse_pattern("qa-(.*@example\\.com").is_err());
+    }
+
+    #[test]
+    fn test_accept_all() {
+        let policy = Policy::default();
+        assert!(!policy.restricted());
+        assert!(policy.accepts("anyone@anywhere.test"));
+        assert!(policy.accepts("not an address"));
+    }
+
+    #[test]
+    fn test_domains() {
+        let policy = build(&["example.com", "*.corp.test"], &[], &[]);
+        assert!(policy.restricted());
+        assert!(policy.accepts("alice@example.com"));
+        assert!(policy.accepts("Bob@EXAMPLE.COM"));
+        assert!(policy.accepts("carol@eu.corp.test"));
+        // neither the parent of a wildcard, nor the subdomains of an exact domain
+        assert!(!policy.accepts("dave@corp.test"));
+        assert!(!policy.accepts("erin@mail.example.com"));
+        assert!(!policy.accepts("frank@example.com.evil.test"));
+        assert!(!policy.accepts("no-domain"));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let policy = build(&["example.com"], &["qa-.*@partner\\.test"], &["bounce-.*@.*", "ghost@example\\.com"]);
+        assert!(policy.accepts("qa-lead@partner.test"));
+        // the whole address has to match
+        assert!(!policy.accepts("ops-qa-lead@partner.test"));
+        assert!(!policy.accepts("lead@partner.test"));
+        // rejected whatever accepts it
+        assert!(!policy.accepts("Bounce-42@example.com"));
+        assert!(!policy.accepts("ghost@example.com"));
+        assert!(policy.accepts("alice@example.com"));
+
+        // a denylist alone accepts everything else
+        let policy = build(&[], &[], &["invalid-.*"]);
+        assert!(!policy.restricted());
+        assert!(!policy.accepts("invalid-user@example.com"));
+        assert!(policy.accepts("user@example.com"));
+    }
+}
@@ -0,0 +1,57 @@
+#[cfg(test)]
+mod retention_tester {
+    use crate::retention;
+    use crate::smtp::mail::Mail;
+    use crate::snowflake::Snowflake;
+    use crate::store::Store;
+    use crate::summary::{self, MailSummary};
+
+    fn store(db: &Store, mail: &Mail) {
+        db.put_mail(mail).unwrap();
+        summary::insert(db, &MailSummary::from_mail(mail)).unwrap();
+    }
+
+    fn mail(id: u128, size: usize) -> Mail {
+        Mail {
+            data: "x".repeat(size).into(),
+            id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_enforce_limits() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        // a virtual clock, so that the ids are in order
+        let mut clock = Snowflake::deterministic(0);
+        let ids: Vec<u128> = (0..5).map(|_| clock.next_id()).collect();
+        for id in &ids {
+            store(&db, &mail(*id, 100));
+        }
+        assert_eq!(summary::usage(&db).unwrap(), (5, 500));
+
+        assert_eq!(retention::enforce_limits(&db, None, None).unwrap(), 0);
+        assert_eq!(retention::enforce_limits(&db, Some(4), None).unwrap(), 1);
+        assert_eq!(retention::enforce_limits(&db, None, Some(250)).unwrap(), 2);
+        assert_eq!(summary::usage(&db).unwrap(), (2, 200));
+
+        // the oldest ones went first
+        assert!(db.get(ids[2].to_le_bytes()).unwrap().is_none());
+        assert!(db.get(ids[3].to_le_bytes()).unwrap().is_some());
+        assert!(summary::get(&db, ids[2]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        // timestamped in 2024
+        let old = mail(Snowflake::deterministic(0).next_id(), 10);
+        let recent = Mail::new(Default::default(), Default::default(), "x".repeat(10), None);
+        store(&db, &old);
+        store(&db, &recent);
+
+        assert_eq!(retention::purge_expired(&db, 60).unwrap(), 1);
+        assert!(db.get(old.id.to_le_bytes()).unwrap().is_none());
+        assert_eq!(summary::usage(&db).unwrap(), (1, 10));
+    }
+}
@@ -0,0 +1,68 @@
+#[cfg(test)]
+mod rules_tester {
+    use crate::rules::{self, Action, Labels};
+    use std::collections::HashSet;
+
+    fn to(addresses: &[&str]) -> HashSet<String> {
+        addresses.iter().map(|address| address.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule = rules::parse("*@Example.com  drop").unwrap();
+        assert_eq!(rule.action, Action::Drop);
+        assert!(rule.matches("alice@example.COM"));
+        assert!(!rule.matches("alice@example.com.evil"));
+
+        let rule = rules::parse("alerts+?*@* tag alerts").unwrap();
+        assert_eq!(rule.action, Action::Tag("alerts".to_string()));
+        assert!(rule.matches("alerts+disk@ops.test"));
+        assert!(!rule.matches("alerts+@ops.test"));
+        assert!(!rule.matches("alerts@ops.test"));
+
+        assert!(rules::parse("*@example.com").is_err());
+        assert!(rules::parse("*@example.com drop now").is_err());
+        assert!(rules::parse("*@example.com tag").is_err());
+        assert!(rules::parse("*@example.com forward relay.test").is_err());
+        assert_eq!(rules::parse("lead@example.com relay").unwrap().action, Action::Relay);
+        assert!(rules::parse("*@example.com relay relay.test:25").is_err());
+        assert!(rules::parse("*@example.com webhook hooks.test").is_err());
+        assert!(rules::parse("*@example.com bounce").is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules = [
+            "keep@noise.test store",
+            "*@noise.test drop",
+            "*@team.test namespace team",
+            "* namespace default",
+            "*@partner.test forward relay.test:2525",
+            "carol@partner.test relay",
+            "*alerts* tag alerts",
+            "*@* tag all",
+        ]
+        .map(|spec| rules::parse(spec).unwrap());
+
+        let route = rules::evaluate(&[], &to(&["anyone@noise.test"]));
+        assert!(route.store);
+
+        let route = rules::evaluate(&rules, &to(&["a@noise.test", "b@noise.test"]));
+        assert!(!route.store);
+        assert_eq!(route.labels, Labels::default());
+
+        // a recipient left to store keeps the mail
+        let route = rules::evaluate(&rules, &to(&["a@noise.test", "keep@noise.test"]));
+        assert!(route.store);
+
+        let route = rules::evaluate(
+            &rules,
+            &to(&["alerts@team.test", "bob@partner.test", "carol@partner.test"]),
+        );
+        assert!(route.store);
+        assert_eq!(route.labels.namespace.as_deref(), Some("team"));
+        assert_eq!(route.labels.tags, ["alerts", "all"]);
+        assert_eq!(route.forward["relay.test:2525"], ["bob@partner.test", "carol@partner.test"]);
+        assert_eq!(route.relay, ["carol@partner.test"]);
+    }
+}
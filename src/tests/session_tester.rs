@@ -0,0 +1,70 @@
+#[cfg(test)]
+mod session_tester {
+    use crate::session::{self, RevokeError, Role};
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_from_cookies() {
+        assert_eq!(
+            session::token_from_cookies("theme=dark; mail_sink_session=abc123; other=1"),
+            Some("abc123")
+        );
+        assert_eq!(session::token_from_cookies("mail_sink_session=abc123"), Some("abc123"));
+        assert_eq!(session::token_from_cookies("not_mail_sink_session=abc123"), None);
+        assert_eq!(session::token_from_cookies(""), None);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let token = session::create(Role::Admin);
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, session::create(Role::Admin));
+
+        assert_eq!(session::role(&token), Some(Role::Admin));
+        session::remove(&token);
+        assert_eq!(session::role(&token), None);
+        assert_eq!(session::role("unknown"), None);
+
+        let token = session::create(Role::Read);
+        assert_eq!(session::role(&token), Some(Role::Read));
+    }
+
+    // the settings can only be set once, so this is the only test setting them
+    #[test]
+    fn test_key_role() {
+        let keys = vec![
+            ("admin-key".to_string(), Role::Admin),
+            ("ci-key".to_string(), Role::Read),
+            ("dashboard-key".to_string(), Role::Read),
+        ];
+        session::init(keys, None, Duration::from_secs(60), false);
+
+        assert_eq!(session::key_role("admin-key"), Some(Role::Admin));
+        assert_eq!(session::key_role("dashboard-key"), Some(Role::Read));
+        assert_eq!(session::key_role("admin-ke"), None);
+        assert_eq!(session::key_role(""), None);
+
+        // created at runtime, listed without the key itself
+        let (key, created) = session::create_key(Role::Read).unwrap();
+        assert_eq!(session::key_role(&key), Some(Role::Read));
+        assert!(!created.configured);
+        assert!(created.hint.starts_with(&key[..4]));
+        assert!(session::keys().iter().all(|listed| !listed.hint.contains(&key)));
+        assert_eq!(session::key_counts(), (1, 3));
+        let hints = session::keys().into_iter().map(|key| key.hint).collect::<Vec<_>>();
+        assert_eq!(hints[..3], ["ad…", "c…", "das…"]);
+
+        // revoking ends the panel sessions opened with the key, not the others
+        let token = session::create_with_key(&key, Role::Read);
+        let other = session::create(Role::Read);
+        assert_eq!(session::revoke_key(created.id), Ok(()));
+        assert_eq!(session::key_role(&key), None);
+        assert_eq!(session::role(&token), None);
+        assert_eq!(session::role(&other), Some(Role::Read));
+        assert_eq!(session::revoke_key(created.id), Err(RevokeError::NotFound));
+
+        let admin = session::keys().into_iter().find(|key| key.role == Role::Admin).unwrap();
+        assert_eq!(session::revoke_key(admin.id), Err(RevokeError::LastAdmin));
+        assert_eq!(session::key_role("admin-key"), Some(Role::Admin));
+    }
+}
@@ -0,0 +1,18 @@
+#[cfg(test)]
+mod shutdown_tester {
+    use crate::shutdown;
+
+    #[test]
+    fn test_track() {
+        // stopping is process wide and would end the other tests' sessions, only the count is tested
+        let before = shutdown::connections();
+        let first = shutdown::track();
+        let second = shutdown::track();
+        assert_eq!(shutdown::connections(), before + 2);
+        drop(first);
+        assert_eq!(shutdown::connections(), before + 1);
+        drop(second);
+        assert_eq!(shutdown::connections(), before);
+        assert!(!shutdown::stopping());
+    }
+}
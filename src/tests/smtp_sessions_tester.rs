@@ -0,0 +1,31 @@
+#[cfg(test)]
+mod smtp_sessions_tester {
+    use crate::smtp::sessions::{self, Session, State};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_registry() {
+        // the registry is global, other tests may have sessions open too
+        let peer: SocketAddr = "127.0.0.1:45321".parse().unwrap();
+        let session = Session::open(peer);
+        session.set_state(State::Rcpt);
+        session.add_bytes(42);
+
+        let info = sessions::list()
+            .into_iter()
+            .find(|info| info.peer == peer.to_string())
+            .unwrap();
+        assert_eq!(info.state, State::Rcpt);
+        assert_eq!(info.bytes, 42);
+
+        assert!(sessions::kill(info.id));
+        tokio::time::timeout(Duration::from_secs(1), session.killed())
+            .await
+            .unwrap();
+
+        drop(session);
+        assert!(!sessions::list().iter().any(|other| other.id == info.id));
+        assert!(!sessions::kill(info.id));
+    }
+}
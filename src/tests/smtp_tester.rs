@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod smtp_tester {
+    use crate::ingest::Queue;
+    use crate::smtp::sessions::Session;
+    use crate::store::Store;
+    use crate::{relay, smtp, tls};
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_parse_mail_from() {
+        assert_eq!(smtp::parse_mail_from("MAIL FROM:<a@b.test>"), ("a@b.test".to_string(), None));
+        assert_eq!(
+            smtp::parse_mail_from("MAIL FROM: <a@b.test> BODY=8BITMIME size=1024"),
+            ("a@b.test".to_string(), Some(1024))
+        );
+        assert_eq!(smtp::parse_mail_from("MAIL FROM:<> SIZE=x"), (String::new(), None));
+        assert_eq!(smtp::parse_mail_from("MAIL FROM"), (String::new(), None));
+        assert_eq!(smtp::parse_mail_from("MAIL"), (String::new(), None));
+        assert_eq!(smtp::parse_mail_from("MAIL FROMé<a@b.test>"), (String::new(), None));
+    }
+
+    // the last line of a reply, with its CRLF
+    async fn reply(stream: &mut BufReader<TcpStream>) -> Vec<u8> {
+        loop {
+            let mut line = Vec::new();
+            stream.read_until(b'\n', &mut line).await.unwrap();
+            if line.get(3) != Some(&b'-') {
+                return line;
+            }
+        }
+    }
+
+    // a session with a sink of its own, greeted already
+    async fn connect() -> BufReader<TcpStream> {
+        let (chain, key) = tls::self_signed().unwrap();
+        let server = Arc::new(tls::server_config(chain, key).unwrap());
+        let db = Store::memory();
+        let queue = Queue::start(db.clone(), 10, relay::channel().0);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let session = Session::open(peer);
+            smtp::handle_client(socket, server, peer, &session, &queue).await
+        });
+
+        let mut stream = BufReader::new(TcpStream::connect(address).await.unwrap());
+        reply(&mut stream).await;
+        stream
+    }
+
+    #[tokio::test]
+    async fn test_data_reply() {
+        let mut stream = connect().await;
+        for command in ["EHLO client.test", "MAIL FROM:<a@b.test>", "RCPT TO:<c@d.test>"] {
+            stream.get_mut().write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+            assert!(reply(&mut stream).await.starts_with(b"250 "));
+        }
+        stream.get_mut().write_all(b"DATA\r\n").await.unwrap();
+        assert_eq!(reply(&mut stream).await, b"354 End data with <CR><LF>.<CR><LF>\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_short_commands() {
+        let mut stream = connect().await;
+        for command in ["EHLO client.test", "MAIL FROM", "RCPT TO", "RCPT TOé"] {
+            stream.get_mut().write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+            reply(&mut stream).await;
+        }
+        // the session is still there
+        stream.get_mut().write_all(b"EHLO client.test\r\n").await.unwrap();
+        assert!(reply(&mut stream).await.starts_with(b"250 "));
+    }
+}
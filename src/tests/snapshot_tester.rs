@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod snapshot_tester {
+    use crate::smtp::mail::Mail;
+    use crate::snapshot::{self, Rejection};
+    use crate::store::Store;
+    use crate::summary::{self, MailSummary};
+
+    fn mail(from: &str, to: &[&str]) -> Mail {
+        Mail::new(
+            [from.to_string()].into(),
+            to.iter().map(|to| to.to_string()).collect(),
+            "Subject: hello\r\n\r\nHello!\r\n".to_string(),
+            Some("hello".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let start = snapshot::query(&db, 0).unwrap();
+        assert_eq!(start.count, 0);
+        assert_eq!(start.last_id, None);
+
+        let mails = [
+            mail("noreply@shop.test", &["alice@example.com"]),
+            mail("noreply@shop.test", &["Alice@example.com", "bob@example.com"]),
+            mail("billing@shop.test", &["alice@example.com"]),
+        ];
+        for mail in &mails {
+            summary::insert(&db, &MailSummary::from_mail(mail)).unwrap();
+        }
+        snapshot::reject(Rejection::Memory);
+
+        let state = snapshot::query(&db, start.now).unwrap();
+        assert_eq!(state.count, 3);
+        assert_eq!(state.last_id, Some(mails[2].id));
+        assert_eq!(state.recipients[0].address, "alice@example.com");
+        assert_eq!(state.recipients[0].count, 3);
+        assert_eq!(state.recipients[1].address, "bob@example.com");
+        assert_eq!(state.recipients[1].last_id, mails[1].id);
+        assert_eq!(state.senders[0].count, 2);
+        assert_eq!(state.rejections.memory, 1);
+
+        let later = snapshot::query(&db, mails[2].timestamp() + 1).unwrap();
+        assert_eq!(later.count, 0);
+        assert!(later.recipients.is_empty());
+    }
+}
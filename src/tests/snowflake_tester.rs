@@ -0,0 +1,33 @@
+#[cfg(test)]
+mod snowflake_tester {
+    use crate::snowflake::{self, Snowflake};
+
+    #[test]
+    fn test_deterministic_ids() {
+        let mut first_run = Snowflake::deterministic(42);
+        let mut second_run = Snowflake::deterministic(42);
+        let ids: Vec<u128> = (0..3).map(|_| first_run.next_id()).collect();
+        assert_eq!(ids, (0..3).map(|_| second_run.next_id()).collect::<Vec<_>>());
+
+        // 2024-01-01 + 42 seconds, then one second apart
+        let timestamps: Vec<u128> = ids.iter().map(|id| snowflake::to_timestamp(*id)).collect();
+        assert_eq!(timestamps, [1704067242000, 1704067243000, 1704067244000]);
+
+        assert_ne!(ids[0], Snowflake::deterministic(43).next_id());
+    }
+
+    #[test]
+    fn test_ids_go_up() {
+        let mut snowflake = Snowflake::new();
+        let ids: Vec<u128> = (0..10_000).map(|_| snowflake.next_id()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // after a restart with a clock an hour behind
+        let now = snowflake::to_timestamp(ids[ids.len() - 1]);
+        let ahead = (now + 3_600_000 - 1704067200000) << 12;
+        let mut snowflake = Snowflake::after(ahead);
+        let next = snowflake.next_id();
+        assert!(next > ahead);
+        assert_eq!(snowflake::to_timestamp(next), snowflake::to_timestamp(ahead));
+    }
+}
@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod stats_tester {
+    use crate::smtp::mail::Mail;
+    use crate::stats;
+    use crate::store::Store;
+
+    const HOUR: u128 = 60 * 60 * 1000;
+
+    fn mail(from: &str, to: &[&str], data: &str) -> Mail {
+        Mail::new(
+            [from.to_string()].into(),
+            to.iter().map(|to| to.to_string()).collect(),
+            data.to_string(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_record_and_query() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+
+        let first = mail("noreply@shop.test", &["Alice@example.com", "bob@example.com"], "0123456789");
+        let second = mail("noreply@shop.test", &["alice@example.com"], "01234");
+        stats::record(&db, &first).unwrap();
+        stats::record(&db, &second).unwrap();
+
+        let stats = stats::query(&db, None, None).unwrap();
+        assert_eq!(stats.total.count, 2);
+        assert_eq!(stats.total.bytes, 15);
+        assert_eq!(stats.total.last_received, second.timestamp());
+
+        assert_eq!(stats.senders.len(), 1);
+        assert_eq!(stats.senders[0].address, "noreply@shop.test");
+        assert_eq!(stats.senders[0].counter.count, 2);
+
+        // addresses are case insensitive, most active first
+        assert_eq!(stats.recipients.len(), 2);
+        assert_eq!(stats.recipients[0].address, "alice@example.com");
+        assert_eq!(stats.recipients[0].counter.count, 2);
+        assert_eq!(stats.recipients[0].counter.bytes, 15);
+        assert_eq!(stats.recipients[1].address, "bob@example.com");
+        assert_eq!(stats.recipients[1].counter.count, 1);
+    }
+
+    #[test]
+    fn test_time_window() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let mail = mail("noreply@shop.test", &["alice@example.com"], "0123456789");
+        stats::record(&db, &mail).unwrap();
+
+        let now = mail.timestamp();
+        let stats = stats::query(&db, Some(now - HOUR), Some(now + HOUR)).unwrap();
+        assert_eq!(stats.total.count, 1);
+
+        let stats = stats::query(&db, Some(now + 2 * HOUR), None).unwrap();
+        assert_eq!(stats.total.count, 0);
+        assert!(stats.recipients.is_empty());
+
+        let stats = stats::query(&db, None, Some(now - 2 * HOUR)).unwrap();
+        assert_eq!(stats.total.count, 0);
+
+        // an empty window doesn't fail
+        let stats = stats::query(&db, Some(now + HOUR), Some(now - HOUR)).unwrap();
+        assert_eq!(stats.total.count, 0);
+    }
+
+    #[test]
+    fn test_clear() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        stats::record(&db, &mail("noreply@shop.test", &["alice@example.com"], "0123456789")).unwrap();
+
+        // the total, the sender and the recipient
+        assert_eq!(stats::clear(&db).unwrap(), 3);
+        assert_eq!(stats::query(&db, None, None).unwrap().total.count, 0);
+    }
+}
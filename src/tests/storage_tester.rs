@@ -0,0 +1,18 @@
+#[cfg(test)]
+mod storage_tester {
+    use crate::storage;
+    use crate::store::{self, Store};
+    use std::io;
+
+    #[tokio::test]
+    async fn test_failing_storage_recovers() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+
+        storage::failed(&db, &store::Error::Io(io::ErrorKind::StorageFull.into()));
+        assert!(storage::failing());
+
+        // the temporary database takes writes again
+        assert!(storage::check(&db).await);
+        assert!(!storage::failing());
+    }
+}
@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod store_tester {
+    use crate::smtp::mail::Mail;
+    use crate::snowflake::Snowflake;
+    use crate::store::{self, Store, Value};
+    use crate::summary::{self, MailSummary, Order};
+    use std::time::Duration;
+
+    // every backend goes through the same tests
+    fn backends() -> [Store; 2] {
+        [Store::new(sled::Config::new().temporary(true).open().unwrap()), Store::memory()]
+    }
+
+    fn store(db: &Store, id: u128) {
+        let mail = Mail {
+            data: format!("Subject: {}\r\n\r\nHello\r\n", id).into(),
+            id,
+            ..Default::default()
+        };
+        db.put_mail(&mail).unwrap();
+        summary::insert(db, &MailSummary::from_mail(&mail)).unwrap();
+    }
+
+    #[test]
+    fn test_put_get_delete() {
+        for db in backends() {
+            store(&db, 1);
+            assert_eq!(db.get_mail(1).unwrap().unwrap().data, &b"Subject: 1\r\n\r\nHello\r\n"[..]);
+            assert!(db.get_mail(2).unwrap().is_none());
+
+            assert!(db.delete(1).unwrap());
+            assert!(!db.delete(1).unwrap());
+            assert!(db.get_mail(1).unwrap().is_none());
+            assert!(summary::get(&db, 1).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_iter_page() {
+        for db in backends() {
+            iter_page(db);
+        }
+    }
+
+    fn iter_page(db: Store) {
+        let mut clock = Snowflake::deterministic(0);
+        let ids: Vec<u128> = (0..5).map(|_| clock.next_id()).collect();
+        for id in &ids {
+            store(&db, *id);
+        }
+
+        let page = |order, after, offset| {
+            db.iter_page(order, after, offset)
+                .unwrap()
+                .map(|summary| summary.unwrap().id)
+                .collect::<Vec<_>>()
+        };
+        // newest first by default
+        let mut newest_first = ids.clone();
+        newest_first.reverse();
+        assert_eq!(page(Order::default(), None, 0), newest_first);
+        assert_eq!(page(Order::Asc, None, 0), ids);
+        assert_eq!(page(Order::Desc, None, 3), newest_first[3..]);
+        assert!(page(Order::Desc, None, 5).is_empty());
+
+        // a cursor doesn't move when mails are received or deleted in between
+        assert_eq!(page(Order::Asc, Some(ids[1]), 0), ids[2..]);
+        assert_eq!(page(Order::Desc, Some(ids[3]), 0), [ids[2], ids[1], ids[0]]);
+        db.delete(ids[2]).unwrap();
+        let newer = clock.next_id();
+        store(&db, newer);
+        assert_eq!(page(Order::Desc, Some(ids[3]), 0), [ids[1], ids[0]]);
+        assert_eq!(page(Order::Asc, Some(ids[1]), 1), [ids[4], newer]);
+    }
+
+    #[test]
+    fn test_trees() {
+        for db in backends() {
+            let tree = db.open_tree("test").unwrap();
+            for key in ["a", "b", "b\u{0}1", "b\u{0}2", "c"] {
+                tree.insert(key, key.as_bytes()).unwrap();
+            }
+            // the trees are apart from the mails and each other
+            assert_eq!(db.len(), 0);
+            assert_eq!(db.open_tree("other").unwrap().len(), 0);
+            assert_eq!(db.open_tree("test").unwrap().len(), 5);
+
+            let keys = |iter: Box<dyn Iterator<Item = store::Result<Value>>>| {
+                iter.map(|key| String::from_utf8(key.unwrap().to_vec()).unwrap()).collect::<Vec<_>>()
+            };
+            assert_eq!(keys(Box::new(tree.iter().keys().rev())), ["c", "b\u{0}2", "b\u{0}1", "b", "a"]);
+            assert_eq!(keys(Box::new(tree.range("b".."c").keys())), ["b", "b\u{0}1", "b\u{0}2"]);
+            assert_eq!(keys(Box::new(tree.range("b\u{0}"..).keys().rev())), ["c", "b\u{0}2", "b\u{0}1"]);
+            assert_eq!(keys(Box::new(tree.scan_prefix("b\u{0}").keys())), ["b\u{0}1", "b\u{0}2"]);
+            assert!(tree.range("c".."a").next().is_none());
+            assert!(tree.range("b".."b").next().is_none());
+
+            // from both ends at once, each entry once
+            let mut iter = tree.iter().keys();
+            assert_eq!(&iter.next().unwrap().unwrap()[..], b"a");
+            assert_eq!(&iter.next_back().unwrap().unwrap()[..], b"c");
+            assert_eq!(iter.count(), 3);
+
+            let conflict = tree.compare_and_swap("a", Some("b"), Some("x")).unwrap().unwrap_err();
+            assert_eq!(conflict.current.as_deref(), Some(&b"a"[..]));
+            assert!(tree.compare_and_swap("a", Some("a"), Some("x")).unwrap().is_ok());
+            assert!(tree.compare_and_swap("d", None::<&[u8]>, Some("d")).unwrap().is_ok());
+            assert!(tree.compare_and_swap("d", Some("d"), None::<&[u8]>).unwrap().is_ok());
+            assert_eq!(&tree.get("a").unwrap().unwrap()[..], b"x");
+            assert!(!tree.contains_key("d").unwrap());
+
+            let add = |old: Option<&[u8]>| Some(vec![old.map_or(0, |old| old[0]) + 1]);
+            assert_eq!(tree.fetch_and_update("n", add).unwrap(), None);
+            assert_eq!(&tree.update_and_fetch("n", add).unwrap().unwrap()[..], [2]);
+            assert_eq!(&tree.last().unwrap().unwrap().0[..], b"n");
+
+            tree.clear().unwrap();
+            assert!(tree.iter().next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_disk_errors() {
+        // the kind of an IO error is kept, to tell a full disk
+        let full = store::Error::from(sled::Error::Io(std::io::ErrorKind::StorageFull.into()));
+        assert!(matches!(full, store::Error::Io(e) if e.kind() == std::io::ErrorKind::StorageFull));
+        let corrupted = store::Error::from(sled::Error::Unsupported("format".to_string()));
+        assert!(matches!(corrupted, store::Error::Backend(_)));
+    }
+
+    #[tokio::test]
+    async fn test_exclusive_waits_for_writers() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let writing = db.writing().await;
+        // several writers at once
+        let other = db.writing().await;
+
+        let exclusive = {
+            let db = db.clone();
+            tokio::spawn(async move {
+                let _exclusive = db.exclusive().await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!exclusive.is_finished());
+
+        drop(writing);
+        drop(other);
+        tokio::time::timeout(Duration::from_secs(1), exclusive).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod summary_tester {
+    use crate::filter::MailFilter;
+    use crate::smtp::mail::*;
+    use crate::store::Store;
+    use crate::summary::{self, MailSummary};
+    use std::collections::HashMap;
+
+    fn sample_mail(path: &str) -> Mail {
+        let body = std::fs::read_to_string(path).unwrap();
+        let (from, to) = get_data_from_to(&body);
+        let subject = get_subject(&body);
+        Mail::new(from, to, body, subject)
+    }
+
+    #[test]
+    fn test_from_mail() {
+        let mail = sample_mail("test/samples/attachment.body");
+        let summary = MailSummary::from_mail(&mail);
+        assert_eq!(summary.id, mail.id);
+        assert_eq!(summary.timestamp, mail.timestamp());
+        assert_eq!(summary.size, mail.data.len() as u64);
+        assert_eq!(summary.subject, mail.subject);
+        assert!(summary.has_attachment);
+
+        assert!(!MailSummary::from_mail(&sample_mail("test/samples/raw.body")).has_attachment);
+    }
+
+    #[test]
+    fn test_filter_summary() {
+        let mail = sample_mail("test/samples/attachment.body");
+        let summary = MailSummary::from_mail(&mail);
+        let filter = |pairs: &[(&str, &str)]| {
+            let query: HashMap<String, String> = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            MailFilter::from_query(&query).unwrap()
+        };
+
+        assert!(filter(&[("to", "alice"), ("has_attachment", "true")]).matches_summary(&summary));
+        assert!(!filter(&[("has_attachment", "false")]).matches_summary(&summary));
+        assert!(filter(&[("subject_contains", "INVOICE")]).matches_summary(&summary));
+        assert!(!filter(&[("subject_contains", "tracker")]).matches_summary(&summary));
+
+        // found in the subject, the data isn't needed
+        let search = filter(&[("search", "invoice #1042")]);
+        assert!(search.matches_search(&summary, || panic!("data loaded")));
+        let search = filter(&[("search", "tracker.shop.test")]);
+        assert!(search.matches_search(&summary, || Some(mail.data.clone())));
+        assert!(!search.matches_search(&summary, || None));
+        let search = filter(&[("q", "tracker.shop.test")]);
+        assert!(search.matches_search(&summary, || Some(mail.data.clone())));
+    }
+
+    #[test]
+    fn test_sync() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let mail = sample_mail("test/samples/raw.body");
+        db.insert(mail.id.to_le_bytes(), bincode::serialize(&mail).unwrap())
+            .unwrap();
+        summary::tree(&db).unwrap().insert(42u128.to_le_bytes(), vec![]).unwrap();
+
+        // one missing, one orphan
+        assert_eq!(summary::sync(&db).unwrap(), 2);
+        assert_eq!(summary::sync(&db).unwrap(), 0);
+
+        let tree = summary::tree(&db).unwrap();
+        assert_eq!(tree.len(), 1);
+        let data = tree.get(mail.id.to_le_bytes()).unwrap().unwrap();
+        let stored: MailSummary = bincode::deserialize(&data).unwrap();
+        assert_eq!(stored, MailSummary::from_mail(&mail));
+    }
+
+    #[test]
+    fn test_mailboxes() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let to = |addresses: &[&str]| addresses.iter().map(|address| address.to_string()).collect();
+        let first = Mail::new(Default::default(), to(&["Alice@example.com", "bob@example.com"]), "", None);
+        let second = Mail::new(Default::default(), to(&["alice@example.com"]), "", None);
+        for mail in [&first, &second] {
+            summary::insert(&db, &MailSummary::from_mail(mail)).unwrap();
+        }
+
+        let mailboxes = summary::mailboxes(&db).unwrap();
+        let found: Vec<(&str, usize)> = mailboxes
+            .iter()
+            .map(|mailbox| (mailbox.address.as_str(), mailbox.count))
+            .collect();
+        assert_eq!(found, [("alice@example.com", 2), ("bob@example.com", 1)]);
+        assert_eq!(mailboxes[0].last_received, second.timestamp());
+
+        summary::remove(&db, first.id).unwrap();
+        assert_eq!(summary::mailboxes(&db).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_sync_rewrites_old_summaries() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let mail = sample_mail("test/samples/raw.body");
+        db.insert(mail.id.to_le_bytes(), bincode::serialize(&mail).unwrap())
+            .unwrap();
+        // the summary as written before it had the ingestion latency
+        let summary = MailSummary::from_mail(&mail);
+        let old = (summary.id, &summary.from, &summary.to, &summary.subject, summary.size, summary.timestamp, false);
+        summary::tree(&db).unwrap().insert(mail.id.to_le_bytes(), bincode::serialize(&old).unwrap()).unwrap();
+
+        assert_eq!(summary::sync(&db).unwrap(), 1);
+        assert_eq!(summary::get(&db, mail.id).unwrap(), Some(summary));
+    }
+
+    #[test]
+    fn test_address_index() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let first = Mail::new(
+            ["noreply@shop.test".to_string()].into(),
+            ["Alice@example.com".to_string(), "bob@example.com".to_string()].into(),
+            "Subject: hello\r\n\r\nHello!\r\n".to_string(),
+            Some("hello".to_string()),
+        );
+        let second = Mail::new(
+            ["billing@shop.test".to_string()].into(),
+            ["alice@example.com".to_string()].into(),
+            "Subject: invoice\r\n\r\nPay!\r\n".to_string(),
+            Some("invoice".to_string()),
+        );
+        for mail in [&first, &second] {
+            db.insert(mail.id.to_le_bytes(), bincode::serialize(mail).unwrap()).unwrap();
+            summary::insert(&db, &MailSummary::from_mail(mail)).unwrap();
+        }
+        let ids = |to: bool, address: &str| -> Vec<u128> {
+            summary::ids_by_address(&db, to, address)
+                .unwrap()
+                .map(|id| id.unwrap())
+                .collect()
+        };
+
+        assert_eq!(ids(true, "ALICE@example.com"), [second.id, first.id]);
+        assert_eq!(ids(false, "noreply@shop.test"), [first.id]);
+        // whole addresses only
+        assert!(ids(true, "alice@example").is_empty());
+
+        summary::remove(&db, first.id).unwrap();
+        assert_eq!(ids(true, "alice@example.com"), [second.id]);
+        assert!(ids(true, "bob@example.com").is_empty());
+
+        // rebuilt for databases written before the index
+        db.remove(first.id.to_le_bytes()).unwrap();
+        db.open_tree("addresses").unwrap().clear().unwrap();
+        assert_eq!(summary::sync(&db).unwrap(), 1);
+        assert_eq!(ids(false, "billing@shop.test"), [second.id]);
+    }
+
+    #[test]
+    fn test_update() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let mail = sample_mail("test/samples/raw.body");
+        summary::insert(&db, &MailSummary::from_mail(&mail)).unwrap();
+        let unread = MailFilter::from_query(&[("unread".to_string(), "true".to_string())].into()).unwrap();
+        assert!(unread.matches_summary(&summary::get(&db, mail.id).unwrap().unwrap()));
+
+        let updated = summary::update(&db, mail.id, |summary| {
+            summary.read = true;
+            summary.tags.push("run-42".to_string());
+        });
+        let updated = updated.unwrap().unwrap();
+        assert!(updated.read);
+        assert_eq!(summary::get(&db, mail.id).unwrap(), Some(updated.clone()));
+        assert!(!unread.matches_summary(&updated));
+        let tagged = MailFilter::from_query(&[("tag".to_string(), "RUN-42".to_string())].into()).unwrap();
+        assert!(tagged.matches_summary(&updated));
+
+        // a deleted mail isn't brought back
+        summary::remove(&db, mail.id).unwrap();
+        assert_eq!(summary::update(&db, mail.id, |summary| summary.read = false).unwrap(), None);
+        assert_eq!(summary::get(&db, mail.id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_received_since() {
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let first = Mail::new(Default::default(), Default::default(), "", None);
+        let second = Mail::new(Default::default(), Default::default(), "", None);
+        for mail in [&first, &second] {
+            summary::insert(&db, &MailSummary::from_mail(mail)).unwrap();
+        }
+
+        assert_eq!(summary::received_since(&db, first.timestamp()).unwrap(), 2);
+        assert!(summary::received_since(&db, second.timestamp()).unwrap() >= 1);
+        assert_eq!(summary::received_since(&db, second.timestamp() + 1).unwrap(), 0);
+    }
+}
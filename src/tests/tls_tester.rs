@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod tls_tester {
+    use crate::ingest::Queue;
+    use crate::{http, relay, smtp, tls};
+    use crate::smtp::sessions::{self, Session};
+    use crate::store::Store;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerName};
+    use tokio_rustls::TlsConnector;
+
+    // a server trusting its own self-signed certificate, and a client trusting it too
+    fn configs() -> (Arc<tokio_rustls::rustls::ServerConfig>, TlsConnector) {
+        let (chain, key) = tls::self_signed().unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(&chain[0]).unwrap();
+        let client = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server = tls::server_config(chain, key).unwrap();
+        (Arc::new(server), TlsConnector::from(Arc::new(client)))
+    }
+
+    // sends a command and reads the reply, up to its last line
+    async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut BufReader<S>,
+        command: &str,
+    ) -> String {
+        stream.get_mut().write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+        read_reply(stream).await
+    }
+
+    async fn read_reply<S: AsyncRead + Unpin>(stream: &mut BufReader<S>) -> String {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            reply.push_str(&line);
+            if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                return reply;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_implicit_tls() {
+        let (server, connector) = configs();
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let queue = Queue::start(db, 10, relay::channel().0);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let session = Session::open(peer);
+            smtp::handle_implicit_tls_client(socket, server, &session, &queue).await
+        });
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let peer = socket.local_addr().unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = BufReader::new(connector.connect(name, socket).await.unwrap());
+        assert!(read_reply(&mut stream).await.starts_with("220"));
+
+        // already encrypted, nothing to upgrade to
+        let ehlo = command(&mut stream, "EHLO client.test").await;
+        assert!(ehlo.ends_with("250 OK\r\n") && !ehlo.contains("STARTTLS"));
+        assert!(command(&mut stream, "STARTTLS").await.starts_with("503"));
+
+        let session = sessions::list()
+            .into_iter()
+            .find(|info| info.peer == peer.to_string())
+            .unwrap();
+        assert!(session.tls);
+        stream.get_mut().write_all(b"QUIT\r\n").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_starttls() {
+        let (server, connector) = configs();
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let queue = Queue::start(db, 10, relay::channel().0);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let session = Session::open(peer);
+            smtp::handle_client(socket, server, peer, &session, &queue).await
+        });
+
+        let mut plain = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let peer = plain.get_ref().local_addr().unwrap();
+        assert!(read_reply(&mut plain).await.starts_with("220"));
+        assert!(command(&mut plain, "EHLO client.test").await.contains("250-STARTTLS"));
+        assert!(command(&mut plain, "STARTTLS").await.starts_with("220"));
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let socket = plain.into_inner();
+        let mut stream = BufReader::new(connector.connect(name, socket).await.unwrap());
+        assert!(!command(&mut stream, "EHLO client.test").await.contains("STARTTLS"));
+        let session = sessions::list()
+            .into_iter()
+            .find(|info| info.peer == peer.to_string())
+            .unwrap();
+        assert!(session.tls);
+        stream.get_mut().write_all(b"QUIT\r\n").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_https() {
+        let (server, connector) = configs();
+        let db = Store::new(sled::Config::new().temporary(true).open().unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let stream = tokio_rustls::TlsAcceptor::from(server).accept(socket).await.unwrap();
+            let queue = Queue::start(db.clone(), 10, relay::channel().0);
+            http::handle_client(stream, Ipv4Addr::LOCALHOST.into(), db, &http::Router::new(queue)).await
+        });
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(name, socket).await.unwrap();
+        stream
+            .write_all(b"GET /login HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        // closed without close_notify, whatever came before is enough
+        let _ = stream.read_to_end(&mut response).await;
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+    }
+}
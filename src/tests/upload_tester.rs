@@ -0,0 +1,55 @@
+#[cfg(test)]
+mod upload_tester {
+    use crate::upload;
+    use std::collections::HashSet;
+
+    fn set(addresses: &[&str]) -> HashSet<String> {
+        addresses.iter().map(|address| address.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_raw() {
+        let data = b"From: sender@example.com\nTo: a@example.com, b@example.com\nSubject: Hi\n\nQuoted:\nTo: c@example.com\n";
+        let upload = upload::from_raw(data, None, None).unwrap();
+        assert_eq!(upload.from, set(&["sender@example.com"]));
+        assert_eq!(upload.to, set(&["a@example.com", "b@example.com"]));
+        assert!(upload.data.starts_with(b"From: sender@example.com\r\nTo: "));
+        assert!(!upload.data.windows(2).any(|pair| pair[1] == b'\n' && pair[0] != b'\r'));
+
+        // the envelope given wins over the headers, like RCPT TO would
+        let upload = upload::from_raw(data, None, Some("bcc@example.com")).unwrap();
+        assert_eq!(upload.to, set(&["bcc@example.com"]));
+
+        assert!(upload::from_raw(b"Subject: Hi\n\nNobody", None, None).is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let body = br#"{"from": "sender@example.com", "to": ["a@example.com", "b@example.com"], "data": "Subject: Hi\n\nHello"}"#;
+        let upload = upload::from_json(body).unwrap();
+        assert_eq!(upload.from, set(&["sender@example.com"]));
+        assert_eq!(upload.to, set(&["a@example.com", "b@example.com"]));
+        assert_eq!(&upload.data[..], b"Subject: Hi\r\n\r\nHello");
+
+        assert!(upload::from_json(br#"{"from": "sender@example.com", "to": []}"#).is_err());
+        assert!(upload::from_json(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_compose() {
+        let body = r#"{"from": "sender@example.com", "to": "rcpt@example.com", "subject": "Café", "text": "Plain", "html": "<b>Rich</b>"}"#;
+        let upload = upload::from_json(body.as_bytes()).unwrap();
+        let data = String::from_utf8(upload.data.to_vec()).unwrap();
+        assert!(data.starts_with("From: <sender@example.com>\r\nTo: <rcpt@example.com>\r\nDate: "));
+        assert!(data.contains("Subject: =?utf-8?B?Q2Fmw6k=?=\r\n"));
+        assert!(data.contains("Content-Type: multipart/alternative;"));
+        assert!(data.contains("Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\nPlain\r\n"));
+        assert!(data.contains("Content-Type: text/html; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n<b>Rich</b>\r\n"));
+
+        let body = br#"{"from": "sender@example.com", "to": "rcpt@example.com", "text": "Only text"}"#;
+        let data = upload::from_json(body).unwrap().data;
+        let data = String::from_utf8_lossy(&data);
+        assert!(!data.contains("multipart"));
+        assert!(data.ends_with("\r\n\r\nOnly text\r\n"));
+    }
+}
@@ -0,0 +1,275 @@
+#[cfg(test)]
+mod verification_tester {
+    use crate::dns::{Lookup, Resolver};
+    use crate::smtp::dkim::{self, canonical_body, canonical_header};
+    use crate::smtp::spf;
+    use crate::smtp::verification::{verify_with, Client, Outcome};
+    use base64::prelude::{Engine, BASE64_STANDARD};
+    use ring::digest;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    // answers from fixed records, names missing from `failing` and the maps have none
+    #[derive(Default)]
+    struct FakeResolver {
+        txt: HashMap<String, Vec<String>>,
+        ips: HashMap<String, Vec<IpAddr>>,
+        mx: HashMap<String, Vec<String>>,
+        failing: Vec<String>,
+    }
+
+    impl FakeResolver {
+        fn txt(mut self, name: &str, record: &str) -> Self {
+            self.txt.entry(name.to_string()).or_default().push(record.to_string());
+            self
+        }
+
+        fn ip(mut self, name: &str, ip: &str) -> Self {
+            self.ips.entry(name.to_string()).or_default().push(ip.parse().unwrap());
+            self
+        }
+
+        fn mx(mut self, name: &str, exchange: &str) -> Self {
+            self.mx.entry(name.to_string()).or_default().push(exchange.to_string());
+            self
+        }
+
+        fn answer<T: Clone + Send>(&self, map: &HashMap<String, Vec<T>>, name: &str) -> Result<Vec<T>, String> {
+            match self.failing.iter().any(|failing| failing == name) {
+                true => Err(format!("no answer for {}", name)),
+                false => Ok(map.get(name).cloned().unwrap_or_default()),
+            }
+        }
+    }
+
+    impl Resolver for FakeResolver {
+        fn txt<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<String>> {
+            Box::pin(async move { self.answer(&self.txt, name) })
+        }
+
+        fn ips<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<IpAddr>> {
+            Box::pin(async move { self.answer(&self.ips, name) })
+        }
+
+        fn mx<'a>(&'a self, name: &'a str) -> Lookup<'a, Vec<String>> {
+            Box::pin(async move { self.answer(&self.mx, name) })
+        }
+    }
+
+    const BODY: &str = "Hello Bob,\r\n\r\nSee you  at noon.\r\n\r\n";
+
+    // a mail signed with relaxed/relaxed ed25519-sha256, and the DKIM record of its key
+    fn signed_mail(body: &str) -> (String, String) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let from = "From: Alice <alice@example.com>\r\n";
+        let subject = "Subject: Lunch\r\n";
+        let body_hash = BASE64_STANDARD.encode(digest::digest(&digest::SHA256, &canonical_body(body.as_bytes(), true)));
+        let unsigned = format!(
+            "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed; d=example.com;\r\n s=mail; h=from:subject; bh={}; b=",
+            body_hash
+        );
+
+        let mut headers = canonical_header(from.as_bytes(), true);
+        headers.extend(canonical_header(subject.as_bytes(), true));
+        let own = canonical_header(format!("{}\r\n", unsigned).as_bytes(), true);
+        headers.extend_from_slice(own.strip_suffix(b"\r\n").unwrap());
+        let signature = key.sign(digest::digest(&digest::SHA256, &headers).as_ref());
+
+        let mail = format!(
+            "{}{}\r\n{}{}\r\n{}",
+            unsigned,
+            BASE64_STANDARD.encode(signature.as_ref()),
+            from,
+            subject,
+            body
+        );
+        let record = format!("v=DKIM1; k=ed25519; p={}", BASE64_STANDARD.encode(key.public_key().as_ref()));
+        (mail, record)
+    }
+
+    #[test]
+    fn test_canonicalization() {
+        // the example of RFC 6376 section 3.4.5
+        assert_eq!(canonical_header(b"A: X\r\n", true), b"a:X\r\n");
+        assert_eq!(canonical_header(b"B : Y\t\r\n\tZ  \r\n", true), b"b:Y Z\r\n");
+        assert_eq!(canonical_header(b"B : Y\t\r\n\tZ  \r\n", false), b"B : Y\t\r\n\tZ  \r\n");
+
+        let body = b" C \r\nD \t E\r\n\r\n\r\n";
+        assert_eq!(canonical_body(body, true), b" C\r\nD E\r\n");
+        assert_eq!(canonical_body(body, false), b" C \r\nD \t E\r\n");
+
+        // an empty body
+        assert_eq!(canonical_body(b"", false), b"\r\n");
+        assert_eq!(canonical_body(b"\r\n\r\n", true), b"");
+    }
+
+    #[tokio::test]
+    async fn test_dkim_signature() {
+        let (mail, record) = signed_mail(BODY);
+        let resolver = FakeResolver::default().txt("mail._domainkey.example.com", &record);
+
+        let verdicts = dkim::verify(mail.as_bytes(), &resolver, 0).await;
+        assert_eq!(verdicts.len(), 1);
+        assert_eq!(verdicts[0].result, Outcome::Pass, "{:?}", verdicts[0].reason);
+        assert_eq!((verdicts[0].domain.as_str(), verdicts[0].selector.as_str()), ("example.com", "mail"));
+
+        // with bare LF line endings, as some clients send them
+        let verdicts = dkim::verify(mail.replace("\r\n", "\n").as_bytes(), &resolver, 0).await;
+        assert_eq!(verdicts[0].result, Outcome::Pass);
+
+        // the body changed on the way
+        let tampered = mail.replace("noon", "one");
+        let verdicts = dkim::verify(tampered.as_bytes(), &resolver, 0).await;
+        assert_eq!(verdicts[0].result, Outcome::Fail);
+        assert_eq!(verdicts[0].reason.as_deref(), Some("body hash mismatch"));
+
+        // a signed header changed
+        let tampered = mail.replace("Subject: Lunch", "Subject: Dinner");
+        let verdicts = dkim::verify(tampered.as_bytes(), &resolver, 0).await;
+        assert_eq!(verdicts[0].result, Outcome::Fail);
+        assert_eq!(verdicts[0].reason.as_deref(), Some("signature mismatch"));
+
+        // another key
+        let (_, other) = signed_mail(BODY);
+        let resolver = FakeResolver::default().txt("mail._domainkey.example.com", &other);
+        assert_eq!(dkim::verify(mail.as_bytes(), &resolver, 0).await[0].result, Outcome::Fail);
+    }
+
+    #[tokio::test]
+    async fn test_dkim_errors() {
+        let (mail, _) = signed_mail(BODY);
+
+        let verdicts = dkim::verify(mail.as_bytes(), &FakeResolver::default(), 0).await;
+        assert_eq!(verdicts[0].result, Outcome::PermError);
+
+        let resolver = FakeResolver {
+            failing: vec!["mail._domainkey.example.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(dkim::verify(mail.as_bytes(), &resolver, 0).await[0].result, Outcome::TempError);
+
+        let resolver = FakeResolver::default().txt("mail._domainkey.example.com", "v=DKIM1; k=ed25519; p=");
+        let verdicts = dkim::verify(mail.as_bytes(), &resolver, 0).await;
+        assert_eq!(verdicts[0].result, Outcome::PermError);
+        assert_eq!(verdicts[0].reason.as_deref(), Some("key revoked"));
+
+        // a signature without the tags it needs
+        let broken = "DKIM-Signature: v=1; a=rsa-sha256; d=example.com\r\nFrom: a@example.com\r\n\r\nHi\r\n";
+        let verdicts = dkim::verify(broken.as_bytes(), &FakeResolver::default(), 0).await;
+        assert_eq!(verdicts[0].result, Outcome::PermError);
+
+        let unsigned = "From: a@example.com\r\nSubject: Hi\r\n\r\nHi\r\n";
+        assert!(dkim::verify(unsigned.as_bytes(), &FakeResolver::default(), 0).await.is_empty());
+    }
+
+    async fn spf_result(resolver: &FakeResolver, ip: &str, mail_from: &str) -> Outcome {
+        spf::verify(ip.parse().unwrap(), Some("mx.example.com"), Some(mail_from), resolver)
+            .await
+            .result
+    }
+
+    #[tokio::test]
+    async fn test_spf_mechanisms() {
+        let resolver = FakeResolver::default()
+            .txt("example.com", "v=spf1 ip4:192.0.2.0/24 ip6:2001:db8::/32 a mx include:_spf.example.net ~all")
+            .txt("example.com", "google-site-verification=abc")
+            .ip("example.com", "198.51.100.1")
+            .mx("example.com", "mx.example.com")
+            .ip("mx.example.com", "198.51.100.2")
+            .txt("_spf.example.net", "v=spf1 ip4:203.0.113.7 -all");
+
+        assert_eq!(spf_result(&resolver, "192.0.2.10", "alice@example.com").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "::ffff:192.0.2.10", "alice@example.com").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "2001:db8::1", "alice@example.com").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "198.51.100.1", "alice@example.com").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "198.51.100.2", "alice@example.com").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "203.0.113.7", "alice@example.com").await, Outcome::Pass);
+        // the include's -all doesn't end the evaluation
+        assert_eq!(spf_result(&resolver, "203.0.113.8", "alice@example.com").await, Outcome::SoftFail);
+
+        let verdict = spf::verify("203.0.113.8".parse().unwrap(), None, Some("Alice@Example.com"), &resolver).await;
+        assert_eq!(verdict.domain, "example.com");
+        assert_eq!(verdict.reason.as_deref(), Some("example.com matched ~all"));
+    }
+
+    #[tokio::test]
+    async fn test_spf_results() {
+        let resolver = FakeResolver::default()
+            .txt("strict.example", "v=spf1 redirect=other.example")
+            .txt("other.example", "v=spf1 ip4:192.0.2.1 -all")
+            .txt("open.example", "v=spf1 ?ip4:192.0.2.1")
+            .txt("twice.example", "v=spf1 -all")
+            .txt("twice.example", "v=spf1 +all")
+            .txt("broken.example", "v=spf1 ip4:300.0.0.1 -all")
+            .txt("dangling.example", "v=spf1 redirect=nowhere.example")
+            .txt("macro.example", "v=spf1 exists:%{l}.allowed.example -all")
+            .ip("alice.allowed.example", "127.0.0.2");
+
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@strict.example").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "192.0.2.2", "a@strict.example").await, Outcome::Fail);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@open.example").await, Outcome::Neutral);
+        assert_eq!(spf_result(&resolver, "192.0.2.2", "a@open.example").await, Outcome::Neutral);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@twice.example").await, Outcome::PermError);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@broken.example").await, Outcome::PermError);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@dangling.example").await, Outcome::PermError);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "alice@macro.example").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "bob@macro.example").await, Outcome::Fail);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@unknown.example").await, Outcome::None);
+
+        let failing = FakeResolver {
+            failing: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(spf_result(&failing, "192.0.2.1", "a@example.com").await, Outcome::TempError);
+
+        // a bounce, checked against the HELO name
+        let resolver = FakeResolver::default().txt("mx.example.com", "v=spf1 ip4:192.0.2.1 -all");
+        let verdict = spf::verify("192.0.2.1".parse().unwrap(), Some("MX.example.com"), None, &resolver).await;
+        assert_eq!((verdict.result, verdict.domain.as_str()), (Outcome::Pass, "mx.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_spf_lookup_limit() {
+        let mut resolver = FakeResolver::default().txt("loop.example", "v=spf1 include:loop.example -all");
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@loop.example").await, Outcome::PermError);
+
+        // ten includes are fine, the eleventh isn't
+        for i in 0..11 {
+            resolver = resolver.txt(&format!("{}.chain.example", i), &format!("v=spf1 include:{}.chain.example", i + 1));
+        }
+        resolver = resolver.txt("11.chain.example", "v=spf1 +all");
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@1.chain.example").await, Outcome::Pass);
+        assert_eq!(spf_result(&resolver, "192.0.2.1", "a@0.chain.example").await, Outcome::PermError);
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let (mail, record) = signed_mail(BODY);
+        let resolver = FakeResolver::default()
+            .txt("mail._domainkey.example.com", &record)
+            .txt("example.com", "v=spf1 ip4:192.0.2.0/24 -all");
+        let client = Client {
+            ip: "192.0.2.1".parse().unwrap(),
+            helo: Some("mx.example.com".to_string()),
+            mail_from: Some("alice@example.com".to_string()),
+        };
+
+        let authentication = verify_with(mail.as_bytes(), &client, &resolver).await;
+        assert_eq!(authentication.dkim_results(), vec![Outcome::Pass]);
+        assert_eq!(authentication.spf.result, Outcome::Pass);
+
+        let json = serde_json::to_value(&authentication).unwrap();
+        assert_eq!(json["dkim"][0]["result"], "pass");
+        assert_eq!(json["dkim"][0]["selector"], "mail");
+        assert_eq!(json["spf"]["result"], "pass");
+        assert_eq!(json["spf"]["ip"], "192.0.2.1");
+
+        let unsigned = "From: alice@example.com\r\n\r\nHi\r\n";
+        let authentication = verify_with(unsigned.as_bytes(), &client, &resolver).await;
+        assert_eq!(authentication.dkim_results(), vec![Outcome::None]);
+    }
+}
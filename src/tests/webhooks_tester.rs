@@ -0,0 +1,82 @@
+#[cfg(test)]
+mod webhooks_tester {
+    use crate::metrics;
+    use crate::webhooks;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_from_args() {
+        let urls = ["https://a.test/hook", " http://b.test ", ""].map(String::from);
+        assert_eq!(webhooks::from_args(&urls).unwrap(), ["https://a.test/hook", "http://b.test"]);
+
+        assert!(webhooks::from_args(&["ftp://c.test".to_string()]).is_err());
+    }
+
+    // answers each request with the next status
+    fn serve(statuses: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                reader.into_inner().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries() {
+        let delivered = metrics::WEBHOOKS_DELIVERED.get();
+        let url = serve(&["503 Service Unavailable", "200 OK"]);
+        webhooks::deliver(url, "{}".to_string(), 1).await;
+        assert_eq!(metrics::WEBHOOKS_DELIVERED.get(), delivered + 1);
+
+        // refused for good, not tried again
+        let failures = metrics::WEBHOOK_FAILURES.get();
+        let url = serve(&["400 Bad Request"]);
+        webhooks::deliver(url, "{}".to_string(), 2).await;
+        assert_eq!(metrics::WEBHOOK_FAILURES.get(), failures + 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        webhooks::spawn(url, "{}".to_string(), 3);
+
+        // busy, to be tried again a second later
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        while !request.ends_with(b"{}") {
+            let mut buffer = [0; 1024];
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let response = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        stream.write_all(response.as_bytes()).await.unwrap();
+        drop(stream);
+
+        // another test's reset may have stopped it first
+        webhooks::cancel();
+        let retried = tokio::time::timeout(Duration::from_millis(1500), listener.accept()).await;
+        assert!(retried.is_err());
+    }
+}
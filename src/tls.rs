@@ -0,0 +1,94 @@
+use crate::SharedError;
+use rustls_pemfile::{certs, pkcs8_private_keys};
+use std::fs::File;
+use std::io::BufReader as StdBufReader;
+use std::path::{Path, PathBuf};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+// read from the working directory when no certificate is given, as before `--tls-cert`
+const DEFAULT_CERT: &str = "cert.pem";
+const DEFAULT_KEY: &str = "key.pem";
+
+/// The names a generated certificate is valid for.
+pub const SELF_SIGNED_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+/// Loads the certificate chain and key given to `--tls-cert`/`--tls-key`, or `cert.pem` and
+/// `key.pem` if both exist, or else generates a self-signed certificate for localhost. Also tells
+/// whether the certificate is a generated one.
+pub fn load(
+    cert: Option<&Path>,
+    key: Option<&Path>,
+) -> Result<(ServerConfig, bool), SharedError> {
+    let files = match (cert, key) {
+        (Some(cert), Some(key)) => Some((cert.to_path_buf(), key.to_path_buf())),
+        (None, None) if Path::new(DEFAULT_CERT).exists() && Path::new(DEFAULT_KEY).exists() => {
+            Some((PathBuf::from(DEFAULT_CERT), PathBuf::from(DEFAULT_KEY)))
+        }
+        (None, None) => None,
+        _ => return Err("--tls-cert and --tls-key go together".into()),
+    };
+
+    let (chain, key) = match &files {
+        Some((cert, key)) => read_pem(cert, key)?,
+        None => self_signed()?,
+    };
+    Ok((server_config(chain, key)?, files.is_none()))
+}
+
+/// Loads the certificate chain and key given to `--http-tls-cert`/`--http-tls-key`, HTTPS has
+/// no default ones.
+pub fn load_files(cert: &Path, key: &Path) -> Result<ServerConfig, SharedError> {
+    let (chain, key) = read_pem(cert, key)?;
+    server_config(chain, key)
+}
+
+fn read_pem(cert: &Path, key: &Path) -> Result<(Vec<Certificate>, PrivateKey), SharedError> {
+    let cert_file = &mut StdBufReader::new(
+        File::open(cert).map_err(|e| format!("Failed to open {}: {}", cert.display(), e))?,
+    );
+    let key_file = &mut StdBufReader::new(
+        File::open(key).map_err(|e| format!("Failed to open {}: {}", key.display(), e))?,
+    );
+
+    // cert pem
+    let chain: Vec<Certificate> = certs(cert_file)
+        .map_err(|_| format!("Failed to read certificate file {}", cert.display()))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if chain.is_empty() {
+        return Err(format!("No certificate found in {}", cert.display()).into());
+    }
+
+    // keys pem
+    let mut keys = pkcs8_private_keys(key_file)
+        .map_err(|_| format!("Failed to read key file {}", key.display()))?
+        .into_iter()
+        .map(PrivateKey)
+        .collect::<Vec<_>>();
+    if keys.is_empty() {
+        return Err(format!("No private keys found in {}", key.display()).into());
+    }
+
+    Ok((chain, keys.remove(0)))
+}
+
+/// A throwaway certificate for [`SELF_SIGNED_NAMES`], for test environments where clients
+/// don't verify it (or trust it explicitly).
+pub fn self_signed() -> Result<(Vec<Certificate>, PrivateKey), SharedError> {
+    let names: Vec<String> = SELF_SIGNED_NAMES.iter().map(|name| name.to_string()).collect();
+    let generated = rcgen::generate_simple_self_signed(names)?;
+    Ok((
+        vec![Certificate(generated.cert.der().to_vec())],
+        PrivateKey(generated.key_pair.serialize_der()),
+    ))
+}
+
+pub fn server_config(chain: Vec<Certificate>, key: PrivateKey) -> Result<ServerConfig, SharedError> {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?;
+
+    Ok(config)
+}
@@ -0,0 +1,174 @@
+use crate::jobs::{civil_date, MONTHS, WEEKDAYS};
+use crate::smtp::mail::get_data_from_to;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// between the alternatives of a mail given with both `text` and `html`
+const BOUNDARY: &str = "mail-sink-alternative";
+
+/// A mail given to `POST /mails`, with its envelope as SMTP would have had it.
+#[derive(Debug)]
+pub struct Upload {
+    pub from: HashSet<String>,
+    pub to: HashSet<String>,
+    pub data: Bytes,
+}
+
+/// A raw RFC 822 message. The envelope is taken from its `From` and `To` headers, unless `from`
+/// or `to` (comma separated) are given, like the `MAIL FROM` and `RCPT TO` of SMTP.
+pub fn from_raw(data: &[u8], from: Option<&str>, to: Option<&str>) -> Result<Upload, String> {
+    let data = crlf(data);
+    let text = String::from_utf8_lossy(&data);
+    // only the headers, a quoted mail in the body has some too
+    let head = text.split("\r\n\r\n").next().unwrap_or_default();
+    let (header_from, header_to) = get_data_from_to(head);
+    let upload = Upload {
+        from: from.map(addresses).unwrap_or(header_from),
+        to: to.map(addresses).unwrap_or(header_to),
+        data: data.into(),
+    };
+    check(upload)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Addresses {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct JsonMail {
+    from: Addresses,
+    to: Addresses,
+    // the whole message, else it's made of the fields below
+    data: Option<String>,
+    subject: Option<String>,
+    text: Option<String>,
+    html: Option<String>,
+}
+
+/// A mail as JSON: `from` and `to` (an address or a list of them), and either the raw message
+/// as `data` or its `subject`, `text` and `html`.
+pub fn from_json(body: &[u8]) -> Result<Upload, String> {
+    let mail: JsonMail = serde_json::from_slice(body).map_err(|e| format!("Invalid mail: {}", e))?;
+    let from = match mail.from {
+        Addresses::One(address) => addresses(&address),
+        Addresses::Many(list) => addresses(&list.join(",")),
+    };
+    let to = match mail.to {
+        Addresses::One(address) => addresses(&address),
+        Addresses::Many(list) => addresses(&list.join(",")),
+    };
+
+    let data = match mail.data {
+        Some(data) => crlf(data.as_bytes()),
+        None => compose(&from, &to, mail.subject.as_deref(), mail.text.as_deref(), mail.html.as_deref()),
+    };
+    check(Upload {
+        from,
+        to,
+        data: data.into(),
+    })
+}
+
+fn check(upload: Upload) -> Result<Upload, String> {
+    if upload.from.is_empty() || upload.to.is_empty() {
+        return Err("Expected a sender and at least one recipient".to_string());
+    }
+    if upload.data.iter().all(u8::is_ascii_whitespace) {
+        return Err("Expected a message".to_string());
+    }
+    Ok(upload)
+}
+
+fn addresses(list: &str) -> HashSet<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// lone LFs made CRLF, as SMTP clients send them
+fn crlf(data: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(data.len() + data.len() / 32);
+    for (index, byte) in data.iter().enumerate() {
+        if *byte == b'\n' && (index == 0 || data[index - 1] != b'\r') {
+            normalized.push(b'\r');
+        }
+        normalized.push(*byte);
+    }
+    normalized
+}
+
+// the message a mail client would have sent for these fields
+fn compose(
+    from: &HashSet<String>,
+    to: &HashSet<String>,
+    subject: Option<&str>,
+    text: Option<&str>,
+    html: Option<&str>,
+) -> Vec<u8> {
+    let mut from: Vec<&String> = from.iter().collect();
+    from.sort();
+    let mut to: Vec<&String> = to.iter().collect();
+    to.sort();
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n",
+        from.iter().map(|address| format!("<{}>", address)).collect::<Vec<_>>().join(", "),
+        to.iter().map(|address| format!("<{}>", address)).collect::<Vec<_>>().join(", "),
+        date(SystemTime::now())
+    );
+    if let Some(subject) = subject {
+        message.push_str(&format!("Subject: {}\r\n", encode_header(subject)));
+    }
+
+    let part = |content_type: &str, content: &str| {
+        format!(
+            "Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}\r\n",
+            content_type,
+            String::from_utf8_lossy(&crlf(content.as_bytes()))
+        )
+    };
+    match (text, html) {
+        (Some(text), Some(html)) => {
+            message.push_str(&format!("Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n", BOUNDARY));
+            message.push_str(&format!("--{}\r\n{}", BOUNDARY, part("text/plain", text)));
+            message.push_str(&format!("--{}\r\n{}", BOUNDARY, part("text/html", html)));
+            message.push_str(&format!("--{}--\r\n", BOUNDARY));
+        }
+        (None, Some(html)) => message.push_str(&part("text/html", html)),
+        (text, None) => message.push_str(&part("text/plain", text.unwrap_or_default())),
+    }
+    message.into_bytes()
+}
+
+// RFC 2047, for the subjects that aren't plain ASCII
+fn encode_header(value: &str) -> String {
+    match value.is_ascii() {
+        true => value.to_string(),
+        false => format!("=?utf-8?B?{}?=", BASE64_STANDARD.encode(value)),
+    }
+}
+
+// e.g. `Mon, 01 Jan 2024 00:00:00 +0000`, as the `Date` header wants it
+fn date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = seconds / 86400;
+    let (year, month, day) = civil_date(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
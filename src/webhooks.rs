@@ -0,0 +1,145 @@
+use crate::http::MailJson;
+use crate::smtp::mail::Mail;
+use crate::summary::MailSummary;
+use crate::{metrics, report};
+use lazy_static::lazy_static;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+const ATTEMPTS: u32 = 5;
+// doubled after every failed attempt: 1s, 2s, 4s then 8s
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref URLS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+    // the deliveries going on or waiting to be retried, for `POST /reset` to stop them
+    static ref DELIVERIES: Mutex<JoinSet<()>> = Mutex::new(JoinSet::new());
+}
+
+/// Checks that every URL is an http(s) one, so that a typo fails at startup rather than on
+/// every mail.
+pub fn validate(urls: &[String]) -> Result<(), String> {
+    match urls
+        .iter()
+        .find(|url| !url.starts_with("http://") && !url.starts_with("https://"))
+    {
+        Some(url) => Err(format!("Invalid webhook URL `{}`, expected http(s)://...", url)),
+        None => Ok(()),
+    }
+}
+
+/// The URLs given to `--webhook`, which `$MAILSINK_WEBHOOK` sets comma separated.
+pub fn from_args(urls: &[String]) -> Result<Vec<String>, String> {
+    let urls: Vec<String> = urls
+        .iter()
+        .map(|url| url.trim())
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+    validate(&urls)?;
+    Ok(urls)
+}
+
+pub fn set(urls: Vec<String>) {
+    *URLS.write().unwrap() = urls;
+}
+
+pub fn get() -> Vec<String> {
+    URLS.read().unwrap().clone()
+}
+
+/// POSTs a stored mail, as returned by `GET /mails/:mail_id`, to every webhook.
+pub fn notify(mail: &Mail, summary: &MailSummary) {
+    let urls = get();
+    if urls.is_empty() {
+        return;
+    }
+
+    let payload = match serde_json::to_string(&MailJson::new(mail, true).with_summary(Some(summary.clone()))) {
+        Ok(payload) => payload,
+        Err(e) => {
+            report::report(
+                report::Kind::Delivery,
+                &format!("Failed to serialize mail {} for the webhooks: {}", mail.id, e),
+            );
+            return;
+        }
+    };
+    for url in urls {
+        spawn(url, payload.clone(), mail.id);
+    }
+}
+
+/// Delivers a JSON payload about a mail in the background, until [`cancel`]led.
+pub fn spawn(url: String, payload: String, id: u128) {
+    let mut deliveries = DELIVERIES.lock().unwrap();
+    while deliveries.try_join_next().is_some() {}
+    deliveries.spawn(deliver(url, payload, id));
+}
+
+/// Stops the deliveries still going on or waiting to be retried, returning how many there were.
+/// A request already sent still gets its answer.
+pub fn cancel() -> usize {
+    let mut deliveries = DELIVERIES.lock().unwrap();
+    // the finished ones are only left to be collected
+    while deliveries.try_join_next().is_some() {}
+    let count = deliveries.len();
+    deliveries.abort_all();
+    deliveries.detach_all();
+    count
+}
+
+/// POSTs a JSON payload about a mail, retrying with an exponential backoff. Only reported once
+/// every attempt failed.
+pub async fn deliver(url: String, payload: String, id: u128) {
+    let mut backoff = FIRST_BACKOFF;
+    for attempt in 1..=ATTEMPTS {
+        let (url_clone, payload_clone) = (url.clone(), payload.clone());
+        // ureq blocks
+        let result = tokio::task::spawn_blocking(move || post(&url_clone, &payload_clone)).await;
+        let (retryable, error) = match result {
+            Ok(Ok(())) => {
+                metrics::WEBHOOKS_DELIVERED.inc();
+                return;
+            }
+            Ok(Err(failure)) => failure,
+            Err(e) => (false, e.to_string()),
+        };
+
+        if attempt == ATTEMPTS || !retryable {
+            metrics::WEBHOOK_FAILURES.inc();
+            report::report(
+                report::Kind::Delivery,
+                &format!(
+                    "Failed to call the webhook {} for mail {} after {} attempts: {}",
+                    url, id, attempt, error
+                ),
+            );
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+// whether trying again could help, and the error
+fn post(url: &str, payload: &str) -> Result<(), (bool, String)> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    agent
+        .post(url)
+        .header("Content-Type", "application/json")
+        .send(payload)
+        .map(|_| ())
+        .map_err(|e| match e {
+            // the endpoint refusing the payload won't change its mind, unless it was only busy
+            ureq::Error::StatusCode(status) => {
+                (status >= 500 || status == 408 || status == 429, format!("HTTP {}", status))
+            }
+            e => (true, e.to_string()),
+        })
+}
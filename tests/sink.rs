@@ -0,0 +1,165 @@
+// the sink's configuration is the process's, so it's tested in its own binary, the way another
+// crate embeds it
+use mail_sink::{AlreadyRunning, MailSink};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+async fn send(addr: std::net::SocketAddr, to: &str, subject: &str) {
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    let commands = [
+        "EHLO test".to_string(),
+        "MAIL FROM:<app@example.com>".to_string(),
+        format!("RCPT TO:<{}>", to),
+        "DATA".to_string(),
+        format!("From: app@example.com\r\nTo: {}\r\nSubject: {}\r\n\r\nHello\r\n.", to, subject),
+        "QUIT".to_string(),
+    ];
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    for command in commands {
+        stream.get_mut().write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+        // the EHLO reply spans several lines
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        assert!(!line.starts_with('5'), "{} refused: {}", command, line);
+    }
+}
+
+async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+    request(addr, "GET", path).await
+}
+
+async fn request(addr: std::net::SocketAddr, method: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nX-Api-Key: secret\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+// a plain SMTP server standing for the `--relay` upstream, handing over the data of each mail
+async fn upstream() -> (u16, mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(socket);
+                stream.get_mut().write_all(b"220 upstream\r\n").await.unwrap();
+                let (mut line, mut data, mut in_data) = (String::new(), String::new(), false);
+                while stream.read_line(&mut line).await.unwrap() > 0 {
+                    let reply = match (in_data, line.as_str()) {
+                        (true, ".\r\n") => {
+                            in_data = false;
+                            let _ = sender.send(std::mem::take(&mut data));
+                            "250 OK\r\n"
+                        }
+                        (true, _) => {
+                            data.push_str(&line);
+                            ""
+                        }
+                        (false, "DATA\r\n") => {
+                            in_data = true;
+                            "354 Go ahead\r\n"
+                        }
+                        (false, "QUIT\r\n") => break,
+                        (false, _) => "250 OK\r\n",
+                    };
+                    stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                    line.clear();
+                }
+            });
+        }
+    });
+    (port, receiver)
+}
+
+#[tokio::test]
+async fn test_embedded_sink() {
+    let sink = MailSink::new().key("secret").option("rule", "*@noise.test drop").start().await.unwrap();
+    assert_ne!(sink.smtp_addr().port(), 0);
+    assert!(sink.smtp_addr().ip().is_loopback());
+    let second = MailSink::new().start().await.err().unwrap();
+    assert_eq!(second.downcast_ref::<AlreadyRunning>(), Some(&AlreadyRunning));
+
+    let mut mails = sink.mails();
+    send(sink.smtp_addr(), "noise@noise.test", "Dropped").await;
+    send(sink.smtp_addr(), "alice@example.com", "Welcome").await;
+    let mail = tokio::time::timeout(Duration::from_secs(5), mails.next()).await.unwrap().unwrap();
+    assert_eq!(mail.subject.as_deref(), Some("Welcome"));
+    assert!(mail.to.contains("alice@example.com"));
+
+    let response = get(sink.http_addr(), "/mails").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains(&mail.id.to_string()));
+
+    sink.shutdown().await.unwrap();
+    assert!(mails.next().await.is_none());
+
+    // the next one starts afresh, on other ports and with an empty database
+    let sink = MailSink::new().smtp_ports(&[0, 0]).pop3_port(0).start().await.unwrap();
+    assert_eq!(sink.smtp_addrs().len(), 2);
+    assert!(sink.pop3_addr().is_some());
+    let mut mails = sink.mails();
+    send(sink.smtp_addrs()[1], "bob@example.com", "Again").await;
+    let mail = tokio::time::timeout(Duration::from_secs(5), mails.next()).await.unwrap().unwrap();
+    assert_eq!(mail.subject.as_deref(), Some("Again"));
+    drop(sink);
+
+    let invalid = MailSink::new().set("max-message-size", "lots").start().await;
+    assert!(invalid.is_err());
+    assert!(MailSink::new().start().await.is_ok());
+
+    // switches take a value like the other options
+    let sink = MailSink::new().key("secret").set("verify-auth", true).start().await.unwrap();
+    assert!(get(sink.http_addr(), "/admin/config").await.contains("\"verify_auth\":true"));
+    sink.shutdown().await.unwrap();
+
+    // each sink relays through its own queue, not only the first one of the process
+    let (port, mut relayed) = upstream().await;
+    for subject in ["First relay", "Second relay"] {
+        let sink = MailSink::new()
+            .set("relay", format!("smtp://127.0.0.1:{}", port))
+            .option("rule", "*@relay.test relay")
+            .start()
+            .await
+            .unwrap();
+        send(sink.smtp_addr(), "qa@relay.test", subject).await;
+        let data = tokio::time::timeout(Duration::from_secs(5), relayed.recv()).await.unwrap().unwrap();
+        assert!(data.contains(subject));
+        sink.shutdown().await.unwrap();
+    }
+
+    // a reset stops the deliveries waiting to be retried, rather than relaying the previous run's mails
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    let sink = MailSink::new()
+        .key("secret")
+        .set("relay", format!("smtp://127.0.0.1:{}", closed))
+        .option("rule", "*@relay.test relay")
+        .start()
+        .await
+        .unwrap();
+    let mut mails = sink.mails();
+    send(sink.smtp_addr(), "qa@relay.test", "Refused").await;
+    let mail = tokio::time::timeout(Duration::from_secs(5), mails.next()).await.unwrap().unwrap();
+    while !get(sink.http_addr(), &format!("/mails/{}", mail.id)).await.contains("\"attempts\":1") {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let response = request(sink.http_addr(), "POST", "/reset").await;
+    assert!(response.contains("\"relays\":1"), "{}", response);
+    assert!(response.contains("\"relay_statuses\":1"), "{}", response);
+    sink.shutdown().await.unwrap();
+}